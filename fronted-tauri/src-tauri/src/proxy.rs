@@ -1,1079 +1,27 @@
-use codex_proxy_core::load_balancer::{
-    EndpointPolicy as CoreEndpointPolicy, LoadBalancerConfig as CoreLoadBalancerConfig,
-    LoadBalancerEndpoint as CoreLoadBalancerEndpoint,
-    LoadBalancerProfile as CoreLoadBalancerProfile, LoadBalancerRuntime,
-    SlotEndpointRef as CoreSlotEndpointRef, SlotMapping as CoreSlotMapping,
+use codex_proxy_core::config::{
+    build_lb_runtime, build_proxy_server, build_runtime_update, build_transform_context,
+    default_config_path, default_converter, default_gemini_model_preset, default_proxy_config,
+    load_config_file, resolve_codex_target_api_key_and_converter, resolve_target_and_api_key,
+    save_config_file, CodexModelMappingConfig, EndpointOption, ProxyConfig, ReasoningEffortConfig,
+};
+use codex_proxy_core::endpoint_test::{
+    build_endpoint_test_request, describe_test_path, run_endpoint_test_attempt,
+    EndpointTestAttempt, TEST_INPUT_MODEL,
 };
-use codex_proxy_core::models::{Message, MessageContent};
 use codex_proxy_core::transform::{CodexBackend, GeminiBackend, OpenAIChatBackend};
 use codex_proxy_core::{
-    AnthropicBackend, AnthropicModelMapping, AnthropicRequest, CodexModelMapping,
-    GeminiReasoningEffortMapping, OpenAIMaxTokensMapping, OpenAIModelMapping, ProxyRuntimeHandle,
-    ProxyServer, ReasoningEffort, ReasoningEffortMapping, RuntimeConfigUpdate, RuntimeRouteUpdate,
-    TransformBackend, TransformContext,
+    AnthropicBackend, AnthropicModelMapping, CodexModelMapping, OpenAIModelMapping,
+    ProxyRuntimeHandle, TransformBackend, TransformContext,
 };
-use futures_util::StreamExt;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::Serialize;
 use std::fs;
 use std::net::TcpListener;
 use std::process::Command;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tauri::{AppHandle, Emitter, Manager};
 use tokio::sync::broadcast;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ReasoningEffortConfig {
-    pub opus: String,
-    pub sonnet: String,
-    pub haiku: String,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct CodexModelMappingConfig {
-    pub opus: String,
-    pub sonnet: String,
-    pub haiku: String,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
-pub struct AnthropicModelMappingConfig {
-    pub opus: String,
-    pub sonnet: String,
-    pub haiku: String,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
-pub struct OpenAIModelMappingConfig {
-    pub opus: String,
-    pub sonnet: String,
-    pub haiku: String,
-}
-
-/// Per-slot max_tokens configuration for OpenAI Chat API.
-/// None means pass-through (use the value from Anthropic request).
-#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
-#[serde(rename_all = "camelCase")]
-pub struct OpenAIMaxTokensMappingConfig {
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub opus: Option<u32>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub sonnet: Option<u32>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub haiku: Option<u32>,
-}
-
-impl From<OpenAIMaxTokensMappingConfig> for OpenAIMaxTokensMapping {
-    fn from(config: OpenAIMaxTokensMappingConfig) -> Self {
-        OpenAIMaxTokensMapping {
-            opus: config.opus,
-            sonnet: config.sonnet,
-            haiku: config.haiku,
-        }
-    }
-}
-
-impl Default for CodexModelMappingConfig {
-    fn default() -> Self {
-        Self {
-            opus: "gpt-5.3-codex".to_string(),
-            sonnet: "gpt-5.2-codex".to_string(),
-            haiku: "gpt-5.1-codex-mini".to_string(),
-        }
-    }
-}
-
-impl Default for ReasoningEffortConfig {
-    fn default() -> Self {
-        Self {
-            opus: "xhigh".to_string(),
-            sonnet: "medium".to_string(),
-            haiku: "low".to_string(),
-        }
-    }
-}
-
-impl ReasoningEffortConfig {
-    pub fn to_mapping(&self) -> ReasoningEffortMapping {
-        ReasoningEffortMapping::new()
-            .with_opus(ReasoningEffort::from_str(&self.opus))
-            .with_sonnet(ReasoningEffort::from_str(&self.sonnet))
-            .with_haiku(ReasoningEffort::from_str(&self.haiku))
-    }
-
-    pub fn to_gemini_mapping(&self) -> GeminiReasoningEffortMapping {
-        GeminiReasoningEffortMapping {
-            opus: self.opus.clone(),
-            sonnet: self.sonnet.clone(),
-            haiku: self.haiku.clone(),
-        }
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct LbSlotEndpointRef {
-    pub endpoint_id: String,
-    pub custom_model_name: Option<String>,
-    pub custom_reasoning_effort: Option<String>,
-    pub converter_override: Option<String>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct ModelSlotMapping {
-    pub opus: Vec<LbSlotEndpointRef>,
-    pub sonnet: Vec<LbSlotEndpointRef>,
-    pub haiku: Vec<LbSlotEndpointRef>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct LbFailoverStrategy {
-    pub error_threshold: u32,
-    pub error_window_seconds: u32,
-    pub cooldown_seconds: u32,
-    pub degraded_concurrency: u32,
-}
-
-impl Default for LbFailoverStrategy {
-    fn default() -> Self {
-        Self {
-            error_threshold: 5,
-            error_window_seconds: 60,
-            cooldown_seconds: 3600,
-            degraded_concurrency: 4,
-        }
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct LoadBalancerProfile {
-    pub id: String,
-    pub name: String,
-    pub description: Option<String>,
-    pub model_mapping: ModelSlotMapping,
-    pub strategy: LbFailoverStrategy,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct LbEndpointConfig {
-    pub endpoint_id: String,
-    pub enabled: bool,
-    pub max_concurrency: u32,
-    pub priority: u32,
-    pub weight: u32,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
-#[serde(rename_all = "camelCase")]
-pub struct LoadBalancerConfig {
-    pub lb_profiles: Vec<LoadBalancerProfile>,
-    pub selected_lb_profile_id: Option<String>,
-    pub lb_endpoint_configs: std::collections::HashMap<String, LbEndpointConfig>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct EndpointOption {
-    pub id: String,
-    pub alias: String,
-    pub url: String,
-    #[serde(rename = "apiKey")]
-    pub api_key: String,
-
-    #[serde(default)]
-    pub converter: Option<String>,
-
-    #[serde(rename = "codexModel", default)]
-    pub codex_model: Option<String>,
-
-    #[serde(rename = "codexModelMapping", default)]
-    pub codex_model_mapping: Option<CodexModelMappingConfig>,
-
-    #[serde(rename = "codexEffortCapabilityMap", default)]
-    pub codex_effort_capability_map: Option<std::collections::HashMap<String, Vec<String>>>,
-
-    #[serde(rename = "geminiModelPreset", default)]
-    pub gemini_model_preset: Option<Vec<String>>,
-
-    #[serde(rename = "anthropicModelMapping", default)]
-    pub anthropic_model_mapping: Option<AnthropicModelMappingConfig>,
-
-    #[serde(rename = "openaiModelMapping", default)]
-    pub openai_model_mapping: Option<OpenAIModelMappingConfig>,
-
-    #[serde(rename = "openaiMaxTokensMapping", default)]
-    pub openai_max_tokens_mapping: Option<OpenAIMaxTokensMappingConfig>,
-
-    #[serde(rename = "reasoningEffort", default)]
-    pub reasoning_effort: Option<ReasoningEffortConfig>,
-
-    #[serde(rename = "geminiReasoningEffort", default)]
-    pub gemini_reasoning_effort: Option<ReasoningEffortConfig>,
-}
-
-fn default_endpoint_options() -> Vec<EndpointOption> {
-    vec![EndpointOption {
-        id: "aicodemirror-default".to_string(),
-        alias: "aicodemirror".to_string(),
-        url: "https://api.aicodemirror.com/api/codex/backend-api/codex/responses".to_string(),
-        api_key: String::new(),
-        converter: None,
-        codex_model: None,
-        codex_model_mapping: None,
-        codex_effort_capability_map: None,
-        gemini_model_preset: None,
-        anthropic_model_mapping: None,
-        openai_model_mapping: None,
-        openai_max_tokens_mapping: None,
-        reasoning_effort: None,
-        gemini_reasoning_effort: None,
-    }]
-}
-
-fn default_selected_endpoint_id() -> String {
-    "aicodemirror-default".to_string()
-}
-
-fn default_codex_endpoint_options() -> Vec<EndpointOption> {
-    vec![EndpointOption {
-        id: "codex-default".to_string(),
-        alias: "CodebuddyProxy".to_string(),
-        url: "https://api.aicodemirror.com/api/codex/backend-api/codex/responses".to_string(),
-        api_key: String::new(),
-        converter: Some(default_converter()),
-        codex_model: None,
-        codex_model_mapping: None,
-        codex_effort_capability_map: None,
-        gemini_model_preset: None,
-        anthropic_model_mapping: None,
-        openai_model_mapping: None,
-        openai_max_tokens_mapping: None,
-        reasoning_effort: None,
-        gemini_reasoning_effort: None,
-    }]
-}
-
-fn default_codex_selected_endpoint_id() -> String {
-    "codex-default".to_string()
-}
-
-fn default_proxy_mode() -> String {
-    "single".to_string()
-}
-
-fn default_image_gen_endpoint_options() -> Vec<EndpointOption> {
-    Vec::new()
-}
-
-fn default_selected_image_gen_endpoint_id() -> String {
-    String::new()
-}
-
-fn default_load_balancer() -> LoadBalancerConfig {
-    LoadBalancerConfig::default()
-}
-
-fn default_lb_model_cooldown_seconds() -> u32 {
-    3600
-}
-
-fn default_lb_transient_backoff_seconds() -> u32 {
-    6
-}
-
-fn default_anthropic_model_mapping() -> AnthropicModelMappingConfig {
-    AnthropicModelMappingConfig::default()
-}
-
-fn default_openai_model_mapping() -> OpenAIModelMappingConfig {
-    OpenAIModelMappingConfig::default()
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct CodexClientConfig {
-    #[serde(rename = "targetUrl")]
-    pub target_url: String,
-    #[serde(rename = "apiKey")]
-    pub api_key: String,
-    #[serde(rename = "endpointOptions", default = "default_codex_endpoint_options")]
-    pub endpoint_options: Vec<EndpointOption>,
-    #[serde(
-        rename = "selectedEndpointId",
-        default = "default_codex_selected_endpoint_id"
-    )]
-    pub selected_endpoint_id: String,
-    #[serde(default = "default_converter")]
-    pub converter: String,
-    #[serde(rename = "proxyMode", default = "default_proxy_mode")]
-    pub proxy_mode: String,
-    #[serde(
-        rename = "imageGenerationEndpointOptions",
-        default = "default_image_gen_endpoint_options"
-    )]
-    pub image_generation_endpoint_options: Vec<EndpointOption>,
-    #[serde(
-        rename = "selectedImageGenerationEndpointId",
-        default = "default_selected_image_gen_endpoint_id"
-    )]
-    pub selected_image_generation_endpoint_id: String,
-    #[serde(rename = "stripImageGenerationTool", default)]
-    pub strip_image_generation_tool: bool,
-}
-
-impl Default for CodexClientConfig {
-    fn default() -> Self {
-        let endpoint_options = default_codex_endpoint_options();
-        Self {
-            target_url: endpoint_options
-                .first()
-                .map(|item| item.url.clone())
-                .unwrap_or_default(),
-            api_key: String::new(),
-            endpoint_options,
-            selected_endpoint_id: default_codex_selected_endpoint_id(),
-            converter: default_converter(),
-            proxy_mode: default_proxy_mode(),
-            image_generation_endpoint_options: default_image_gen_endpoint_options(),
-            selected_image_generation_endpoint_id: default_selected_image_gen_endpoint_id(),
-            strip_image_generation_tool: false,
-        }
-    }
-}
-
-fn default_codex_config() -> CodexClientConfig {
-    CodexClientConfig::default()
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
-pub struct ProxyConfig {
-    pub port: u16,
-    #[serde(rename = "targetUrl")]
-    pub target_url: String,
-    #[serde(rename = "apiKey")]
-    pub api_key: String,
-    #[serde(rename = "endpointOptions", default = "default_endpoint_options")]
-    pub endpoint_options: Vec<EndpointOption>,
-    #[serde(
-        rename = "selectedEndpointId",
-        default = "default_selected_endpoint_id"
-    )]
-    pub selected_endpoint_id: String,
-    #[serde(rename = "codexConfig", default = "default_codex_config")]
-    pub codex_config: CodexClientConfig,
-    #[serde(default = "default_converter")]
-    pub converter: String,
-    #[serde(rename = "codexModel", default = "default_codex_model")]
-    pub codex_model: String,
-    #[serde(rename = "codexModelMapping", default)]
-    pub codex_model_mapping: CodexModelMappingConfig,
-    #[serde(
-        rename = "anthropicModelMapping",
-        default = "default_anthropic_model_mapping"
-    )]
-    pub anthropic_model_mapping: AnthropicModelMappingConfig,
-
-    #[serde(
-        rename = "openaiModelMapping",
-        default = "default_openai_model_mapping"
-    )]
-    pub openai_model_mapping: OpenAIModelMappingConfig,
-
-    #[serde(rename = "codexEffortCapabilityMap", default)]
-    pub codex_effort_capability_map: Option<std::collections::HashMap<String, Vec<String>>>,
-
-    #[serde(rename = "geminiModelPreset", default = "default_gemini_model_preset")]
-    pub gemini_model_preset: Vec<String>,
-
-    #[serde(rename = "maxConcurrency", default)]
-    pub max_concurrency: u32,
-    #[serde(rename = "ignoreProbeRequests", default)]
-    pub ignore_probe_requests: bool,
-    #[serde(
-        rename = "allowCountTokensFallbackEstimate",
-        default = "default_allow_count_tokens_fallback_estimate"
-    )]
-    pub allow_count_tokens_fallback_estimate: bool,
-    #[serde(
-        rename = "enableCodexFastMode",
-        default = "default_enable_codex_fast_mode"
-    )]
-    pub enable_codex_fast_mode: bool,
-    #[serde(
-        rename = "forceStreamForCodex",
-        default = "default_force_stream_for_codex"
-    )]
-    pub force_stream_for_codex: bool,
-    #[serde(
-        rename = "enableSseFrameParser",
-        default = "default_enable_sse_frame_parser"
-    )]
-    pub enable_sse_frame_parser: bool,
-    #[serde(
-        rename = "enableStreamHeartbeat",
-        default = "default_enable_stream_heartbeat"
-    )]
-    pub enable_stream_heartbeat: bool,
-    #[serde(
-        rename = "streamHeartbeatIntervalMs",
-        default = "default_stream_heartbeat_interval_ms"
-    )]
-    pub stream_heartbeat_interval_ms: u64,
-    #[serde(
-        rename = "enableStreamLogSampling",
-        default = "default_enable_stream_log_sampling"
-    )]
-    pub enable_stream_log_sampling: bool,
-    #[serde(
-        rename = "streamLogSampleEveryN",
-        default = "default_stream_log_sample_every_n"
-    )]
-    pub stream_log_sample_every_n: u32,
-    #[serde(rename = "streamLogMaxChars", default = "default_stream_log_max_chars")]
-    pub stream_log_max_chars: usize,
-    #[serde(
-        rename = "enableStreamMetrics",
-        default = "default_enable_stream_metrics"
-    )]
-    pub enable_stream_metrics: bool,
-    #[serde(
-        rename = "enableStreamEventMetrics",
-        default = "default_enable_stream_event_metrics"
-    )]
-    pub enable_stream_event_metrics: bool,
-    #[serde(
-        rename = "streamSilenceWarnMs",
-        default = "default_stream_silence_warn_ms"
-    )]
-    pub stream_silence_warn_ms: u64,
-    #[serde(
-        rename = "streamSilenceErrorMs",
-        default = "default_stream_silence_error_ms"
-    )]
-    pub stream_silence_error_ms: u64,
-    #[serde(rename = "enableStallRetry", default = "default_enable_stall_retry")]
-    pub enable_stall_retry: bool,
-    #[serde(rename = "stallTimeoutMs", default = "default_stall_timeout_ms")]
-    pub stall_timeout_ms: u64,
-    #[serde(
-        rename = "stallRetryMaxAttempts",
-        default = "default_stall_retry_max_attempts"
-    )]
-    pub stall_retry_max_attempts: u32,
-    #[serde(
-        rename = "stallRetryOnlyHeartbeatPhase",
-        default = "default_stall_retry_only_heartbeat_phase"
-    )]
-    pub stall_retry_only_heartbeat_phase: bool,
-    #[serde(
-        rename = "enableEmptyCompletionRetry",
-        default = "default_enable_empty_completion_retry"
-    )]
-    pub enable_empty_completion_retry: bool,
-    #[serde(
-        rename = "emptyCompletionRetryMaxAttempts",
-        default = "default_empty_completion_retry_max_attempts"
-    )]
-    pub empty_completion_retry_max_attempts: u32,
-    #[serde(
-        rename = "enableIncompleteStreamRetry",
-        default = "default_enable_incomplete_stream_retry"
-    )]
-    pub enable_incomplete_stream_retry: bool,
-    #[serde(
-        rename = "incompleteStreamRetryMaxAttempts",
-        default = "default_incomplete_stream_retry_max_attempts"
-    )]
-    pub incomplete_stream_retry_max_attempts: u32,
-    #[serde(
-        rename = "enableSiblingToolErrorRetry",
-        default = "default_enable_sibling_tool_error_retry"
-    )]
-    pub enable_sibling_tool_error_retry: bool,
-    #[serde(rename = "preferCodexV1Path", default = "default_prefer_codex_v1_path")]
-    pub prefer_codex_v1_path: bool,
-    #[serde(
-        rename = "enableCodexToolSchemaCompaction",
-        default = "default_enable_codex_tool_schema_compaction"
-    )]
-    pub enable_codex_tool_schema_compaction: bool,
-    #[serde(
-        rename = "enableSkillRoutingHint",
-        default = "default_enable_skill_routing_hint"
-    )]
-    pub enable_skill_routing_hint: bool,
-    #[serde(
-        rename = "enableStatefulResponsesChain",
-        default = "default_enable_stateful_responses_chain"
-    )]
-    pub enable_stateful_responses_chain: bool,
-    #[serde(rename = "allowExternalAccess", default)]
-    pub allow_external_access: bool,
-    #[serde(default)]
-    pub force: bool,
-    #[serde(rename = "proxyMode", default = "default_proxy_mode")]
-    pub proxy_mode: String,
-    #[serde(rename = "loadBalancer", default = "default_load_balancer")]
-    pub load_balancer: LoadBalancerConfig,
-    #[serde(
-        rename = "lbModelCooldownSeconds",
-        default = "default_lb_model_cooldown_seconds"
-    )]
-    pub lb_model_cooldown_seconds: u32,
-    #[serde(
-        rename = "lbTransientBackoffSeconds",
-        default = "default_lb_transient_backoff_seconds"
-    )]
-    pub lb_transient_backoff_seconds: u32,
-    #[serde(rename = "reasoningEffort", default)]
-    pub reasoning_effort: ReasoningEffortConfig,
-    #[serde(rename = "geminiReasoningEffort", default)]
-    pub gemini_reasoning_effort: ReasoningEffortConfig,
-    #[serde(
-        rename = "customInjectionPrompt",
-        alias = "skillInjectionPrompt",
-        default = "default_custom_injection_prompt"
-    )]
-    pub custom_injection_prompt: String,
-    #[serde(default = "default_lang")]
-    pub lang: String,
-}
-
-fn default_lang() -> String {
-    "zh".to_string()
-}
-
-const DEFAULT_CUSTOM_INJECTION_PROMPT: &str = "回复前先看下skills是否有一个skill符合的，可以执行。\n\nskills里的技能如果需要依赖，先安装，不要先用其他方案，如果还有问题告知用户解决方案让用户选择。\n\n如果你收到 <tool_use_error>Sibling tool call errored</tool_use_error>，请不要将其作为普通文本输出，而是分析导致该错误的原始Sibling的报错原因并继续工作。";
-
-fn default_custom_injection_prompt() -> String {
-    DEFAULT_CUSTOM_INJECTION_PROMPT.to_string()
-}
-
-fn resolve_custom_injection_prompt(raw: &str) -> String {
-    if raw.trim().is_empty() {
-        default_custom_injection_prompt()
-    } else {
-        raw.to_string()
-    }
-}
-
-fn default_converter() -> String {
-    "codex".to_string()
-}
-
-fn default_codex_model() -> String {
-    "gpt-5.3-codex".to_string()
-}
-
-fn default_allow_count_tokens_fallback_estimate() -> bool {
-    true
-}
-
-fn default_enable_codex_fast_mode() -> bool {
-    true
-}
-
-fn default_force_stream_for_codex() -> bool {
-    true
-}
-
-fn default_enable_sse_frame_parser() -> bool {
-    true
-}
-
-fn default_enable_stream_heartbeat() -> bool {
-    true
-}
-
-fn default_stream_heartbeat_interval_ms() -> u64 {
-    3_000
-}
-
-fn default_enable_stream_log_sampling() -> bool {
-    true
-}
-
-fn default_stream_log_sample_every_n() -> u32 {
-    20
-}
-
-fn default_stream_log_max_chars() -> usize {
-    512
-}
-
-fn default_enable_stream_metrics() -> bool {
-    true
-}
-
-fn default_enable_stream_event_metrics() -> bool {
-    true
-}
-
-fn default_stream_silence_warn_ms() -> u64 {
-    20_000
-}
-
-fn default_stream_silence_error_ms() -> u64 {
-    90_000
-}
-
-fn default_enable_stall_retry() -> bool {
-    false
-}
-
-fn default_stall_timeout_ms() -> u64 {
-    300_000
-}
-
-fn default_stall_retry_max_attempts() -> u32 {
-    0
-}
-
-fn default_stall_retry_only_heartbeat_phase() -> bool {
-    false
-}
-
-fn default_enable_empty_completion_retry() -> bool {
-    false
-}
-
-fn default_empty_completion_retry_max_attempts() -> u32 {
-    0
-}
-
-fn default_enable_incomplete_stream_retry() -> bool {
-    true
-}
-
-fn default_incomplete_stream_retry_max_attempts() -> u32 {
-    2
-}
-
-fn default_enable_sibling_tool_error_retry() -> bool {
-    true
-}
-
-fn default_prefer_codex_v1_path() -> bool {
-    true
-}
-
-fn default_enable_codex_tool_schema_compaction() -> bool {
-    true
-}
-
-fn default_enable_skill_routing_hint() -> bool {
-    false
-}
-
-fn default_enable_stateful_responses_chain() -> bool {
-    true
-}
-
-fn default_gemini_model_preset() -> Vec<String> {
-    vec![
-        "gemini-2.5-flash-lite".to_string(),
-        "gemini-3-pro-preview".to_string(),
-        "gemini-3-pro-image-preview".to_string(),
-        "gemini-3-flash-preview".to_string(),
-        "gemini-2.5-flash".to_string(),
-        "gemini-2.5-pro".to_string(),
-    ]
-}
-
-fn default_proxy_config() -> ProxyConfig {
-    ProxyConfig {
-        port: 8889,
-        target_url: default_endpoint_options()
-            .into_iter()
-            .next()
-            .map(|item| item.url)
-            .unwrap_or_default(),
-        api_key: String::new(),
-        endpoint_options: default_endpoint_options(),
-        selected_endpoint_id: default_selected_endpoint_id(),
-        codex_config: default_codex_config(),
-        converter: default_converter(),
-        codex_model: default_codex_model(),
-        codex_model_mapping: CodexModelMappingConfig::default(),
-        anthropic_model_mapping: default_anthropic_model_mapping(),
-        openai_model_mapping: default_openai_model_mapping(),
-        codex_effort_capability_map: None,
-        gemini_model_preset: default_gemini_model_preset(),
-        max_concurrency: 0,
-        ignore_probe_requests: false,
-        allow_count_tokens_fallback_estimate: default_allow_count_tokens_fallback_estimate(),
-        enable_codex_fast_mode: default_enable_codex_fast_mode(),
-        force_stream_for_codex: default_force_stream_for_codex(),
-        enable_sse_frame_parser: default_enable_sse_frame_parser(),
-        enable_stream_heartbeat: default_enable_stream_heartbeat(),
-        stream_heartbeat_interval_ms: default_stream_heartbeat_interval_ms(),
-        enable_stream_log_sampling: default_enable_stream_log_sampling(),
-        stream_log_sample_every_n: default_stream_log_sample_every_n(),
-        stream_log_max_chars: default_stream_log_max_chars(),
-        enable_stream_metrics: default_enable_stream_metrics(),
-        enable_stream_event_metrics: default_enable_stream_event_metrics(),
-        stream_silence_warn_ms: default_stream_silence_warn_ms(),
-        stream_silence_error_ms: default_stream_silence_error_ms(),
-        enable_stall_retry: default_enable_stall_retry(),
-        stall_timeout_ms: default_stall_timeout_ms(),
-        stall_retry_max_attempts: default_stall_retry_max_attempts(),
-        stall_retry_only_heartbeat_phase: default_stall_retry_only_heartbeat_phase(),
-        enable_empty_completion_retry: default_enable_empty_completion_retry(),
-        empty_completion_retry_max_attempts: default_empty_completion_retry_max_attempts(),
-        enable_incomplete_stream_retry: default_enable_incomplete_stream_retry(),
-        incomplete_stream_retry_max_attempts: default_incomplete_stream_retry_max_attempts(),
-        enable_sibling_tool_error_retry: default_enable_sibling_tool_error_retry(),
-        prefer_codex_v1_path: default_prefer_codex_v1_path(),
-        enable_codex_tool_schema_compaction: default_enable_codex_tool_schema_compaction(),
-        enable_skill_routing_hint: default_enable_skill_routing_hint(),
-        enable_stateful_responses_chain: default_enable_stateful_responses_chain(),
-        allow_external_access: false,
-        force: false,
-        proxy_mode: default_proxy_mode(),
-        load_balancer: default_load_balancer(),
-        lb_model_cooldown_seconds: default_lb_model_cooldown_seconds(),
-        lb_transient_backoff_seconds: default_lb_transient_backoff_seconds(),
-        reasoning_effort: ReasoningEffortConfig::default(),
-        gemini_reasoning_effort: ReasoningEffortConfig::default(),
-        custom_injection_prompt: default_custom_injection_prompt(),
-        lang: default_lang(),
-    }
-}
-
-fn build_lb_runtime(
-    config: &ProxyConfig,
-    log_tx: Option<broadcast::Sender<String>>,
-) -> Option<LoadBalancerRuntime> {
-    let selected_profile_id = config.load_balancer.selected_lb_profile_id.clone();
-    let selected_profile_strategy = selected_profile_id
-        .as_ref()
-        .and_then(|profile_id| {
-            config
-                .load_balancer
-                .lb_profiles
-                .iter()
-                .find(|p| &p.id == profile_id)
-        })
-        .map(|p| p.strategy.clone())
-        .unwrap_or_default();
-
-    let error_threshold = selected_profile_strategy.error_threshold.max(1);
-    let error_window_seconds = selected_profile_strategy.error_window_seconds.max(1);
-    let degraded_concurrency = selected_profile_strategy.degraded_concurrency.max(1);
-    let cooldown_seconds = if config.lb_model_cooldown_seconds == 0 {
-        default_lb_model_cooldown_seconds()
-    } else {
-        config.lb_model_cooldown_seconds
-    };
-    let transient_backoff_seconds = if config.lb_transient_backoff_seconds == 0 {
-        default_lb_transient_backoff_seconds()
-    } else {
-        config.lb_transient_backoff_seconds
-    };
-
-    let profiles: Vec<CoreLoadBalancerProfile> = config
-        .load_balancer
-        .lb_profiles
-        .iter()
-        .map(|profile| CoreLoadBalancerProfile {
-            id: profile.id.clone(),
-            name: profile.name.clone(),
-            model_mapping: CoreSlotMapping {
-                opus: profile
-                    .model_mapping
-                    .opus
-                    .iter()
-                    .map(|item| CoreSlotEndpointRef {
-                        endpoint_id: item.endpoint_id.clone(),
-                        custom_model_name: item.custom_model_name.clone(),
-                        custom_reasoning_effort: item.custom_reasoning_effort.clone(),
-                        converter_override: item.converter_override.clone(),
-                    })
-                    .collect(),
-                sonnet: profile
-                    .model_mapping
-                    .sonnet
-                    .iter()
-                    .map(|item| CoreSlotEndpointRef {
-                        endpoint_id: item.endpoint_id.clone(),
-                        custom_model_name: item.custom_model_name.clone(),
-                        custom_reasoning_effort: item.custom_reasoning_effort.clone(),
-                        converter_override: item.converter_override.clone(),
-                    })
-                    .collect(),
-                haiku: profile
-                    .model_mapping
-                    .haiku
-                    .iter()
-                    .map(|item| CoreSlotEndpointRef {
-                        endpoint_id: item.endpoint_id.clone(),
-                        custom_model_name: item.custom_model_name.clone(),
-                        custom_reasoning_effort: item.custom_reasoning_effort.clone(),
-                        converter_override: item.converter_override.clone(),
-                    })
-                    .collect(),
-            },
-        })
-        .collect();
-
-    if profiles.is_empty() || selected_profile_id.is_none() {
-        return None;
-    }
-
-    let endpoint_directory: HashMap<String, CoreLoadBalancerEndpoint> = config
-        .endpoint_options
-        .iter()
-        .map(|item| {
-            let converter = item
-                .converter
-                .clone()
-                .unwrap_or_else(|| config.converter.clone());
-            let api_key = if item.api_key.is_empty() {
-                if config.api_key.is_empty() {
-                    None
-                } else {
-                    Some(config.api_key.clone())
-                }
-            } else {
-                Some(item.api_key.clone())
-            };
-
-            (
-                item.id.clone(),
-                CoreLoadBalancerEndpoint {
-                    id: item.id.clone(),
-                    target_url: item.url.clone(),
-                    api_key,
-                    converter,
-                },
-            )
-        })
-        .collect();
-
-    let endpoint_policies: HashMap<String, CoreEndpointPolicy> = config
-        .endpoint_options
-        .iter()
-        .map(|endpoint| {
-            let endpoint_cfg = config.load_balancer.lb_endpoint_configs.get(&endpoint.id);
-            let enabled = endpoint_cfg.map(|cfg| cfg.enabled).unwrap_or(true);
-            let max_concurrency = endpoint_cfg.map(|cfg| cfg.max_concurrency).unwrap_or(16);
-
-            (
-                endpoint.id.clone(),
-                CoreEndpointPolicy {
-                    enabled,
-                    max_concurrency: if max_concurrency == 0 {
-                        1
-                    } else {
-                        max_concurrency
-                    },
-                    error_threshold,
-                    error_window_seconds,
-                    cooldown_seconds,
-                    degraded_concurrency,
-                    transient_backoff_seconds,
-                },
-            )
-        })
-        .collect();
-
-    Some(LoadBalancerRuntime::new(
-        CoreLoadBalancerConfig {
-            selected_profile_id,
-            profiles,
-            endpoint_policies,
-        },
-        endpoint_directory,
-        log_tx,
-    ))
-}
-
-fn selected_endpoint_from<'a>(
-    endpoint_options: &'a [EndpointOption],
-    selected_endpoint_id: &str,
-) -> Option<&'a EndpointOption> {
-    endpoint_options
-        .iter()
-        .find(|item| item.id == selected_endpoint_id)
-}
-
-fn selected_endpoint<'a>(config: &'a ProxyConfig) -> Option<&'a EndpointOption> {
-    selected_endpoint_from(&config.endpoint_options, &config.selected_endpoint_id)
-}
-
-fn selected_codex_endpoint<'a>(config: &'a ProxyConfig) -> Option<&'a EndpointOption> {
-    selected_endpoint_from(
-        &config.codex_config.endpoint_options,
-        &config.codex_config.selected_endpoint_id,
-    )
-}
-
-fn selected_image_gen_endpoint<'a>(config: &'a ProxyConfig) -> Option<&'a EndpointOption> {
-    selected_endpoint_from(
-        &config.codex_config.image_generation_endpoint_options,
-        &config.codex_config.selected_image_generation_endpoint_id,
-    )
-}
-
-fn resolve_target_and_api_key(config: &ProxyConfig) -> (String, Option<String>) {
-    let selected = selected_endpoint(config);
-    let target_url = selected
-        .map(|item| item.url.clone())
-        .unwrap_or_else(|| config.target_url.clone());
-    let resolved_api_key = selected
-        .map(|item| item.api_key.clone())
-        .unwrap_or_else(|| config.api_key.clone());
-    let api_key = if resolved_api_key.is_empty() {
-        None
-    } else {
-        Some(resolved_api_key)
-    };
-    (target_url, api_key)
-}
-
-fn resolve_codex_target_api_key_and_converter(
-    config: &ProxyConfig,
-) -> (String, Option<String>, String, String, Option<String>) {
-    let selected = selected_codex_endpoint(config);
-    let target_url = selected
-        .map(|item| item.url.clone())
-        .unwrap_or_else(|| config.codex_config.target_url.clone());
-    let resolved_api_key = selected
-        .map(|item| item.api_key.clone())
-        .unwrap_or_else(|| config.codex_config.api_key.clone());
-    let api_key = if resolved_api_key.is_empty() {
-        None
-    } else {
-        Some(resolved_api_key)
-    };
-
-    // Resolve image generation endpoint
-    let image_gen_selected = selected_image_gen_endpoint(config);
-    let image_generation_url = image_gen_selected
-        .map(|item| item.url.clone())
-        .unwrap_or_default();
-    let image_generation_api_key = image_gen_selected
-        .and_then(|item| {
-            let key = item.api_key.clone();
-            if key.is_empty() { None } else { Some(key) }
-        });
-
-    (
-        target_url,
-        api_key,
-        default_converter(),
-        image_generation_url,
-        image_generation_api_key,
-    )
-}
-
-fn build_transform_context(
-    config: &ProxyConfig,
-    converter: String,
-    openai_max_tokens_mapping: OpenAIMaxTokensMapping,
-) -> TransformContext {
-    let custom_injection_prompt = resolve_custom_injection_prompt(&config.custom_injection_prompt);
-    TransformContext {
-        reasoning_mapping: config.reasoning_effort.to_mapping(),
-        codex_model_mapping: CodexModelMapping {
-            opus: config.codex_model_mapping.opus.clone(),
-            sonnet: config.codex_model_mapping.sonnet.clone(),
-            haiku: config.codex_model_mapping.haiku.clone(),
-        },
-        anthropic_model_mapping: AnthropicModelMapping {
-            opus: config.anthropic_model_mapping.opus.clone(),
-            sonnet: config.anthropic_model_mapping.sonnet.clone(),
-            haiku: config.anthropic_model_mapping.haiku.clone(),
-        },
-        openai_model_mapping: OpenAIModelMapping {
-            opus: config.openai_model_mapping.opus.clone(),
-            sonnet: config.openai_model_mapping.sonnet.clone(),
-            haiku: config.openai_model_mapping.haiku.clone(),
-        },
-        openai_max_tokens_mapping,
-        custom_injection_prompt,
-        converter,
-        codex_model: config.codex_model.clone(),
-        gemini_reasoning_effort: config.gemini_reasoning_effort.to_gemini_mapping(),
-        enable_codex_tool_schema_compaction: config.enable_codex_tool_schema_compaction,
-        enable_codex_fast_mode: config.enable_codex_fast_mode,
-        enable_skill_routing_hint: config.enable_skill_routing_hint,
-    }
-}
-
-fn build_runtime_update(
-    config: &ProxyConfig,
-    log_tx: Option<broadcast::Sender<String>>,
-) -> RuntimeConfigUpdate {
-    let (target_url, api_key) = resolve_target_and_api_key(config);
-    let (codex_target_url, codex_api_key, codex_converter, image_generation_url, image_generation_api_key) =
-        resolve_codex_target_api_key_and_converter(config);
-    let load_balancer_runtime = if config.proxy_mode.eq_ignore_ascii_case("load_balancer") {
-        build_lb_runtime(config, log_tx)
-    } else {
-        None
-    };
-
-    // Get openai_max_tokens_mapping from selected endpoint
-    let selected = selected_endpoint(config);
-    let openai_max_tokens_mapping = selected
-        .and_then(|ep| ep.openai_max_tokens_mapping.clone())
-        .map(|m| m.into())
-        .unwrap_or_default();
-    let codex_selected = selected_codex_endpoint(config);
-    let codex_openai_max_tokens_mapping = codex_selected
-        .and_then(|ep| ep.openai_max_tokens_mapping.clone())
-        .map(|m| m.into())
-        .unwrap_or_default();
-
-    RuntimeConfigUpdate {
-        target_url,
-        api_key,
-        ctx: build_transform_context(config, config.converter.clone(), openai_max_tokens_mapping),
-        codex_route: Some(RuntimeRouteUpdate {
-            target_url: codex_target_url,
-            api_key: codex_api_key,
-            ctx: build_transform_context(config, codex_converter, codex_openai_max_tokens_mapping),
-            load_balancer_runtime: None,
-            image_generation_url,
-            image_generation_api_key,
-            strip_image_generation_tool: config.codex_config.strip_image_generation_tool,
-        }),
-        ignore_probe_requests: config.ignore_probe_requests,
-        allow_count_tokens_fallback_estimate: config.allow_count_tokens_fallback_estimate,
-        enable_codex_fast_mode: config.enable_codex_fast_mode,
-        force_stream_for_codex: config.force_stream_for_codex,
-        enable_sse_frame_parser: config.enable_sse_frame_parser,
-        enable_stream_heartbeat: config.enable_stream_heartbeat,
-        stream_heartbeat_interval_ms: config.stream_heartbeat_interval_ms,
-        enable_stream_log_sampling: config.enable_stream_log_sampling,
-        stream_log_sample_every_n: config.stream_log_sample_every_n,
-        stream_log_max_chars: config.stream_log_max_chars,
-        enable_stream_metrics: config.enable_stream_metrics,
-        enable_stream_event_metrics: config.enable_stream_event_metrics,
-        stream_silence_warn_ms: config.stream_silence_warn_ms,
-        stream_silence_error_ms: config.stream_silence_error_ms,
-        enable_stall_retry: config.enable_stall_retry,
-        stall_timeout_ms: config.stall_timeout_ms,
-        stall_retry_max_attempts: config.stall_retry_max_attempts,
-        stall_retry_only_heartbeat_phase: config.stall_retry_only_heartbeat_phase,
-        enable_empty_completion_retry: config.enable_empty_completion_retry,
-        empty_completion_retry_max_attempts: config.empty_completion_retry_max_attempts,
-        enable_incomplete_stream_retry: config.enable_incomplete_stream_retry,
-        incomplete_stream_retry_max_attempts: config.incomplete_stream_retry_max_attempts,
-        enable_sibling_tool_error_retry: config.enable_sibling_tool_error_retry,
-        prefer_codex_v1_path: config.prefer_codex_v1_path,
-        enable_codex_tool_schema_compaction: config.enable_codex_tool_schema_compaction,
-        enable_skill_routing_hint: config.enable_skill_routing_hint,
-        enable_stateful_responses_chain: config.enable_stateful_responses_chain,
-        load_balancer_runtime,
-    }
-}
-
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EndpointTestResult {
@@ -1086,9 +34,7 @@ pub struct EndpointTestResult {
     pub error_category: Option<String>,
 }
 
-const TEST_INPUT_MODEL: &str = "claude-sonnet-4-6";
 const TEST_CODEX_MODEL: &str = "gpt-5.4";
-const TEST_PROMPT: &str = "Who are you?";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum TestUpstreamOperation {
@@ -1296,18 +242,6 @@ fn should_retry_codex_test_v1_path_with_legacy(status: u16, error_text: &str) ->
     false
 }
 
-fn describe_test_path(url: &str) -> String {
-    let without_query = url.split('?').next().unwrap_or(url);
-    if let Some(protocol_idx) = without_query.find("://") {
-        let after_scheme = &without_query[protocol_idx + 3..];
-        if let Some(path_idx) = after_scheme.find('/') {
-            return after_scheme[path_idx..].to_string();
-        }
-        return "/".to_string();
-    }
-    without_query.to_string()
-}
-
 fn first_non_empty(values: &[&str], fallback: &str) -> String {
     values
         .iter()
@@ -1545,36 +479,6 @@ fn resolve_test_model_for_sonnet(converter: &str, ctx: &TransformContext) -> Str
     TEST_CODEX_MODEL.to_string()
 }
 
-fn build_endpoint_test_request() -> AnthropicRequest {
-    AnthropicRequest {
-        model: Some(TEST_INPUT_MODEL.to_string()),
-        messages: vec![Message {
-            role: "user".to_string(),
-            content: Some(MessageContent::Text(TEST_PROMPT.to_string())),
-        }],
-        system: None,
-        tools: None,
-        metadata: None,
-        tool_choice: None,
-        thinking: None,
-        stream: true,
-        max_tokens: Some(16),
-        temperature: None,
-        top_p: None,
-        top_k: None,
-        stop_sequences: None,
-    }
-}
-
-fn truncate_error_body(body: &str) -> String {
-    let trimmed = body.trim();
-    if trimmed.chars().count() <= 500 {
-        trimmed.to_string()
-    } else {
-        format!("{}...", trimmed.chars().take(500).collect::<String>())
-    }
-}
-
 fn detect_error_category(http_status: Option<u16>, message: &str) -> String {
     let lower = message.to_ascii_lowercase();
     if matches!(http_status, Some(401) | Some(403))
@@ -1636,131 +540,6 @@ fn endpoint_test_failure(
     }
 }
 
-fn build_test_failure_message(
-    status_code: u16,
-    reason: &str,
-    body_text: &str,
-    test_url: &str,
-) -> String {
-    let body_summary = truncate_error_body(body_text);
-    let path = describe_test_path(test_url);
-    let prefix = if status_code == 405 {
-        format!("HTTP {status_code} {reason}: 目标地址存在，但不接受当前测试路径/方法（{path}）")
-    } else {
-        format!("HTTP {status_code} {reason}: 测试路径 {path}")
-    };
-    if body_summary.is_empty() {
-        prefix
-    } else {
-        format!("{prefix}: {body_summary}")
-    }
-}
-
-async fn wait_for_first_response_chunk(response: reqwest::Response) -> Result<bool, String> {
-    let mut stream = response.bytes_stream();
-    for _ in 0..8 {
-        match tokio::time::timeout(Duration::from_secs(45), stream.next()).await {
-            Ok(Some(Ok(bytes))) => {
-                if !bytes.is_empty() {
-                    return Ok(true);
-                }
-            }
-            Ok(Some(Err(error))) => return Err(format!("读取响应失败: {error}")),
-            Ok(None) => return Ok(false),
-            Err(_) => return Err("等待模型响应超时".to_string()),
-        }
-    }
-    Ok(false)
-}
-
-enum EndpointTestAttempt {
-    Success {
-        response_time_ms: u64,
-        http_status: u16,
-    },
-    HttpFailure {
-        message: String,
-        response_time_ms: u64,
-        http_status: u16,
-        body_text: String,
-    },
-    TransportFailure {
-        message: String,
-        response_time_ms: u64,
-    },
-    EmptyResponse {
-        message: String,
-        response_time_ms: u64,
-        http_status: u16,
-    },
-}
-
-async fn run_endpoint_test_attempt(
-    client: &reqwest::Client,
-    backend: &Arc<dyn TransformBackend>,
-    target_url: &str,
-    api_key: &str,
-    body: &serde_json::Value,
-    session_id: &str,
-) -> EndpointTestAttempt {
-    let started_at = Instant::now();
-    let response = match backend
-        .build_upstream_request(client, target_url, api_key, body, session_id, "2023-06-01")
-        .send()
-        .await
-    {
-        Ok(response) => response,
-        Err(error) => {
-            let elapsed = started_at.elapsed().as_millis() as u64;
-            let message = if error.is_timeout() {
-                "请求超时".to_string()
-            } else if error.is_connect() {
-                format!("网络连接失败: {error}")
-            } else {
-                format!("请求失败: {error}")
-            };
-            return EndpointTestAttempt::TransportFailure {
-                message,
-                response_time_ms: elapsed,
-            };
-        }
-    };
-
-    let status = response.status();
-    let status_code = status.as_u16();
-    if !status.is_success() {
-        let body_text = response.text().await.unwrap_or_default();
-        let reason = status.canonical_reason().unwrap_or("Upstream error");
-        let message = build_test_failure_message(status_code, reason, &body_text, target_url);
-        return EndpointTestAttempt::HttpFailure {
-            message,
-            response_time_ms: started_at.elapsed().as_millis() as u64,
-            http_status: status_code,
-            body_text,
-        };
-    }
-
-    match wait_for_first_response_chunk(response).await {
-        Ok(true) => EndpointTestAttempt::Success {
-            response_time_ms: started_at.elapsed().as_millis() as u64,
-            http_status: status_code,
-        },
-        Ok(false) => EndpointTestAttempt::EmptyResponse {
-            message: format!(
-                "上游连接成功，但未收到响应数据（测试路径 {}）",
-                describe_test_path(target_url)
-            ),
-            response_time_ms: started_at.elapsed().as_millis() as u64,
-            http_status: status_code,
-        },
-        Err(message) => EndpointTestAttempt::EmptyResponse {
-            message: format!("{}（测试路径 {}）", message, describe_test_path(target_url)),
-            response_time_ms: started_at.elapsed().as_millis() as u64,
-            http_status: status_code,
-        },
-    }
-}
-
 #[tauri::command]
 pub async fn test_endpoint_model(
     app: AppHandle,
@@ -2003,31 +782,19 @@ impl ProxyManager {
 }
 
 fn get_config_path() -> Result<std::path::PathBuf, String> {
-    dirs::config_dir()
-        .map(|p| p.join("com.codex.proxy").join("proxy-config.json"))
-        .ok_or_else(|| "Cannot find config directory".to_string())
+    default_config_path()
 }
 
 #[tauri::command]
 pub fn load_config() -> Result<Option<ProxyConfig>, String> {
     let path = get_config_path()?;
-    if path.exists() {
-        let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
-        let config: ProxyConfig = serde_json::from_str(&content).map_err(|e| e.to_string())?;
-        Ok(Some(config))
-    } else {
-        Ok(None)
-    }
+    load_config_file(&path)
 }
 
 #[tauri::command]
 pub fn save_config(config: ProxyConfig) -> Result<(), String> {
     let path = get_config_path()?;
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
-    }
-    let content = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
-    fs::write(&path, content).map_err(|e| e.to_string())
+    save_config_file(&path, &config)
 }
 
 #[tauri::command]
@@ -2137,8 +904,8 @@ async fn start_proxy_with_manager(
         ),
     )
     .map_err(|e| e.to_string())?;
-    let (resolved_target_url, api_key) = resolve_target_and_api_key(&config);
-    let (resolved_codex_target_url, codex_api_key, codex_converter, image_generation_url, image_generation_api_key) =
+    let (resolved_target_url, _) = resolve_target_and_api_key(&config);
+    let (resolved_codex_target_url, _, _, image_generation_url, image_generation_api_key) =
         resolve_codex_target_api_key_and_converter(&config);
 
     app.emit(
@@ -2156,63 +923,12 @@ async fn start_proxy_with_manager(
         format!("[System] Codex Image Generation URL: [{}] api_key={}", image_generation_url, image_generation_api_key.as_deref().unwrap_or("(none)")),
     )
     .map_err(|e| e.to_string())?;
-    let custom_injection_prompt = resolve_custom_injection_prompt(&config.custom_injection_prompt);
 
     // 创建日志通道（容量 2048 减少高频场景下的 lag）
     let (log_tx, mut log_rx) = broadcast::channel::<String>(2048);
     manager.log_tx = Some(log_tx.clone());
 
-    let server = ProxyServer::new(config.port, resolved_target_url.clone(), api_key)
-        .with_reasoning_mapping(config.reasoning_effort.to_mapping())
-        .with_custom_injection_prompt(custom_injection_prompt)
-        .with_converter(config.converter.clone())
-        .with_codex_model(config.codex_model.clone())
-        .with_codex_model_mapping(CodexModelMapping {
-            opus: config.codex_model_mapping.opus.clone(),
-            sonnet: config.codex_model_mapping.sonnet.clone(),
-            haiku: config.codex_model_mapping.haiku.clone(),
-        })
-        .with_anthropic_model_mapping(AnthropicModelMapping {
-            opus: config.anthropic_model_mapping.opus.clone(),
-            sonnet: config.anthropic_model_mapping.sonnet.clone(),
-            haiku: config.anthropic_model_mapping.haiku.clone(),
-        })
-        .with_openai_model_mapping(OpenAIModelMapping {
-            opus: config.openai_model_mapping.opus.clone(),
-            sonnet: config.openai_model_mapping.sonnet.clone(),
-            haiku: config.openai_model_mapping.haiku.clone(),
-        })
-        .with_gemini_reasoning_effort(config.gemini_reasoning_effort.to_gemini_mapping())
-        .with_ignore_probe_requests(config.ignore_probe_requests)
-        .with_allow_count_tokens_fallback_estimate(config.allow_count_tokens_fallback_estimate)
-        .with_enable_codex_fast_mode(config.enable_codex_fast_mode)
-        .with_force_stream_for_codex(config.force_stream_for_codex)
-        .with_enable_sse_frame_parser(config.enable_sse_frame_parser)
-        .with_enable_stream_heartbeat(config.enable_stream_heartbeat)
-        .with_stream_heartbeat_interval_ms(config.stream_heartbeat_interval_ms)
-        .with_enable_stream_log_sampling(config.enable_stream_log_sampling)
-        .with_stream_log_sample_every_n(config.stream_log_sample_every_n)
-        .with_stream_log_max_chars(config.stream_log_max_chars)
-        .with_enable_stream_metrics(config.enable_stream_metrics)
-        .with_enable_stream_event_metrics(config.enable_stream_event_metrics)
-        .with_stream_silence_warn_ms(config.stream_silence_warn_ms)
-        .with_stream_silence_error_ms(config.stream_silence_error_ms)
-        .with_enable_stall_retry(config.enable_stall_retry)
-        .with_stall_timeout_ms(config.stall_timeout_ms)
-        .with_stall_retry_max_attempts(config.stall_retry_max_attempts)
-        .with_stall_retry_only_heartbeat_phase(config.stall_retry_only_heartbeat_phase)
-        .with_enable_empty_completion_retry(config.enable_empty_completion_retry)
-        .with_empty_completion_retry_max_attempts(config.empty_completion_retry_max_attempts)
-        .with_enable_incomplete_stream_retry(config.enable_incomplete_stream_retry)
-        .with_incomplete_stream_retry_max_attempts(config.incomplete_stream_retry_max_attempts)
-        .with_enable_sibling_tool_error_retry(config.enable_sibling_tool_error_retry)
-        .with_prefer_codex_v1_path(config.prefer_codex_v1_path)
-        .with_enable_codex_tool_schema_compaction(config.enable_codex_tool_schema_compaction)
-        .with_enable_skill_routing_hint(config.enable_skill_routing_hint)
-        .with_enable_stateful_responses_chain(config.enable_stateful_responses_chain)
-        .with_codex_route(resolved_codex_target_url, codex_api_key, codex_converter, image_generation_url, image_generation_api_key, config.codex_config.strip_image_generation_tool)
-        .with_allow_external_access(config.allow_external_access)
-        .with_max_concurrency(config.max_concurrency);
+    let server = build_proxy_server(&config);
 
     let server = if config.proxy_mode.eq_ignore_ascii_case("load_balancer") {
         if let Some(runtime) = build_lb_runtime(&config, Some(log_tx.clone())) {
@@ -2371,6 +1087,16 @@ pub async fn stop_proxy(app: AppHandle) -> Result<(), String> {
     Ok(())
 }
 
+/// 代理运行时的花费与预算概览；代理未运行时返回 None
+#[tauri::command]
+pub async fn get_budget_status(app: AppHandle) -> Option<serde_json::Value> {
+    let state = app.state::<crate::AppState>();
+    let manager = state.proxy_manager.lock().await;
+    manager
+        .runtime_handle()
+        .map(|runtime_handle| runtime_handle.budget_status())
+}
+
 #[tauri::command]
 pub fn export_config() -> Result<String, String> {
     let path = get_config_path()?;
@@ -2532,138 +1258,3 @@ pub fn apply_codex_config(port: u16) -> Result<String, String> {
     fs::write(&path, content).map_err(|e| format!("写入配置文件失败: {}", e))?;
     Ok(path.to_string_lossy().to_string())
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
-
-    #[test]
-    fn custom_injection_prompt_uses_default_when_field_missing() {
-        let config: ProxyConfig = serde_json::from_value(json!({
-            "port": 8889,
-            "targetUrl": "http://127.0.0.1:3000",
-            "apiKey": "test-key"
-        }))
-        .expect("config should deserialize");
-
-        assert_eq!(
-            config.custom_injection_prompt,
-            DEFAULT_CUSTOM_INJECTION_PROMPT
-        );
-        assert!(
-            config.enable_codex_fast_mode,
-            "missing config field should default Codex fast mode to enabled"
-        );
-    }
-
-    #[test]
-    fn old_config_deserializes_with_default_codex_config() {
-        let config: ProxyConfig = serde_json::from_value(json!({
-            "port": 8889,
-            "targetUrl": "https://claude.example/messages",
-            "apiKey": "claude-key",
-            "endpointOptions": [{
-                "id": "claude-1",
-                "alias": "Claude",
-                "url": "https://claude.example/messages",
-                "apiKey": "claude-key"
-            }],
-            "selectedEndpointId": "claude-1"
-        }))
-        .expect("old config should deserialize");
-
-        assert_eq!(config.target_url, "https://claude.example/messages");
-        assert_eq!(config.api_key, "claude-key");
-        assert_eq!(config.selected_endpoint_id, "claude-1");
-        assert_eq!(config.codex_config.selected_endpoint_id, "codex-default");
-        assert_eq!(config.codex_config.converter, "codex");
-        assert_eq!(config.codex_config.endpoint_options.len(), 1);
-    }
-
-    #[test]
-    fn build_runtime_update_falls_back_for_blank_prompt_and_keeps_custom_prompt() {
-        let mut blank = default_proxy_config();
-        blank.target_url = "http://127.0.0.1:3000".to_string();
-        blank.custom_injection_prompt = "   ".to_string();
-        let blank_update = build_runtime_update(&blank, None);
-        assert_eq!(
-            blank_update.ctx.custom_injection_prompt,
-            DEFAULT_CUSTOM_INJECTION_PROMPT
-        );
-        assert!(blank_update.enable_codex_fast_mode);
-        assert!(blank_update.ctx.enable_codex_fast_mode);
-
-        let mut custom = default_proxy_config();
-        custom.target_url = "http://127.0.0.1:3000".to_string();
-        custom.custom_injection_prompt = "user custom prompt".to_string();
-        let custom_update = build_runtime_update(&custom, None);
-        assert_eq!(
-            custom_update.ctx.custom_injection_prompt,
-            "user custom prompt"
-        );
-
-        let mut disabled_fast = default_proxy_config();
-        disabled_fast.target_url = "http://127.0.0.1:3000".to_string();
-        disabled_fast.enable_codex_fast_mode = false;
-        let disabled_fast_update = build_runtime_update(&disabled_fast, None);
-        assert!(!disabled_fast_update.enable_codex_fast_mode);
-        assert!(!disabled_fast_update.ctx.enable_codex_fast_mode);
-    }
-
-    #[test]
-    fn build_runtime_update_keeps_codex_target_separate_and_forces_codex_converter() {
-        let mut config = default_proxy_config();
-        config.target_url = "https://claude.example/messages".to_string();
-        config.api_key = "claude-key".to_string();
-        config.endpoint_options = vec![EndpointOption {
-            id: "claude-1".to_string(),
-            alias: "Claude".to_string(),
-            url: "https://claude.example/messages".to_string(),
-            api_key: "claude-key".to_string(),
-            converter: Some("anthropic".to_string()),
-            codex_model: None,
-            codex_model_mapping: None,
-            codex_effort_capability_map: None,
-            gemini_model_preset: None,
-            anthropic_model_mapping: None,
-            openai_model_mapping: None,
-            openai_max_tokens_mapping: None,
-            reasoning_effort: None,
-            gemini_reasoning_effort: None,
-        }];
-        config.selected_endpoint_id = "claude-1".to_string();
-        config.codex_config.target_url = "https://codex.example/responses".to_string();
-        config.codex_config.api_key = "codex-key".to_string();
-        config.codex_config.converter = "gemini".to_string();
-        config.codex_config.endpoint_options = vec![EndpointOption {
-            id: "codex-1".to_string(),
-            alias: "Codex".to_string(),
-            url: "https://codex-selected.example/responses".to_string(),
-            api_key: "codex-selected-key".to_string(),
-            converter: Some("anthropic".to_string()),
-            codex_model: None,
-            codex_model_mapping: None,
-            codex_effort_capability_map: None,
-            gemini_model_preset: None,
-            anthropic_model_mapping: None,
-            openai_model_mapping: None,
-            openai_max_tokens_mapping: None,
-            reasoning_effort: None,
-            gemini_reasoning_effort: None,
-        }];
-        config.codex_config.selected_endpoint_id = "codex-1".to_string();
-
-        let update = build_runtime_update(&config, None);
-        assert_eq!(update.target_url, "https://claude.example/messages");
-        assert_eq!(update.api_key.as_deref(), Some("claude-key"));
-
-        let codex_route = update.codex_route.expect("codex route");
-        assert_eq!(
-            codex_route.target_url,
-            "https://codex-selected.example/responses"
-        );
-        assert_eq!(codex_route.api_key.as_deref(), Some("codex-selected-key"));
-        assert_eq!(codex_route.ctx.converter, "codex");
-    }
-}
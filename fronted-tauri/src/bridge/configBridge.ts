@@ -1,5 +1,5 @@
 import { invoke } from '@tauri-apps/api/core'
-import type { EndpointTestResult, ProxyConfigV2 } from '../types/configTypes'
+import type { BudgetStatus, EndpointTestResult, ProxyConfigV2 } from '../types/configTypes'
 
 export const loadConfig = (): Promise<ProxyConfigV2 | null> =>
     invoke<ProxyConfigV2 | null>('load_config')
@@ -19,6 +19,9 @@ export const restartProxy = (config: ProxyConfigV2): Promise<void> =>
 export const stopProxy = (): Promise<void> =>
     invoke('stop_proxy')
 
+export const getBudgetStatus = (): Promise<BudgetStatus | null> =>
+    invoke<BudgetStatus | null>('get_budget_status')
+
 export const saveLang = (lang: string): Promise<void> =>
     invoke('save_lang', { lang })
 
@@ -89,6 +89,14 @@ export default {
     logsTitle: 'System Logs',
     clearLogs: 'Clear Logs',
     noLogs: 'No logs yet...',
+    budgetTitle: 'Spend & Budgets',
+    budgetGlobal: 'Global',
+    budgetClient: 'Client',
+    budgetEndpoint: 'Endpoint',
+    budgetDay: 'Today',
+    budgetMonth: 'Month',
+    budgetSoft: 'Soft limit',
+    budgetHard: 'Hard limit',
 
     // Menu
     menuPromptSettings: 'Prompt Settings',
@@ -89,6 +89,14 @@ export default {
     logsTitle: '系统日志',
     clearLogs: '清除日志',
     noLogs: '暂无日志...',
+    budgetTitle: '花费与预算',
+    budgetGlobal: '全局',
+    budgetClient: '客户端',
+    budgetEndpoint: '端点',
+    budgetDay: '今日',
+    budgetMonth: '本月',
+    budgetSoft: '软预算',
+    budgetHard: '硬预算',
 
     // Menu
     menuPromptSettings: '提示词设置',
@@ -1,17 +1,22 @@
 import {
     DEFAULT_LOAD_BALANCER_CONFIG,
     DEFAULT_PROXY_MODE,
+    type LbSlotEndpointRef,
     type LoadBalancerConfigV2,
     type ProxyMode,
 } from './loadBalancerTypes'
 
 export type ConverterType = 'codex' | 'gemini' | 'anthropic' | 'openai'
 
+export type KeyRotation = 'round_robin' | 'least_used'
+
 export interface EndpointOption {
     id: string
     alias: string
     url: string
     apiKey: string
+    apiKeys?: string[]
+    keyRotation?: KeyRotation
     converter?: ConverterType
     codexModel?: string
     codexModelMapping?: CodexModelMapping
@@ -72,12 +77,14 @@ export interface ProxyConfig {
     codexEffortCapabilityMap: CodexEffortCapabilityMap
     geminiModelPreset: GeminiModelPreset
     maxConcurrency: number
+    maxQueueWaitMs?: number
     ignoreProbeRequests: boolean
     allowCountTokensFallbackEstimate: boolean
     enableCodexFastMode: boolean
     allowExternalAccess: boolean
     lbModelCooldownSeconds: number
     lbTransientBackoffSeconds: number
+    lbProbeIntervalSeconds?: number
     reasoningEffort: ReasoningEffort
     geminiReasoningEffort: ReasoningEffort
     customInjectionPrompt: string
@@ -97,10 +104,92 @@ export interface CodexClientConfig {
     stripImageGenerationTool?: boolean
 }
 
+export type ModelSlotName = 'opus' | 'sonnet' | 'haiku'
+
+export interface ModelRouteRule {
+    match: string
+    matchType?: 'exact' | 'glob' | 'regex'
+    requestKind?: 'session_title' | 'conversation_turn' | 'subagent' | 'compaction' | 'probe'
+    route: string
+}
+
+export interface ModelRoute {
+    name: string
+    slot?: ModelSlotName
+    model?: string
+    reasoningEffort?: string
+    maxTokens?: number
+    endpoints?: LbSlotEndpointRef[]
+}
+
+export interface ModelRoutesConfig {
+    rules: ModelRouteRule[]
+    routes: ModelRoute[]
+    defaultRoute?: string
+    rejectUnmatched?: boolean
+}
+
 export interface ProxyConfigV2 extends ProxyConfig {
     proxyMode?: ProxyMode
     loadBalancer?: LoadBalancerConfigV2
     codexConfig?: CodexClientConfig
+    modelRoutes?: ModelRoutesConfig
+    usageLedger?: UsageLedgerConfig
+    tokenCalibration?: TokenCalibrationConfig
+    sessionStore?: SessionStoreConfig
+    pricing?: ModelPriceConfig[]
+    budgets?: BudgetsConfig
+}
+
+export interface UsageLedgerConfig {
+    enabled: boolean
+    path?: string
+}
+
+export interface TokenCalibrationConfig {
+    enabled: boolean
+    path?: string
+}
+
+export interface SessionStoreConfig {
+    enabled: boolean
+    path?: string
+    ttlSecs?: number
+}
+
+export interface ModelPriceConfig {
+    match: string
+    matchType?: 'exact' | 'glob' | 'regex'
+    input: number
+    cachedInput?: number
+    output: number
+    reasoning?: number
+}
+
+export interface BudgetLimits {
+    dailySoft?: number
+    dailyHard?: number
+    monthlySoft?: number
+    monthlyHard?: number
+}
+
+export interface BudgetsConfig {
+    global?: BudgetLimits
+    endpoints?: Record<string, BudgetLimits>
+    clients?: Record<string, BudgetLimits>
+}
+
+export interface BudgetScopeStatus {
+    day_usd: number
+    month_usd: number
+    budget?: 'soft' | 'hard' | null
+    budget_period?: 'day' | 'month' | null
+    limits?: BudgetLimits | null
+}
+
+export interface BudgetStatus {
+    priced_models: number
+    spend: Record<string, BudgetScopeStatus>
 }
 
 export interface EndpointTestResult {
@@ -14,11 +14,22 @@ export interface ModelSlotMapping {
   haiku: LbSlotEndpointRef[]
 }
 
+export type LbSelectionStrategy =
+  | 'priority'
+  | 'weighted_random'
+  | 'round_robin'
+  | 'least_in_flight'
+  | 'lowest_latency'
+
 export interface LbFailoverStrategy {
   errorThreshold: number
   errorWindowSeconds: number
   cooldownSeconds: number
   degradedConcurrency: number
+  selection?: LbSelectionStrategy
+  latencyThresholdMs?: number
+  sessionAffinityTtlSeconds?: number
+  hedgeAfterMs?: number
 }
 
 export interface LbEndpointConfig {
@@ -29,12 +40,23 @@ export interface LbEndpointConfig {
   weight: number
 }
 
+export interface LbDegradationStep {
+  slot: 'opus' | 'sonnet' | 'haiku'
+  reasoningEffort?: string
+}
+
+export interface LbDegradationConfig {
+  chain: LbDegradationStep[]
+  responseHeader?: boolean
+}
+
 export interface LoadBalancerProfile {
   id: string
   name: string
   description?: string
   modelMapping: ModelSlotMapping
   strategy: LbFailoverStrategy
+  degradation?: LbDegradationConfig
 }
 
 export interface LoadBalancerConfigV2 {
@@ -50,6 +72,7 @@ export const DEFAULT_LB_FAILOVER_STRATEGY: LbFailoverStrategy = {
   errorWindowSeconds: 60,
   cooldownSeconds: 3600,
   degradedConcurrency: 4,
+  selection: 'priority',
 }
 
 export const DEFAULT_LOAD_BALANCER_CONFIG: LoadBalancerConfigV2 = {
@@ -1,39 +1,172 @@
-use codex_proxy_core::{set_debug_log, ProxyServer, ReasoningEffort, ReasoningEffortMapping};
+use codex_proxy_core::config::{
+    build_lb_runtime, build_proxy_server, default_config_path, default_proxy_config,
+    load_config_file, resolve_codex_target_api_key_and_converter, resolve_target_and_api_key,
+};
+use codex_proxy_core::{set_debug_log, AdminApiConfig};
+use std::path::PathBuf;
 use tokio::sync::broadcast;
 
+const USAGE: &str = "Usage: codex-proxy-server [OPTIONS]
+
+Options:
+  -c, --config <PATH>   Path to proxy-config.json (default: desktop app config)
+  -p, --port <PORT>     Override listen port from config
+      --log-dir <DIR>   Override log directory (default: ~/.codexProxy/logs)
+      --admin-token <TOKEN>
+                        Enable the /admin API guarded by this token
+                        (also CODEX_PROXY_ADMIN_TOKEN or adminToken in config)
+  -h, --help            Print help";
+
+/// 命令行参数（均为可选，覆盖配置文件中的值）
+#[derive(Debug, Default)]
+struct CliArgs {
+    config_path: Option<PathBuf>,
+    port: Option<u16>,
+    log_dir: Option<String>,
+    admin_token: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<CliArgs>, String> {
+    let mut cli = CliArgs::default();
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-c" | "--config" => cli.config_path = Some(PathBuf::from(value(&flag)?)),
+            "-p" | "--port" => {
+                let raw = value(&flag)?;
+                let port = raw
+                    .parse::<u16>()
+                    .map_err(|_| format!("Invalid port: {}", raw))?;
+                cli.port = Some(port);
+            }
+            "--log-dir" => cli.log_dir = Some(value(&flag)?),
+            "--admin-token" => cli.admin_token = Some(value(&flag)?),
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+    Ok(Some(cli))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     env_logger::init();
     set_debug_log(true);
 
-    let reasoning_mapping = ReasoningEffortMapping::new()
-        .with_opus(ReasoningEffort::Xhigh)
-        .with_sonnet(ReasoningEffort::Medium)
-        .with_haiku(ReasoningEffort::Low);
+    let cli = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(cli)) => cli,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let config_path = match cli.config_path {
+        Some(path) => path,
+        None => default_config_path()?,
+    };
+    let file_config = match load_config_file(&config_path)? {
+        Some(config) => {
+            println!("[System] Loaded config: {}", config_path.display());
+            config
+        }
+        None => {
+            println!(
+                "[Warning] Config not found at {}, using defaults",
+                config_path.display()
+            );
+            default_proxy_config()
+        }
+    };
+    // 命令行覆盖只作用于本次运行；管理接口写回的是 `file_config`
+    let mut config = file_config.clone();
+    if let Some(port) = cli.port {
+        config.port = port;
+    }
 
-    println!("🚀 Starting Codex Proxy Server with dynamic reasoning effort mapping...");
-    println!("📋 Default reasoning effort mappings:");
-    println!("  Opus models -> {:?}", reasoning_mapping.opus);
-    println!("  Sonnet models -> {:?}", reasoning_mapping.sonnet);
-    println!("  Haiku models -> {:?}", reasoning_mapping.haiku);
+    let (target_url, _) = resolve_target_and_api_key(&config);
+    let (codex_target_url, _, _, _, _) = resolve_codex_target_api_key_and_converter(&config);
+    println!(
+        "[System] Starting proxy on {}:{}",
+        if config.allow_external_access {
+            "0.0.0.0"
+        } else {
+            "127.0.0.1"
+        },
+        config.port
+    );
+    println!(
+        "[System] Target: {} (converter={})",
+        target_url, config.converter
+    );
+    println!("[System] Codex Target: {}", codex_target_url);
 
-    let server = ProxyServer::new(
-        8889,
-        "https://api.aicodemirror.com/api/codex/backend-api/codex/responses".to_string(),
-        std::env::var("ANTHROPIC_API_KEY").ok(),
-    )
-    .with_reasoning_mapping(reasoning_mapping);
+    let (log_tx, mut log_rx) = broadcast::channel::<String>(2048);
 
-    println!("🌐 Server listening on http://127.0.0.1:8889");
-    println!("📡 Ready to proxy requests to Anthropic API");
+    let server = build_proxy_server(&config).with_log_dir(cli.log_dir);
+    // 管理接口令牌：命令行 > 环境变量 > 配置文件
+    let admin_token = cli
+        .admin_token
+        .or_else(|| std::env::var("CODEX_PROXY_ADMIN_TOKEN").ok())
+        .or_else(|| config.admin_token.clone())
+        .filter(|token| !token.trim().is_empty());
+    let server = match admin_token {
+        Some(token) => {
+            println!("[System] Admin API enabled at /admin");
+            server.with_admin_api(AdminApiConfig {
+                token,
+                proxy_config: file_config,
+                config_path: Some(config_path.clone()),
+            })
+        }
+        None => server,
+    };
+    let server = if config.proxy_mode.eq_ignore_ascii_case("load_balancer") {
+        if let Some(runtime) = build_lb_runtime(&config, Some(log_tx.clone())) {
+            println!("[System] Load balancer mode enabled");
+            server.with_load_balancer_runtime(runtime)
+        } else {
+            println!("[Warning] Load balancer config incomplete, fallback to single mode");
+            server
+        }
+    } else {
+        server
+    };
 
-    let (log_tx, _log_rx) = broadcast::channel(100);
-    let _shutdown_tx = server.start(log_tx).await?;
+    // 日志输出到 stdout（Lagged 时跳过丢失的消息继续接收）
+    tokio::spawn(async move {
+        loop {
+            match log_rx.recv().await {
+                Ok(msg) => println!("{}", msg),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    println!("[Warning] Log receiver lagged, skipped {} messages", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
 
-    println!("✅ Server started successfully!");
+    let (shutdown_tx, server_handle, _runtime_handle) = server.start(log_tx).await?;
 
     tokio::signal::ctrl_c().await?;
-    println!("🛑 Shutting down server...");
+    println!("[System] Shutting down proxy...");
+    let _ = shutdown_tx.send(());
+    server_handle.abort();
 
     Ok(())
 }
@@ -0,0 +1,641 @@
+//! 费用估算与花费预算
+//!
+//! 价目表按上游模型把每个请求的 usage 折算成美元；预算按全局、端点、客户端三类范围，
+//! 分日 / 月两个周期（本地时间）累计。软预算触达后负载均衡把该端点排到最后，
+//! 硬预算触达后端点按 Cooldown 处理、客户端与全局直接拒绝，直到周期重置。
+//! 启动时从用量账本回放当日与当月的花费，重启不会清零。
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDate, TimeZone};
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::config::{BudgetLimits, BudgetsConfig, ModelPriceConfig};
+use crate::model_routes::ModelMatcher;
+use crate::usage_ledger::UsageRecord;
+
+const TOKENS_PER_PRICE_UNIT: f64 = 1_000_000.0;
+/// 全局花费的范围键（端点与客户端带前缀，不会与之冲突）
+const GLOBAL_SCOPE: &str = "*";
+
+/// 一次请求的 token 用量；`cached_input` 包含在 `input` 中，`reasoning` 包含在 `output` 中
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub input: u64,
+    pub cached_input: u64,
+    pub output: u64,
+    pub reasoning: u64,
+}
+
+/// 单个模型的单价（美元 / 百万 token）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub cached_input: f64,
+    pub output: f64,
+    pub reasoning: f64,
+}
+
+impl ModelPrice {
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        let cached = usage.cached_input.min(usage.input);
+        let reasoning = usage.reasoning.min(usage.output);
+        ((usage.input - cached) as f64 * self.input
+            + cached as f64 * self.cached_input
+            + (usage.output - reasoning) as f64 * self.output
+            + reasoning as f64 * self.reasoning)
+            / TOKENS_PER_PRICE_UNIT
+    }
+}
+
+/// 价目表：按配置顺序匹配上游模型名，第一条命中者生效
+#[derive(Debug, Clone, Default)]
+pub struct PriceTable {
+    entries: Vec<(ModelMatcher, ModelPrice)>,
+    warnings: Vec<String>,
+}
+
+impl PriceTable {
+    pub fn from_config(prices: &[ModelPriceConfig]) -> Self {
+        let mut table = Self::default();
+        for (index, price) in prices.iter().enumerate() {
+            match ModelMatcher::parse(&price.pattern, price.match_type.as_deref()) {
+                Ok(matcher) => table.entries.push((
+                    matcher,
+                    ModelPrice {
+                        input: price.input,
+                        cached_input: price.cached_input.unwrap_or(price.input),
+                        output: price.output,
+                        reasoning: price.reasoning.unwrap_or(price.output),
+                    },
+                )),
+                Err(error) => {
+                    table
+                        .warnings
+                        .push(format!("price #{} skipped: {}", index + 1, error))
+                }
+            }
+        }
+        table
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn price_for(&self, model: &str) -> Option<&ModelPrice> {
+        self.entries
+            .iter()
+            .find(|(matcher, _)| matcher.matches(model))
+            .map(|(_, price)| price)
+    }
+
+    /// 未配置该模型单价时返回 None
+    pub fn cost(&self, model: &str, usage: &TokenUsage) -> Option<f64> {
+        self.price_for(model).map(|price| price.cost(usage))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BudgetLevel {
+    Soft,
+    Hard,
+}
+
+impl BudgetLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Soft => "soft",
+            Self::Hard => "hard",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BudgetPeriod {
+    Day,
+    Month,
+}
+
+impl BudgetPeriod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Month => "month",
+        }
+    }
+}
+
+/// 某个范围当前触达的预算
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetBreach {
+    pub level: BudgetLevel,
+    pub period: BudgetPeriod,
+    pub spent_usd: f64,
+    pub limit_usd: f64,
+    /// 距离周期重置的时间
+    pub resets_in: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Spend {
+    day: f64,
+    month: f64,
+}
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    prices: PriceTable,
+    budgets: BudgetsConfig,
+    day: Option<NaiveDate>,
+    spend: HashMap<String, Spend>,
+    /// 本周期内已经提示过的触达，避免每个请求重复输出
+    reported: HashSet<(String, BudgetLevel, BudgetPeriod)>,
+}
+
+impl TrackerState {
+    /// 跨日 / 跨月时清零对应周期的花费
+    fn roll(&mut self, now: &DateTime<Local>) {
+        let today = now.date_naive();
+        let Some(previous) = self.day.replace(today) else {
+            return;
+        };
+        if previous == today {
+            return;
+        }
+        let new_month = (previous.year(), previous.month()) != (today.year(), today.month());
+        for spend in self.spend.values_mut() {
+            spend.day = 0.0;
+            if new_month {
+                spend.month = 0.0;
+            }
+        }
+        self.reported
+            .retain(|(_, _, period)| *period == BudgetPeriod::Month && !new_month);
+    }
+
+    fn limits(&self, scope: &str) -> Option<&BudgetLimits> {
+        if scope == GLOBAL_SCOPE {
+            return Some(&self.budgets.global);
+        }
+        if let Some(endpoint) = scope.strip_prefix("endpoint:") {
+            return self.budgets.endpoints.get(endpoint);
+        }
+        scope
+            .strip_prefix("client:")
+            .and_then(|client| self.budgets.clients.get(client))
+    }
+
+    /// 硬预算优先；同一档位取重置更晚的周期
+    fn breach(&self, scope: &str, now: &DateTime<Local>) -> Option<BudgetBreach> {
+        let limits = self.limits(scope)?;
+        let spend = self.spend.get(scope).copied().unwrap_or_default();
+        let checks = [
+            (
+                BudgetLevel::Hard,
+                BudgetPeriod::Month,
+                spend.month,
+                limits.monthly_hard,
+            ),
+            (
+                BudgetLevel::Hard,
+                BudgetPeriod::Day,
+                spend.day,
+                limits.daily_hard,
+            ),
+            (
+                BudgetLevel::Soft,
+                BudgetPeriod::Month,
+                spend.month,
+                limits.monthly_soft,
+            ),
+            (
+                BudgetLevel::Soft,
+                BudgetPeriod::Day,
+                spend.day,
+                limits.daily_soft,
+            ),
+        ];
+        checks
+            .into_iter()
+            .find(|(_, _, spent, limit)| *limit > 0.0 && spent >= limit)
+            .map(|(level, period, spent_usd, limit_usd)| BudgetBreach {
+                level,
+                period,
+                spent_usd,
+                limit_usd,
+                resets_in: time_until_reset(period, now),
+            })
+    }
+}
+
+fn time_until_reset(period: BudgetPeriod, now: &DateTime<Local>) -> Duration {
+    let today = now.date_naive();
+    let next = match period {
+        BudgetPeriod::Day => today + ChronoDuration::days(1),
+        BudgetPeriod::Month => {
+            let (year, month) = if today.month() == 12 {
+                (today.year() + 1, 1)
+            } else {
+                (today.year(), today.month() + 1)
+            };
+            NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(today)
+        }
+    };
+    next.and_hms_opt(0, 0, 0)
+        .and_then(|midnight| Local.from_local_datetime(&midnight).earliest())
+        .and_then(|reset_at| (reset_at - *now).to_std().ok())
+        .unwrap_or_default()
+}
+
+fn scopes_for(endpoint: &str, client: Option<&str>) -> Vec<String> {
+    let mut scopes = vec![GLOBAL_SCOPE.to_string(), format!("endpoint:{}", endpoint)];
+    if let Some(client) = client {
+        scopes.push(format!("client:{}", client));
+    }
+    scopes
+}
+
+/// 进程内的花费累计（随 `ProxyRuntimeHandle` 共享，价目表与预算随热更新替换）
+#[derive(Debug, Default)]
+pub struct BudgetTracker {
+    state: Mutex<TrackerState>,
+    log_tx: Option<broadcast::Sender<String>>,
+}
+
+impl BudgetTracker {
+    pub fn new(log_tx: Option<broadcast::Sender<String>>) -> Self {
+        Self {
+            state: Mutex::new(TrackerState::default()),
+            log_tx,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TrackerState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn send_log(&self, msg: String) {
+        if let Some(tx) = self.log_tx.as_ref() {
+            let _ = tx.send(msg);
+        }
+    }
+
+    /// 替换价目表与预算；已累计的花费保留
+    pub fn configure(&self, prices: &[ModelPriceConfig], budgets: BudgetsConfig) {
+        let prices = PriceTable::from_config(prices);
+        for warning in prices.warnings() {
+            self.send_log(format!("[Warn] pricing {}", warning));
+        }
+        let mut state = self.lock();
+        state.prices = prices;
+        state.budgets = budgets;
+        state.reported.clear();
+    }
+
+    pub fn cost(&self, model: &str, usage: &TokenUsage) -> Option<f64> {
+        self.lock().prices.cost(model, usage)
+    }
+
+    /// 从用量账本回放当日与当月的花费
+    pub fn seed<I>(&self, records: I)
+    where
+        I: IntoIterator<Item = UsageRecord>,
+    {
+        self.seed_at(records, Local::now());
+    }
+
+    fn seed_at<I>(&self, records: I, now: DateTime<Local>)
+    where
+        I: IntoIterator<Item = UsageRecord>,
+    {
+        let today = now.format("%Y-%m-%d").to_string();
+        let month = now.format("%Y-%m").to_string();
+        let mut state = self.lock();
+        state.roll(&now);
+        for record in records {
+            let Some(cost) = record.cost_usd.filter(|cost| *cost > 0.0) else {
+                continue;
+            };
+            if !record.ts.starts_with(&month) {
+                continue;
+            }
+            let client = Some(record.client.as_str()).filter(|client| *client != "-");
+            for scope in scopes_for(&record.endpoint, client) {
+                let spend = state.spend.entry(scope).or_default();
+                spend.month += cost;
+                if record.day() == today {
+                    spend.day += cost;
+                }
+            }
+        }
+    }
+
+    /// 折算并累计一次请求的花费；未配置单价时返回 None
+    pub fn record(
+        &self,
+        request_id: &str,
+        model: &str,
+        endpoint: &str,
+        client: Option<&str>,
+        usage: &TokenUsage,
+    ) -> Option<f64> {
+        self.record_at(request_id, model, endpoint, client, usage, Local::now())
+    }
+
+    fn record_at(
+        &self,
+        request_id: &str,
+        model: &str,
+        endpoint: &str,
+        client: Option<&str>,
+        usage: &TokenUsage,
+        now: DateTime<Local>,
+    ) -> Option<f64> {
+        let mut state = self.lock();
+        let cost = state.prices.cost(model, usage)?;
+        state.roll(&now);
+        let mut logs = vec![format!(
+            "[Cost] #{} model={} endpoint={} cost_usd={:.6}",
+            request_id, model, endpoint, cost
+        )];
+        for scope in scopes_for(endpoint, client) {
+            let spend = state.spend.entry(scope.clone()).or_default();
+            spend.day += cost;
+            spend.month += cost;
+            let Some(breach) = state.breach(&scope, &now) else {
+                continue;
+            };
+            if state
+                .reported
+                .insert((scope.clone(), breach.level, breach.period))
+            {
+                logs.push(format!(
+                    "[Budget] scope={} level={} period={} spent_usd={:.4} limit_usd={:.4} resets_in_secs={}",
+                    scope,
+                    breach.level.as_str(),
+                    breach.period.as_str(),
+                    breach.spent_usd,
+                    breach.limit_usd,
+                    breach.resets_in.as_secs(),
+                ));
+            }
+        }
+        drop(state);
+        for msg in logs {
+            self.send_log(msg);
+        }
+        Some(cost)
+    }
+
+    pub fn endpoint_breach(&self, endpoint_id: &str) -> Option<BudgetBreach> {
+        let now = Local::now();
+        let mut state = self.lock();
+        state.roll(&now);
+        state.breach(&format!("endpoint:{}", endpoint_id), &now)
+    }
+
+    /// 全局或客户端已触达的硬预算，用于入站请求准入；返回 (`global` 或客户端名, 触达情况)
+    pub fn admission_breach(&self, client: Option<&str>) -> Option<(String, BudgetBreach)> {
+        let now = Local::now();
+        let mut state = self.lock();
+        state.roll(&now);
+        let scopes = std::iter::once(("global".to_string(), GLOBAL_SCOPE.to_string()))
+            .chain(client.map(|client| (client.to_string(), format!("client:{}", client))));
+        for (label, scope) in scopes {
+            if let Some(breach) = state
+                .breach(&scope, &now)
+                .filter(|breach| breach.level == BudgetLevel::Hard)
+            {
+                return Some((label, breach));
+            }
+        }
+        None
+    }
+
+    /// `/status` 中的花费与预算概览
+    pub fn status_json(&self) -> Value {
+        let now = Local::now();
+        let mut state = self.lock();
+        state.roll(&now);
+        // 有花费或配置了预算的范围都列出，桌面端据此展示各客户端的花费与上限
+        let mut scopes: Vec<String> = state.spend.keys().cloned().collect();
+        if !state.budgets.global.is_unlimited() {
+            scopes.push(GLOBAL_SCOPE.to_string());
+        }
+        scopes.extend(
+            state
+                .budgets
+                .endpoints
+                .keys()
+                .map(|id| format!("endpoint:{}", id)),
+        );
+        scopes.extend(
+            state
+                .budgets
+                .clients
+                .keys()
+                .map(|name| format!("client:{}", name)),
+        );
+        scopes.sort();
+        scopes.dedup();
+        let mut spend = Map::new();
+        for scope in scopes {
+            let value = state.spend.get(&scope).copied().unwrap_or_default();
+            let breach = state.breach(&scope, &now);
+            let limits = state.limits(&scope).filter(|limits| !limits.is_unlimited());
+            spend.insert(
+                scope.clone(),
+                json!({
+                    "day_usd": value.day,
+                    "month_usd": value.month,
+                    "budget": breach.as_ref().map(|breach| breach.level.as_str()),
+                    "budget_period": breach.as_ref().map(|breach| breach.period.as_str()),
+                    "limits": limits,
+                }),
+            );
+        }
+        json!({
+            "priced_models": state.prices.entries.len(),
+            "spend": Value::Object(spend),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(pattern: &str, input: f64, output: f64) -> ModelPriceConfig {
+        ModelPriceConfig {
+            pattern: pattern.to_string(),
+            input,
+            output,
+            ..ModelPriceConfig::default()
+        }
+    }
+
+    fn at(y: i32, m: u32, d: u32, h: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(y, m, d, h, 0, 0)
+            .earliest()
+            .expect("valid local time")
+    }
+
+    #[test]
+    fn price_splits_cached_input_and_reasoning_output() {
+        let table = PriceTable::from_config(&[
+            ModelPriceConfig {
+                cached_input: Some(0.125),
+                reasoning: Some(20.0),
+                ..price("gpt-5*", 1.25, 10.0)
+            },
+            ModelPriceConfig {
+                match_type: Some("regex".to_string()),
+                ..price("(", 1.0, 1.0)
+            },
+        ]);
+        assert_eq!(table.warnings().len(), 1);
+        let usage = TokenUsage {
+            input: 1_000_000,
+            cached_input: 800_000,
+            output: 100_000,
+            reasoning: 50_000,
+        };
+        let cost = table.cost("GPT-5-codex", &usage).expect("priced");
+        // 0.2M*1.25 + 0.8M*0.125 + 0.05M*10 + 0.05M*20
+        assert!((cost - 1.85).abs() < 1e-9, "cost={}", cost);
+        assert!(table.cost("gemini-2.5-pro", &usage).is_none());
+    }
+
+    #[test]
+    fn budgets_trip_per_scope_and_reset_with_the_period() {
+        let tracker = BudgetTracker::new(None);
+        let mut budgets = BudgetsConfig::default();
+        budgets.endpoints.insert(
+            "ep-a".to_string(),
+            BudgetLimits {
+                daily_soft: 1.0,
+                daily_hard: 2.0,
+                ..BudgetLimits::default()
+            },
+        );
+        budgets.clients.insert(
+            "alice".to_string(),
+            BudgetLimits {
+                monthly_hard: 2.5,
+                ..BudgetLimits::default()
+            },
+        );
+        tracker.configure(&[price("gpt-5", 1.0, 0.0)], budgets);
+        let million = TokenUsage {
+            input: 1_000_000,
+            ..TokenUsage::default()
+        };
+        let day1 = at(2026, 10, 30, 10);
+
+        tracker.record_at("1", "gpt-5", "ep-a", Some("alice"), &million, day1);
+        let state = tracker.lock();
+        let breach = state.breach("endpoint:ep-a", &day1).expect("soft");
+        assert_eq!(breach.level, BudgetLevel::Soft);
+        assert!(state.breach("client:alice", &day1).is_none());
+        drop(state);
+
+        tracker.record_at("2", "gpt-5", "ep-a", Some("alice"), &million, day1);
+        let state = tracker.lock();
+        let breach = state.breach("endpoint:ep-a", &day1).expect("hard");
+        assert_eq!(
+            (breach.level, breach.period),
+            (BudgetLevel::Hard, BudgetPeriod::Day)
+        );
+        assert_eq!(breach.resets_in, Duration::from_secs(14 * 3600));
+        assert!(state.breach("*", &day1).is_none());
+        drop(state);
+
+        // 次日端点日预算重置，客户端月预算继续累计
+        let day2 = at(2026, 10, 31, 9);
+        tracker.record_at("3", "gpt-5", "ep-b", Some("alice"), &million, day2);
+        let mut state = tracker.lock();
+        state.roll(&day2);
+        assert!(state.breach("endpoint:ep-a", &day2).is_none());
+        let breach = state.breach("client:alice", &day2).expect("monthly hard");
+        assert_eq!(breach.period, BudgetPeriod::Month);
+        drop(state);
+
+        let november = at(2026, 11, 1, 0);
+        let mut state = tracker.lock();
+        state.roll(&november);
+        assert!(state.breach("client:alice", &november).is_none());
+    }
+
+    #[test]
+    fn seed_replays_current_month_costs_from_the_ledger() {
+        let tracker = BudgetTracker::new(None);
+        let budgets = BudgetsConfig {
+            global: BudgetLimits {
+                daily_hard: 1.0,
+                monthly_soft: 1.5,
+                ..BudgetLimits::default()
+            },
+            ..BudgetsConfig::default()
+        };
+        tracker.configure(&[], budgets);
+        let record = |ts: &str, cost: f64| UsageRecord {
+            ts: ts.to_string(),
+            client: "-".to_string(),
+            endpoint: "ep-a".to_string(),
+            cost_usd: Some(cost),
+            ..UsageRecord::default()
+        };
+        let now = at(2026, 10, 17, 12);
+        tracker.seed_at(
+            [
+                record("2026-09-30T23:00:00+08:00", 5.0),
+                record("2026-10-16T10:00:00+08:00", 0.75),
+                record("2026-10-17T08:00:00+08:00", 0.5),
+            ],
+            now,
+        );
+        let state = tracker.lock();
+        let spend = state.spend["*"];
+        assert!((spend.day - 0.5).abs() < 1e-9);
+        assert!((spend.month - 1.25).abs() < 1e-9);
+        assert!(state.breach("*", &now).is_none());
+        assert!(!state.spend.contains_key("client:-"));
+    }
+
+    #[test]
+    fn status_lists_configured_scopes_with_their_limits() {
+        let tracker = BudgetTracker::new(None);
+        let mut budgets = BudgetsConfig::default();
+        budgets.clients.insert(
+            "alice".to_string(),
+            BudgetLimits {
+                daily_hard: 2.0,
+                ..BudgetLimits::default()
+            },
+        );
+        tracker.configure(&[price("gpt-5", 1.0, 0.0)], budgets);
+        let usage = TokenUsage {
+            input: 500_000,
+            ..TokenUsage::default()
+        };
+        tracker.record_at("1", "gpt-5", "ep-a", Some("bob"), &usage, Local::now());
+
+        let status = tracker.status_json();
+        let spend = &status["spend"];
+        assert_eq!(spend["client:alice"]["day_usd"], json!(0.0));
+        assert_eq!(spend["client:alice"]["limits"]["dailyHard"], json!(2.0));
+        assert_eq!(spend["client:bob"]["day_usd"], json!(0.5));
+        assert!(spend["client:bob"]["limits"].is_null());
+        assert!(spend["*"]["limits"].is_null());
+    }
+}
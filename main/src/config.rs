@@ -0,0 +1,2058 @@
+//! 代理配置模型（与桌面端 proxy-config.json 格式一致）
+//!
+//! 桌面端与 headless `codex-proxy-server` 共用同一份配置结构，
+//! 以及从配置构建 `RuntimeConfigUpdate` / `LoadBalancerRuntime` / `ProxyServer` 的逻辑。
+
+use crate::load_balancer::{
+    DegradationStep, EndpointPolicy as CoreEndpointPolicy, KeyRotation,
+    LoadBalancerConfig as CoreLoadBalancerConfig, LoadBalancerEndpoint as CoreLoadBalancerEndpoint,
+    LoadBalancerProfile as CoreLoadBalancerProfile, LoadBalancerRuntime, ModelSlot, NamedRoute,
+    SelectionStrategy, SlotEndpointRef as CoreSlotEndpointRef, SlotMapping as CoreSlotMapping,
+};
+use crate::model_routes::{
+    parse_request_kind, ModelMatcher, ModelRoute, ModelRouteRule, ModelRouteTable,
+};
+use crate::models::{
+    AnthropicModelMapping, CodexModelMapping, GeminiReasoningEffortMapping, OpenAIMaxTokensMapping,
+    OpenAIModelMapping, ReasoningEffort, ReasoningEffortMapping,
+};
+use crate::server::{ProxyServer, RuntimeConfigUpdate, RuntimeRouteUpdate};
+use crate::transform::TransformContext;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::sync::broadcast;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReasoningEffortConfig {
+    pub opus: String,
+    pub sonnet: String,
+    pub haiku: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CodexModelMappingConfig {
+    pub opus: String,
+    pub sonnet: String,
+    pub haiku: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AnthropicModelMappingConfig {
+    pub opus: String,
+    pub sonnet: String,
+    pub haiku: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OpenAIModelMappingConfig {
+    pub opus: String,
+    pub sonnet: String,
+    pub haiku: String,
+}
+
+/// Per-slot max_tokens configuration for OpenAI Chat API.
+/// None means pass-through (use the value from Anthropic request).
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenAIMaxTokensMappingConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opus: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sonnet: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub haiku: Option<u32>,
+}
+
+impl From<OpenAIMaxTokensMappingConfig> for OpenAIMaxTokensMapping {
+    fn from(config: OpenAIMaxTokensMappingConfig) -> Self {
+        OpenAIMaxTokensMapping {
+            opus: config.opus,
+            sonnet: config.sonnet,
+            haiku: config.haiku,
+        }
+    }
+}
+
+impl Default for CodexModelMappingConfig {
+    fn default() -> Self {
+        Self {
+            opus: "gpt-5.3-codex".to_string(),
+            sonnet: "gpt-5.2-codex".to_string(),
+            haiku: "gpt-5.1-codex-mini".to_string(),
+        }
+    }
+}
+
+impl Default for ReasoningEffortConfig {
+    fn default() -> Self {
+        Self {
+            opus: "xhigh".to_string(),
+            sonnet: "medium".to_string(),
+            haiku: "low".to_string(),
+        }
+    }
+}
+
+impl ReasoningEffortConfig {
+    pub fn to_mapping(&self) -> ReasoningEffortMapping {
+        ReasoningEffortMapping::new()
+            .with_opus(ReasoningEffort::from_str(&self.opus))
+            .with_sonnet(ReasoningEffort::from_str(&self.sonnet))
+            .with_haiku(ReasoningEffort::from_str(&self.haiku))
+    }
+
+    pub fn to_gemini_mapping(&self) -> GeminiReasoningEffortMapping {
+        GeminiReasoningEffortMapping {
+            opus: self.opus.clone(),
+            sonnet: self.sonnet.clone(),
+            haiku: self.haiku.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LbSlotEndpointRef {
+    pub endpoint_id: String,
+    pub custom_model_name: Option<String>,
+    pub custom_reasoning_effort: Option<String>,
+    pub converter_override: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelSlotMapping {
+    pub opus: Vec<LbSlotEndpointRef>,
+    pub sonnet: Vec<LbSlotEndpointRef>,
+    pub haiku: Vec<LbSlotEndpointRef>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LbFailoverStrategy {
+    pub error_threshold: u32,
+    pub error_window_seconds: u32,
+    pub cooldown_seconds: u32,
+    pub degraded_concurrency: u32,
+    /// slot 内分流策略：`priority` / `weighted_random` / `round_robin` / `least_in_flight` /
+    /// `lowest_latency`
+    #[serde(default = "default_lb_selection")]
+    pub selection: String,
+    /// TTFB 的 EWMA 超过该毫秒数时把路由降为 Constrained；0 表示不按延迟降级
+    #[serde(default)]
+    pub latency_threshold_ms: u32,
+    /// 会话粘性时长（秒）：同一会话固定路由以保持上游 prompt cache；0 表示关闭
+    #[serde(default = "default_lb_session_affinity_ttl_seconds")]
+    pub session_affinity_ttl_seconds: u64,
+    /// 对冲请求：流式请求的首个候选超过该毫秒数仍无业务事件时，向下一个候选并行发出；0 表示关闭
+    #[serde(default)]
+    pub hedge_after_ms: u64,
+}
+
+fn default_lb_selection() -> String {
+    "priority".to_string()
+}
+
+fn default_lb_session_affinity_ttl_seconds() -> u64 {
+    1800
+}
+
+impl Default for LbFailoverStrategy {
+    fn default() -> Self {
+        Self {
+            error_threshold: 5,
+            error_window_seconds: 60,
+            cooldown_seconds: 3600,
+            degraded_concurrency: 4,
+            selection: default_lb_selection(),
+            latency_threshold_ms: 0,
+            session_affinity_ttl_seconds: default_lb_session_affinity_ttl_seconds(),
+            hedge_after_ms: 0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadBalancerProfile {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub model_mapping: ModelSlotMapping,
+    pub strategy: LbFailoverStrategy,
+    #[serde(default, skip_serializing_if = "LbDegradationConfig::is_empty")]
+    pub degradation: LbDegradationConfig,
+}
+
+/// 跨 slot 降级：主 slot 没有可用候选时按 `chain` 顺序改用其后的 slot
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LbDegradationConfig {
+    #[serde(default)]
+    pub chain: Vec<LbDegradationStep>,
+    /// 降级服务时在响应中附加 `x-codex-proxy-degraded-slot` 头
+    #[serde(default)]
+    pub response_header: bool,
+}
+
+impl LbDegradationConfig {
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty() && !self.response_header
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LbDegradationStep {
+    /// `opus` / `sonnet` / `haiku`
+    pub slot: String,
+    /// 降级到该 slot 时覆盖的推理强度
+    #[serde(default)]
+    pub reasoning_effort: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LbEndpointConfig {
+    pub endpoint_id: String,
+    pub enabled: bool,
+    pub max_concurrency: u32,
+    pub priority: u32,
+    pub weight: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadBalancerConfig {
+    pub lb_profiles: Vec<LoadBalancerProfile>,
+    pub selected_lb_profile_id: Option<String>,
+    pub lb_endpoint_configs: HashMap<String, LbEndpointConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EndpointOption {
+    pub id: String,
+    pub alias: String,
+    pub url: String,
+    #[serde(rename = "apiKey")]
+    pub api_key: String,
+
+    /// 同一地址下的其他密钥；与 `apiKey` 合并为密钥池，负载均衡模式下按 `keyRotation` 轮换
+    #[serde(rename = "apiKeys", default)]
+    pub api_keys: Vec<String>,
+
+    /// 密钥池轮换方式：`round_robin`（默认）/ `least_used`
+    #[serde(rename = "keyRotation", default)]
+    pub key_rotation: Option<String>,
+
+    #[serde(default)]
+    pub converter: Option<String>,
+
+    #[serde(rename = "codexModel", default)]
+    pub codex_model: Option<String>,
+
+    #[serde(rename = "codexModelMapping", default)]
+    pub codex_model_mapping: Option<CodexModelMappingConfig>,
+
+    #[serde(rename = "codexEffortCapabilityMap", default)]
+    pub codex_effort_capability_map: Option<HashMap<String, Vec<String>>>,
+
+    #[serde(rename = "geminiModelPreset", default)]
+    pub gemini_model_preset: Option<Vec<String>>,
+
+    #[serde(rename = "anthropicModelMapping", default)]
+    pub anthropic_model_mapping: Option<AnthropicModelMappingConfig>,
+
+    #[serde(rename = "openaiModelMapping", default)]
+    pub openai_model_mapping: Option<OpenAIModelMappingConfig>,
+
+    #[serde(rename = "openaiMaxTokensMapping", default)]
+    pub openai_max_tokens_mapping: Option<OpenAIMaxTokensMappingConfig>,
+
+    #[serde(rename = "reasoningEffort", default)]
+    pub reasoning_effort: Option<ReasoningEffortConfig>,
+
+    #[serde(rename = "geminiReasoningEffort", default)]
+    pub gemini_reasoning_effort: Option<ReasoningEffortConfig>,
+}
+
+impl EndpointOption {
+    /// `apiKey` 与 `apiKeys` 合并去重后的密钥池（忽略空值）
+    pub fn key_pool(&self) -> Vec<String> {
+        let mut pool: Vec<String> = Vec::new();
+        for key in std::iter::once(&self.api_key).chain(&self.api_keys) {
+            if !key.trim().is_empty() && !pool.contains(key) {
+                pool.push(key.clone());
+            }
+        }
+        pool
+    }
+
+    /// 单模型模式使用的密钥：`apiKey`，为空时取密钥池中的第一个
+    pub fn primary_api_key(&self) -> String {
+        self.key_pool().into_iter().next().unwrap_or_default()
+    }
+}
+
+fn default_endpoint_options() -> Vec<EndpointOption> {
+    vec![EndpointOption {
+        id: "aicodemirror-default".to_string(),
+        alias: "aicodemirror".to_string(),
+        url: "https://api.aicodemirror.com/api/codex/backend-api/codex/responses".to_string(),
+        api_key: String::new(),
+        api_keys: Vec::new(),
+        key_rotation: None,
+        converter: None,
+        codex_model: None,
+        codex_model_mapping: None,
+        codex_effort_capability_map: None,
+        gemini_model_preset: None,
+        anthropic_model_mapping: None,
+        openai_model_mapping: None,
+        openai_max_tokens_mapping: None,
+        reasoning_effort: None,
+        gemini_reasoning_effort: None,
+    }]
+}
+
+fn default_selected_endpoint_id() -> String {
+    "aicodemirror-default".to_string()
+}
+
+fn default_codex_endpoint_options() -> Vec<EndpointOption> {
+    vec![EndpointOption {
+        id: "codex-default".to_string(),
+        alias: "CodebuddyProxy".to_string(),
+        url: "https://api.aicodemirror.com/api/codex/backend-api/codex/responses".to_string(),
+        api_key: String::new(),
+        api_keys: Vec::new(),
+        key_rotation: None,
+        converter: Some(default_converter()),
+        codex_model: None,
+        codex_model_mapping: None,
+        codex_effort_capability_map: None,
+        gemini_model_preset: None,
+        anthropic_model_mapping: None,
+        openai_model_mapping: None,
+        openai_max_tokens_mapping: None,
+        reasoning_effort: None,
+        gemini_reasoning_effort: None,
+    }]
+}
+
+fn default_codex_selected_endpoint_id() -> String {
+    "codex-default".to_string()
+}
+
+fn default_proxy_mode() -> String {
+    "single".to_string()
+}
+
+fn default_image_gen_endpoint_options() -> Vec<EndpointOption> {
+    Vec::new()
+}
+
+fn default_selected_image_gen_endpoint_id() -> String {
+    String::new()
+}
+
+fn default_load_balancer() -> LoadBalancerConfig {
+    LoadBalancerConfig::default()
+}
+
+pub fn default_lb_model_cooldown_seconds() -> u32 {
+    3600
+}
+
+pub fn default_lb_transient_backoff_seconds() -> u32 {
+    6
+}
+
+fn default_anthropic_model_mapping() -> AnthropicModelMappingConfig {
+    AnthropicModelMappingConfig::default()
+}
+
+fn default_openai_model_mapping() -> OpenAIModelMappingConfig {
+    OpenAIModelMappingConfig::default()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CodexClientConfig {
+    #[serde(rename = "targetUrl")]
+    pub target_url: String,
+    #[serde(rename = "apiKey")]
+    pub api_key: String,
+    #[serde(rename = "endpointOptions", default = "default_codex_endpoint_options")]
+    pub endpoint_options: Vec<EndpointOption>,
+    #[serde(
+        rename = "selectedEndpointId",
+        default = "default_codex_selected_endpoint_id"
+    )]
+    pub selected_endpoint_id: String,
+    #[serde(default = "default_converter")]
+    pub converter: String,
+    #[serde(rename = "proxyMode", default = "default_proxy_mode")]
+    pub proxy_mode: String,
+    #[serde(
+        rename = "imageGenerationEndpointOptions",
+        default = "default_image_gen_endpoint_options"
+    )]
+    pub image_generation_endpoint_options: Vec<EndpointOption>,
+    #[serde(
+        rename = "selectedImageGenerationEndpointId",
+        default = "default_selected_image_gen_endpoint_id"
+    )]
+    pub selected_image_generation_endpoint_id: String,
+    #[serde(rename = "stripImageGenerationTool", default)]
+    pub strip_image_generation_tool: bool,
+}
+
+impl Default for CodexClientConfig {
+    fn default() -> Self {
+        let endpoint_options = default_codex_endpoint_options();
+        Self {
+            target_url: endpoint_options
+                .first()
+                .map(|item| item.url.clone())
+                .unwrap_or_default(),
+            api_key: String::new(),
+            endpoint_options,
+            selected_endpoint_id: default_codex_selected_endpoint_id(),
+            converter: default_converter(),
+            proxy_mode: default_proxy_mode(),
+            image_generation_endpoint_options: default_image_gen_endpoint_options(),
+            selected_image_generation_endpoint_id: default_selected_image_gen_endpoint_id(),
+            strip_image_generation_tool: false,
+        }
+    }
+}
+
+fn default_codex_config() -> CodexClientConfig {
+    CodexClientConfig::default()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProxyConfig {
+    pub port: u16,
+    #[serde(rename = "targetUrl")]
+    pub target_url: String,
+    #[serde(rename = "apiKey")]
+    pub api_key: String,
+    #[serde(rename = "endpointOptions", default = "default_endpoint_options")]
+    pub endpoint_options: Vec<EndpointOption>,
+    #[serde(
+        rename = "selectedEndpointId",
+        default = "default_selected_endpoint_id"
+    )]
+    pub selected_endpoint_id: String,
+    #[serde(rename = "codexConfig", default = "default_codex_config")]
+    pub codex_config: CodexClientConfig,
+    #[serde(default = "default_converter")]
+    pub converter: String,
+    #[serde(rename = "codexModel", default = "default_codex_model")]
+    pub codex_model: String,
+    #[serde(rename = "codexModelMapping", default)]
+    pub codex_model_mapping: CodexModelMappingConfig,
+    #[serde(
+        rename = "anthropicModelMapping",
+        default = "default_anthropic_model_mapping"
+    )]
+    pub anthropic_model_mapping: AnthropicModelMappingConfig,
+
+    #[serde(
+        rename = "openaiModelMapping",
+        default = "default_openai_model_mapping"
+    )]
+    pub openai_model_mapping: OpenAIModelMappingConfig,
+
+    #[serde(rename = "codexEffortCapabilityMap", default)]
+    pub codex_effort_capability_map: Option<HashMap<String, Vec<String>>>,
+
+    #[serde(rename = "geminiModelPreset", default = "default_gemini_model_preset")]
+    pub gemini_model_preset: Vec<String>,
+
+    #[serde(rename = "maxConcurrency", default)]
+    pub max_concurrency: u32,
+    /// 并发已满时请求的最长排队时间（毫秒），超时返回 `overloaded_error`；0 表示不限制
+    #[serde(rename = "maxQueueWaitMs", default = "default_max_queue_wait_ms")]
+    pub max_queue_wait_ms: u64,
+    #[serde(rename = "ignoreProbeRequests", default)]
+    pub ignore_probe_requests: bool,
+    #[serde(
+        rename = "allowCountTokensFallbackEstimate",
+        default = "default_allow_count_tokens_fallback_estimate"
+    )]
+    pub allow_count_tokens_fallback_estimate: bool,
+    #[serde(
+        rename = "enableCodexFastMode",
+        default = "default_enable_codex_fast_mode"
+    )]
+    pub enable_codex_fast_mode: bool,
+    #[serde(
+        rename = "forceStreamForCodex",
+        default = "default_force_stream_for_codex"
+    )]
+    pub force_stream_for_codex: bool,
+    #[serde(
+        rename = "enableSseFrameParser",
+        default = "default_enable_sse_frame_parser"
+    )]
+    pub enable_sse_frame_parser: bool,
+    #[serde(
+        rename = "enableStreamHeartbeat",
+        default = "default_enable_stream_heartbeat"
+    )]
+    pub enable_stream_heartbeat: bool,
+    #[serde(
+        rename = "streamHeartbeatIntervalMs",
+        default = "default_stream_heartbeat_interval_ms"
+    )]
+    pub stream_heartbeat_interval_ms: u64,
+    #[serde(
+        rename = "enableStreamLogSampling",
+        default = "default_enable_stream_log_sampling"
+    )]
+    pub enable_stream_log_sampling: bool,
+    #[serde(
+        rename = "streamLogSampleEveryN",
+        default = "default_stream_log_sample_every_n"
+    )]
+    pub stream_log_sample_every_n: u32,
+    #[serde(rename = "streamLogMaxChars", default = "default_stream_log_max_chars")]
+    pub stream_log_max_chars: usize,
+    #[serde(
+        rename = "enableStreamMetrics",
+        default = "default_enable_stream_metrics"
+    )]
+    pub enable_stream_metrics: bool,
+    #[serde(
+        rename = "enableStreamEventMetrics",
+        default = "default_enable_stream_event_metrics"
+    )]
+    pub enable_stream_event_metrics: bool,
+    #[serde(
+        rename = "streamSilenceWarnMs",
+        default = "default_stream_silence_warn_ms"
+    )]
+    pub stream_silence_warn_ms: u64,
+    #[serde(
+        rename = "streamSilenceErrorMs",
+        default = "default_stream_silence_error_ms"
+    )]
+    pub stream_silence_error_ms: u64,
+    #[serde(rename = "enableStallRetry", default = "default_enable_stall_retry")]
+    pub enable_stall_retry: bool,
+    #[serde(rename = "stallTimeoutMs", default = "default_stall_timeout_ms")]
+    pub stall_timeout_ms: u64,
+    #[serde(
+        rename = "stallRetryMaxAttempts",
+        default = "default_stall_retry_max_attempts"
+    )]
+    pub stall_retry_max_attempts: u32,
+    #[serde(
+        rename = "stallRetryOnlyHeartbeatPhase",
+        default = "default_stall_retry_only_heartbeat_phase"
+    )]
+    pub stall_retry_only_heartbeat_phase: bool,
+    #[serde(
+        rename = "enableEmptyCompletionRetry",
+        default = "default_enable_empty_completion_retry"
+    )]
+    pub enable_empty_completion_retry: bool,
+    #[serde(
+        rename = "emptyCompletionRetryMaxAttempts",
+        default = "default_empty_completion_retry_max_attempts"
+    )]
+    pub empty_completion_retry_max_attempts: u32,
+    #[serde(
+        rename = "enableIncompleteStreamRetry",
+        default = "default_enable_incomplete_stream_retry"
+    )]
+    pub enable_incomplete_stream_retry: bool,
+    #[serde(
+        rename = "incompleteStreamRetryMaxAttempts",
+        default = "default_incomplete_stream_retry_max_attempts"
+    )]
+    pub incomplete_stream_retry_max_attempts: u32,
+    #[serde(
+        rename = "enableSiblingToolErrorRetry",
+        default = "default_enable_sibling_tool_error_retry"
+    )]
+    pub enable_sibling_tool_error_retry: bool,
+    #[serde(rename = "preferCodexV1Path", default = "default_prefer_codex_v1_path")]
+    pub prefer_codex_v1_path: bool,
+    #[serde(
+        rename = "enableCodexToolSchemaCompaction",
+        default = "default_enable_codex_tool_schema_compaction"
+    )]
+    pub enable_codex_tool_schema_compaction: bool,
+    #[serde(
+        rename = "enableSkillRoutingHint",
+        default = "default_enable_skill_routing_hint"
+    )]
+    pub enable_skill_routing_hint: bool,
+    #[serde(
+        rename = "enableStatefulResponsesChain",
+        default = "default_enable_stateful_responses_chain"
+    )]
+    pub enable_stateful_responses_chain: bool,
+    #[serde(rename = "allowExternalAccess", default)]
+    pub allow_external_access: bool,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(rename = "proxyMode", default = "default_proxy_mode")]
+    pub proxy_mode: String,
+    #[serde(rename = "loadBalancer", default = "default_load_balancer")]
+    pub load_balancer: LoadBalancerConfig,
+    #[serde(
+        rename = "lbModelCooldownSeconds",
+        default = "default_lb_model_cooldown_seconds"
+    )]
+    pub lb_model_cooldown_seconds: u32,
+    #[serde(
+        rename = "lbTransientBackoffSeconds",
+        default = "default_lb_transient_backoff_seconds"
+    )]
+    pub lb_transient_backoff_seconds: u32,
+    /// 后台探测冷却中/已停用端点的间隔（秒）；0 表示关闭探测
+    #[serde(rename = "lbProbeIntervalSeconds", default)]
+    pub lb_probe_interval_seconds: u32,
+    #[serde(rename = "reasoningEffort", default)]
+    pub reasoning_effort: ReasoningEffortConfig,
+    #[serde(rename = "geminiReasoningEffort", default)]
+    pub gemini_reasoning_effort: ReasoningEffortConfig,
+    #[serde(
+        rename = "customInjectionPrompt",
+        alias = "skillInjectionPrompt",
+        default = "default_custom_injection_prompt"
+    )]
+    pub custom_injection_prompt: String,
+    #[serde(default = "default_lang")]
+    pub lang: String,
+    /// `/admin` 管理接口令牌；为空时不启用管理接口
+    #[serde(
+        rename = "adminToken",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub admin_token: Option<String>,
+    /// 客户端访问令牌；非空时入站请求必须携带其中一个有效令牌
+    #[serde(
+        rename = "clientTokens",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub client_tokens: Vec<ClientTokenConfig>,
+    /// 全局限流与 token 配额（所有客户端合计）
+    #[serde(rename = "rateLimit", default)]
+    pub rate_limit: RateLimitConfig,
+    /// 模型路由表；没有规则时按模型名中的 opus / sonnet / haiku 归入固定 slot
+    #[serde(
+        rename = "modelRoutes",
+        default,
+        skip_serializing_if = "ModelRoutesConfig::is_empty"
+    )]
+    pub model_routes: ModelRoutesConfig,
+    /// 本地 token 用量账本
+    #[serde(rename = "usageLedger", default)]
+    pub usage_ledger: UsageLedgerConfig,
+    /// 本地 token 估算的自校准
+    #[serde(rename = "tokenCalibration", default)]
+    pub token_calibration: TokenCalibrationConfig,
+    /// 有状态 Responses 链与技能目录提醒的磁盘持久化
+    #[serde(rename = "sessionStore", default)]
+    pub session_store: SessionStoreConfig,
+    /// 上游模型价目表；按顺序匹配，用于折算每个请求的费用
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pricing: Vec<ModelPriceConfig>,
+    /// 全局、端点与客户端的日 / 月花费预算
+    #[serde(default, skip_serializing_if = "BudgetsConfig::is_empty")]
+    pub budgets: BudgetsConfig,
+}
+
+/// 代理监听端的客户端访问令牌（通过 `x-api-key` 或 `Authorization: Bearer` 携带）
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ClientTokenConfig {
+    /// 客户端名称，出现在日志与指标中
+    pub name: String,
+    pub token: String,
+    #[serde(default = "default_client_token_enabled")]
+    pub enabled: bool,
+    /// 允许的入口路由（`claude` / `codex`）；为空表示不限
+    #[serde(rename = "allowedRoutes", default)]
+    pub allowed_routes: Vec<String>,
+    /// 允许的模型槽位（`opus` / `sonnet` / `haiku`）；为空表示不限
+    #[serde(rename = "allowedSlots", default)]
+    pub allowed_slots: Vec<String>,
+    /// 该客户端自身的限流与配额
+    #[serde(rename = "rateLimit", default)]
+    pub rate_limit: RateLimitConfig,
+}
+
+/// 请求频率、并发流与 token 配额；各项为 0 表示不限
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    #[serde(rename = "requestsPerMinute", default)]
+    pub requests_per_minute: u32,
+    #[serde(rename = "maxConcurrentStreams", default)]
+    pub max_concurrent_streams: u32,
+    #[serde(rename = "inputTokensPerHour", default)]
+    pub input_tokens_per_hour: u64,
+    #[serde(rename = "outputTokensPerHour", default)]
+    pub output_tokens_per_hour: u64,
+    #[serde(rename = "inputTokensPerDay", default)]
+    pub input_tokens_per_day: u64,
+    #[serde(rename = "outputTokensPerDay", default)]
+    pub output_tokens_per_day: u64,
+}
+
+impl RateLimitConfig {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// 每个完成的请求追加到本地 JSONL 账本，供 `/usage` 按日期 / 端点 / 模型 / 会话汇总
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct UsageLedgerConfig {
+    #[serde(default = "default_usage_ledger_enabled")]
+    pub enabled: bool,
+    /// 账本文件路径；留空时为 `~/.codexProxy/usage/usage-ledger.jsonl`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl Default for UsageLedgerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_usage_ledger_enabled(),
+            path: None,
+        }
+    }
+}
+
+/// 对比本地估算与上游 usage 学习 count_tokens 回退估算的修正系数；系数单独落盘，不依赖用量账本
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TokenCalibrationConfig {
+    #[serde(default = "default_token_calibration_enabled")]
+    pub enabled: bool,
+    /// 系数文件路径；留空时与用量账本放在同一目录（默认 `~/.codexProxy/usage/token-calibration.json`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl Default for TokenCalibrationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_token_calibration_enabled(),
+            path: None,
+        }
+    }
+}
+
+/// 把有状态链（`previous_response_id`）与技能目录提醒保存到磁盘，重启后会话可继续增量续写
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SessionStoreConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 存储文件路径；留空时为 `~/.codexProxy/sessions/session-store.json`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// 条目自最后一次更新起保留的秒数；过期条目不再加载与写入
+    #[serde(rename = "ttlSecs", default = "default_session_store_ttl_secs")]
+    pub ttl_secs: u64,
+}
+
+impl Default for SessionStoreConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            ttl_secs: default_session_store_ttl_secs(),
+        }
+    }
+}
+
+/// 上游模型单价（美元 / 百万 token）
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelPriceConfig {
+    /// 上游模型名：精确 id、glob 或正则，不区分大小写
+    #[serde(rename = "match")]
+    pub pattern: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_type: Option<String>,
+    #[serde(default)]
+    pub input: f64,
+    /// 缓存命中的输入；留空时按 `input` 计
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_input: Option<f64>,
+    #[serde(default)]
+    pub output: f64,
+    /// 推理 token；留空时按 `output` 计
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<f64>,
+}
+
+/// 日 / 月花费上限（美元）；各项为 0 表示不限
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetLimits {
+    #[serde(default)]
+    pub daily_soft: f64,
+    #[serde(default)]
+    pub daily_hard: f64,
+    #[serde(default)]
+    pub monthly_soft: f64,
+    #[serde(default)]
+    pub monthly_hard: f64,
+}
+
+impl BudgetLimits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// 软预算触达后端点降级排序，硬预算触达后端点冷却、客户端与全局拒绝请求，直到周期重置
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct BudgetsConfig {
+    #[serde(default)]
+    pub global: BudgetLimits,
+    /// 按端点 id
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub endpoints: HashMap<String, BudgetLimits>,
+    /// 按客户端令牌名称
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub clients: HashMap<String, BudgetLimits>,
+}
+
+impl BudgetsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.global.is_unlimited() && self.endpoints.is_empty() && self.clients.is_empty()
+    }
+}
+
+/// 模型路由表：`rules` 按顺序匹配客户端模型名，第一条命中的规则使用 `routes` 中的同名路由
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ModelRoutesConfig {
+    #[serde(default)]
+    pub rules: Vec<ModelRouteRuleConfig>,
+    #[serde(default)]
+    pub routes: Vec<ModelRouteConfig>,
+    /// 未命中规则、模型名又不含 opus / sonnet / haiku 时使用的路由；留空时归入 sonnet slot
+    #[serde(
+        rename = "defaultRoute",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub default_route: Option<String>,
+    /// 未配置 `defaultRoute` 时直接拒绝这类模型，而不是归入 sonnet slot
+    #[serde(rename = "rejectUnmatched", default)]
+    pub reject_unmatched: bool,
+}
+
+impl ModelRoutesConfig {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+            && self.routes.is_empty()
+            && self.default_route.is_none()
+            && !self.reject_unmatched
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelRouteRuleConfig {
+    /// 客户端模型名：精确 id、glob（`*` / `?`）或正则，不区分大小写
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// `exact` / `glob` / `regex`；留空时含通配符按 glob，否则精确匹配
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_type: Option<String>,
+    /// 只匹配该类型的 Claude Code 请求
+    /// （`conversation_turn` / `subagent` / `session_title` / `compaction` / `probe`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_kind: Option<String>,
+    pub route: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelRouteConfig {
+    pub name: String,
+    /// `opus` / `sonnet` / `haiku`：用于客户端槽位权限、日志与未指定 model 时的映射；
+    /// 留空时按路由名推断
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<String>,
+    /// 上游模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<String>,
+    /// OpenAI Chat 上游的 max_tokens 上限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// 负载均衡模式下该路由自己的候选端点；为空时沿用 profile 中 slot 的候选
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<LbSlotEndpointRef>,
+}
+
+impl ModelRouteConfig {
+    fn resolved_slot(&self) -> ModelSlot {
+        self.slot
+            .as_deref()
+            .and_then(ModelSlot::parse)
+            .unwrap_or_else(|| ModelSlot::from_model_name(&self.name))
+    }
+
+    fn trimmed_model(&self) -> Option<String> {
+        non_empty_trimmed(self.model.as_deref())
+    }
+
+    fn trimmed_reasoning_effort(&self) -> Option<String> {
+        non_empty_trimmed(self.reasoning_effort.as_deref())
+    }
+}
+
+fn non_empty_trimmed(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+fn default_client_token_enabled() -> bool {
+    true
+}
+
+fn default_lang() -> String {
+    "zh".to_string()
+}
+
+pub const DEFAULT_CUSTOM_INJECTION_PROMPT: &str = "回复前先看下skills是否有一个skill符合的，可以执行。\n\nskills里的技能如果需要依赖，先安装，不要先用其他方案，如果还有问题告知用户解决方案让用户选择。\n\n如果你收到 <tool_use_error>Sibling tool call errored</tool_use_error>，请不要将其作为普通文本输出，而是分析导致该错误的原始Sibling的报错原因并继续工作。";
+
+fn default_custom_injection_prompt() -> String {
+    DEFAULT_CUSTOM_INJECTION_PROMPT.to_string()
+}
+
+pub fn resolve_custom_injection_prompt(raw: &str) -> String {
+    if raw.trim().is_empty() {
+        default_custom_injection_prompt()
+    } else {
+        raw.to_string()
+    }
+}
+
+pub fn default_converter() -> String {
+    "codex".to_string()
+}
+
+fn default_codex_model() -> String {
+    "gpt-5.3-codex".to_string()
+}
+
+fn default_allow_count_tokens_fallback_estimate() -> bool {
+    true
+}
+
+fn default_enable_codex_fast_mode() -> bool {
+    true
+}
+
+fn default_force_stream_for_codex() -> bool {
+    true
+}
+
+fn default_enable_sse_frame_parser() -> bool {
+    true
+}
+
+fn default_enable_stream_heartbeat() -> bool {
+    true
+}
+
+fn default_usage_ledger_enabled() -> bool {
+    true
+}
+
+fn default_token_calibration_enabled() -> bool {
+    true
+}
+
+fn default_session_store_ttl_secs() -> u64 {
+    24 * 3600
+}
+
+fn default_max_queue_wait_ms() -> u64 {
+    120_000
+}
+
+fn default_stream_heartbeat_interval_ms() -> u64 {
+    3_000
+}
+
+fn default_enable_stream_log_sampling() -> bool {
+    true
+}
+
+fn default_stream_log_sample_every_n() -> u32 {
+    20
+}
+
+fn default_stream_log_max_chars() -> usize {
+    512
+}
+
+fn default_enable_stream_metrics() -> bool {
+    true
+}
+
+fn default_enable_stream_event_metrics() -> bool {
+    true
+}
+
+fn default_stream_silence_warn_ms() -> u64 {
+    20_000
+}
+
+fn default_stream_silence_error_ms() -> u64 {
+    90_000
+}
+
+fn default_enable_stall_retry() -> bool {
+    false
+}
+
+fn default_stall_timeout_ms() -> u64 {
+    300_000
+}
+
+fn default_stall_retry_max_attempts() -> u32 {
+    0
+}
+
+fn default_stall_retry_only_heartbeat_phase() -> bool {
+    false
+}
+
+fn default_enable_empty_completion_retry() -> bool {
+    false
+}
+
+fn default_empty_completion_retry_max_attempts() -> u32 {
+    0
+}
+
+fn default_enable_incomplete_stream_retry() -> bool {
+    true
+}
+
+fn default_incomplete_stream_retry_max_attempts() -> u32 {
+    2
+}
+
+fn default_enable_sibling_tool_error_retry() -> bool {
+    true
+}
+
+fn default_prefer_codex_v1_path() -> bool {
+    true
+}
+
+fn default_enable_codex_tool_schema_compaction() -> bool {
+    true
+}
+
+fn default_enable_skill_routing_hint() -> bool {
+    false
+}
+
+fn default_enable_stateful_responses_chain() -> bool {
+    true
+}
+
+pub fn default_gemini_model_preset() -> Vec<String> {
+    vec![
+        "gemini-2.5-flash-lite".to_string(),
+        "gemini-3-pro-preview".to_string(),
+        "gemini-3-pro-image-preview".to_string(),
+        "gemini-3-flash-preview".to_string(),
+        "gemini-2.5-flash".to_string(),
+        "gemini-2.5-pro".to_string(),
+    ]
+}
+
+pub fn default_proxy_config() -> ProxyConfig {
+    ProxyConfig {
+        port: 8889,
+        target_url: default_endpoint_options()
+            .into_iter()
+            .next()
+            .map(|item| item.url)
+            .unwrap_or_default(),
+        api_key: String::new(),
+        endpoint_options: default_endpoint_options(),
+        selected_endpoint_id: default_selected_endpoint_id(),
+        codex_config: default_codex_config(),
+        converter: default_converter(),
+        codex_model: default_codex_model(),
+        codex_model_mapping: CodexModelMappingConfig::default(),
+        anthropic_model_mapping: default_anthropic_model_mapping(),
+        openai_model_mapping: default_openai_model_mapping(),
+        codex_effort_capability_map: None,
+        gemini_model_preset: default_gemini_model_preset(),
+        max_concurrency: 0,
+        max_queue_wait_ms: default_max_queue_wait_ms(),
+        ignore_probe_requests: false,
+        allow_count_tokens_fallback_estimate: default_allow_count_tokens_fallback_estimate(),
+        enable_codex_fast_mode: default_enable_codex_fast_mode(),
+        force_stream_for_codex: default_force_stream_for_codex(),
+        enable_sse_frame_parser: default_enable_sse_frame_parser(),
+        enable_stream_heartbeat: default_enable_stream_heartbeat(),
+        stream_heartbeat_interval_ms: default_stream_heartbeat_interval_ms(),
+        enable_stream_log_sampling: default_enable_stream_log_sampling(),
+        stream_log_sample_every_n: default_stream_log_sample_every_n(),
+        stream_log_max_chars: default_stream_log_max_chars(),
+        enable_stream_metrics: default_enable_stream_metrics(),
+        enable_stream_event_metrics: default_enable_stream_event_metrics(),
+        stream_silence_warn_ms: default_stream_silence_warn_ms(),
+        stream_silence_error_ms: default_stream_silence_error_ms(),
+        enable_stall_retry: default_enable_stall_retry(),
+        stall_timeout_ms: default_stall_timeout_ms(),
+        stall_retry_max_attempts: default_stall_retry_max_attempts(),
+        stall_retry_only_heartbeat_phase: default_stall_retry_only_heartbeat_phase(),
+        enable_empty_completion_retry: default_enable_empty_completion_retry(),
+        empty_completion_retry_max_attempts: default_empty_completion_retry_max_attempts(),
+        enable_incomplete_stream_retry: default_enable_incomplete_stream_retry(),
+        incomplete_stream_retry_max_attempts: default_incomplete_stream_retry_max_attempts(),
+        enable_sibling_tool_error_retry: default_enable_sibling_tool_error_retry(),
+        prefer_codex_v1_path: default_prefer_codex_v1_path(),
+        enable_codex_tool_schema_compaction: default_enable_codex_tool_schema_compaction(),
+        enable_skill_routing_hint: default_enable_skill_routing_hint(),
+        enable_stateful_responses_chain: default_enable_stateful_responses_chain(),
+        allow_external_access: false,
+        force: false,
+        proxy_mode: default_proxy_mode(),
+        load_balancer: default_load_balancer(),
+        lb_model_cooldown_seconds: default_lb_model_cooldown_seconds(),
+        lb_transient_backoff_seconds: default_lb_transient_backoff_seconds(),
+        lb_probe_interval_seconds: 0,
+        reasoning_effort: ReasoningEffortConfig::default(),
+        gemini_reasoning_effort: ReasoningEffortConfig::default(),
+        custom_injection_prompt: default_custom_injection_prompt(),
+        lang: default_lang(),
+        admin_token: None,
+        client_tokens: Vec::new(),
+        rate_limit: RateLimitConfig::default(),
+        model_routes: ModelRoutesConfig::default(),
+        usage_ledger: UsageLedgerConfig::default(),
+        token_calibration: TokenCalibrationConfig::default(),
+        session_store: SessionStoreConfig::default(),
+        pricing: Vec::new(),
+        budgets: BudgetsConfig::default(),
+    }
+}
+
+/// 构建模型路由表；无效的规则或路由会被跳过，原因记录在 `ModelRouteTable::warnings`
+pub fn build_model_route_table(config: &ProxyConfig) -> ModelRouteTable {
+    let mut table = ModelRouteTable::new();
+    for route in &config.model_routes.routes {
+        let name = route.name.trim();
+        if name.is_empty() {
+            table = table.with_warning("route skipped: empty name");
+            continue;
+        }
+        let mut entry = ModelRoute::new(name, route.resolved_slot())
+            .with_endpoints(!route.endpoints.is_empty());
+        if let Some(model) = route.trimmed_model() {
+            entry = entry.with_model(model);
+        }
+        if let Some(effort) = route.trimmed_reasoning_effort() {
+            entry = entry.with_reasoning_effort(ReasoningEffort::from_str(&effort));
+        }
+        if let Some(max_tokens) = route.max_tokens.filter(|value| *value > 0) {
+            entry = entry.with_max_tokens(max_tokens);
+        }
+        table = table.with_route(entry);
+    }
+
+    for (index, rule) in config.model_routes.rules.iter().enumerate() {
+        let matcher = match ModelMatcher::parse(&rule.pattern, rule.match_type.as_deref()) {
+            Ok(matcher) => matcher,
+            Err(error) => {
+                table = table.with_warning(format!("rule #{} skipped: {}", index + 1, error));
+                continue;
+            }
+        };
+        let request_kind = match non_empty_trimmed(rule.request_kind.as_deref()) {
+            None => None,
+            Some(value) => match parse_request_kind(&value) {
+                Some(kind) => Some(kind),
+                None => {
+                    table = table.with_warning(format!(
+                        "rule #{} skipped: unknown requestKind '{}'",
+                        index + 1,
+                        value
+                    ));
+                    continue;
+                }
+            },
+        };
+        table = table.with_rule(ModelRouteRule {
+            matcher,
+            request_kind,
+            route: rule.route.trim().to_string(),
+        });
+    }
+    if let Some(name) = non_empty_trimmed(config.model_routes.default_route.as_deref()) {
+        table = table.with_default_route(name);
+    }
+    if config.model_routes.reject_unmatched {
+        table = table.with_reject_unmatched();
+    }
+    table
+}
+
+pub fn build_lb_runtime(
+    config: &ProxyConfig,
+    log_tx: Option<broadcast::Sender<String>>,
+) -> Option<LoadBalancerRuntime> {
+    let selected_profile_id = config.load_balancer.selected_lb_profile_id.clone();
+    let selected_profile_strategy = selected_profile_id
+        .as_ref()
+        .and_then(|profile_id| {
+            config
+                .load_balancer
+                .lb_profiles
+                .iter()
+                .find(|p| &p.id == profile_id)
+        })
+        .map(|p| p.strategy.clone())
+        .unwrap_or_default();
+
+    let error_threshold = selected_profile_strategy.error_threshold.max(1);
+    let error_window_seconds = selected_profile_strategy.error_window_seconds.max(1);
+    let degraded_concurrency = selected_profile_strategy.degraded_concurrency.max(1);
+    let latency_threshold_ms = selected_profile_strategy.latency_threshold_ms;
+    let probe_interval_seconds = config.lb_probe_interval_seconds;
+    let cooldown_seconds = if config.lb_model_cooldown_seconds == 0 {
+        default_lb_model_cooldown_seconds()
+    } else {
+        config.lb_model_cooldown_seconds
+    };
+    let transient_backoff_seconds = if config.lb_transient_backoff_seconds == 0 {
+        default_lb_transient_backoff_seconds()
+    } else {
+        config.lb_transient_backoff_seconds
+    };
+
+    let profiles: Vec<CoreLoadBalancerProfile> = config
+        .load_balancer
+        .lb_profiles
+        .iter()
+        .map(|profile| CoreLoadBalancerProfile {
+            id: profile.id.clone(),
+            name: profile.name.clone(),
+            model_mapping: CoreSlotMapping {
+                opus: profile
+                    .model_mapping
+                    .opus
+                    .iter()
+                    .map(|item| CoreSlotEndpointRef {
+                        endpoint_id: item.endpoint_id.clone(),
+                        custom_model_name: item.custom_model_name.clone(),
+                        custom_reasoning_effort: item.custom_reasoning_effort.clone(),
+                        converter_override: item.converter_override.clone(),
+                    })
+                    .collect(),
+                sonnet: profile
+                    .model_mapping
+                    .sonnet
+                    .iter()
+                    .map(|item| CoreSlotEndpointRef {
+                        endpoint_id: item.endpoint_id.clone(),
+                        custom_model_name: item.custom_model_name.clone(),
+                        custom_reasoning_effort: item.custom_reasoning_effort.clone(),
+                        converter_override: item.converter_override.clone(),
+                    })
+                    .collect(),
+                haiku: profile
+                    .model_mapping
+                    .haiku
+                    .iter()
+                    .map(|item| CoreSlotEndpointRef {
+                        endpoint_id: item.endpoint_id.clone(),
+                        custom_model_name: item.custom_model_name.clone(),
+                        custom_reasoning_effort: item.custom_reasoning_effort.clone(),
+                        converter_override: item.converter_override.clone(),
+                    })
+                    .collect(),
+            },
+            selection: SelectionStrategy::from_config(&profile.strategy.selection),
+            session_affinity_ttl_seconds: profile.strategy.session_affinity_ttl_seconds,
+            hedge_after_ms: profile.strategy.hedge_after_ms,
+            // 无法识别的 slot 名直接跳过
+            degradation_chain: profile
+                .degradation
+                .chain
+                .iter()
+                .filter_map(|step| {
+                    Some(DegradationStep {
+                        slot: ModelSlot::parse(&step.slot)?,
+                        reasoning_effort: step
+                            .reasoning_effort
+                            .as_deref()
+                            .map(str::trim)
+                            .filter(|effort| !effort.is_empty())
+                            .map(str::to_string),
+                    })
+                })
+                .collect(),
+            degradation_header: profile.degradation.response_header,
+        })
+        .collect();
+
+    // 模型路由表中带端点列表的路由：路由级 model / reasoningEffort 作为端点未单独指定时的默认值
+    let named_routes: Vec<NamedRoute> = config
+        .model_routes
+        .routes
+        .iter()
+        .filter(|route| !route.name.trim().is_empty() && !route.endpoints.is_empty())
+        .map(|route| NamedRoute {
+            name: route.name.trim().to_string(),
+            slot: route.resolved_slot(),
+            endpoints: route
+                .endpoints
+                .iter()
+                .map(|item| CoreSlotEndpointRef {
+                    endpoint_id: item.endpoint_id.clone(),
+                    custom_model_name: item
+                        .custom_model_name
+                        .clone()
+                        .or_else(|| route.trimmed_model()),
+                    custom_reasoning_effort: item
+                        .custom_reasoning_effort
+                        .clone()
+                        .or_else(|| route.trimmed_reasoning_effort()),
+                    converter_override: item.converter_override.clone(),
+                })
+                .collect(),
+        })
+        .collect();
+
+    if profiles.is_empty() || selected_profile_id.is_none() {
+        return None;
+    }
+
+    let endpoint_directory: HashMap<String, CoreLoadBalancerEndpoint> = config
+        .endpoint_options
+        .iter()
+        .map(|item| {
+            let converter = item
+                .converter
+                .clone()
+                .unwrap_or_else(|| config.converter.clone());
+            let key_pool = item.key_pool();
+            let api_key = match key_pool.first() {
+                Some(key) => Some(key.clone()),
+                None if config.api_key.is_empty() => None,
+                None => Some(config.api_key.clone()),
+            };
+            let key_rotation = KeyRotation::from_config(item.key_rotation.as_deref().unwrap_or(""));
+
+            (
+                item.id.clone(),
+                CoreLoadBalancerEndpoint {
+                    id: item.id.clone(),
+                    target_url: item.url.clone(),
+                    api_key,
+                    converter,
+                    // 只有一个密钥时不启用密钥池，行为与单密钥端点一致
+                    api_keys: if key_pool.len() > 1 {
+                        key_pool
+                    } else {
+                        Vec::new()
+                    },
+                    key_rotation,
+                },
+            )
+        })
+        .collect();
+
+    let endpoint_policies: HashMap<String, CoreEndpointPolicy> = config
+        .endpoint_options
+        .iter()
+        .map(|endpoint| {
+            let endpoint_cfg = config.load_balancer.lb_endpoint_configs.get(&endpoint.id);
+            let enabled = endpoint_cfg.map(|cfg| cfg.enabled).unwrap_or(true);
+            let max_concurrency = endpoint_cfg.map(|cfg| cfg.max_concurrency).unwrap_or(16);
+            let priority = endpoint_cfg.map(|cfg| cfg.priority).unwrap_or(0);
+            let weight = endpoint_cfg.map(|cfg| cfg.weight).unwrap_or(1);
+
+            (
+                endpoint.id.clone(),
+                CoreEndpointPolicy {
+                    enabled,
+                    max_concurrency: if max_concurrency == 0 {
+                        1
+                    } else {
+                        max_concurrency
+                    },
+                    error_threshold,
+                    error_window_seconds,
+                    cooldown_seconds,
+                    degraded_concurrency,
+                    transient_backoff_seconds,
+                    priority,
+                    weight: weight.max(1),
+                    latency_threshold_ms,
+                    probe_interval_seconds,
+                },
+            )
+        })
+        .collect();
+
+    Some(LoadBalancerRuntime::new(
+        CoreLoadBalancerConfig {
+            selected_profile_id,
+            profiles,
+            endpoint_policies,
+            named_routes,
+        },
+        endpoint_directory,
+        log_tx,
+    ))
+}
+
+fn selected_endpoint_from<'a>(
+    endpoint_options: &'a [EndpointOption],
+    selected_endpoint_id: &str,
+) -> Option<&'a EndpointOption> {
+    endpoint_options
+        .iter()
+        .find(|item| item.id == selected_endpoint_id)
+}
+
+pub fn selected_endpoint(config: &ProxyConfig) -> Option<&EndpointOption> {
+    selected_endpoint_from(&config.endpoint_options, &config.selected_endpoint_id)
+}
+
+pub fn selected_codex_endpoint(config: &ProxyConfig) -> Option<&EndpointOption> {
+    selected_endpoint_from(
+        &config.codex_config.endpoint_options,
+        &config.codex_config.selected_endpoint_id,
+    )
+}
+
+pub fn selected_image_gen_endpoint(config: &ProxyConfig) -> Option<&EndpointOption> {
+    selected_endpoint_from(
+        &config.codex_config.image_generation_endpoint_options,
+        &config.codex_config.selected_image_generation_endpoint_id,
+    )
+}
+
+pub fn resolve_target_and_api_key(config: &ProxyConfig) -> (String, Option<String>) {
+    let selected = selected_endpoint(config);
+    let target_url = selected
+        .map(|item| item.url.clone())
+        .unwrap_or_else(|| config.target_url.clone());
+    let resolved_api_key = selected
+        .map(EndpointOption::primary_api_key)
+        .unwrap_or_else(|| config.api_key.clone());
+    let api_key = if resolved_api_key.is_empty() {
+        None
+    } else {
+        Some(resolved_api_key)
+    };
+    (target_url, api_key)
+}
+
+pub fn resolve_codex_target_api_key_and_converter(
+    config: &ProxyConfig,
+) -> (String, Option<String>, String, String, Option<String>) {
+    let selected = selected_codex_endpoint(config);
+    let target_url = selected
+        .map(|item| item.url.clone())
+        .unwrap_or_else(|| config.codex_config.target_url.clone());
+    let resolved_api_key = selected
+        .map(EndpointOption::primary_api_key)
+        .unwrap_or_else(|| config.codex_config.api_key.clone());
+    let api_key = if resolved_api_key.is_empty() {
+        None
+    } else {
+        Some(resolved_api_key)
+    };
+
+    // Resolve image generation endpoint
+    let image_gen_selected = selected_image_gen_endpoint(config);
+    let image_generation_url = image_gen_selected
+        .map(|item| item.url.clone())
+        .unwrap_or_default();
+    let image_generation_api_key = image_gen_selected.and_then(|item| {
+        let key = item.api_key.clone();
+        if key.is_empty() {
+            None
+        } else {
+            Some(key)
+        }
+    });
+
+    // Codex 路由默认原生透传；选中端点声明了其他 converter 时改走 Responses 入站转换
+    let converter = selected
+        .and_then(|item| item.converter.as_deref())
+        .map(str::trim)
+        .filter(|converter| !converter.is_empty())
+        .map(|converter| converter.to_ascii_lowercase())
+        .unwrap_or_else(default_converter);
+
+    (
+        target_url,
+        api_key,
+        converter,
+        image_generation_url,
+        image_generation_api_key,
+    )
+}
+
+pub fn build_transform_context(
+    config: &ProxyConfig,
+    converter: String,
+    openai_max_tokens_mapping: OpenAIMaxTokensMapping,
+) -> TransformContext {
+    let custom_injection_prompt = resolve_custom_injection_prompt(&config.custom_injection_prompt);
+    TransformContext {
+        reasoning_mapping: config.reasoning_effort.to_mapping(),
+        codex_model_mapping: CodexModelMapping {
+            opus: config.codex_model_mapping.opus.clone(),
+            sonnet: config.codex_model_mapping.sonnet.clone(),
+            haiku: config.codex_model_mapping.haiku.clone(),
+        },
+        anthropic_model_mapping: AnthropicModelMapping {
+            opus: config.anthropic_model_mapping.opus.clone(),
+            sonnet: config.anthropic_model_mapping.sonnet.clone(),
+            haiku: config.anthropic_model_mapping.haiku.clone(),
+        },
+        openai_model_mapping: OpenAIModelMapping {
+            opus: config.openai_model_mapping.opus.clone(),
+            sonnet: config.openai_model_mapping.sonnet.clone(),
+            haiku: config.openai_model_mapping.haiku.clone(),
+        },
+        openai_max_tokens_mapping,
+        custom_injection_prompt,
+        converter,
+        codex_model: config.codex_model.clone(),
+        gemini_reasoning_effort: config.gemini_reasoning_effort.to_gemini_mapping(),
+        enable_codex_tool_schema_compaction: config.enable_codex_tool_schema_compaction,
+        enable_codex_fast_mode: config.enable_codex_fast_mode,
+        enable_skill_routing_hint: config.enable_skill_routing_hint,
+    }
+}
+
+pub fn build_runtime_update(
+    config: &ProxyConfig,
+    log_tx: Option<broadcast::Sender<String>>,
+) -> RuntimeConfigUpdate {
+    let (target_url, api_key) = resolve_target_and_api_key(config);
+    let (
+        codex_target_url,
+        codex_api_key,
+        codex_converter,
+        image_generation_url,
+        image_generation_api_key,
+    ) = resolve_codex_target_api_key_and_converter(config);
+    let model_routes = build_model_route_table(config);
+    if let Some(tx) = log_tx.as_ref() {
+        for warning in model_routes.warnings() {
+            let _ = tx.send(format!("[Warn] model_routes {}", warning));
+        }
+    }
+    let load_balancer_runtime = if config.proxy_mode.eq_ignore_ascii_case("load_balancer") {
+        build_lb_runtime(config, log_tx)
+    } else {
+        None
+    };
+
+    // Get openai_max_tokens_mapping from selected endpoint
+    let selected = selected_endpoint(config);
+    let openai_max_tokens_mapping = selected
+        .and_then(|ep| ep.openai_max_tokens_mapping.clone())
+        .map(|m| m.into())
+        .unwrap_or_default();
+    let codex_selected = selected_codex_endpoint(config);
+    let codex_openai_max_tokens_mapping = codex_selected
+        .and_then(|ep| ep.openai_max_tokens_mapping.clone())
+        .map(|m| m.into())
+        .unwrap_or_default();
+
+    RuntimeConfigUpdate {
+        target_url,
+        api_key,
+        ctx: build_transform_context(config, config.converter.clone(), openai_max_tokens_mapping),
+        codex_route: Some(RuntimeRouteUpdate {
+            target_url: codex_target_url,
+            api_key: codex_api_key,
+            ctx: build_transform_context(config, codex_converter, codex_openai_max_tokens_mapping),
+            load_balancer_runtime: None,
+            image_generation_url,
+            image_generation_api_key,
+            strip_image_generation_tool: config.codex_config.strip_image_generation_tool,
+        }),
+        ignore_probe_requests: config.ignore_probe_requests,
+        allow_count_tokens_fallback_estimate: config.allow_count_tokens_fallback_estimate,
+        enable_codex_fast_mode: config.enable_codex_fast_mode,
+        force_stream_for_codex: config.force_stream_for_codex,
+        enable_sse_frame_parser: config.enable_sse_frame_parser,
+        enable_stream_heartbeat: config.enable_stream_heartbeat,
+        stream_heartbeat_interval_ms: config.stream_heartbeat_interval_ms,
+        enable_stream_log_sampling: config.enable_stream_log_sampling,
+        stream_log_sample_every_n: config.stream_log_sample_every_n,
+        stream_log_max_chars: config.stream_log_max_chars,
+        enable_stream_metrics: config.enable_stream_metrics,
+        enable_stream_event_metrics: config.enable_stream_event_metrics,
+        stream_silence_warn_ms: config.stream_silence_warn_ms,
+        stream_silence_error_ms: config.stream_silence_error_ms,
+        enable_stall_retry: config.enable_stall_retry,
+        stall_timeout_ms: config.stall_timeout_ms,
+        stall_retry_max_attempts: config.stall_retry_max_attempts,
+        stall_retry_only_heartbeat_phase: config.stall_retry_only_heartbeat_phase,
+        enable_empty_completion_retry: config.enable_empty_completion_retry,
+        empty_completion_retry_max_attempts: config.empty_completion_retry_max_attempts,
+        enable_incomplete_stream_retry: config.enable_incomplete_stream_retry,
+        incomplete_stream_retry_max_attempts: config.incomplete_stream_retry_max_attempts,
+        enable_sibling_tool_error_retry: config.enable_sibling_tool_error_retry,
+        prefer_codex_v1_path: config.prefer_codex_v1_path,
+        enable_codex_tool_schema_compaction: config.enable_codex_tool_schema_compaction,
+        enable_skill_routing_hint: config.enable_skill_routing_hint,
+        enable_stateful_responses_chain: config.enable_stateful_responses_chain,
+        client_tokens: config.client_tokens.clone(),
+        rate_limit: config.rate_limit.clone(),
+        model_routes,
+        pricing: config.pricing.clone(),
+        budgets: config.budgets.clone(),
+        load_balancer_runtime,
+    }
+}
+
+/// 使用配置构建 `ProxyServer`（不含负载均衡运行时，由调用方按 proxy_mode 追加）
+pub fn build_proxy_server(config: &ProxyConfig) -> ProxyServer {
+    let (target_url, api_key) = resolve_target_and_api_key(config);
+    let (
+        codex_target_url,
+        codex_api_key,
+        codex_converter,
+        image_generation_url,
+        image_generation_api_key,
+    ) = resolve_codex_target_api_key_and_converter(config);
+    let openai_max_tokens_mapping: OpenAIMaxTokensMapping = selected_endpoint(config)
+        .and_then(|ep| ep.openai_max_tokens_mapping.clone())
+        .map(|m| m.into())
+        .unwrap_or_default();
+
+    ProxyServer::new(config.port, target_url, api_key)
+        .with_reasoning_mapping(config.reasoning_effort.to_mapping())
+        .with_custom_injection_prompt(resolve_custom_injection_prompt(
+            &config.custom_injection_prompt,
+        ))
+        .with_converter(config.converter.clone())
+        .with_codex_model(config.codex_model.clone())
+        .with_codex_model_mapping(CodexModelMapping {
+            opus: config.codex_model_mapping.opus.clone(),
+            sonnet: config.codex_model_mapping.sonnet.clone(),
+            haiku: config.codex_model_mapping.haiku.clone(),
+        })
+        .with_anthropic_model_mapping(AnthropicModelMapping {
+            opus: config.anthropic_model_mapping.opus.clone(),
+            sonnet: config.anthropic_model_mapping.sonnet.clone(),
+            haiku: config.anthropic_model_mapping.haiku.clone(),
+        })
+        .with_openai_model_mapping(OpenAIModelMapping {
+            opus: config.openai_model_mapping.opus.clone(),
+            sonnet: config.openai_model_mapping.sonnet.clone(),
+            haiku: config.openai_model_mapping.haiku.clone(),
+        })
+        .with_openai_max_tokens_mapping(openai_max_tokens_mapping)
+        .with_gemini_reasoning_effort(config.gemini_reasoning_effort.to_gemini_mapping())
+        .with_ignore_probe_requests(config.ignore_probe_requests)
+        .with_allow_count_tokens_fallback_estimate(config.allow_count_tokens_fallback_estimate)
+        .with_enable_codex_fast_mode(config.enable_codex_fast_mode)
+        .with_force_stream_for_codex(config.force_stream_for_codex)
+        .with_enable_sse_frame_parser(config.enable_sse_frame_parser)
+        .with_enable_stream_heartbeat(config.enable_stream_heartbeat)
+        .with_stream_heartbeat_interval_ms(config.stream_heartbeat_interval_ms)
+        .with_enable_stream_log_sampling(config.enable_stream_log_sampling)
+        .with_stream_log_sample_every_n(config.stream_log_sample_every_n)
+        .with_stream_log_max_chars(config.stream_log_max_chars)
+        .with_enable_stream_metrics(config.enable_stream_metrics)
+        .with_enable_stream_event_metrics(config.enable_stream_event_metrics)
+        .with_stream_silence_warn_ms(config.stream_silence_warn_ms)
+        .with_stream_silence_error_ms(config.stream_silence_error_ms)
+        .with_enable_stall_retry(config.enable_stall_retry)
+        .with_stall_timeout_ms(config.stall_timeout_ms)
+        .with_stall_retry_max_attempts(config.stall_retry_max_attempts)
+        .with_stall_retry_only_heartbeat_phase(config.stall_retry_only_heartbeat_phase)
+        .with_enable_empty_completion_retry(config.enable_empty_completion_retry)
+        .with_empty_completion_retry_max_attempts(config.empty_completion_retry_max_attempts)
+        .with_enable_incomplete_stream_retry(config.enable_incomplete_stream_retry)
+        .with_incomplete_stream_retry_max_attempts(config.incomplete_stream_retry_max_attempts)
+        .with_enable_sibling_tool_error_retry(config.enable_sibling_tool_error_retry)
+        .with_prefer_codex_v1_path(config.prefer_codex_v1_path)
+        .with_enable_codex_tool_schema_compaction(config.enable_codex_tool_schema_compaction)
+        .with_enable_skill_routing_hint(config.enable_skill_routing_hint)
+        .with_enable_stateful_responses_chain(config.enable_stateful_responses_chain)
+        .with_client_tokens(config.client_tokens.clone())
+        .with_rate_limit(config.rate_limit.clone())
+        .with_model_routes(build_model_route_table(config))
+        .with_codex_route(
+            codex_target_url,
+            codex_api_key,
+            codex_converter,
+            image_generation_url,
+            image_generation_api_key,
+            config.codex_config.strip_image_generation_tool,
+        )
+        .with_allow_external_access(config.allow_external_access)
+        .with_max_concurrency(config.max_concurrency)
+        .with_max_queue_wait_ms(config.max_queue_wait_ms)
+        .with_usage_ledger(config.usage_ledger.clone())
+        .with_token_calibration(config.token_calibration.clone())
+        .with_session_store(config.session_store.clone())
+        .with_pricing(config.pricing.clone())
+        .with_budgets(config.budgets.clone())
+}
+
+/// 桌面端写入的配置文件路径：<config_dir>/com.codex.proxy/proxy-config.json
+pub fn default_config_path() -> Result<PathBuf, String> {
+    dirs::config_dir()
+        .map(|p| p.join("com.codex.proxy").join("proxy-config.json"))
+        .ok_or_else(|| "Cannot find config directory".to_string())
+}
+
+/// 读取配置文件；文件不存在时返回 None
+pub fn load_config_file(path: &Path) -> Result<Option<ProxyConfig>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let config: ProxyConfig = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+    Ok(Some(config))
+}
+
+/// 写入配置文件（自动创建父目录）
+pub fn save_config_file(path: &Path, config: &ProxyConfig) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let content = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
+    fs::write(path, content).map_err(|e| e.to_string())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_routes::UnmatchedModels;
+    use crate::transform::ClaudeCodeRequestKind;
+    use serde_json::json;
+
+    #[test]
+    fn custom_injection_prompt_uses_default_when_field_missing() {
+        let config: ProxyConfig = serde_json::from_value(json!({
+            "port": 8889,
+            "targetUrl": "http://127.0.0.1:3000",
+            "apiKey": "test-key"
+        }))
+        .expect("config should deserialize");
+
+        assert_eq!(
+            config.custom_injection_prompt,
+            DEFAULT_CUSTOM_INJECTION_PROMPT
+        );
+        assert!(
+            config.enable_codex_fast_mode,
+            "missing config field should default Codex fast mode to enabled"
+        );
+    }
+
+    #[test]
+    fn old_config_deserializes_with_default_codex_config() {
+        let config: ProxyConfig = serde_json::from_value(json!({
+            "port": 8889,
+            "targetUrl": "https://claude.example/messages",
+            "apiKey": "claude-key",
+            "endpointOptions": [{
+                "id": "claude-1",
+                "alias": "Claude",
+                "url": "https://claude.example/messages",
+                "apiKey": "claude-key"
+            }],
+            "selectedEndpointId": "claude-1"
+        }))
+        .expect("old config should deserialize");
+
+        assert_eq!(config.target_url, "https://claude.example/messages");
+        assert_eq!(config.api_key, "claude-key");
+        assert_eq!(config.selected_endpoint_id, "claude-1");
+        assert_eq!(config.codex_config.selected_endpoint_id, "codex-default");
+        assert_eq!(config.codex_config.converter, "codex");
+        assert_eq!(config.codex_config.endpoint_options.len(), 1);
+    }
+
+    #[test]
+    fn build_runtime_update_falls_back_for_blank_prompt_and_keeps_custom_prompt() {
+        let mut blank = default_proxy_config();
+        blank.target_url = "http://127.0.0.1:3000".to_string();
+        blank.custom_injection_prompt = "   ".to_string();
+        let blank_update = build_runtime_update(&blank, None);
+        assert_eq!(
+            blank_update.ctx.custom_injection_prompt,
+            DEFAULT_CUSTOM_INJECTION_PROMPT
+        );
+        assert!(blank_update.enable_codex_fast_mode);
+        assert!(blank_update.ctx.enable_codex_fast_mode);
+
+        let mut custom = default_proxy_config();
+        custom.target_url = "http://127.0.0.1:3000".to_string();
+        custom.custom_injection_prompt = "user custom prompt".to_string();
+        let custom_update = build_runtime_update(&custom, None);
+        assert_eq!(
+            custom_update.ctx.custom_injection_prompt,
+            "user custom prompt"
+        );
+
+        let mut disabled_fast = default_proxy_config();
+        disabled_fast.target_url = "http://127.0.0.1:3000".to_string();
+        disabled_fast.enable_codex_fast_mode = false;
+        let disabled_fast_update = build_runtime_update(&disabled_fast, None);
+        assert!(!disabled_fast_update.enable_codex_fast_mode);
+        assert!(!disabled_fast_update.ctx.enable_codex_fast_mode);
+    }
+
+    #[test]
+    fn build_runtime_update_keeps_codex_target_separate_and_uses_endpoint_converter() {
+        let mut config = default_proxy_config();
+        config.target_url = "https://claude.example/messages".to_string();
+        config.api_key = "claude-key".to_string();
+        config.endpoint_options = vec![EndpointOption {
+            id: "claude-1".to_string(),
+            alias: "Claude".to_string(),
+            url: "https://claude.example/messages".to_string(),
+            api_key: "claude-key".to_string(),
+            api_keys: Vec::new(),
+            key_rotation: None,
+            converter: Some("anthropic".to_string()),
+            codex_model: None,
+            codex_model_mapping: None,
+            codex_effort_capability_map: None,
+            gemini_model_preset: None,
+            anthropic_model_mapping: None,
+            openai_model_mapping: None,
+            openai_max_tokens_mapping: None,
+            reasoning_effort: None,
+            gemini_reasoning_effort: None,
+        }];
+        config.selected_endpoint_id = "claude-1".to_string();
+        config.codex_config.target_url = "https://codex.example/responses".to_string();
+        config.codex_config.api_key = "codex-key".to_string();
+        config.codex_config.converter = "gemini".to_string();
+        config.codex_config.endpoint_options = vec![EndpointOption {
+            id: "codex-1".to_string(),
+            alias: "Codex".to_string(),
+            url: "https://codex-selected.example/responses".to_string(),
+            api_key: "codex-selected-key".to_string(),
+            api_keys: Vec::new(),
+            key_rotation: None,
+            converter: Some("anthropic".to_string()),
+            codex_model: None,
+            codex_model_mapping: None,
+            codex_effort_capability_map: None,
+            gemini_model_preset: None,
+            anthropic_model_mapping: None,
+            openai_model_mapping: None,
+            openai_max_tokens_mapping: None,
+            reasoning_effort: None,
+            gemini_reasoning_effort: None,
+        }];
+        config.codex_config.selected_endpoint_id = "codex-1".to_string();
+
+        let update = build_runtime_update(&config, None);
+        assert_eq!(update.target_url, "https://claude.example/messages");
+        assert_eq!(update.api_key.as_deref(), Some("claude-key"));
+
+        let codex_route = update.codex_route.expect("codex route");
+        assert_eq!(
+            codex_route.target_url,
+            "https://codex-selected.example/responses"
+        );
+        assert_eq!(codex_route.api_key.as_deref(), Some("codex-selected-key"));
+        assert_eq!(codex_route.ctx.converter, "anthropic");
+
+        // 未声明 converter 的端点仍走 Codex 原生透传（忽略 codexConfig.converter）
+        config.codex_config.endpoint_options[0].converter = None;
+        let codex_route = build_runtime_update(&config, None)
+            .codex_route
+            .expect("codex route");
+        assert_eq!(codex_route.ctx.converter, "codex");
+    }
+
+    #[test]
+    fn load_config_file_builds_load_balancer_runtime_from_profiles() {
+        let path = std::env::temp_dir().join(format!(
+            "codex-proxy-config-test-{}.json",
+            std::process::id()
+        ));
+        let raw = json!({
+            "port": 9900,
+            "targetUrl": "https://a.example/responses",
+            "apiKey": "global-key",
+            "endpointOptions": [
+                { "id": "a", "alias": "A", "url": "https://a.example/responses", "apiKey": "" },
+                { "id": "b", "alias": "B", "url": "https://b.example/v1", "apiKey": "b-key", "converter": "openai" }
+            ],
+            "selectedEndpointId": "a",
+            "proxyMode": "load_balancer",
+            "enableStallRetry": true,
+            "loadBalancer": {
+                "lbProfiles": [{
+                    "id": "p1",
+                    "name": "Profile 1",
+                    "modelMapping": {
+                        "opus": [{ "endpointId": "a" }, { "endpointId": "b", "customModelName": "gpt-4.1" }],
+                        "sonnet": [{ "endpointId": "b" }],
+                        "haiku": []
+                    },
+                    "strategy": {
+                        "errorThreshold": 3,
+                        "errorWindowSeconds": 30,
+                        "cooldownSeconds": 60,
+                        "degradedConcurrency": 2
+                    }
+                }],
+                "selectedLbProfileId": "p1",
+                "lbEndpointConfigs": {}
+            }
+        });
+        fs::write(&path, raw.to_string()).expect("write config");
+
+        let config = load_config_file(&path)
+            .expect("config should load")
+            .expect("config should exist");
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(config.port, 9900);
+        assert!(config.enable_stall_retry);
+        let update = build_runtime_update(&config, None);
+        assert!(update.enable_stall_retry);
+        let runtime = update
+            .load_balancer_runtime
+            .expect("load balancer runtime should be built");
+        assert_eq!(runtime.candidate_count_for_model("claude-opus-4-6"), 2);
+        assert_eq!(runtime.candidate_count_for_model("claude-sonnet-4-6"), 1);
+
+        assert!(load_config_file(&path)
+            .expect("missing file is ok")
+            .is_none());
+    }
+
+    #[test]
+    fn endpoint_key_pool_merges_api_key_and_api_keys() {
+        let mut config = default_proxy_config();
+        config.endpoint_options = vec![serde_json::from_value(json!({
+            "id": "pool",
+            "alias": "Pool",
+            "url": "https://pool.example/responses",
+            "apiKey": "",
+            "apiKeys": ["k1", " ", "k2", "k1"],
+            "keyRotation": "least_used"
+        }))
+        .expect("endpoint should deserialize")];
+        config.selected_endpoint_id = "pool".to_string();
+
+        let endpoint = &config.endpoint_options[0];
+        assert_eq!(endpoint.key_pool(), ["k1", "k2"]);
+        // 单模型模式不轮换，使用池中第一个密钥
+        let (_, api_key) = resolve_target_and_api_key(&config);
+        assert_eq!(api_key.as_deref(), Some("k1"));
+
+        let serialized = serde_json::to_value(endpoint).unwrap();
+        assert_eq!(serialized["apiKeys"], json!(["k1", " ", "k2", "k1"]));
+        assert_eq!(serialized["keyRotation"], "least_used");
+    }
+
+    #[test]
+    fn model_routes_config_builds_table_and_reports_invalid_rules() {
+        let config: ProxyConfig = serde_json::from_value(json!({
+            "port": 8889,
+            "targetUrl": "http://127.0.0.1:3000",
+            "apiKey": "test-key",
+            "modelRoutes": {
+                "rules": [
+                    {"match": "*", "requestKind": "session_title", "route": "fast"},
+                    {"match": "gpt-5-codex", "route": "codex"},
+                    {"match": "(", "matchType": "regex", "route": "codex"},
+                    {"match": "x", "requestKind": "bogus", "route": "codex"},
+                    {"match": "gemini-*", "route": "missing"}
+                ],
+                "routes": [
+                    {"name": "fast", "slot": "haiku", "model": "gpt-5-mini", "maxTokens": 2048},
+                    {"name": "codex", "reasoningEffort": "xhigh"}
+                ]
+            }
+        }))
+        .expect("config should deserialize");
+
+        let table = build_model_route_table(&config);
+        assert_eq!(table.warnings().len(), 3);
+        let title = table
+            .resolve("claude-opus-4-6", ClaudeCodeRequestKind::SessionTitle)
+            .unwrap();
+        assert_eq!(title.name, "fast");
+        assert_eq!(title.slot, ModelSlot::Haiku);
+        assert_eq!(title.max_tokens, Some(2048));
+        let codex = table
+            .resolve("gpt-5-codex", ClaudeCodeRequestKind::ConversationTurn)
+            .unwrap();
+        // 未填 slot 时按路由名推断，推断不出归入 sonnet
+        assert_eq!(codex.slot, ModelSlot::Sonnet);
+        assert_eq!(codex.reasoning_effort, Some(ReasoningEffort::Xhigh));
+        assert!(table
+            .resolve("gemini-fast", ClaudeCodeRequestKind::ConversationTurn)
+            .is_none());
+
+        assert_eq!(table.unmatched(), &UnmatchedModels::SonnetSlot);
+
+        // 空配置不写回 modelRoutes
+        let serialized = serde_json::to_value(default_proxy_config()).unwrap();
+        assert!(serialized.get("modelRoutes").is_none());
+    }
+
+    #[test]
+    fn model_routes_config_sets_unmatched_model_handling() {
+        let config: ProxyConfig = serde_json::from_value(json!({
+            "port": 8889,
+            "targetUrl": "http://127.0.0.1:3000",
+            "apiKey": "test-key",
+            "modelRoutes": {
+                "routes": [{"name": "catch-all", "slot": "haiku"}],
+                "defaultRoute": " catch-all ",
+                "rejectUnmatched": true
+            }
+        }))
+        .expect("config should deserialize");
+        let table = build_model_route_table(&config);
+        assert_eq!(
+            table.unmatched(),
+            &UnmatchedModels::Route("catch-all".to_string())
+        );
+
+        let mut config = config;
+        config.model_routes.default_route = None;
+        assert_eq!(
+            build_model_route_table(&config).unmatched(),
+            &UnmatchedModels::Reject
+        );
+        let serialized = serde_json::to_value(&config).unwrap();
+        assert_eq!(serialized["modelRoutes"]["rejectUnmatched"], true);
+    }
+}
@@ -0,0 +1,252 @@
+//! 端点连通性测试：桌面端"测试端点"与负载均衡后台探测共用的最小请求
+//!
+//! 发送一条很短的流式消息，收到首个非空数据块即视为上游可用。
+
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::models::{AnthropicRequest, Message, MessageContent};
+use crate::transform::TransformBackend;
+
+pub const TEST_INPUT_MODEL: &str = "claude-sonnet-4-6";
+pub const TEST_PROMPT: &str = "Who are you?";
+/// 等待首个响应数据块的超时
+const FIRST_CHUNK_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// 测试用的最小 Anthropic 请求（流式、max_tokens=16）
+pub fn build_endpoint_test_request() -> AnthropicRequest {
+    build_endpoint_test_request_for_model(TEST_INPUT_MODEL)
+}
+
+/// 同上，但指定入站模型名（用于按 slot 解析映射后的上游模型）
+pub fn build_endpoint_test_request_for_model(model: &str) -> AnthropicRequest {
+    AnthropicRequest {
+        model: Some(model.to_string()),
+        messages: vec![Message {
+            role: "user".to_string(),
+            content: Some(MessageContent::Text(TEST_PROMPT.to_string())),
+        }],
+        system: None,
+        tools: None,
+        metadata: None,
+        tool_choice: None,
+        thinking: None,
+        stream: true,
+        max_tokens: Some(16),
+        temperature: None,
+        top_p: None,
+        top_k: None,
+        stop_sequences: None,
+    }
+}
+
+pub fn truncate_error_body(body: &str) -> String {
+    let trimmed = body.trim();
+    if trimmed.chars().count() <= 500 {
+        trimmed.to_string()
+    } else {
+        format!("{}...", trimmed.chars().take(500).collect::<String>())
+    }
+}
+
+/// 只保留 URL 的路径部分，便于日志展示
+pub fn describe_test_path(url: &str) -> String {
+    let without_query = url.split('?').next().unwrap_or(url);
+    if let Some(protocol_idx) = without_query.find("://") {
+        let after_scheme = &without_query[protocol_idx + 3..];
+        if let Some(path_idx) = after_scheme.find('/') {
+            return after_scheme[path_idx..].to_string();
+        }
+        return "/".to_string();
+    }
+    without_query.to_string()
+}
+
+fn build_test_failure_message(
+    status_code: u16,
+    reason: &str,
+    body_text: &str,
+    test_url: &str,
+) -> String {
+    let body_summary = truncate_error_body(body_text);
+    let path = describe_test_path(test_url);
+    let prefix = if status_code == 405 {
+        format!("HTTP {status_code} {reason}: 目标地址存在，但不接受当前测试路径/方法（{path}）")
+    } else {
+        format!("HTTP {status_code} {reason}: 测试路径 {path}")
+    };
+    if body_summary.is_empty() {
+        prefix
+    } else {
+        format!("{prefix}: {body_summary}")
+    }
+}
+
+async fn wait_for_first_response_chunk(response: reqwest::Response) -> Result<bool, String> {
+    let mut stream = response.bytes_stream();
+    for _ in 0..8 {
+        match tokio::time::timeout(FIRST_CHUNK_TIMEOUT, stream.next()).await {
+            Ok(Some(Ok(bytes))) => {
+                if !bytes.is_empty() {
+                    return Ok(true);
+                }
+            }
+            Ok(Some(Err(error))) => return Err(format!("读取响应失败: {error}")),
+            Ok(None) => return Ok(false),
+            Err(_) => return Err("等待模型响应超时".to_string()),
+        }
+    }
+    Ok(false)
+}
+
+pub enum EndpointTestAttempt {
+    Success {
+        response_time_ms: u64,
+        http_status: u16,
+    },
+    HttpFailure {
+        message: String,
+        response_time_ms: u64,
+        http_status: u16,
+        body_text: String,
+    },
+    TransportFailure {
+        message: String,
+        response_time_ms: u64,
+    },
+    EmptyResponse {
+        message: String,
+        response_time_ms: u64,
+        http_status: u16,
+    },
+}
+
+impl EndpointTestAttempt {
+    pub fn is_success(&self) -> bool {
+        matches!(self, EndpointTestAttempt::Success { .. })
+    }
+
+    pub fn http_status(&self) -> Option<u16> {
+        match self {
+            EndpointTestAttempt::Success { http_status, .. }
+            | EndpointTestAttempt::HttpFailure { http_status, .. }
+            | EndpointTestAttempt::EmptyResponse { http_status, .. } => Some(*http_status),
+            EndpointTestAttempt::TransportFailure { .. } => None,
+        }
+    }
+
+    /// 日志用的简短描述
+    pub fn summary(&self) -> String {
+        match self {
+            EndpointTestAttempt::Success {
+                response_time_ms,
+                http_status,
+            } => format!("ok status={} {}ms", http_status, response_time_ms),
+            EndpointTestAttempt::HttpFailure { message, .. }
+            | EndpointTestAttempt::TransportFailure { message, .. }
+            | EndpointTestAttempt::EmptyResponse { message, .. } => message.clone(),
+        }
+    }
+}
+
+/// 发送一次测试请求并等待首个数据块
+pub async fn run_endpoint_test_attempt(
+    client: &reqwest::Client,
+    backend: &Arc<dyn TransformBackend>,
+    target_url: &str,
+    api_key: &str,
+    body: &Value,
+    session_id: &str,
+) -> EndpointTestAttempt {
+    let started_at = Instant::now();
+    let response = match backend
+        .build_upstream_request(client, target_url, api_key, body, session_id, "2023-06-01")
+        .send()
+        .await
+    {
+        Ok(response) => response,
+        Err(error) => {
+            let elapsed = started_at.elapsed().as_millis() as u64;
+            let message = if error.is_timeout() {
+                "请求超时".to_string()
+            } else if error.is_connect() {
+                format!("网络连接失败: {error}")
+            } else {
+                format!("请求失败: {error}")
+            };
+            return EndpointTestAttempt::TransportFailure {
+                message,
+                response_time_ms: elapsed,
+            };
+        }
+    };
+
+    let status = response.status();
+    let status_code = status.as_u16();
+    if !status.is_success() {
+        let body_text = response.text().await.unwrap_or_default();
+        let reason = status.canonical_reason().unwrap_or("Upstream error");
+        let message = build_test_failure_message(status_code, reason, &body_text, target_url);
+        return EndpointTestAttempt::HttpFailure {
+            message,
+            response_time_ms: started_at.elapsed().as_millis() as u64,
+            http_status: status_code,
+            body_text,
+        };
+    }
+
+    match wait_for_first_response_chunk(response).await {
+        Ok(true) => EndpointTestAttempt::Success {
+            response_time_ms: started_at.elapsed().as_millis() as u64,
+            http_status: status_code,
+        },
+        Ok(false) => EndpointTestAttempt::EmptyResponse {
+            message: format!(
+                "上游连接成功，但未收到响应数据（测试路径 {}）",
+                describe_test_path(target_url)
+            ),
+            response_time_ms: started_at.elapsed().as_millis() as u64,
+            http_status: status_code,
+        },
+        Err(message) => EndpointTestAttempt::EmptyResponse {
+            message: format!("{}（测试路径 {}）", message, describe_test_path(target_url)),
+            response_time_ms: started_at.elapsed().as_millis() as u64,
+            http_status: status_code,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_test_path_strips_host_and_query() {
+        assert_eq!(
+            describe_test_path("https://api.example.com/v1/responses?key=1"),
+            "/v1/responses"
+        );
+        assert_eq!(describe_test_path("https://api.example.com"), "/");
+        assert_eq!(describe_test_path("/v1/messages"), "/v1/messages");
+    }
+
+    #[test]
+    fn test_request_is_short_streaming_message() {
+        let request = build_endpoint_test_request_for_model("claude-opus-4-6");
+        assert_eq!(request.model.as_deref(), Some("claude-opus-4-6"));
+        assert!(request.stream);
+        assert_eq!(request.max_tokens, Some(16));
+        assert_eq!(request.messages.len(), 1);
+    }
+
+    #[test]
+    fn failure_message_includes_truncated_body() {
+        let body = "x".repeat(600);
+        let message =
+            build_test_failure_message(500, "Internal Server Error", &body, "https://a/v1/x");
+        assert!(message.starts_with("HTTP 500 Internal Server Error: 测试路径 /v1/x: "));
+        assert!(message.ends_with("..."));
+    }
+}
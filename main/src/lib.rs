@@ -1,9 +1,17 @@
+pub mod budget;
+pub mod config;
+pub mod endpoint_test;
 pub mod load_balancer;
 pub mod logger;
+pub mod metrics;
+pub mod model_routes;
 pub mod models;
 mod prompts;
 mod server;
+pub mod token_calibration;
+pub mod tokenizer;
 pub mod transform;
+pub mod usage_ledger;
 
 pub use logger::{is_debug_log_enabled, set_debug_log, AppLogger};
 pub use models::{
@@ -11,7 +19,7 @@ pub use models::{
     GeminiReasoningEffortMapping, OpenAIMaxTokensMapping, OpenAIModelMapping, ReasoningEffort,
     ReasoningEffortMapping,
 };
-pub use server::{ProxyRuntimeHandle, ProxyServer, RuntimeConfigUpdate, RuntimeRouteUpdate};
+pub use server::{AdminApiConfig, ProxyRuntimeHandle, ProxyServer, RuntimeConfigUpdate, RuntimeRouteUpdate};
 pub use transform::codex::TransformResponse;
 pub use transform::{
     AnthropicAdapter, AnthropicBackend, CodexAdapter, GeminiAdapter, OpenAIChatAdapter,
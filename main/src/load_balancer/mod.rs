@@ -1,8 +1,27 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::broadcast;
 
+use crate::budget::{BudgetBreach, BudgetLevel, BudgetTracker};
+use crate::logger::mask_secret;
+
+/// 延迟 EWMA 的平滑系数（新样本权重）
+const LATENCY_EWMA_ALPHA: f64 = 0.3;
+/// 至少积累这么多样本才会按延迟降级
+const LATENCY_MIN_SAMPLES: u32 = 3;
+/// 降级后 EWMA 回落到阈值的该比例以下才恢复，避免来回抖动
+const LATENCY_RECOVERY_RATIO: f64 = 0.8;
+/// 估算"一次典型响应耗时"时假定的输出 token 数
+const LATENCY_REFERENCE_OUTPUT_TOKENS: f64 = 512.0;
+/// 其余策略下，同层路由的估算耗时超过最快者的该倍数才被排到层末
+const LATENCY_TIE_BREAK_RATIO: f64 = 1.5;
+/// lowest_latency 策略下约每 N 次选择探测一次非最快路由，让其统计保持新鲜
+const LATENCY_EXPLORE_ONE_IN: u64 = 20;
+/// 会话绑定表超过该规模时清理过期条目
+const SESSION_BINDING_PRUNE_THRESHOLD: usize = 4096;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProxyMode {
     Single,
@@ -38,6 +57,16 @@ impl ModelSlot {
         }
     }
 
+    /// 解析配置中的 slot 名（`opus` / `sonnet` / `haiku`），不做子串匹配
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "opus" => Some(Self::Opus),
+            "sonnet" => Some(Self::Sonnet),
+            "haiku" => Some(Self::Haiku),
+            _ => None,
+        }
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
             Self::Opus => "opus",
@@ -47,7 +76,7 @@ impl ModelSlot {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct SlotEndpointRef {
     pub endpoint_id: String,
     pub custom_model_name: Option<String>,
@@ -72,11 +101,93 @@ impl SlotMapping {
     }
 }
 
-#[derive(Debug, Clone)]
+/// slot 内候选路由的选择策略；先按 `EndpointPolicy.priority` 分层（数值小者优先），
+/// 同层内再按策略排序（除 priority 外，明显更慢的路由靠后），排在前面的不可用时依次尝试后面的
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelectionStrategy {
+    /// 严格按配置顺序故障转移
+    #[default]
+    Priority,
+    /// 按权重随机
+    WeightedRandom,
+    /// 平滑加权轮询
+    RoundRobin,
+    /// 在途请求数 / 权重 最小者优先
+    LeastInFlight,
+    /// 按观测到的 TTFB 与输出速度，估算耗时最短者优先
+    LowestLatency,
+}
+
+impl SelectionStrategy {
+    pub fn from_config(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "weighted_random" | "weighted" | "random" => Self::WeightedRandom,
+            "round_robin" | "weighted_round_robin" => Self::RoundRobin,
+            "least_in_flight" | "least_loaded" => Self::LeastInFlight,
+            "lowest_latency" | "latency" | "fastest" => Self::LowestLatency,
+            _ => Self::Priority,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Priority => "priority",
+            Self::WeightedRandom => "weighted_random",
+            Self::RoundRobin => "round_robin",
+            Self::LeastInFlight => "least_in_flight",
+            Self::LowestLatency => "lowest_latency",
+        }
+    }
+}
+
+/// 端点密钥池的轮换方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyRotation {
+    /// 按池内顺序轮流使用，跳过冷却中的密钥
+    #[default]
+    RoundRobin,
+    /// 在途请求数最少者优先，相同时取累计使用次数少者
+    LeastUsed,
+}
+
+impl KeyRotation {
+    pub fn from_config(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "least_used" | "least_in_flight" | "least_loaded" => Self::LeastUsed,
+            _ => Self::RoundRobin,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RoundRobin => "round_robin",
+            Self::LeastUsed => "least_used",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct LoadBalancerProfile {
     pub id: String,
     pub name: String,
     pub model_mapping: SlotMapping,
+    pub selection: SelectionStrategy,
+    /// 会话粘性：同一会话在该时长（秒）内固定使用同一路由以保持上游 prompt cache；0 表示关闭
+    pub session_affinity_ttl_seconds: u64,
+    /// 对冲请求：流式请求的首个候选超过该毫秒数仍无业务事件时，向下一个候选发出同样的请求；0 表示关闭
+    pub hedge_after_ms: u64,
+    /// 跨 slot 降级链（如 opus → sonnet → haiku）：主 slot 没有可用候选时依次尝试链上其后的 slot
+    pub degradation_chain: Vec<DegradationStep>,
+    /// 降级服务时在响应中附加 `x-codex-proxy-degraded-slot` 头
+    pub degradation_header: bool,
+}
+
+/// 跨 slot 降级链中的一步
+#[derive(Debug, Clone)]
+pub struct DegradationStep {
+    pub slot: ModelSlot,
+    /// 降级到该 slot 时使用的推理强度；为空时沿用候选自身的配置
+    pub reasoning_effort: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -88,6 +199,14 @@ pub struct EndpointPolicy {
     pub cooldown_seconds: u32,
     pub degraded_concurrency: u32,
     pub transient_backoff_seconds: u32,
+    /// 优先级层级，数值小者优先；同层内才按选择策略分流
+    pub priority: u32,
+    /// 同层内的分流权重（0 按 1 处理）
+    pub weight: u32,
+    /// TTFB 的 EWMA 超过该值（毫秒）时降为 Constrained；0 表示不按延迟降级
+    pub latency_threshold_ms: u32,
+    /// 后台探测冷却中/已停用路由的间隔（秒）；0 表示不探测
+    pub probe_interval_seconds: u32,
 }
 
 impl Default for EndpointPolicy {
@@ -100,23 +219,46 @@ impl Default for EndpointPolicy {
             cooldown_seconds: 3600,
             degraded_concurrency: 4,
             transient_backoff_seconds: 6,
+            priority: 0,
+            weight: 1,
+            latency_threshold_ms: 0,
+            probe_interval_seconds: 0,
         }
     }
 }
 
+impl EndpointPolicy {
+    fn effective_weight(&self) -> u64 {
+        self.weight.max(1) as u64
+    }
+}
+
+/// 模型路由表中带自有端点列表的命名路由（与 profile 无关，选择策略沿用当前 profile）
+#[derive(Debug, Clone)]
+pub struct NamedRoute {
+    pub name: String,
+    /// 路由键、状态日志与指标使用的 slot
+    pub slot: ModelSlot,
+    pub endpoints: Vec<SlotEndpointRef>,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct LoadBalancerConfig {
     pub selected_profile_id: Option<String>,
     pub profiles: Vec<LoadBalancerProfile>,
     pub endpoint_policies: HashMap<String, EndpointPolicy>,
+    pub named_routes: Vec<NamedRoute>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct LoadBalancerEndpoint {
     pub id: String,
     pub target_url: String,
     pub api_key: Option<String>,
     pub converter: String,
+    /// 密钥池；非空时每次请求从池中按 `key_rotation` 选一个密钥，`api_key` 只用于后台探测
+    pub api_keys: Vec<String>,
+    pub key_rotation: KeyRotation,
 }
 
 #[derive(Debug, Clone)]
@@ -130,6 +272,10 @@ pub struct ResolvedEndpoint {
     pub slot: ModelSlot,
     pub route_key: String,
     pub model_hint: String,
+    /// 本次选中的密钥在端点密钥池中的下标；未使用密钥池时为 None
+    pub key_index: Option<usize>,
+    /// 主 slot 没有可用候选、按降级链改用当前 slot 时为原 slot
+    pub degraded_from: Option<ModelSlot>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -137,6 +283,64 @@ pub enum EndpointHealth {
     Healthy,
     Constrained,
     Cooldown,
+    /// 冷却中的路由探测成功后的试探状态：只放行 1 个并发，下一次成功转 Healthy，失败回到 Cooldown
+    HalfOpen,
+}
+
+impl EndpointHealth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EndpointHealth::Healthy => "Healthy",
+            EndpointHealth::Constrained => "Constrained",
+            EndpointHealth::Cooldown => "Cooldown",
+            EndpointHealth::HalfOpen => "HalfOpen",
+        }
+    }
+}
+
+/// 只读状态快照：slot 内单个候选路由的当前健康与并发情况（不触发状态迁移）
+#[derive(Debug, Clone)]
+pub struct SlotCandidateStatus {
+    pub endpoint_id: String,
+    pub target_url: String,
+    pub converter: String,
+    pub model: Option<String>,
+    pub reasoning_effort: Option<String>,
+    pub route_key: String,
+    pub enabled: bool,
+    pub health: EndpointHealth,
+    pub cooldown_remaining_secs: Option<u64>,
+    pub backoff_remaining_secs: Option<u64>,
+    pub in_flight: u32,
+    pub max_concurrency: u32,
+    /// 观测到的 TTFB EWMA（毫秒），尚无样本时为 None
+    pub ttfb_ewma_ms: Option<u64>,
+    /// 观测到的输出速度 EWMA（token/s）
+    pub output_tokens_per_sec: Option<f64>,
+    /// 端点密钥池中各密钥的状态；未配置密钥池时为空
+    pub keys: Vec<KeyStatus>,
+    /// 端点已触达的花费预算（`soft` / `hard`）
+    pub budget: Option<&'static str>,
+}
+
+/// 只读状态快照：密钥池中单个密钥的使用情况
+#[derive(Debug, Clone)]
+pub struct KeyStatus {
+    /// 脱敏后的密钥
+    pub key: String,
+    pub in_flight: u32,
+    pub uses: u64,
+    pub cooldown_remaining_secs: Option<u64>,
+}
+
+impl SlotCandidateStatus {
+    /// 与 resolve_and_acquire 的判定一致：启用、未冷却、未退避、未打满（Constrained 时已按降级并发计算）
+    pub fn is_available(&self) -> bool {
+        self.enabled
+            && self.health != EndpointHealth::Cooldown
+            && self.backoff_remaining_secs.is_none()
+            && self.in_flight < self.max_concurrency
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -154,11 +358,57 @@ impl Default for EndpointState {
     }
 }
 
+/// 密钥池中单个密钥的运行时状态（按 `endpoint_id#下标` 存放）
+#[derive(Debug, Clone, Default)]
+struct KeyState {
+    in_flight: u32,
+    uses: u64,
+    cooldown_until: Option<Instant>,
+}
+
+impl KeyState {
+    fn is_cooling_down(&self, now: Instant) -> bool {
+        self.cooldown_until.is_some_and(|until| until > now)
+    }
+}
+
+/// 路由的延迟观测（EWMA）
+#[derive(Debug, Clone, Default)]
+struct RouteLatency {
+    ttfb_ms: Option<f64>,
+    tokens_per_sec: Option<f64>,
+    samples: u32,
+    /// 因延迟超阈值而降级
+    slow: bool,
+}
+
+impl RouteLatency {
+    fn ewma(previous: Option<f64>, sample: f64) -> f64 {
+        match previous {
+            Some(value) => value + LATENCY_EWMA_ALPHA * (sample - value),
+            None => sample,
+        }
+    }
+
+    /// 估算一次典型响应的耗时（毫秒）：TTFB + 参考输出量 / 输出速度
+    fn expected_ms(&self) -> Option<f64> {
+        let ttfb_ms = self.ttfb_ms?;
+        let generation_ms = self
+            .tokens_per_sec
+            .filter(|tps| *tps > 0.0)
+            .map(|tps| LATENCY_REFERENCE_OUTPUT_TOKENS / tps * 1000.0)
+            .unwrap_or(0.0);
+        Some(ttfb_ms + generation_ms)
+    }
+}
+
 #[derive(Debug, Clone)]
 struct RouteState {
     errors: VecDeque<Instant>,
     cooldown_until: Option<Instant>,
     health: EndpointHealth,
+    latency: RouteLatency,
+    last_probe_at: Option<Instant>,
 }
 
 impl Default for RouteState {
@@ -167,14 +417,38 @@ impl Default for RouteState {
             errors: VecDeque::new(),
             cooldown_until: None,
             health: EndpointHealth::Healthy,
+            latency: RouteLatency::default(),
+            last_probe_at: None,
         }
     }
 }
 
+/// 到期需要后台探测的路由
+#[derive(Debug, Clone)]
+pub struct ProbeTarget {
+    pub route: ResolvedEndpoint,
+    pub health: EndpointHealth,
+    /// 端点在配置中已停用：只记录探测结果，不会自动启用
+    pub enabled: bool,
+}
+
 #[derive(Debug, Default)]
 struct RuntimeState {
     by_endpoint: HashMap<String, EndpointState>,
     by_route: HashMap<String, RouteState>,
+    /// 平滑加权轮询的当前权重（按 route_key）
+    round_robin_weights: HashMap<String, i64>,
+    /// 会话绑定（`slot|session` -> 路由）
+    session_routes: HashMap<String, SessionBinding>,
+    by_key: HashMap<String, KeyState>,
+    /// 密钥池轮询游标（按 endpoint_id）
+    key_cursors: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone)]
+struct SessionBinding {
+    route_key: String,
+    expires_at: Instant,
 }
 
 #[derive(Debug, Clone)]
@@ -183,12 +457,47 @@ pub struct LoadBalancerRuntime {
     profile_index_by_id: HashMap<String, usize>,
     endpoint_directory: HashMap<String, LoadBalancerEndpoint>,
     state: Arc<Mutex<RuntimeState>>,
+    random_state: Arc<AtomicU64>,
     log_tx: Option<broadcast::Sender<String>>,
+    /// 端点花费预算：软预算降级排序，硬预算视同冷却
+    budget: Option<Arc<BudgetTracker>>,
+}
+
+/// resolve 过程中一个已通过目录与启用检查的候选
+struct RouteCandidate<'a> {
+    slot_ref: &'a SlotEndpointRef,
+    endpoint: &'a LoadBalancerEndpoint,
+    policy: EndpointPolicy,
+    converter: String,
+    model_hint: String,
+    route_key: String,
+}
+
+impl RouteCandidate<'_> {
+    fn into_resolved(self, slot: ModelSlot, key_index: Option<usize>) -> ResolvedEndpoint {
+        ResolvedEndpoint {
+            endpoint_id: self.slot_ref.endpoint_id.clone(),
+            target_url: self.endpoint.target_url.clone(),
+            api_key: key_index
+                .and_then(|index| self.endpoint.api_keys.get(index).cloned())
+                .or_else(|| self.endpoint.api_key.clone()),
+            converter: self.converter,
+            model: self.slot_ref.custom_model_name.clone(),
+            reasoning_effort: self.slot_ref.custom_reasoning_effort.clone(),
+            slot,
+            route_key: self.route_key,
+            model_hint: self.model_hint,
+            key_index,
+            degraded_from: None,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct EndpointPermit {
     endpoint_id: String,
+    /// 占用的密钥池密钥（`endpoint_id#下标`）
+    key_state: Option<String>,
     state: Arc<Mutex<RuntimeState>>,
 }
 
@@ -198,6 +507,13 @@ impl Drop for EndpointPermit {
             if let Some(endpoint_state) = guard.by_endpoint.get_mut(&self.endpoint_id) {
                 endpoint_state.in_flight = endpoint_state.in_flight.saturating_sub(1);
             }
+            if let Some(key_state) = self
+                .key_state
+                .as_ref()
+                .and_then(|key| guard.by_key.get_mut(key))
+            {
+                key_state.in_flight = key_state.in_flight.saturating_sub(1);
+            }
         }
     }
 }
@@ -207,6 +523,22 @@ enum AcquireRejectReason {
     RouteCooldown,
     EndpointBackoff,
     EndpointBusy,
+    /// 端点密钥池中的密钥全部冷却中
+    KeysCooldown,
+    /// 端点已触达硬预算
+    BudgetExhausted,
+}
+
+impl AcquireRejectReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AcquireRejectReason::RouteCooldown => "cooldown",
+            AcquireRejectReason::EndpointBackoff => "endpoint_backoff",
+            AcquireRejectReason::EndpointBusy => "in_flight_limit",
+            AcquireRejectReason::KeysCooldown => "key_pool_cooldown",
+            AcquireRejectReason::BudgetExhausted => "budget_exhausted",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -228,15 +560,34 @@ impl LoadBalancerRuntime {
             .map(|(index, profile)| (profile.id.clone(), index))
             .collect();
 
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or(0x9E37_79B9_7F4A_7C15);
+
         Self {
             config,
             profile_index_by_id,
             endpoint_directory,
             state: Arc::new(Mutex::new(RuntimeState::default())),
+            random_state: Arc::new(AtomicU64::new(seed)),
             log_tx,
+            budget: None,
         }
     }
 
+    /// 接入花费预算（配置热更新后由服务端重新挂载同一个 tracker）
+    pub fn with_budget_tracker(mut self, budget: Arc<BudgetTracker>) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    fn budget_breach(&self, endpoint_id: &str) -> Option<BudgetBreach> {
+        self.budget
+            .as_ref()
+            .and_then(|budget| budget.endpoint_breach(endpoint_id))
+    }
+
     fn send_log(&self, msg: String) {
         if let Some(ref tx) = self.log_tx {
             let _ = tx.send(msg);
@@ -274,58 +625,244 @@ impl LoadBalancerRuntime {
         &self,
         model_name: &str,
     ) -> Option<(ResolvedEndpoint, EndpointPermit)> {
-        let slot = ModelSlot::from_model_name(model_name);
+        self.resolve_and_acquire_for_session(model_name, None)
+    }
+
+    /// 带会话提示的选择：会话已绑定且路由可用时直接复用；绑定路由冷却/退避或被移除时
+    /// 重新选择并改绑（记录 cache 亲和中断），仅因并发打满时临时分流、不改绑
+    pub fn resolve_and_acquire_for_session(
+        &self,
+        model_name: &str,
+        session: Option<&str>,
+    ) -> Option<(ResolvedEndpoint, EndpointPermit)> {
+        self.resolve_and_acquire_in_slot(
+            ModelSlot::from_model_name(model_name),
+            model_name,
+            session,
+        )
+    }
+
+    /// 在指定 slot 的候选内选择（模型路由表命中、但路由没有自己的端点列表时使用）
+    pub fn resolve_and_acquire_in_slot(
+        &self,
+        slot: ModelSlot,
+        model_name: &str,
+        session: Option<&str>,
+    ) -> Option<(ResolvedEndpoint, EndpointPermit)> {
+        self.resolve_and_acquire_in_slot_allowing(slot, model_name, session, |_| true)
+    }
+
+    /// 同 `resolve_and_acquire_in_slot`，降级链只经过 `allow_slot` 允许的 slot（客户端的 allowedSlots）
+    pub fn resolve_and_acquire_in_slot_allowing(
+        &self,
+        slot: ModelSlot,
+        model_name: &str,
+        session: Option<&str>,
+        allow_slot: impl Fn(ModelSlot) -> bool,
+    ) -> Option<(ResolvedEndpoint, EndpointPermit)> {
         let profile = self.current_profile()?;
+        self.resolve_from_refs(
+            profile,
+            slot,
+            slot.as_str(),
+            profile.model_mapping.get(slot),
+            model_name,
+            session,
+        )
+        .or_else(|| self.resolve_degraded(profile, slot, model_name, allow_slot))
+    }
 
-        for candidate in profile.model_mapping.get(slot) {
-            let endpoint = match self.endpoint_directory.get(&candidate.endpoint_id) {
-                Some(endpoint) => endpoint,
-                None => {
-                    self.send_log(format!(
-                        "[LB] resolve endpoint_id={} not found in directory",
-                        candidate.endpoint_id
-                    ));
-                    continue;
-                }
+    /// 主 slot 没有可用候选时按降级链依次尝试其后的 slot；降级选择不读写会话绑定
+    fn resolve_degraded(
+        &self,
+        profile: &LoadBalancerProfile,
+        slot: ModelSlot,
+        model_name: &str,
+        allow_slot: impl Fn(ModelSlot) -> bool,
+    ) -> Option<(ResolvedEndpoint, EndpointPermit)> {
+        let start = profile
+            .degradation_chain
+            .iter()
+            .position(|step| step.slot == slot)?;
+        for step in &profile.degradation_chain[start + 1..] {
+            if !allow_slot(step.slot) {
+                continue;
+            }
+            let Some((mut resolved, permit)) = self.resolve_from_refs(
+                profile,
+                step.slot,
+                step.slot.as_str(),
+                profile.model_mapping.get(step.slot),
+                model_name,
+                None,
+            ) else {
+                continue;
             };
+            resolved.degraded_from = Some(slot);
+            if let Some(effort) = step.reasoning_effort.clone() {
+                resolved.reasoning_effort = Some(effort);
+            }
+            self.send_log(format!(
+                "[LB] degrade model={} from_slot={} to_slot={} endpoint_id={} route_key={} effort={}",
+                model_name,
+                slot.as_str(),
+                step.slot.as_str(),
+                resolved.endpoint_id,
+                resolved.route_key,
+                resolved.reasoning_effort.as_deref().unwrap_or("-"),
+            ));
+            crate::metrics::record_lb_degradation(slot.as_str(), step.slot.as_str());
+            return Some((resolved, permit));
+        }
+        None
+    }
 
-            let policy = self
-                .config
-                .endpoint_policies
-                .get(&candidate.endpoint_id)
-                .cloned()
-                .unwrap_or_default();
+    /// 当前 profile 是否要求在降级响应中附加 `x-codex-proxy-degraded-slot` 头
+    pub fn degradation_header_enabled(&self) -> bool {
+        self.current_profile()
+            .is_some_and(|profile| profile.degradation_header)
+    }
 
-            if !policy.enabled {
-                self.send_log(format!(
-                    "[LB] resolve endpoint_id={} skipped (disabled)",
-                    candidate.endpoint_id
-                ));
-                continue;
+    /// 在命名路由自己的端点列表内选择；选择策略与会话粘性沿用当前 profile
+    pub fn resolve_and_acquire_for_route(
+        &self,
+        route_name: &str,
+        model_name: &str,
+        session: Option<&str>,
+    ) -> Option<(ResolvedEndpoint, EndpointPermit)> {
+        let profile = self.current_profile()?;
+        let Some(route) = self.named_route(route_name) else {
+            self.send_log(format!(
+                "[LB] resolve model={} route={} not found",
+                model_name,
+                Self::sanitize_token(route_name)
+            ));
+            return None;
+        };
+        let scope = format!("route:{}", Self::sanitize_token(&route.name));
+        self.resolve_from_refs(
+            profile,
+            route.slot,
+            &scope,
+            &route.endpoints,
+            model_name,
+            session,
+        )
+    }
+
+    /// 为对冲请求或流内故障转移选择另一个候选：与原候选同一 slot / 命名路由，
+    /// 排除原候选所在端点，且不读写会话绑定（胜负未定时不改绑）
+    pub fn resolve_alternate_candidate(
+        &self,
+        current: &ResolvedEndpoint,
+        route_name: Option<&str>,
+        model_name: &str,
+    ) -> Option<(ResolvedEndpoint, EndpointPermit)> {
+        let profile = self.current_profile()?;
+        let (scope, refs) = match route_name.and_then(|name| self.named_route(name)) {
+            Some(route) => (
+                format!("route:{}", Self::sanitize_token(&route.name)),
+                route.endpoints.as_slice(),
+            ),
+            None => (
+                current.slot.as_str().to_string(),
+                profile.model_mapping.get(current.slot),
+            ),
+        };
+        let refs: Vec<SlotEndpointRef> = refs
+            .iter()
+            .filter(|candidate| candidate.endpoint_id != current.endpoint_id)
+            .cloned()
+            .collect();
+        self.resolve_from_refs(profile, current.slot, &scope, &refs, model_name, None)
+    }
+
+    /// 当前 profile 的对冲等待时长；未开启时为 None
+    pub fn hedge_delay(&self) -> Option<Duration> {
+        self.current_profile()
+            .map(|profile| profile.hedge_after_ms)
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis)
+    }
+
+    /// `scope` 为日志与会话绑定使用的分组名：slot 名或 `route:<名称>`
+    fn resolve_from_refs(
+        &self,
+        profile: &LoadBalancerProfile,
+        slot: ModelSlot,
+        scope: &str,
+        refs: &[SlotEndpointRef],
+        model_name: &str,
+        session: Option<&str>,
+    ) -> Option<(ResolvedEndpoint, EndpointPermit)> {
+        let candidates = self.collect_candidates(refs, slot);
+        let session_key = session
+            .map(str::trim)
+            .filter(|session| !session.is_empty() && profile.session_affinity_ttl_seconds > 0)
+            .map(|session| format!("{}|{}", scope, session));
+        let session_ttl = Duration::from_secs(profile.session_affinity_ttl_seconds);
+
+        let mut ordered = self.order_candidates(profile.selection, candidates);
+        let mut keep_binding = false;
+        if let Some(session_key) = session_key.as_deref() {
+            if let Some(bound_route) = self.session_route(session_key) {
+                match ordered.iter().position(|c| c.route_key == bound_route) {
+                    Some(index) => {
+                        let bound = ordered.remove(index);
+                        match self.acquire_candidate(&bound, slot) {
+                            Ok((permit, key_index)) => {
+                                self.bind_session(session_key, &bound.route_key, session_ttl);
+                                self.send_log(format!(
+                                    "[LB] resolve model={} slot={} -> endpoint_id={} url={} converter={} route_key={} session=sticky{}",
+                                    model_name,
+                                    scope,
+                                    bound.slot_ref.endpoint_id,
+                                    bound.endpoint.target_url,
+                                    bound.converter,
+                                    bound.route_key,
+                                    Self::describe_key(bound.endpoint, key_index),
+                                ));
+                                return Some((bound.into_resolved(slot, key_index), permit));
+                            }
+                            Err(AcquireRejectReason::EndpointBusy) => {
+                                keep_binding = true;
+                                self.send_log(format!(
+                                    "[LB] session_affinity_overflow session={} route_key={} reason=in_flight_limit",
+                                    Self::sanitize_token(session_key),
+                                    bound_route,
+                                ));
+                            }
+                            Err(reason) => {
+                                self.send_log(format!(
+                                    "[LB] session_affinity_broken session={} route_key={} reason={}",
+                                    Self::sanitize_token(session_key),
+                                    bound_route,
+                                    reason.as_str(),
+                                ));
+                            }
+                        }
+                    }
+                    None => {
+                        self.send_log(format!(
+                            "[LB] session_affinity_broken session={} route_key={} reason=route_removed",
+                            Self::sanitize_token(session_key),
+                            bound_route,
+                        ));
+                    }
+                }
             }
+        }
 
-            let converter = candidate
-                .converter_override
-                .clone()
-                .unwrap_or_else(|| endpoint.converter.clone());
-            let model_hint = Self::normalize_model_hint(candidate.custom_model_name.as_deref());
-            let route_key =
-                Self::build_route_key(slot, &candidate.endpoint_id, &converter, &model_hint);
-
-            match self.try_acquire_endpoint_for_route(
-                &candidate.endpoint_id,
-                &route_key,
-                &policy,
-                slot,
-                &converter,
-                &model_hint,
-            ) {
-                Ok(()) => {}
+        for candidate in ordered {
+            let route_key = candidate.route_key.as_str();
+            let endpoint_id = candidate.slot_ref.endpoint_id.as_str();
+            let (permit, key_index) = match self.acquire_candidate(&candidate, slot) {
+                Ok(acquired) => acquired,
                 Err(AcquireRejectReason::RouteCooldown) => {
                     self.send_log(format!(
                         "[LB] resolve endpoint_id={} slot={} route_key={} skipped (health=Cooldown)",
-                        candidate.endpoint_id,
-                        slot.as_str(),
+                        endpoint_id,
+                        scope,
                         route_key,
                     ));
                     continue;
@@ -333,8 +870,8 @@ impl LoadBalancerRuntime {
                 Err(AcquireRejectReason::EndpointBackoff) => {
                     self.send_log(format!(
                         "[LB] resolve endpoint_id={} slot={} route_key={} skipped (endpoint backoff)",
-                        candidate.endpoint_id,
-                        slot.as_str(),
+                        endpoint_id,
+                        scope,
                         route_key,
                     ));
                     continue;
@@ -342,51 +879,351 @@ impl LoadBalancerRuntime {
                 Err(AcquireRejectReason::EndpointBusy) => {
                     self.send_log(format!(
                         "[LB] resolve endpoint_id={} slot={} route_key={} skipped (in_flight limit reached)",
-                        candidate.endpoint_id,
-                        slot.as_str(),
+                        endpoint_id,
+                        scope,
                         route_key,
                     ));
                     continue;
                 }
-            }
+                Err(AcquireRejectReason::KeysCooldown) => {
+                    self.send_log(format!(
+                        "[LB] resolve endpoint_id={} slot={} route_key={} skipped (all keys cooling down)",
+                        endpoint_id,
+                        scope,
+                        route_key,
+                    ));
+                    continue;
+                }
+                Err(AcquireRejectReason::BudgetExhausted) => {
+                    self.send_log(format!(
+                        "[LB] resolve endpoint_id={} slot={} route_key={} skipped (budget exhausted)",
+                        endpoint_id,
+                        scope,
+                        route_key,
+                    ));
+                    continue;
+                }
+            };
 
             self.send_log(format!(
-                "[LB] resolve model={} slot={} -> endpoint_id={} url={} converter={} route_key={}",
+                "[LB] resolve model={} slot={} -> endpoint_id={} url={} converter={} route_key={} strategy={} priority={}{}",
                 model_name,
-                slot.as_str(),
-                candidate.endpoint_id,
-                endpoint.target_url,
-                converter,
+                scope,
+                endpoint_id,
+                candidate.endpoint.target_url,
+                candidate.converter,
                 route_key,
+                profile.selection.as_str(),
+                candidate.policy.priority,
+                Self::describe_key(candidate.endpoint, key_index),
             ));
 
-            let permit = EndpointPermit {
-                endpoint_id: candidate.endpoint_id.clone(),
-                state: Arc::clone(&self.state),
+            if let (Some(session_key), false) = (session_key.as_deref(), keep_binding) {
+                self.bind_session(session_key, route_key, session_ttl);
+            }
+
+            return Some((candidate.into_resolved(slot, key_index), permit));
+        }
+
+        self.send_log(format!(
+            "[LB] resolve failed model={} slot={} no available endpoint",
+            model_name, scope
+        ));
+        None
+    }
+
+    fn session_route(&self, session_key: &str) -> Option<String> {
+        let guard = self.state.lock().ok()?;
+        guard
+            .session_routes
+            .get(session_key)
+            .filter(|binding| binding.expires_at > Instant::now())
+            .map(|binding| binding.route_key.clone())
+    }
+
+    fn bind_session(&self, session_key: &str, route_key: &str, ttl: Duration) {
+        let Ok(mut guard) = self.state.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if guard.session_routes.len() >= SESSION_BINDING_PRUNE_THRESHOLD {
+            guard
+                .session_routes
+                .retain(|_, binding| binding.expires_at > now);
+        }
+        guard.session_routes.insert(
+            session_key.to_string(),
+            SessionBinding {
+                route_key: route_key.to_string(),
+                expires_at: now + ttl,
+            },
+        );
+    }
+
+    /// 过滤目录中不存在或已停用的端点，并计算 route_key
+    fn collect_candidates<'a>(
+        &'a self,
+        refs: &'a [SlotEndpointRef],
+        slot: ModelSlot,
+    ) -> Vec<RouteCandidate<'a>> {
+        let mut candidates = Vec::with_capacity(refs.len());
+        for slot_ref in refs {
+            let Some(endpoint) = self.endpoint_directory.get(&slot_ref.endpoint_id) else {
+                self.send_log(format!(
+                    "[LB] resolve endpoint_id={} not found in directory",
+                    slot_ref.endpoint_id
+                ));
+                continue;
             };
 
-            return Some((
-                ResolvedEndpoint {
-                    endpoint_id: candidate.endpoint_id.clone(),
-                    target_url: endpoint.target_url.clone(),
-                    api_key: endpoint.api_key.clone(),
-                    converter,
-                    model: candidate.custom_model_name.clone(),
-                    reasoning_effort: candidate.custom_reasoning_effort.clone(),
-                    slot,
-                    route_key,
-                    model_hint,
-                },
-                permit,
-            ));
+            let candidate = self.build_candidate(slot_ref, endpoint, slot);
+            if !candidate.policy.enabled {
+                self.send_log(format!(
+                    "[LB] resolve endpoint_id={} skipped (disabled)",
+                    slot_ref.endpoint_id
+                ));
+                continue;
+            }
+            candidates.push(candidate);
+        }
+        candidates
+    }
+
+    fn build_candidate<'a>(
+        &self,
+        slot_ref: &'a SlotEndpointRef,
+        endpoint: &'a LoadBalancerEndpoint,
+        slot: ModelSlot,
+    ) -> RouteCandidate<'a> {
+        let policy = self
+            .config
+            .endpoint_policies
+            .get(&slot_ref.endpoint_id)
+            .cloned()
+            .unwrap_or_default();
+        let converter = slot_ref
+            .converter_override
+            .clone()
+            .unwrap_or_else(|| endpoint.converter.clone());
+        let model_hint = Self::normalize_model_hint(slot_ref.custom_model_name.as_deref());
+        let route_key = Self::build_route_key(slot, &slot_ref.endpoint_id, &converter, &model_hint);
+        RouteCandidate {
+            slot_ref,
+            endpoint,
+            policy,
+            converter,
+            model_hint,
+            route_key,
+        }
+    }
+
+    /// 按优先级分层（层内保持配置顺序），再在每层内按选择策略排出尝试顺序
+    fn order_candidates<'a>(
+        &self,
+        strategy: SelectionStrategy,
+        mut candidates: Vec<RouteCandidate<'a>>,
+    ) -> Vec<RouteCandidate<'a>> {
+        candidates.sort_by_key(|candidate| candidate.policy.priority);
+        if strategy == SelectionStrategy::Priority {
+            return self.demote_over_soft_budget(candidates);
+        }
+
+        let mut ordered = Vec::with_capacity(candidates.len());
+        let mut remaining = candidates.into_iter().peekable();
+        while let Some(first) = remaining.next() {
+            let priority = first.policy.priority;
+            let mut tier = vec![first];
+            while let Some(next) = remaining.next_if(|next| next.policy.priority == priority) {
+                tier.push(next);
+            }
+            if tier.len() > 1 {
+                tier = match strategy {
+                    SelectionStrategy::Priority => tier,
+                    SelectionStrategy::WeightedRandom => self.order_weighted_random(tier),
+                    SelectionStrategy::RoundRobin => self.order_round_robin(tier),
+                    SelectionStrategy::LeastInFlight => self.order_least_in_flight(tier),
+                    SelectionStrategy::LowestLatency => self.order_lowest_latency(tier),
+                };
+                if strategy != SelectionStrategy::LowestLatency {
+                    tier = self.demote_slow_in_tier(tier);
+                }
+            }
+            ordered.extend(tier);
+        }
+        self.demote_over_soft_budget(ordered)
+    }
+
+    /// 同层内以延迟作为次要依据：估算耗时明显高于最快路由的排到层末（彼此按耗时升序），
+    /// 其余保持策略排出的顺序；样本不足的路由不参与比较
+    fn demote_slow_in_tier<'a>(&self, tier: Vec<RouteCandidate<'a>>) -> Vec<RouteCandidate<'a>> {
+        let expected: HashMap<String, f64> = match self.state.lock() {
+            Ok(guard) => tier
+                .iter()
+                .filter_map(|candidate| {
+                    let latency = &guard.by_route.get(&candidate.route_key)?.latency;
+                    if latency.samples < LATENCY_MIN_SAMPLES {
+                        return None;
+                    }
+                    Some((candidate.route_key.clone(), latency.expected_ms()?))
+                })
+                .collect(),
+            Err(_) => return tier,
+        };
+        let Some(fastest_ms) = expected.values().copied().reduce(f64::min) else {
+            return tier;
+        };
+        let limit_ms = fastest_ms * LATENCY_TIE_BREAK_RATIO;
+        let (mut ordered, mut slow): (Vec<_>, Vec<_>) = tier.into_iter().partition(|candidate| {
+            expected
+                .get(&candidate.route_key)
+                .is_none_or(|expected_ms| *expected_ms <= limit_ms)
+        });
+        slow.sort_by(|a, b| expected[&a.route_key].total_cmp(&expected[&b.route_key]));
+        ordered.extend(slow);
+        ordered
+    }
+
+    /// 触达软预算的端点整体排到最后，彼此之间保持已排好的顺序
+    fn demote_over_soft_budget<'a>(
+        &self,
+        candidates: Vec<RouteCandidate<'a>>,
+    ) -> Vec<RouteCandidate<'a>> {
+        if self.budget.is_none() {
+            return candidates;
+        }
+        let (mut within, over): (Vec<_>, Vec<_>) = candidates.into_iter().partition(|candidate| {
+            self.budget_breach(&candidate.slot_ref.endpoint_id)
+                .is_none_or(|breach| breach.level != BudgetLevel::Soft)
+        });
+        within.extend(over);
+        within
+    }
+
+    /// 按权重不放回抽样
+    fn order_weighted_random<'a>(
+        &self,
+        mut tier: Vec<RouteCandidate<'a>>,
+    ) -> Vec<RouteCandidate<'a>> {
+        let mut ordered = Vec::with_capacity(tier.len());
+        while !tier.is_empty() {
+            let total: u64 = tier.iter().map(|c| c.policy.effective_weight()).sum();
+            let mut pick = self.next_random() % total;
+            let mut index = 0;
+            for (i, candidate) in tier.iter().enumerate() {
+                let weight = candidate.policy.effective_weight();
+                if pick < weight {
+                    index = i;
+                    break;
+                }
+                pick -= weight;
+            }
+            ordered.push(tier.remove(index));
+        }
+        ordered
+    }
+
+    /// 平滑加权轮询（nginx 算法）：选中者排第一，其余保持配置顺序作为兜底
+    fn order_round_robin<'a>(&self, mut tier: Vec<RouteCandidate<'a>>) -> Vec<RouteCandidate<'a>> {
+        let Ok(mut guard) = self.state.lock() else {
+            return tier;
+        };
+        let total: i64 = tier
+            .iter()
+            .map(|c| c.policy.effective_weight() as i64)
+            .sum();
+        let mut best: Option<(usize, i64)> = None;
+        for (index, candidate) in tier.iter().enumerate() {
+            let current = guard
+                .round_robin_weights
+                .entry(candidate.route_key.clone())
+                .or_insert(0);
+            *current += candidate.policy.effective_weight() as i64;
+            if best.is_none_or(|(_, best_weight)| *current > best_weight) {
+                best = Some((index, *current));
+            }
+        }
+        if let Some((index, _)) = best {
+            if let Some(current) = guard.round_robin_weights.get_mut(&tier[index].route_key) {
+                *current -= total;
+            }
+            let picked = tier.remove(index);
+            tier.insert(0, picked);
+        }
+        tier
+    }
+
+    /// 在途数 / 权重 升序；相同时保持配置顺序
+    fn order_least_in_flight<'a>(
+        &self,
+        mut tier: Vec<RouteCandidate<'a>>,
+    ) -> Vec<RouteCandidate<'a>> {
+        let in_flight: HashMap<String, u32> = match self.state.lock() {
+            Ok(guard) => guard
+                .by_endpoint
+                .iter()
+                .map(|(id, state)| (id.clone(), state.in_flight))
+                .collect(),
+            Err(_) => return tier,
+        };
+        // 交叉相乘比较 in_flight_a / weight_a 与 in_flight_b / weight_b，避免浮点
+        tier.sort_by(|a, b| {
+            let load_a = *in_flight.get(&a.slot_ref.endpoint_id).unwrap_or(&0) as u64;
+            let load_b = *in_flight.get(&b.slot_ref.endpoint_id).unwrap_or(&0) as u64;
+            (load_a * b.policy.effective_weight()).cmp(&(load_b * a.policy.effective_weight()))
+        });
+        tier
+    }
+
+    /// 估算耗时升序；尚无样本的路由排在最前以便尽快测得，偶尔把次优路由提前做探测
+    fn order_lowest_latency<'a>(
+        &self,
+        mut tier: Vec<RouteCandidate<'a>>,
+    ) -> Vec<RouteCandidate<'a>> {
+        let expected: HashMap<String, Option<f64>> = match self.state.lock() {
+            Ok(guard) => tier
+                .iter()
+                .map(|candidate| {
+                    let expected_ms = guard
+                        .by_route
+                        .get(&candidate.route_key)
+                        .and_then(|state| state.latency.expected_ms());
+                    (candidate.route_key.clone(), expected_ms)
+                })
+                .collect(),
+            Err(_) => return tier,
+        };
+        tier.sort_by(|a, b| {
+            let a_ms = expected.get(&a.route_key).copied().flatten();
+            let b_ms = expected.get(&b.route_key).copied().flatten();
+            match (a_ms, b_ms) {
+                (None, None) => std::cmp::Ordering::Equal,
+                (None, Some(_)) => std::cmp::Ordering::Less,
+                (Some(_), None) => std::cmp::Ordering::Greater,
+                (Some(a_ms), Some(b_ms)) => a_ms.total_cmp(&b_ms),
+            }
+        });
+        // 只有全部路由都已测得时才需要探测次优路由
+        let all_measured = tier
+            .iter()
+            .all(|candidate| matches!(expected.get(&candidate.route_key), Some(Some(_))));
+        if tier.len() > 1
+            && all_measured
+            && self.next_random().is_multiple_of(LATENCY_EXPLORE_ONE_IN)
+        {
+            tier.swap(0, 1);
         }
+        tier
+    }
 
-        self.send_log(format!(
-            "[LB] resolve failed model={} slot={} no available endpoint",
-            model_name,
-            slot.as_str()
-        ));
-        None
+    /// splitmix64；只用于分流，不需要密码学强度
+    fn next_random(&self) -> u64 {
+        let mut z = self
+            .random_state
+            .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+            .wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
     }
 
     pub fn record_result(
@@ -420,9 +1257,39 @@ impl LoadBalancerRuntime {
             }
 
             if !Self::is_counted_error(status, network_error) {
+                let half_open_recovered = route_state.health == EndpointHealth::HalfOpen
+                    && status.is_some_and(|code| (200..=299).contains(&code));
+                if half_open_recovered {
+                    route_state.health = EndpointHealth::Healthy;
+                }
                 drop(guard);
 
+                if half_open_recovered {
+                    Self::record_transition(
+                        resolved,
+                        EndpointHealth::HalfOpen,
+                        EndpointHealth::Healthy,
+                    );
+                    self.send_log(format!(
+                        "[LB] route={} state=HalfOpen->Healthy (request succeeded)",
+                        resolved.route_key,
+                    ));
+                    self.send_route_status(
+                        resolved.slot,
+                        &resolved.endpoint_id,
+                        &resolved.converter,
+                        &resolved.model_hint,
+                        "available",
+                        "half_open_success",
+                        None,
+                    );
+                }
                 if became_available {
+                    Self::record_transition(
+                        resolved,
+                        EndpointHealth::Cooldown,
+                        EndpointHealth::Healthy,
+                    );
                     self.send_log(format!(
                         "[LB] route={} state=Cooldown->Healthy (cooldown expired)",
                         resolved.route_key,
@@ -443,7 +1310,12 @@ impl LoadBalancerRuntime {
             route_state.errors.push_back(now);
             Self::prune_errors(route_state, &policy, now);
 
-            if route_state.errors.len() as u32 >= policy.error_threshold {
+            if route_state.health == EndpointHealth::HalfOpen {
+                // 试探失败直接回到冷却
+                route_state.cooldown_until =
+                    Some(now + Duration::from_secs(policy.cooldown_seconds as u64));
+                route_state.health = EndpointHealth::Cooldown;
+            } else if route_state.errors.len() as u32 >= policy.error_threshold {
                 route_state.health = match route_state.health {
                     EndpointHealth::Healthy => EndpointHealth::Constrained,
                     EndpointHealth::Constrained => {
@@ -452,6 +1324,7 @@ impl LoadBalancerRuntime {
                         EndpointHealth::Cooldown
                     }
                     EndpointHealth::Cooldown => EndpointHealth::Cooldown,
+                    EndpointHealth::HalfOpen => EndpointHealth::HalfOpen,
                 };
             }
 
@@ -459,6 +1332,7 @@ impl LoadBalancerRuntime {
             drop(guard);
 
             if previous_health != current_health {
+                Self::record_transition(resolved, previous_health, current_health);
                 match (previous_health, current_health) {
                     (EndpointHealth::Healthy, EndpointHealth::Constrained) => {
                         self.send_log(format!(
@@ -467,7 +1341,8 @@ impl LoadBalancerRuntime {
                         ));
                     }
                     (EndpointHealth::Constrained, EndpointHealth::Cooldown)
-                    | (EndpointHealth::Healthy, EndpointHealth::Cooldown) => {
+                    | (EndpointHealth::Healthy, EndpointHealth::Cooldown)
+                    | (EndpointHealth::HalfOpen, EndpointHealth::Cooldown) => {
                         became_unavailable = true;
                         self.send_log(format!(
                             "[LB] route={} state={:?}->Cooldown cooldown_secs={}",
@@ -515,6 +1390,79 @@ impl LoadBalancerRuntime {
         }
     }
 
+    /// 流式响应在输出业务事件前卡住、空完成或中途断开：按网络错误计一次失败
+    pub fn record_stream_failure(&self, resolved: &ResolvedEndpoint, cause: &str) {
+        self.send_log(format!(
+            "[LB] route={} stream_failure cause={}",
+            resolved.route_key, cause
+        ));
+        self.record_result(resolved, None, true);
+    }
+
+    /// 记录一次成功请求的 TTFB 与输出速度，更新路由延迟 EWMA；
+    /// 超过 `latency_threshold_ms` 时降为 Constrained，回落后恢复
+    pub fn record_latency(
+        &self,
+        resolved: &ResolvedEndpoint,
+        ttfb: Duration,
+        output_tokens_per_sec: Option<f64>,
+    ) {
+        let policy = self
+            .config
+            .endpoint_policies
+            .get(&resolved.endpoint_id)
+            .cloned()
+            .unwrap_or_default();
+
+        let Ok(mut guard) = self.state.lock() else {
+            return;
+        };
+        let route_state = guard
+            .by_route
+            .entry(resolved.route_key.clone())
+            .or_insert_with(RouteState::default);
+        let latency = &mut route_state.latency;
+        let ttfb_ms = RouteLatency::ewma(latency.ttfb_ms, ttfb.as_secs_f64() * 1000.0);
+        latency.ttfb_ms = Some(ttfb_ms);
+        if let Some(tps) = output_tokens_per_sec.filter(|tps| tps.is_finite() && *tps > 0.0) {
+            latency.tokens_per_sec = Some(RouteLatency::ewma(latency.tokens_per_sec, tps));
+        }
+        latency.samples = latency.samples.saturating_add(1);
+
+        let threshold_ms = policy.latency_threshold_ms as f64;
+        let was_slow = latency.slow;
+        latency.slow = if policy.latency_threshold_ms == 0 {
+            false
+        } else if was_slow {
+            ttfb_ms >= threshold_ms * LATENCY_RECOVERY_RATIO
+        } else {
+            latency.samples >= LATENCY_MIN_SAMPLES && ttfb_ms > threshold_ms
+        };
+        if latency.slow == was_slow {
+            return;
+        }
+
+        let previous_health = route_state.health;
+        Self::refresh_route_state(route_state, &policy, Instant::now());
+        let current_health = route_state.health;
+        drop(guard);
+
+        self.send_log(format!(
+            "[LB] route={} latency_{} ttfb_ewma_ms={:.0} threshold_ms={}",
+            resolved.route_key,
+            if was_slow { "recovered" } else { "degraded" },
+            ttfb_ms,
+            policy.latency_threshold_ms,
+        ));
+        if previous_health != current_health {
+            Self::record_transition(resolved, previous_health, current_health);
+            self.send_log(format!(
+                "[LB] route={} state={:?}->{:?} reason=latency",
+                resolved.route_key, previous_health, current_health,
+            ));
+        }
+    }
+
     pub fn handle_upstream_outcome(
         &self,
         resolved: &ResolvedEndpoint,
@@ -580,6 +1528,10 @@ impl LoadBalancerRuntime {
                 .by_route
                 .entry(resolved.route_key.clone())
                 .or_insert_with(RouteState::default);
+            let previous_health = route_state.health;
+            if previous_health != EndpointHealth::Cooldown {
+                Self::record_transition(resolved, previous_health, EndpointHealth::Cooldown);
+            }
             route_state.health = EndpointHealth::Cooldown;
             route_state.cooldown_until =
                 Some(Instant::now() + Duration::from_secs(policy.cooldown_seconds as u64));
@@ -602,6 +1554,294 @@ impl LoadBalancerRuntime {
         );
     }
 
+    /// 密钥池端点返回 401/403/429 时只冷却本次使用的密钥：401/403 按端点冷却时长，
+    /// 429 按上游给出的重置时间（缺省为瞬时退避时长）。返回 false 表示未使用密钥池或池中
+    /// 已无其他可用密钥，调用方应按路由级结果处理
+    pub fn cool_down_key(
+        &self,
+        resolved: &ResolvedEndpoint,
+        status: u16,
+        retry_after_secs: Option<u64>,
+    ) -> bool {
+        if !matches!(status, 401 | 403 | 429) {
+            return false;
+        }
+        let Some(key_index) = resolved.key_index else {
+            return false;
+        };
+        let Some(endpoint) = self.endpoint_directory.get(&resolved.endpoint_id) else {
+            return false;
+        };
+        let Some(key) = endpoint.api_keys.get(key_index) else {
+            return false;
+        };
+        let policy = self
+            .config
+            .endpoint_policies
+            .get(&resolved.endpoint_id)
+            .cloned()
+            .unwrap_or_default();
+        let cooldown_secs = if status == 429 {
+            retry_after_secs.unwrap_or(policy.transient_backoff_seconds.max(1) as u64)
+        } else {
+            policy.cooldown_seconds as u64
+        };
+
+        let Ok(mut guard) = self.state.lock() else {
+            return false;
+        };
+        let now = Instant::now();
+        let others_available = (0..endpoint.api_keys.len())
+            .filter(|index| *index != key_index)
+            .any(|index| {
+                guard
+                    .by_key
+                    .get(&Self::key_state_id(&endpoint.id, index))
+                    .is_none_or(|state| !state.is_cooling_down(now))
+            });
+        if !others_available {
+            return false;
+        }
+        guard
+            .by_key
+            .entry(Self::key_state_id(&endpoint.id, key_index))
+            .or_default()
+            .cooldown_until = Some(now + Duration::from_secs(cooldown_secs));
+        drop(guard);
+
+        self.send_log(format!(
+            "[LB] endpoint={} key={}/{}:{} cooldown status={} cooldown_secs={}",
+            resolved.endpoint_id,
+            key_index + 1,
+            endpoint.api_keys.len(),
+            mask_secret(key),
+            status,
+            cooldown_secs,
+        ));
+        true
+    }
+
+    /// 当前 profile 中到期需要探测的路由：冷却中（自进入冷却或上次探测起满一个间隔）、
+    /// 试探中以及配置停用的端点；返回时即记为已探测，避免重复派发
+    pub fn due_probe_targets(&self) -> Vec<ProbeTarget> {
+        let Some(profile) = self.current_profile() else {
+            return Vec::new();
+        };
+        let Ok(mut guard) = self.state.lock() else {
+            return Vec::new();
+        };
+        let now = Instant::now();
+        let mut targets = Vec::new();
+
+        let mut seen_routes = HashSet::new();
+        for (slot, refs) in self.all_route_refs(profile) {
+            for slot_ref in refs {
+                let Some(endpoint) = self.endpoint_directory.get(&slot_ref.endpoint_id) else {
+                    continue;
+                };
+                let candidate = self.build_candidate(slot_ref, endpoint, slot);
+                if !seen_routes.insert(candidate.route_key.clone()) {
+                    continue;
+                }
+                let interval_secs = candidate.policy.probe_interval_seconds;
+                if interval_secs == 0 {
+                    continue;
+                }
+                let interval = Duration::from_secs(interval_secs as u64);
+                let route_state = guard
+                    .by_route
+                    .entry(candidate.route_key.clone())
+                    .or_insert_with(RouteState::default);
+                let enabled = candidate.policy.enabled;
+                let since = if enabled {
+                    Self::refresh_route_state(route_state, &candidate.policy, now);
+                    match route_state.health {
+                        EndpointHealth::Cooldown => route_state.last_probe_at.or_else(|| {
+                            let cooldown =
+                                Duration::from_secs(candidate.policy.cooldown_seconds as u64);
+                            route_state
+                                .cooldown_until
+                                .and_then(|until| until.checked_sub(cooldown))
+                        }),
+                        EndpointHealth::HalfOpen => route_state.last_probe_at,
+                        _ => continue,
+                    }
+                } else {
+                    route_state.last_probe_at
+                };
+                let health = route_state.health;
+                if since.is_some_and(|at| now.duration_since(at) < interval) {
+                    continue;
+                }
+                route_state.last_probe_at = Some(now);
+                targets.push(ProbeTarget {
+                    route: candidate.into_resolved(slot, None),
+                    health,
+                    enabled,
+                });
+            }
+        }
+        targets
+    }
+
+    /// 记录后台探测结果：Cooldown --成功--> HalfOpen --成功--> Healthy，HalfOpen 失败回到 Cooldown
+    pub fn record_probe_result(&self, resolved: &ResolvedEndpoint, success: bool, detail: &str) {
+        let policy = self
+            .config
+            .endpoint_policies
+            .get(&resolved.endpoint_id)
+            .cloned()
+            .unwrap_or_default();
+        let outcome = if success { "ok" } else { "failed" };
+
+        if !policy.enabled {
+            self.send_log(format!(
+                "[LB] route={} probe={} endpoint disabled, not re-enabled detail={}",
+                resolved.route_key,
+                outcome,
+                Self::sanitize_token(detail),
+            ));
+            return;
+        }
+
+        let (previous_health, current_health) = {
+            let Ok(mut guard) = self.state.lock() else {
+                return;
+            };
+            let route_state = guard
+                .by_route
+                .entry(resolved.route_key.clone())
+                .or_insert_with(RouteState::default);
+            let now = Instant::now();
+            Self::refresh_route_state(route_state, &policy, now);
+            let previous_health = route_state.health;
+            match (previous_health, success) {
+                (EndpointHealth::Cooldown, true) => {
+                    route_state.cooldown_until = None;
+                    route_state.errors.clear();
+                    route_state.health = EndpointHealth::HalfOpen;
+                }
+                (EndpointHealth::HalfOpen, true) => {
+                    route_state.health = EndpointHealth::Healthy;
+                }
+                (EndpointHealth::HalfOpen, false) => {
+                    route_state.cooldown_until =
+                        Some(now + Duration::from_secs(policy.cooldown_seconds as u64));
+                    route_state.health = EndpointHealth::Cooldown;
+                }
+                _ => {}
+            }
+            (previous_health, route_state.health)
+        };
+
+        self.send_log(format!(
+            "[LB] route={} probe={} state={:?}->{:?} detail={}",
+            resolved.route_key,
+            outcome,
+            previous_health,
+            current_health,
+            Self::sanitize_token(detail),
+        ));
+        if previous_health == current_health {
+            return;
+        }
+
+        Self::record_transition(resolved, previous_health, current_health);
+        let (state, reason, cooldown_secs) = match current_health {
+            EndpointHealth::HalfOpen => ("half_open", "probe_ok", None),
+            EndpointHealth::Cooldown => {
+                ("unavailable", "probe_failed", Some(policy.cooldown_seconds))
+            }
+            _ => ("available", "probe_recovered", None),
+        };
+        self.send_route_status(
+            resolved.slot,
+            &resolved.endpoint_id,
+            &resolved.converter,
+            &resolved.model_hint,
+            state,
+            reason,
+            cooldown_secs,
+        );
+    }
+
+    /// 清空所有路由的错误计数、冷却、端点瞬时退避与密钥冷却（在途计数保留）；返回被重置的条目数
+    pub fn clear_cooldowns(&self) -> usize {
+        let now = Instant::now();
+        let mut cleared = 0usize;
+        if let Ok(mut guard) = self.state.lock() {
+            for route_state in guard.by_route.values_mut() {
+                let cooling = route_state.cooldown_until.is_some_and(|until| until > now);
+                if cooling || route_state.health != EndpointHealth::Healthy {
+                    cleared += 1;
+                }
+                // 延迟统计保留，只撤销降级标记
+                let latency = RouteLatency {
+                    slow: false,
+                    ..route_state.latency.clone()
+                };
+                *route_state = RouteState {
+                    latency,
+                    ..RouteState::default()
+                };
+            }
+            for endpoint_state in guard.by_endpoint.values_mut() {
+                if endpoint_state
+                    .transient_backoff_until
+                    .is_some_and(|until| until > now)
+                {
+                    cleared += 1;
+                }
+                endpoint_state.transient_backoff_until = None;
+            }
+            for key_state in guard.by_key.values_mut() {
+                if key_state.is_cooling_down(now) {
+                    cleared += 1;
+                }
+                key_state.cooldown_until = None;
+            }
+        }
+        self.send_log(format!("[LB] cooldowns_cleared entries={}", cleared));
+        cleared
+    }
+
+    fn record_transition(resolved: &ResolvedEndpoint, from: EndpointHealth, to: EndpointHealth) {
+        crate::metrics::record_lb_transition(
+            resolved.slot.as_str(),
+            &resolved.endpoint_id,
+            from.as_str(),
+            to.as_str(),
+        );
+    }
+
+    pub fn selected_profile_id(&self) -> Option<&str> {
+        self.current_profile().map(|profile| profile.id.as_str())
+    }
+
+    fn named_route(&self, name: &str) -> Option<&NamedRoute> {
+        self.config
+            .named_routes
+            .iter()
+            .find(|route| route.name == name)
+    }
+
+    /// 当前 profile 的三个 slot 加上所有命名路由的候选列表
+    fn all_route_refs<'a>(
+        &'a self,
+        profile: &'a LoadBalancerProfile,
+    ) -> Vec<(ModelSlot, &'a [SlotEndpointRef])> {
+        [ModelSlot::Opus, ModelSlot::Sonnet, ModelSlot::Haiku]
+            .into_iter()
+            .map(|slot| (slot, profile.model_mapping.get(slot)))
+            .chain(
+                self.config
+                    .named_routes
+                    .iter()
+                    .map(|route| (route.slot, route.endpoints.as_slice())),
+            )
+            .collect()
+    }
+
     fn current_profile(&self) -> Option<&LoadBalancerProfile> {
         let selected_id = self.config.selected_profile_id.as_ref()?;
         let index = self.profile_index_by_id.get(selected_id)?;
@@ -609,12 +1849,279 @@ impl LoadBalancerRuntime {
     }
 
     pub fn candidate_count_for_model(&self, model_name: &str) -> usize {
-        let slot = ModelSlot::from_model_name(model_name);
+        self.candidate_count_in_slot(ModelSlot::from_model_name(model_name))
+    }
+
+    pub fn candidate_count_in_slot(&self, slot: ModelSlot) -> usize {
         self.current_profile()
-            .map(|profile| profile.model_mapping.get(slot).len())
+            .map(|profile| self.attempt_budget(profile.model_mapping.get(slot)))
+            .unwrap_or(0)
+    }
+
+    /// 命名路由的候选数；未选中 profile 时与 slot 一样视为 0
+    pub fn candidate_count_for_route(&self, route_name: &str) -> usize {
+        if self.current_profile().is_none() {
+            return 0;
+        }
+        self.named_route(route_name)
+            .map(|route| self.attempt_budget(&route.endpoints))
             .unwrap_or(0)
     }
 
+    /// 候选数：配置了密钥池的端点按密钥数计，使换密钥重试不占用其他候选的尝试次数
+    fn attempt_budget(&self, refs: &[SlotEndpointRef]) -> usize {
+        refs.iter()
+            .map(|candidate| {
+                self.endpoint_directory
+                    .get(&candidate.endpoint_id)
+                    .map(|endpoint| endpoint.api_keys.len().max(1))
+                    .unwrap_or(1)
+            })
+            .sum()
+    }
+
+    /// 当前 profile 下某 slot 的候选路由状态（按配置顺序）
+    pub fn slot_status(&self, slot: ModelSlot) -> Vec<SlotCandidateStatus> {
+        let Some(profile) = self.current_profile() else {
+            return Vec::new();
+        };
+        self.candidate_status(slot, profile.model_mapping.get(slot))
+    }
+
+    /// 命名路由的候选路由状态；未选中 profile 时与 slot 一样视为无候选
+    pub fn named_route_status(&self, route_name: &str) -> Vec<SlotCandidateStatus> {
+        if self.current_profile().is_none() {
+            return Vec::new();
+        }
+        self.named_route(route_name)
+            .map(|route| self.candidate_status(route.slot, &route.endpoints))
+            .unwrap_or_default()
+    }
+
+    /// 已配置的命名路由名称（按配置顺序）
+    pub fn named_route_names(&self) -> Vec<&str> {
+        self.config
+            .named_routes
+            .iter()
+            .map(|route| route.name.as_str())
+            .collect()
+    }
+
+    fn candidate_status(
+        &self,
+        slot: ModelSlot,
+        refs: &[SlotEndpointRef],
+    ) -> Vec<SlotCandidateStatus> {
+        let guard = self.state.lock().ok();
+        let now = Instant::now();
+
+        refs.iter()
+            .filter_map(|candidate| {
+                let endpoint = self.endpoint_directory.get(&candidate.endpoint_id)?;
+                let policy = self
+                    .config
+                    .endpoint_policies
+                    .get(&candidate.endpoint_id)
+                    .cloned()
+                    .unwrap_or_default();
+                let converter = candidate
+                    .converter_override
+                    .clone()
+                    .unwrap_or_else(|| endpoint.converter.clone());
+                let model_hint =
+                    Self::normalize_model_hint(candidate.custom_model_name.as_deref());
+                let route_key =
+                    Self::build_route_key(slot, &candidate.endpoint_id, &converter, &model_hint);
+
+                let route_state = guard.as_ref().and_then(|g| g.by_route.get(&route_key));
+                let endpoint_state = guard
+                    .as_ref()
+                    .and_then(|g| g.by_endpoint.get(&candidate.endpoint_id));
+                let budget = self.budget_breach(&candidate.endpoint_id);
+                let budget_reset_secs = budget
+                    .as_ref()
+                    .filter(|breach| breach.level == BudgetLevel::Hard)
+                    .map(|breach| breach.resets_in.as_secs().max(1));
+                let cooldown_remaining_secs = route_state
+                    .and_then(|state| state.cooldown_until)
+                    .filter(|until| *until > now)
+                    .map(|until| until.duration_since(now).as_secs().max(1))
+                    .max(budget_reset_secs);
+                let health = match route_state {
+                    _ if budget_reset_secs.is_some() => EndpointHealth::Cooldown,
+                    Some(_) if cooldown_remaining_secs.is_some() => EndpointHealth::Cooldown,
+                    Some(state) if state.health == EndpointHealth::HalfOpen => {
+                        EndpointHealth::HalfOpen
+                    }
+                    Some(state) => {
+                        let window = Duration::from_secs(policy.error_window_seconds as u64);
+                        let recent_errors = state
+                            .errors
+                            .iter()
+                            .filter(|at| now.duration_since(**at) <= window)
+                            .count() as u32;
+                        if recent_errors >= policy.error_threshold || state.latency.slow {
+                            EndpointHealth::Constrained
+                        } else if state.health == EndpointHealth::Cooldown {
+                            EndpointHealth::Healthy
+                        } else {
+                            state.health
+                        }
+                    }
+                    None => EndpointHealth::Healthy,
+                };
+                let max_concurrency = Self::allowed_concurrency(&policy, health);
+                let keys = endpoint
+                    .api_keys
+                    .iter()
+                    .enumerate()
+                    .map(|(index, key)| {
+                        let key_state = guard.as_ref().and_then(|g| {
+                            g.by_key
+                                .get(&Self::key_state_id(&candidate.endpoint_id, index))
+                        });
+                        KeyStatus {
+                            key: mask_secret(key),
+                            in_flight: key_state.map(|state| state.in_flight).unwrap_or(0),
+                            uses: key_state.map(|state| state.uses).unwrap_or(0),
+                            cooldown_remaining_secs: key_state
+                                .and_then(|state| state.cooldown_until)
+                                .filter(|until| *until > now)
+                                .map(|until| until.duration_since(now).as_secs().max(1)),
+                        }
+                    })
+                    .collect();
+
+                Some(SlotCandidateStatus {
+                    endpoint_id: candidate.endpoint_id.clone(),
+                    target_url: endpoint.target_url.clone(),
+                    converter,
+                    model: candidate.custom_model_name.clone(),
+                    reasoning_effort: candidate.custom_reasoning_effort.clone(),
+                    route_key,
+                    enabled: policy.enabled,
+                    health,
+                    cooldown_remaining_secs,
+                    backoff_remaining_secs: endpoint_state
+                        .and_then(|state| state.transient_backoff_until)
+                        .filter(|until| *until > now)
+                        .map(|until| until.duration_since(now).as_secs().max(1)),
+                    in_flight: endpoint_state.map(|state| state.in_flight).unwrap_or(0),
+                    max_concurrency,
+                    ttfb_ewma_ms: route_state
+                        .and_then(|state| state.latency.ttfb_ms)
+                        .map(|ms| ms.round() as u64),
+                    output_tokens_per_sec: route_state
+                        .and_then(|state| state.latency.tokens_per_sec),
+                    keys,
+                    budget: budget.map(|breach| breach.level.as_str()),
+                })
+            })
+            .collect()
+    }
+
+    /// 占用端点并发，端点配置了密钥池时再选出一个密钥；密钥全部冷却时释放端点并视为不可用
+    fn acquire_candidate(
+        &self,
+        candidate: &RouteCandidate<'_>,
+        slot: ModelSlot,
+    ) -> Result<(EndpointPermit, Option<usize>), AcquireRejectReason> {
+        let endpoint_id = candidate.slot_ref.endpoint_id.as_str();
+        if self
+            .budget_breach(endpoint_id)
+            .is_some_and(|breach| breach.level == BudgetLevel::Hard)
+        {
+            return Err(AcquireRejectReason::BudgetExhausted);
+        }
+        self.try_acquire_endpoint_for_route(
+            endpoint_id,
+            &candidate.route_key,
+            &candidate.policy,
+            slot,
+            &candidate.converter,
+            &candidate.model_hint,
+        )?;
+        let mut permit = EndpointPermit {
+            endpoint_id: endpoint_id.to_string(),
+            key_state: None,
+            state: Arc::clone(&self.state),
+        };
+        let key_index = self.acquire_key(candidate.endpoint)?;
+        permit.key_state = key_index.map(|index| Self::key_state_id(endpoint_id, index));
+        Ok((permit, key_index))
+    }
+
+    /// 按端点的轮换方式从密钥池中选出一个未冷却的密钥并计入在途数
+    fn acquire_key(
+        &self,
+        endpoint: &LoadBalancerEndpoint,
+    ) -> Result<Option<usize>, AcquireRejectReason> {
+        if endpoint.api_keys.is_empty() {
+            return Ok(None);
+        }
+        let Ok(mut guard) = self.state.lock() else {
+            return Err(AcquireRejectReason::EndpointBusy);
+        };
+        let now = Instant::now();
+        let available: Vec<usize> = (0..endpoint.api_keys.len())
+            .filter(|index| {
+                guard
+                    .by_key
+                    .get(&Self::key_state_id(&endpoint.id, *index))
+                    .is_none_or(|state| !state.is_cooling_down(now))
+            })
+            .collect();
+        let picked = match endpoint.key_rotation {
+            KeyRotation::RoundRobin => {
+                let cursor = guard.key_cursors.entry(endpoint.id.clone()).or_insert(0);
+                let picked = available
+                    .iter()
+                    .copied()
+                    .find(|index| *index >= *cursor)
+                    .or_else(|| available.first().copied());
+                if let Some(index) = picked {
+                    *cursor = (index + 1) % endpoint.api_keys.len();
+                }
+                picked
+            }
+            KeyRotation::LeastUsed => available.iter().copied().min_by_key(|index| {
+                guard
+                    .by_key
+                    .get(&Self::key_state_id(&endpoint.id, *index))
+                    .map(|state| (state.in_flight, state.uses))
+                    .unwrap_or((0, 0))
+            }),
+        };
+        let index = picked.ok_or(AcquireRejectReason::KeysCooldown)?;
+        let state = guard
+            .by_key
+            .entry(Self::key_state_id(&endpoint.id, index))
+            .or_default();
+        state.in_flight = state.in_flight.saturating_add(1);
+        state.uses = state.uses.saturating_add(1);
+        state.cooldown_until = None;
+        Ok(Some(index))
+    }
+
+    fn key_state_id(endpoint_id: &str, index: usize) -> String {
+        format!("{}#{}", endpoint_id, index)
+    }
+
+    /// resolve 日志中的密钥说明（脱敏）；未使用密钥池时为空
+    fn describe_key(endpoint: &LoadBalancerEndpoint, key_index: Option<usize>) -> String {
+        key_index
+            .and_then(|index| endpoint.api_keys.get(index).map(|key| (index, key)))
+            .map(|(index, key)| {
+                format!(
+                    " key={}/{}:{}",
+                    index + 1,
+                    endpoint.api_keys.len(),
+                    mask_secret(key)
+                )
+            })
+            .unwrap_or_default()
+    }
+
     fn try_acquire_endpoint_for_route(
         &self,
         endpoint_id: &str,
@@ -642,11 +2149,7 @@ impl LoadBalancerRuntime {
             if route_health == EndpointHealth::Cooldown {
                 result = Err(AcquireRejectReason::RouteCooldown);
             } else {
-                let allowed = if route_health == EndpointHealth::Constrained {
-                    policy.max_concurrency.min(policy.degraded_concurrency)
-                } else {
-                    policy.max_concurrency
-                };
+                let allowed = Self::allowed_concurrency(policy, route_health);
                 let endpoint_state = guard
                     .by_endpoint
                     .entry(endpoint_id.to_string())
@@ -686,6 +2189,14 @@ impl LoadBalancerRuntime {
         result
     }
 
+    fn allowed_concurrency(policy: &EndpointPolicy, health: EndpointHealth) -> u32 {
+        match health {
+            EndpointHealth::Constrained => policy.max_concurrency.min(policy.degraded_concurrency),
+            EndpointHealth::HalfOpen => policy.max_concurrency.min(1),
+            _ => policy.max_concurrency,
+        }
+    }
+
     fn refresh_route_state(
         route_state: &mut RouteState,
         policy: &EndpointPolicy,
@@ -703,7 +2214,13 @@ impl LoadBalancerRuntime {
             cooldown_expired = true;
         }
 
-        route_state.health = if route_state.errors.len() as u32 >= policy.error_threshold {
+        if route_state.health == EndpointHealth::HalfOpen {
+            return cooldown_expired;
+        }
+
+        route_state.health = if route_state.errors.len() as u32 >= policy.error_threshold
+            || route_state.latency.slow
+        {
             EndpointHealth::Constrained
         } else {
             EndpointHealth::Healthy
@@ -867,3 +2384,48 @@ impl LoadBalancerRuntime {
             .collect()
     }
 }
+
+/// crate 内单元测试共用的运行时构造
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::*;
+
+    pub(crate) fn slot_ref(endpoint_id: &str) -> SlotEndpointRef {
+        SlotEndpointRef {
+            endpoint_id: endpoint_id.to_string(),
+            ..SlotEndpointRef::default()
+        }
+    }
+
+    /// 只有一个 profile 的运行时；`endpoints` 为 (id, converter)，未配置策略的端点按默认策略
+    pub(crate) fn runtime(
+        profile: LoadBalancerProfile,
+        endpoints: &[(&str, &str)],
+        endpoint_policies: HashMap<String, EndpointPolicy>,
+    ) -> LoadBalancerRuntime {
+        let endpoint_directory = endpoints
+            .iter()
+            .map(|(id, converter)| {
+                (
+                    id.to_string(),
+                    LoadBalancerEndpoint {
+                        id: id.to_string(),
+                        target_url: format!("https://{}.example", id),
+                        converter: converter.to_string(),
+                        ..LoadBalancerEndpoint::default()
+                    },
+                )
+            })
+            .collect();
+        LoadBalancerRuntime::new(
+            LoadBalancerConfig {
+                selected_profile_id: Some(profile.id.clone()),
+                profiles: vec![profile],
+                endpoint_policies,
+                named_routes: Vec::new(),
+            },
+            endpoint_directory,
+            None,
+        )
+    }
+}
@@ -46,6 +46,20 @@ pub fn truncate_for_log(s: &str, max_len: usize) -> String {
     }
 }
 
+/// 脱敏：保留前 3 位与后 4 位，短值整体隐藏
+pub fn mask_secret(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.is_empty() {
+        return String::new();
+    }
+    if chars.len() <= 12 {
+        return "****".to_string();
+    }
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}****{}", head, tail)
+}
+
 /// 应用级日志记录器 - 每次启动应用一个日志文件
 pub struct AppLogger {
     log_path: PathBuf,
@@ -0,0 +1,490 @@
+//! 进程级 Prometheus 指标：计数器与直方图，`/metrics` 以文本格式导出
+//!
+//! 与日志广播通道并行：`log_tx` 上的 `[Metrics]` / `[StreamSummary]` / `[LB]` 仍保留，
+//! 这里只累计可聚合的数值，供本地 Prometheus 抓取与告警。
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Mutex, OnceLock};
+
+/// 时延类直方图的桶边界（秒）
+const LATENCY_BUCKETS: &[f64] = &[
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricKind {
+    Counter,
+    Histogram,
+}
+
+impl MetricKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+}
+
+struct MetricFamily {
+    name: &'static str,
+    help: &'static str,
+    kind: MetricKind,
+}
+
+const REQUESTS_TOTAL: &str = "codex_proxy_requests_total";
+const UPSTREAM_TTFB_SECONDS: &str = "codex_proxy_upstream_ttfb_seconds";
+const STREAM_DURATION_SECONDS: &str = "codex_proxy_stream_duration_seconds";
+const STREAM_CLOSE_TOTAL: &str = "codex_proxy_stream_close_total";
+const RETRIES_TOTAL: &str = "codex_proxy_retries_total";
+const TOKENS_TOTAL: &str = "codex_proxy_tokens_total";
+const LB_TRANSITIONS_TOTAL: &str = "codex_proxy_lb_transitions_total";
+const LB_HEDGES_TOTAL: &str = "codex_proxy_lb_hedges_total";
+const LB_DEGRADATIONS_TOTAL: &str = "codex_proxy_lb_degradations_total";
+const QUEUE_WAIT_SECONDS: &str = "codex_proxy_queue_wait_seconds";
+const QUEUE_TIMEOUTS_TOTAL: &str = "codex_proxy_queue_timeouts_total";
+const TOKEN_ESTIMATE_TOKENS_TOTAL: &str = "codex_proxy_token_estimate_tokens_total";
+
+const FAMILIES: &[MetricFamily] = &[
+    MetricFamily {
+        name: REQUESTS_TOTAL,
+        help: "Proxied requests by client route, client, converter, endpoint and response status.",
+        kind: MetricKind::Counter,
+    },
+    MetricFamily {
+        name: UPSTREAM_TTFB_SECONDS,
+        help: "Time from request start to the first upstream response byte.",
+        kind: MetricKind::Histogram,
+    },
+    MetricFamily {
+        name: STREAM_DURATION_SECONDS,
+        help: "Total duration of streamed responses.",
+        kind: MetricKind::Histogram,
+    },
+    MetricFamily {
+        name: STREAM_CLOSE_TOTAL,
+        help: "Streamed responses by close cause.",
+        kind: MetricKind::Counter,
+    },
+    MetricFamily {
+        name: RETRIES_TOTAL,
+        help: "Upstream retries by kind.",
+        kind: MetricKind::Counter,
+    },
+    MetricFamily {
+        name: TOKENS_TOTAL,
+        help: "Tokens reported by upstream usage, by type (input/output/cached_input).",
+        kind: MetricKind::Counter,
+    },
+    MetricFamily {
+        name: LB_TRANSITIONS_TOTAL,
+        help: "Load balancer route state transitions.",
+        kind: MetricKind::Counter,
+    },
+    MetricFamily {
+        name: LB_HEDGES_TOTAL,
+        help: "Hedged streaming requests by outcome (started/primary_won/hedge_won/hedge_unavailable).",
+        kind: MetricKind::Counter,
+    },
+    MetricFamily {
+        name: LB_DEGRADATIONS_TOTAL,
+        help: "Requests routed to a fallback slot because the primary slot had no available route.",
+        kind: MetricKind::Counter,
+    },
+    MetricFamily {
+        name: QUEUE_WAIT_SECONDS,
+        help: "Time requests waited for a concurrency permit, by priority.",
+        kind: MetricKind::Histogram,
+    },
+    MetricFamily {
+        name: QUEUE_TIMEOUTS_TOTAL,
+        help: "Requests rejected with overloaded_error after exceeding the maximum queue wait.",
+        kind: MetricKind::Counter,
+    },
+    MetricFamily {
+        name: TOKEN_ESTIMATE_TOKENS_TOTAL,
+        help: "Local input token estimates compared with upstream usage, by kind (estimated/calibrated/actual/abs_error).",
+        kind: MetricKind::Counter,
+    },
+];
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (index, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if value <= *bound {
+                self.buckets[index] += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// 指标存储；按 (指标名, 已编码的标签) 聚合
+#[derive(Default)]
+pub struct MetricsRegistry {
+    counters: Mutex<BTreeMap<(&'static str, String), u64>>,
+    histograms: Mutex<BTreeMap<(&'static str, String), Histogram>>,
+}
+
+static METRICS: OnceLock<MetricsRegistry> = OnceLock::new();
+
+/// 全局指标注册表
+pub fn global() -> &'static MetricsRegistry {
+    METRICS.get_or_init(MetricsRegistry::default)
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn encode_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn with_extra_label(labels: &str, extra: &str) -> String {
+    if labels.is_empty() {
+        format!("{{{}}}", extra)
+    } else {
+        format!("{{{},{}}}", labels, extra)
+    }
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_infinite() {
+        "+Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+impl MetricsRegistry {
+    fn inc(&self, name: &'static str, labels: &[(&str, &str)], value: u64) {
+        if let Ok(mut counters) = self.counters.lock() {
+            *counters.entry((name, encode_labels(labels))).or_insert(0) += value;
+        }
+    }
+
+    fn observe(&self, name: &'static str, labels: &[(&str, &str)], seconds: f64) {
+        if !seconds.is_finite() || seconds < 0.0 {
+            return;
+        }
+        if let Ok(mut histograms) = self.histograms.lock() {
+            histograms
+                .entry((name, encode_labels(labels)))
+                .or_insert_with(Histogram::new)
+                .observe(seconds);
+        }
+    }
+
+    /// 读取单个计数器（主要用于测试与状态页）
+    pub fn counter_value(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        let key = encode_labels(labels);
+        self.counters
+            .lock()
+            .ok()
+            .and_then(|counters| {
+                counters
+                    .iter()
+                    .find(|((n, l), _)| *n == name && *l == key)
+                    .map(|(_, value)| *value)
+            })
+            .unwrap_or(0)
+    }
+
+    /// 按 Prometheus 文本格式（0.0.4）导出全部指标
+    pub fn render(&self) -> String {
+        let counters = self
+            .counters
+            .lock()
+            .map(|guard| guard.clone())
+            .unwrap_or_default();
+        let histograms = self
+            .histograms
+            .lock()
+            .map(|guard| guard.clone())
+            .unwrap_or_default();
+
+        let mut out = String::new();
+        for family in FAMILIES {
+            let _ = writeln!(out, "# HELP {} {}", family.name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", family.name, family.kind.as_str());
+            match family.kind {
+                MetricKind::Counter => {
+                    for ((name, labels), value) in &counters {
+                        if *name != family.name {
+                            continue;
+                        }
+                        if labels.is_empty() {
+                            let _ = writeln!(out, "{} {}", name, value);
+                        } else {
+                            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+                        }
+                    }
+                }
+                MetricKind::Histogram => {
+                    for ((name, labels), histogram) in &histograms {
+                        if *name != family.name {
+                            continue;
+                        }
+                        for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+                            let le = format!("le=\"{}\"", format_value(*bound));
+                            let _ = writeln!(
+                                out,
+                                "{}_bucket{} {}",
+                                name,
+                                with_extra_label(labels, &le),
+                                count
+                            );
+                        }
+                        let _ = writeln!(
+                            out,
+                            "{}_bucket{} {}",
+                            name,
+                            with_extra_label(labels, "le=\"+Inf\""),
+                            histogram.count
+                        );
+                        let braced = if labels.is_empty() {
+                            String::new()
+                        } else {
+                            format!("{{{}}}", labels)
+                        };
+                        let _ = writeln!(out, "{}_sum{} {}", name, braced, histogram.sum);
+                        let _ = writeln!(out, "{}_count{} {}", name, braced, histogram.count);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// 记录一次完成的请求（流式请求按响应头状态计入）
+pub fn record_request(
+    route: &str,
+    client: &str,
+    kind: &str,
+    converter: &str,
+    endpoint: &str,
+    status: u16,
+) {
+    let status = status.to_string();
+    global().inc(
+        REQUESTS_TOTAL,
+        &[
+            ("route", route),
+            ("client", client),
+            ("kind", kind),
+            ("converter", converter),
+            ("endpoint", endpoint),
+            ("status", &status),
+        ],
+        1,
+    );
+}
+
+/// 记录上游首字节时延
+pub fn observe_upstream_ttfb(route: &str, converter: &str, endpoint: &str, seconds: f64) {
+    global().observe(
+        UPSTREAM_TTFB_SECONDS,
+        &[
+            ("route", route),
+            ("converter", converter),
+            ("endpoint", endpoint),
+        ],
+        seconds,
+    );
+}
+
+/// 记录流式响应的总时长与关闭原因
+pub fn record_stream_end(
+    route: &str,
+    client: &str,
+    converter: &str,
+    endpoint: &str,
+    close_cause: &str,
+    seconds: f64,
+) {
+    let labels = [
+        ("route", route),
+        ("converter", converter),
+        ("endpoint", endpoint),
+    ];
+    global().observe(STREAM_DURATION_SECONDS, &labels, seconds);
+    global().inc(
+        STREAM_CLOSE_TOTAL,
+        &[
+            ("route", route),
+            ("client", client),
+            ("converter", converter),
+            ("cause", close_cause),
+        ],
+        1,
+    );
+}
+
+/// 记录一次上游重试；`kind` 与日志中的重试标签一致（如 `stream_retry`、`lb_failover`）
+pub fn record_retry(kind: &str) {
+    global().inc(RETRIES_TOTAL, &[("kind", kind)], 1);
+}
+
+/// 记录上游 usage 中的 token 数
+pub fn record_tokens(
+    route: &str,
+    client: &str,
+    converter: &str,
+    input_tokens: u64,
+    output_tokens: u64,
+    cached_input_tokens: u64,
+) {
+    for (token_type, value) in [
+        ("input", input_tokens),
+        ("output", output_tokens),
+        ("cached_input", cached_input_tokens),
+    ] {
+        if value > 0 {
+            global().inc(
+                TOKENS_TOTAL,
+                &[
+                    ("route", route),
+                    ("client", client),
+                    ("converter", converter),
+                    ("type", token_type),
+                ],
+                value,
+            );
+        }
+    }
+}
+
+/// 记录负载均衡路由状态迁移
+pub fn record_lb_transition(slot: &str, endpoint: &str, from: &str, to: &str) {
+    global().inc(
+        LB_TRANSITIONS_TOTAL,
+        &[
+            ("slot", slot),
+            ("endpoint", endpoint),
+            ("from", from),
+            ("to", to),
+        ],
+        1,
+    );
+}
+
+/// 记录对冲请求的发起与胜负
+pub fn record_hedge(slot: &str, outcome: &str) {
+    global().inc(LB_HEDGES_TOTAL, &[("slot", slot), ("outcome", outcome)], 1);
+}
+
+/// 记录一次跨 slot 降级选择
+pub fn record_lb_degradation(from: &str, to: &str) {
+    global().inc(LB_DEGRADATIONS_TOTAL, &[("from", from), ("to", to)], 1);
+}
+
+/// 记录请求等待并发名额的时长
+pub fn observe_queue_wait(priority: &str, seconds: f64) {
+    global().observe(QUEUE_WAIT_SECONDS, &[("priority", priority)], seconds);
+}
+
+/// 记录一次排队超时
+pub fn record_queue_timeout(priority: &str) {
+    global().inc(QUEUE_TIMEOUTS_TOTAL, &[("priority", priority)], 1);
+}
+
+/// 记录一次本地估算与上游实际输入的对比；`abs_error` 为校准后估算的绝对误差
+pub fn record_token_estimate(
+    endpoint: &str,
+    family: &str,
+    estimated: u64,
+    calibrated: u64,
+    actual: u64,
+) {
+    let abs_error = calibrated.abs_diff(actual);
+    for (kind, value) in [
+        ("estimated", estimated),
+        ("calibrated", calibrated),
+        ("actual", actual),
+        ("abs_error", abs_error),
+    ] {
+        global().inc(
+            TOKEN_ESTIMATE_TOKENS_TOTAL,
+            &[("endpoint", endpoint), ("family", family), ("kind", kind)],
+            value,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_emits_counters_and_cumulative_histogram_buckets() {
+        let registry = MetricsRegistry::default();
+        registry.inc(RETRIES_TOTAL, &[("kind", "stream_retry")], 2);
+        registry.observe(
+            UPSTREAM_TTFB_SECONDS,
+            &[("route", "claude"), ("converter", "codex")],
+            0.3,
+        );
+        registry.observe(
+            UPSTREAM_TTFB_SECONDS,
+            &[("route", "claude"), ("converter", "codex")],
+            7.0,
+        );
+
+        let text = registry.render();
+        assert!(text.contains("# TYPE codex_proxy_retries_total counter"));
+        assert!(text.contains("codex_proxy_retries_total{kind=\"stream_retry\"} 2"));
+        assert!(text.contains(
+            "codex_proxy_upstream_ttfb_seconds_bucket{route=\"claude\",converter=\"codex\",le=\"0.25\"} 0"
+        ));
+        assert!(text.contains(
+            "codex_proxy_upstream_ttfb_seconds_bucket{route=\"claude\",converter=\"codex\",le=\"0.5\"} 1"
+        ));
+        assert!(text.contains(
+            "codex_proxy_upstream_ttfb_seconds_bucket{route=\"claude\",converter=\"codex\",le=\"10\"} 2"
+        ));
+        assert!(text.contains(
+            "codex_proxy_upstream_ttfb_seconds_bucket{route=\"claude\",converter=\"codex\",le=\"+Inf\"} 2"
+        ));
+        assert!(text.contains(
+            "codex_proxy_upstream_ttfb_seconds_count{route=\"claude\",converter=\"codex\"} 2"
+        ));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let registry = MetricsRegistry::default();
+        registry.inc(STREAM_CLOSE_TOTAL, &[("cause", "a\"b\\c\nd")], 1);
+        assert!(registry
+            .render()
+            .contains("codex_proxy_stream_close_total{cause=\"a\\\"b\\\\c\\nd\"} 1"));
+        assert_eq!(
+            registry.counter_value(STREAM_CLOSE_TOTAL, &[("cause", "a\"b\\c\nd")]),
+            1
+        );
+    }
+}
@@ -0,0 +1,421 @@
+//! 模型路由表：按顺序匹配客户端模型名（可附加 Claude Code 请求类型），命中后使用对应的命名路由
+//!
+//! 命名路由可以指定自己的端点列表（负载均衡模式）、上游模型、推理强度与 max_tokens，
+//! 让 `gpt-5-codex`、`gemini-fast` 这类自定义模型 id 直接寻址；未命中任何规则时仍按
+//! 模型名中的 opus / sonnet / haiku 归入固定 slot，三者都不含的模型按 [`UnmatchedModels`] 处理。
+
+use regex::Regex;
+use std::collections::HashMap;
+
+use crate::load_balancer::ModelSlot;
+use crate::models::{OpenAIMaxTokensMapping, ReasoningEffort};
+use crate::transform::{ClaudeCodeRequestKind, TransformContext};
+
+/// 客户端模型名的匹配方式（均不区分大小写）
+#[derive(Debug, Clone)]
+pub enum ModelMatcher {
+    Exact(String),
+    /// `*` 匹配任意长度，`?` 匹配单个字符
+    Glob(String),
+    Regex(Regex),
+}
+
+impl ModelMatcher {
+    /// `match_type` 为 `exact` / `glob` / `regex`；留空时含 `*` 或 `?` 的按 glob，否则精确匹配
+    pub fn parse(pattern: &str, match_type: Option<&str>) -> Result<Self, String> {
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Err("empty pattern".to_string());
+        }
+        let match_type = match_type
+            .map(|value| value.trim().to_ascii_lowercase())
+            .filter(|value| !value.is_empty());
+        match match_type.as_deref() {
+            Some("exact") => Ok(Self::Exact(pattern.to_ascii_lowercase())),
+            Some("glob") => Ok(Self::Glob(pattern.to_ascii_lowercase())),
+            Some("regex") => Regex::new(&format!("(?i){}", pattern))
+                .map(Self::Regex)
+                .map_err(|error| format!("invalid regex '{}': {}", pattern, error)),
+            Some(other) => Err(format!("unknown matchType '{}'", other)),
+            None if pattern.contains(['*', '?']) => Ok(Self::Glob(pattern.to_ascii_lowercase())),
+            None => Ok(Self::Exact(pattern.to_ascii_lowercase())),
+        }
+    }
+
+    pub fn matches(&self, model: &str) -> bool {
+        match self {
+            Self::Exact(expected) => model.trim().eq_ignore_ascii_case(expected),
+            Self::Glob(pattern) => glob_matches(pattern, &model.trim().to_ascii_lowercase()),
+            Self::Regex(regex) => regex.is_match(model.trim()),
+        }
+    }
+}
+
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    // 最近一个 `*` 的位置，以及它当时对应的 value 位置（用于回溯）
+    let mut star: Option<(usize, usize)> = None;
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, v));
+            p += 1;
+        } else if let Some((star_p, star_v)) = star {
+            p = star_p + 1;
+            v = star_v + 1;
+            star = Some((star_p, star_v + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|ch| *ch == '*')
+}
+
+/// 解析规则中的请求类型：`session_title` / `conversation_turn` / `subagent` / `compaction` / `probe`
+pub fn parse_request_kind(value: &str) -> Option<ClaudeCodeRequestKind> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "session_title" | "title" | "topic" => Some(ClaudeCodeRequestKind::SessionTitle),
+        "conversation_turn" | "conversation" | "turn" | "main" => {
+            Some(ClaudeCodeRequestKind::ConversationTurn)
+        }
+        "subagent" | "task" | "agent" => Some(ClaudeCodeRequestKind::Subagent),
+        "compaction" | "compact" => Some(ClaudeCodeRequestKind::Compaction),
+        "probe" => Some(ClaudeCodeRequestKind::Probe),
+        _ => None,
+    }
+}
+
+/// 命名路由
+#[derive(Debug, Clone)]
+pub struct ModelRoute {
+    pub name: String,
+    /// 用于客户端槽位权限、日志与负载均衡路由键的 slot
+    pub slot: ModelSlot,
+    /// 上游模型；为空时按 converter 的 slot 映射解析
+    pub model: Option<String>,
+    /// 推理强度；为空时取该 slot 的映射
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// OpenAI Chat 上游的 max_tokens 上限
+    pub max_tokens: Option<u32>,
+    /// 负载均衡模式下是否有自己的端点列表（否则沿用 slot 的候选）
+    pub has_endpoints: bool,
+}
+
+impl ModelRoute {
+    pub fn new(name: impl Into<String>, slot: ModelSlot) -> Self {
+        Self {
+            name: name.into(),
+            slot,
+            model: None,
+            reasoning_effort: None,
+            max_tokens: None,
+            has_endpoints: false,
+        }
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn with_reasoning_effort(mut self, effort: ReasoningEffort) -> Self {
+        self.reasoning_effort = Some(effort);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_endpoints(mut self, has_endpoints: bool) -> Self {
+        self.has_endpoints = has_endpoints;
+        self
+    }
+
+    /// 把路由的 max_tokens 上限覆盖到转换上下文（不再按上游模型名猜 slot）
+    pub fn apply_to_context(&self, ctx: &TransformContext) -> TransformContext {
+        let mut ctx = ctx.clone();
+        if let Some(limit) = self.max_tokens {
+            ctx.openai_max_tokens_mapping = OpenAIMaxTokensMapping {
+                opus: Some(limit),
+                sonnet: Some(limit),
+                haiku: Some(limit),
+            };
+        }
+        ctx
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelRouteRule {
+    pub matcher: ModelMatcher,
+    /// 仅匹配该类型的请求；None 表示不限
+    pub request_kind: Option<ClaudeCodeRequestKind>,
+    pub route: String,
+}
+
+impl ModelRouteRule {
+    fn matches(&self, model: &str, kind: ClaudeCodeRequestKind) -> bool {
+        self.request_kind.is_none_or(|expected| expected == kind) && self.matcher.matches(model)
+    }
+}
+
+/// 未命中任何规则、且模型名不含 opus / sonnet / haiku 的请求如何处理
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum UnmatchedModels {
+    /// 归入 sonnet slot（未配置时的行为）
+    #[default]
+    SonnetSlot,
+    /// 使用指定的命名路由
+    Route(String),
+    /// 以 400 拒绝
+    Reject,
+}
+
+impl UnmatchedModels {
+    /// 状态页与日志中的描述：`slot:sonnet` / `route:<name>` / `reject`
+    pub fn describe(&self) -> String {
+        match self {
+            Self::SonnetSlot => "slot:sonnet".to_string(),
+            Self::Route(name) => format!("route:{}", name),
+            Self::Reject => "reject".to_string(),
+        }
+    }
+}
+
+/// 有序规则表与命名路由；第一条命中的规则生效
+#[derive(Debug, Clone, Default)]
+pub struct ModelRouteTable {
+    rules: Vec<ModelRouteRule>,
+    routes: HashMap<String, ModelRoute>,
+    unmatched: UnmatchedModels,
+    /// 构建时被跳过的无效规则与路由说明，启动与热更新时写入日志
+    warnings: Vec<String>,
+}
+
+impl ModelRouteTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_route(mut self, route: ModelRoute) -> Self {
+        self.routes.insert(route.name.clone(), route);
+        self
+    }
+
+    /// 追加规则；引用了不存在的路由时跳过并记录原因
+    pub fn with_rule(mut self, rule: ModelRouteRule) -> Self {
+        if self.routes.contains_key(&rule.route) {
+            self.rules.push(rule);
+        } else {
+            self.warnings
+                .push(format!("rule skipped: unknown route '{}'", rule.route));
+        }
+        self
+    }
+
+    /// 未知模型族使用的默认路由；路由不存在时保持原处理方式并记录原因
+    pub fn with_default_route(mut self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        if self.routes.contains_key(&name) {
+            self.unmatched = UnmatchedModels::Route(name);
+        } else {
+            self.warnings
+                .push(format!("defaultRoute ignored: unknown route '{}'", name));
+        }
+        self
+    }
+
+    /// 拒绝未命中规则的未知模型族（已配置默认路由时以默认路由为准）
+    pub fn with_reject_unmatched(mut self) -> Self {
+        if self.unmatched == UnmatchedModels::SonnetSlot {
+            self.unmatched = UnmatchedModels::Reject;
+        }
+        self
+    }
+
+    pub fn with_warning(mut self, warning: impl Into<String>) -> Self {
+        self.warnings.push(warning.into());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn route(&self, name: &str) -> Option<&ModelRoute> {
+        self.routes.get(name)
+    }
+
+    pub fn unmatched(&self) -> &UnmatchedModels {
+        &self.unmatched
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// 按名称排序的路由名，供状态页展示
+    pub fn route_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.routes.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn resolve(&self, model: &str, kind: ClaudeCodeRequestKind) -> Option<&ModelRoute> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(model, kind))
+            .and_then(|rule| self.routes.get(&rule.route))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, match_type: Option<&str>, route: &str) -> ModelRouteRule {
+        ModelRouteRule {
+            matcher: ModelMatcher::parse(pattern, match_type).unwrap(),
+            request_kind: None,
+            route: route.to_string(),
+        }
+    }
+
+    #[test]
+    fn matcher_infers_glob_and_is_case_insensitive() {
+        let exact = ModelMatcher::parse("gpt-5-codex", None).unwrap();
+        assert!(matches!(exact, ModelMatcher::Exact(_)));
+        assert!(exact.matches("GPT-5-Codex"));
+        assert!(!exact.matches("gpt-5-codex-mini"));
+
+        let glob = ModelMatcher::parse("gemini-*", None).unwrap();
+        assert!(glob.matches("gemini-fast"));
+        assert!(glob.matches("Gemini-"));
+        assert!(!glob.matches("my-gemini-fast"));
+        assert!(ModelMatcher::parse("claude-?-*", None)
+            .unwrap()
+            .matches("claude-3-opus"));
+
+        let regex = ModelMatcher::parse("^claude-(opus|sonnet)-5", Some("regex")).unwrap();
+        assert!(regex.matches("Claude-Opus-5-20260101"));
+        assert!(!regex.matches("claude-haiku-5"));
+
+        assert!(ModelMatcher::parse("(", Some("regex")).is_err());
+        assert!(ModelMatcher::parse("x", Some("prefix")).is_err());
+    }
+
+    #[test]
+    fn first_matching_rule_wins_and_request_kind_filters() {
+        let table = ModelRouteTable::new()
+            .with_route(ModelRoute::new("title", ModelSlot::Haiku).with_model("gpt-5-mini"))
+            .with_route(ModelRoute::new("codex", ModelSlot::Opus).with_model("gpt-5-codex"))
+            .with_route(ModelRoute::new("fallback", ModelSlot::Sonnet))
+            .with_rule(ModelRouteRule {
+                request_kind: Some(ClaudeCodeRequestKind::SessionTitle),
+                ..rule("*", None, "title")
+            })
+            .with_rule(rule("gpt-5-codex", None, "codex"))
+            .with_rule(rule("*", None, "fallback"))
+            .with_rule(rule("never", None, "missing"));
+
+        assert_eq!(table.warnings(), ["rule skipped: unknown route 'missing'"]);
+        let resolve = |model, kind| table.resolve(model, kind).map(|r| r.name.as_str());
+        assert_eq!(
+            resolve("gpt-5-codex", ClaudeCodeRequestKind::SessionTitle),
+            Some("title")
+        );
+        assert_eq!(
+            resolve("gpt-5-codex", ClaudeCodeRequestKind::ConversationTurn),
+            Some("codex")
+        );
+        assert_eq!(
+            resolve("anything", ClaudeCodeRequestKind::Unknown),
+            Some("fallback")
+        );
+        assert!(ModelRouteTable::new()
+            .resolve("gpt-5-codex", ClaudeCodeRequestKind::Unknown)
+            .is_none());
+    }
+
+    #[test]
+    fn unmatched_models_default_to_sonnet_until_configured() {
+        let table =
+            ModelRouteTable::new().with_route(ModelRoute::new("catch-all", ModelSlot::Opus));
+        assert_eq!(table.unmatched(), &UnmatchedModels::SonnetSlot);
+        assert_eq!(table.unmatched().describe(), "slot:sonnet");
+
+        let routed = table
+            .clone()
+            .with_default_route("catch-all")
+            .with_reject_unmatched();
+        assert_eq!(
+            routed.unmatched(),
+            &UnmatchedModels::Route("catch-all".to_string())
+        );
+        assert_eq!(routed.unmatched().describe(), "route:catch-all");
+
+        let missing = table.clone().with_default_route("missing");
+        assert_eq!(missing.unmatched(), &UnmatchedModels::SonnetSlot);
+        assert_eq!(
+            missing.warnings(),
+            ["defaultRoute ignored: unknown route 'missing'"]
+        );
+
+        let rejecting = table.with_reject_unmatched();
+        assert_eq!(rejecting.unmatched().describe(), "reject");
+    }
+
+    #[test]
+    fn request_kind_aliases_parse() {
+        assert_eq!(
+            parse_request_kind("Task"),
+            Some(ClaudeCodeRequestKind::Subagent)
+        );
+        assert_eq!(
+            parse_request_kind(" compact "),
+            Some(ClaudeCodeRequestKind::Compaction)
+        );
+        assert_eq!(
+            parse_request_kind("topic"),
+            Some(ClaudeCodeRequestKind::SessionTitle)
+        );
+        assert_eq!(
+            parse_request_kind("main"),
+            Some(ClaudeCodeRequestKind::ConversationTurn)
+        );
+        assert_eq!(
+            parse_request_kind("probe"),
+            Some(ClaudeCodeRequestKind::Probe)
+        );
+        assert_eq!(parse_request_kind("unknown"), None);
+    }
+
+    #[test]
+    fn route_max_tokens_applies_regardless_of_upstream_model_name() {
+        let route = ModelRoute::new("fast", ModelSlot::Sonnet).with_max_tokens(4096);
+        let base = crate::config::build_transform_context(
+            &crate::config::default_proxy_config(),
+            "openai".to_string(),
+            OpenAIMaxTokensMapping::default(),
+        );
+        let ctx = route.apply_to_context(&base);
+        assert_eq!(
+            ctx.openai_max_tokens_mapping.get_limit("gpt-4o"),
+            Some(4096)
+        );
+        assert_eq!(
+            ctx.openai_max_tokens_mapping.get_limit("claude-haiku"),
+            Some(4096)
+        );
+    }
+}
@@ -3,7 +3,7 @@ use serde_json::{json, Value};
 use std::collections::HashMap;
 
 /// Anthropic 请求体
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AnthropicRequest {
     pub model: Option<String>,
     pub messages: Vec<Message>,
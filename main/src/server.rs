@@ -1,14 +1,30 @@
+use crate::budget::{BudgetPeriod, BudgetTracker, TokenUsage};
+use crate::config::{
+    BudgetsConfig, ClientTokenConfig, ModelPriceConfig, RateLimitConfig, SessionStoreConfig,
+    TokenCalibrationConfig, UsageLedgerConfig,
+};
 use crate::load_balancer::{
     EndpointPermit, LoadBalancerRuntime, ModelSlot, ResolvedEndpoint, UpstreamOutcomeAction,
 };
 use crate::logger::AppLogger;
+use crate::model_routes::{ModelRoute, ModelRouteTable, UnmatchedModels};
 use crate::models::{
     AnthropicModelMapping, AnthropicRequest, CodexModelMapping, ContentBlock,
     GeminiReasoningEffortMapping, Message, MessageContent, OpenAIMaxTokensMapping,
     OpenAIModelMapping, ReasoningEffort, ReasoningEffortMapping,
 };
+use crate::token_calibration::{TokenCalibration, CALIBRATION_FILE_NAME};
+use crate::tokenizer::{count_request_tokens, TokenizerFamily};
 use crate::transform::anthropic::build_raw_passthrough_body;
 use crate::transform::codex::build_codex_unified_request;
+use crate::transform::inbound::openai_chat::{
+    decode_chat_completions_request, encode_chat_completion, encode_chat_completion_error,
+    ChatCompletionsStreamEncoder,
+};
+use crate::transform::inbound::responses::{
+    decode_responses_request, encode_response, encode_response_error, ResponsesStreamEncoder,
+};
+use crate::transform::inbound::InboundStreamEncoder;
 use crate::transform::providers::build_gemini_explicit_cache_plan;
 use crate::transform::request_envelope_hints_from_anthropic;
 use crate::transform::{
@@ -16,6 +32,7 @@ use crate::transform::{
     OpenAIChatAdapter, OpenAIChatBackend, PreparedCountTokensRequest, PreparedRequest,
     RequestEnvelopeHints, ResponseTransformRequestContext, TransformBackend, TransformContext,
 };
+use crate::usage_ledger::{UsageLedger, UsageRecord};
 use bytes::Bytes;
 use futures_util::StreamExt;
 use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
@@ -30,14 +47,40 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::Infallible;
 use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
-use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore};
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
+mod admin;
+mod client_auth;
+mod failover;
+mod health;
+mod hedge;
+mod model_catalog;
+mod probe;
+mod rate_limit;
+mod scheduler;
+mod session_store;
 mod stream_decision;
+mod usage;
+pub use admin::AdminApiConfig;
+use admin::{handle_admin_request, AdminState};
+use client_auth::{authenticate_client, ClientAuthError, ClientIdentity};
+use failover::StreamFailover;
+use health::{
+    build_status_report, liveness_report, process_metrics_text, readiness_report, InFlightGuard,
+    RequestObservation, ServerStats, DEGRADED_SLOT_HEADER,
+};
+use hedge::{HedgePrimary, PendingStream, UpstreamByteStream};
+use model_catalog::{build_slot_models, encode_model_entry, encode_model_list, find_slot_model};
+use rate_limit::{RateLimitRejection, RateLimiter};
+use scheduler::{RequestPriority, RequestScheduler};
+use session_store::SessionStorePersistence;
 use stream_decision::{OutputDisposition, StreamDecisionState};
+use usage::{parse_usage_query, usage_report_response};
 
 pub struct ProxyServer {
     port: u16,
@@ -54,6 +97,8 @@ pub struct ProxyServer {
     openai_max_tokens_mapping: OpenAIMaxTokensMapping,
     gemini_reasoning_effort: GeminiReasoningEffortMapping,
     max_concurrency: u32,
+    /// 并发已满时的最长排队时间（毫秒），0 表示不限制
+    max_queue_wait_ms: u64,
     ignore_probe_requests: bool,
     allow_count_tokens_fallback_estimate: bool,
     enable_codex_fast_mode: bool,
@@ -83,6 +128,16 @@ pub struct ProxyServer {
     enable_stateful_responses_chain: bool,
     load_balancer_runtime: Option<LoadBalancerRuntime>,
     codex_route_config: Option<InitialRouteConfig>,
+    log_dir: Option<String>,
+    admin_api: Option<AdminApiConfig>,
+    client_tokens: Vec<ClientTokenConfig>,
+    rate_limit: RateLimitConfig,
+    model_routes: ModelRouteTable,
+    usage_ledger: Option<UsageLedgerConfig>,
+    token_calibration: TokenCalibrationConfig,
+    session_store: Option<SessionStoreConfig>,
+    pricing: Vec<ModelPriceConfig>,
+    budgets: BudgetsConfig,
 }
 
 #[derive(Clone)]
@@ -139,6 +194,11 @@ pub struct RuntimeConfigUpdate {
     pub enable_codex_tool_schema_compaction: bool,
     pub enable_skill_routing_hint: bool,
     pub enable_stateful_responses_chain: bool,
+    pub client_tokens: Vec<ClientTokenConfig>,
+    pub rate_limit: RateLimitConfig,
+    pub model_routes: ModelRouteTable,
+    pub pricing: Vec<ModelPriceConfig>,
+    pub budgets: BudgetsConfig,
     pub load_balancer_runtime: Option<LoadBalancerRuntime>,
 }
 
@@ -159,6 +219,15 @@ enum ClientRouteKind {
     Codex,
 }
 
+impl ClientRouteKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ClientRouteKind::Claude => "claude",
+            ClientRouteKind::Codex => "codex",
+        }
+    }
+}
+
 impl RuntimeRouteState {
     fn from_update(
         value: RuntimeRouteUpdate,
@@ -208,6 +277,11 @@ struct RuntimeConfigState {
     enable_codex_tool_schema_compaction: bool,
     enable_skill_routing_hint: bool,
     enable_stateful_responses_chain: bool,
+    client_tokens: Vec<ClientTokenConfig>,
+    rate_limit: RateLimitConfig,
+    model_routes: Arc<ModelRouteTable>,
+    /// 本次配置生效时间（unix 秒），用作 /v1/models 的 created
+    applied_at: i64,
 }
 
 impl From<RuntimeConfigUpdate> for RuntimeConfigState {
@@ -262,6 +336,10 @@ impl From<RuntimeConfigUpdate> for RuntimeConfigState {
             enable_codex_tool_schema_compaction: value.enable_codex_tool_schema_compaction,
             enable_skill_routing_hint: value.enable_skill_routing_hint,
             enable_stateful_responses_chain: value.enable_stateful_responses_chain,
+            client_tokens: value.client_tokens,
+            rate_limit: value.rate_limit,
+            model_routes: Arc::new(value.model_routes),
+            applied_at: chrono::Utc::now().timestamp(),
         }
     }
 }
@@ -278,11 +356,37 @@ impl RuntimeConfigState {
 #[derive(Clone)]
 pub struct ProxyRuntimeHandle {
     state: Arc<RwLock<RuntimeConfigState>>,
+    /// 花费累计跨配置热更新保留，价目表与预算随更新替换
+    budget: Arc<BudgetTracker>,
 }
 
 impl ProxyRuntimeHandle {
+    fn new(update: RuntimeConfigUpdate, budget: Arc<BudgetTracker>) -> Self {
+        let state = Self::prepare_state(&budget, update);
+        Self {
+            state: Arc::new(RwLock::new(state)),
+            budget,
+        }
+    }
+
+    /// 更新价目表与预算，并把 tracker 挂到新的负载均衡运行时上
+    fn prepare_state(
+        budget: &Arc<BudgetTracker>,
+        update: RuntimeConfigUpdate,
+    ) -> RuntimeConfigState {
+        budget.configure(&update.pricing, update.budgets.clone());
+        let mut state = RuntimeConfigState::from(update);
+        for route in [&mut state.claude_route, &mut state.codex_route] {
+            route.load_balancer_runtime = route
+                .load_balancer_runtime
+                .take()
+                .map(|runtime| runtime.with_budget_tracker(Arc::clone(budget)));
+        }
+        state
+    }
+
     pub fn apply_update(&self, update: RuntimeConfigUpdate) {
-        let next = RuntimeConfigState::from(update);
+        let next = Self::prepare_state(&self.budget, update);
         match self.state.write() {
             Ok(mut guard) => {
                 *guard = next;
@@ -294,6 +398,11 @@ impl ProxyRuntimeHandle {
         }
     }
 
+    /// 各范围的花费与预算上限，与 `/status` 中的 `budget` 一致
+    pub fn budget_status(&self) -> Value {
+        self.budget.status_json()
+    }
+
     fn snapshot(&self) -> RuntimeConfigState {
         match self.state.read() {
             Ok(guard) => guard.clone(),
@@ -1169,7 +1278,7 @@ async fn prepare_gemini_explicit_cache(
     unsupported_store: &GeminiExplicitCacheUnsupportedEndpointStore,
     log_tx: &broadcast::Sender<String>,
 ) {
-    if request_hints.request_kind != crate::transform::ClaudeCodeRequestKind::ConversationTurn {
+    if !request_hints.request_kind.is_conversation() {
         return;
     }
 
@@ -1341,7 +1450,7 @@ fn mark_codex_fast_endpoint_unsupported(
     }
 }
 
-fn extract_stateful_chain_hint(req: &Request<hyper::body::Incoming>) -> Option<String> {
+fn extract_stateful_chain_hint<B>(req: &Request<B>) -> Option<String> {
     for name in STATEFUL_CHAIN_HINT_HEADERS {
         if let Some(value) = req.headers().get(name).and_then(|v| v.to_str().ok()) {
             let trimmed = value.trim();
@@ -2212,12 +2321,105 @@ impl SseFrameParser {
     }
 }
 
+/// `/metrics` 导出所需的标签；导出一次后清空，避免多个退出分支重复计数
+struct StreamMetricsExport {
+    route: &'static str,
+    client: String,
+    converter: String,
+    endpoint: String,
+    streaming: bool,
+    /// 用量同时计入客户端配额
+    rate_limiter: Option<Arc<RateLimiter>>,
+    lb_feedback: Option<LbLatencyFeedback>,
+    usage: Option<UsageTags>,
+}
+
+/// 折算费用与写入用量账本时 `/metrics` 标签之外的请求信息
+#[derive(Clone)]
+struct UsageTags {
+    ledger: Option<Arc<UsageLedger>>,
+    budget: Arc<BudgetTracker>,
+    request_id: String,
+    session: Option<String>,
+    kind: &'static str,
+    slot: &'static str,
+    model: String,
+    estimate: Option<InputEstimate>,
+}
+
+/// 按上游分词器族做的原始输入估算；与上游 usage 对比后更新 count_tokens 的校准系数
+#[derive(Clone)]
+struct InputEstimate {
+    calibration: Arc<TokenCalibration>,
+    family: TokenizerFamily,
+    request: Arc<AnthropicRequest>,
+    log_tx: broadcast::Sender<String>,
+}
+
+impl InputEstimate {
+    /// 分词（含图片解码）与系数落盘都放到阻塞线程池，不占用响应所在的异步线程
+    fn record(&self, request_id: &str, endpoint: &str, model: &str, actual: u64) {
+        let estimate = self.clone();
+        let request_id = request_id.to_string();
+        let endpoint = endpoint.to_string();
+        let model = model.to_string();
+        let observe = move || estimate.observe(&request_id, &endpoint, &model, actual);
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => drop(handle.spawn_blocking(observe)),
+            Err(_) => observe(),
+        }
+    }
+
+    fn observe(&self, request_id: &str, endpoint: &str, model: &str, actual: u64) {
+        let tokens = count_request_tokens(&self.request, self.family);
+        let Some(sample) = self.calibration.observe(endpoint, model, tokens, actual) else {
+            return;
+        };
+        crate::metrics::record_token_estimate(
+            endpoint,
+            self.family.as_str(),
+            sample.estimate,
+            sample.calibrated,
+            sample.actual,
+        );
+        let _ = self.log_tx.send(format!(
+            "[Tokens] #{} estimate family={} raw={} calibrated={} actual={} error_pct={:+.1} factor={:.3} samples={} endpoint={} model={}",
+            request_id,
+            self.family.as_str(),
+            sample.estimate,
+            sample.calibrated,
+            sample.actual,
+            sample.error_ratio * 100.0,
+            sample.factor,
+            sample.samples,
+            endpoint,
+            model,
+        ));
+    }
+}
+
+/// 负载均衡路由的延迟回馈；TTFB 从成功的那次尝试发出时算起，不含之前失败的尝试
+#[derive(Clone)]
+struct LbLatencyFeedback {
+    runtime: LoadBalancerRuntime,
+    route: ResolvedEndpoint,
+    attempt_started_at: Instant,
+}
+
+/// 输出速度只在生成时长足够时才有意义
+const MIN_GENERATION_FOR_TOKENS_PER_SEC: Duration = Duration::from_millis(200);
+
 struct StreamMetrics {
     started_at: Instant,
     first_upstream_byte_at: Option<Instant>,
     first_delta_at: Option<Instant>,
     last_emit_at: Option<Instant>,
     max_silent_gap_ms: u128,
+    export: Option<StreamMetricsExport>,
+    close_cause: Option<String>,
+    usage: TokenUsage,
+    /// Anthropic 的缓存写入 token；只用于还原上游实际计入的输入总量
+    cache_creation_input: u64,
 }
 
 impl StreamMetrics {
@@ -2228,6 +2430,92 @@ impl StreamMetrics {
             first_delta_at: None,
             last_emit_at: None,
             max_silent_gap_ms: 0,
+            export: None,
+            close_cause: None,
+            usage: TokenUsage::default(),
+            cache_creation_input: 0,
+        }
+    }
+
+    fn with_export(
+        mut self,
+        route: ClientRouteKind,
+        client: &str,
+        converter: &str,
+        endpoint: &str,
+        streaming: bool,
+    ) -> Self {
+        self.export = Some(StreamMetricsExport {
+            route: route.as_str(),
+            client: client.to_string(),
+            converter: converter.to_ascii_lowercase(),
+            endpoint: endpoint.to_string(),
+            streaming,
+            rate_limiter: None,
+            lb_feedback: None,
+            usage: None,
+        });
+        self
+    }
+
+    fn with_lb_feedback(mut self, feedback: Option<LbLatencyFeedback>) -> Self {
+        self.set_lb_feedback(feedback);
+        self
+    }
+
+    fn set_lb_feedback(&mut self, feedback: Option<LbLatencyFeedback>) {
+        if let Some(export) = self.export.as_mut() {
+            export.lb_feedback = feedback;
+        }
+    }
+
+    /// 流内故障转移后改记新路由的端点与模型，并回馈新路由
+    fn set_failover_upstream(&mut self, converter: &str, model: &str, feedback: LbLatencyFeedback) {
+        if let Some(export) = self.export.as_mut() {
+            export.converter = converter.to_ascii_lowercase();
+            export.endpoint = feedback.route.endpoint_id.clone();
+            if let Some(tags) = export.usage.as_mut() {
+                tags.slot = feedback.route.slot.as_str();
+                tags.model = model.to_string();
+                // 新上游的分词器族可能不同，原估算不再可比
+                tags.estimate = None;
+            }
+        }
+        self.set_lb_feedback(Some(feedback));
+    }
+
+    fn with_usage_tags(mut self, tags: UsageTags) -> Self {
+        if let Some(export) = self.export.as_mut() {
+            export.usage = Some(tags);
+        }
+        self
+    }
+
+    fn with_rate_limiter(mut self, rate_limiter: &Arc<RateLimiter>) -> Self {
+        if let Some(export) = self.export.as_mut() {
+            export.rate_limiter = Some(Arc::clone(rate_limiter));
+        }
+        self
+    }
+
+    fn set_close_cause(&mut self, cause: &str) {
+        self.close_cause = Some(cause.to_string());
+    }
+
+    /// 记录 Anthropic 格式的 usage（message_delta / 非流式 message），后到的值覆盖先到的
+    fn mark_usage(&mut self, usage: &Value) {
+        let (input, output, cached) = usage_token_counts(usage);
+        self.usage = TokenUsage {
+            input: input.unwrap_or(self.usage.input),
+            output: output.unwrap_or(self.usage.output),
+            cached_input: cached.unwrap_or(self.usage.cached_input),
+            reasoning: usage_reasoning_tokens(usage).unwrap_or(self.usage.reasoning),
+        };
+        if let Some(tokens) = usage
+            .get("cache_creation_input_tokens")
+            .and_then(|v| v.as_u64())
+        {
+            self.cache_creation_input = tokens;
         }
     }
 
@@ -2248,10 +2536,114 @@ impl StreamMetrics {
         if self.first_delta_at.is_none() && output.contains("event: content_block_delta") {
             self.first_delta_at = Some(now);
         }
+        if output.contains("event: message_delta") {
+            for event in output.split("\n\n") {
+                if let Some((name, payload)) = parse_sse_chunk(event) {
+                    if let (true, Some(usage)) = (name == "message_delta", payload.get("usage")) {
+                        self.mark_usage(usage);
+                    }
+                }
+            }
+        }
         self.last_emit_at = Some(now);
     }
 
-    fn emit(&self, log_tx: &broadcast::Sender<String>, request_id: &str, enabled: bool) {
+    fn export(&mut self) {
+        let Some(export) = self.export.take() else {
+            return;
+        };
+        if let Some(first_byte_at) = self.first_upstream_byte_at {
+            crate::metrics::observe_upstream_ttfb(
+                export.route,
+                &export.converter,
+                &export.endpoint,
+                first_byte_at.duration_since(self.started_at).as_secs_f64(),
+            );
+        }
+        if export.streaming {
+            // 终态汇总之前就返回的分支都是下游断开
+            crate::metrics::record_stream_end(
+                export.route,
+                &export.client,
+                &export.converter,
+                &export.endpoint,
+                self.close_cause.as_deref().unwrap_or("client_disconnected"),
+                self.started_at.elapsed().as_secs_f64(),
+            );
+        }
+        let usage = self.usage;
+        let (input, output) = (usage.input, usage.output);
+        crate::metrics::record_tokens(
+            export.route,
+            &export.client,
+            &export.converter,
+            input,
+            output,
+            usage.cached_input,
+        );
+        let client = Some(export.client.as_str()).filter(|client| *client != "-");
+        if let Some(rate_limiter) = export.rate_limiter.as_ref() {
+            rate_limiter.record_tokens(client, input, output);
+        }
+        if let Some(tags) = export.usage.as_ref() {
+            let cost_usd = tags.budget.record(
+                &tags.request_id,
+                &tags.model,
+                &export.endpoint,
+                client,
+                &usage,
+            );
+            let record = UsageRecord {
+                ts: chrono::Local::now().to_rfc3339(),
+                request_id: tags.request_id.clone(),
+                client: export.client.clone(),
+                session: tags.session.clone(),
+                kind: tags.kind.to_string(),
+                route: export.route.to_string(),
+                slot: tags.slot.to_string(),
+                endpoint: export.endpoint.clone(),
+                converter: export.converter.clone(),
+                model: tags.model.clone(),
+                input_tokens: input,
+                output_tokens: output,
+                cached_input_tokens: usage.cached_input,
+                reasoning_tokens: usage.reasoning,
+                cost_usd,
+                streaming: export.streaming,
+            };
+            if let Some(ledger) = tags.ledger.as_ref() {
+                let _ = ledger.append(&record);
+            }
+            if let Some(estimate) = tags.estimate.as_ref() {
+                // Anthropic 原生上游的 input_tokens 不含缓存读写，其余后端回报的就是输入总量
+                let actual = if export.converter == "anthropic" {
+                    input + usage.cached_input + self.cache_creation_input
+                } else {
+                    input
+                };
+                estimate.record(&tags.request_id, &export.endpoint, &tags.model, actual);
+            }
+        }
+        if let (Some(feedback), Some(first_byte_at)) =
+            (export.lb_feedback.as_ref(), self.first_upstream_byte_at)
+        {
+            // 故障转移前的路由已经产出过首字节时，新路由的 TTFB 无从得知
+            if first_byte_at < feedback.attempt_started_at {
+                return;
+            }
+            let generation = first_byte_at.elapsed();
+            let tokens_per_sec = (output > 0 && generation >= MIN_GENERATION_FOR_TOKENS_PER_SEC)
+                .then(|| output as f64 / generation.as_secs_f64());
+            feedback.runtime.record_latency(
+                &feedback.route,
+                first_byte_at.saturating_duration_since(feedback.attempt_started_at),
+                tokens_per_sec,
+            );
+        }
+    }
+
+    fn emit(&mut self, log_tx: &broadcast::Sender<String>, request_id: &str, enabled: bool) {
+        self.export();
         if !enabled {
             return;
         }
@@ -3322,6 +3714,26 @@ fn extract_cached_input_tokens_from_response_usage(usage: &Value) -> Option<u64>
         .and_then(|value| value.as_u64())
 }
 
+/// Responses / Chat Completions 的推理 token（包含在 output 中）
+fn usage_reasoning_tokens(usage: &Value) -> Option<u64> {
+    usage
+        .pointer("/output_tokens_details/reasoning_tokens")
+        .or_else(|| usage.pointer("/completion_tokens_details/reasoning_tokens"))
+        .and_then(|value| value.as_u64())
+}
+
+/// 从 usage 中取 (input, output, cached_input)；兼容 Anthropic 与 Responses 两种缓存字段
+fn usage_token_counts(usage: &Value) -> (Option<u64>, Option<u64>, Option<u64>) {
+    let input = usage.get("input_tokens").and_then(|v| v.as_u64());
+    let output = usage.get("output_tokens").and_then(|v| v.as_u64());
+    let cached = extract_cached_input_tokens_from_response_usage(usage).or_else(|| {
+        usage
+            .get("cache_read_input_tokens")
+            .and_then(|v| v.as_u64())
+    });
+    (input, output, cached)
+}
+
 fn log_usage_tokens(
     log_tx: &broadcast::Sender<String>,
     request_id: &str,
@@ -3438,6 +3850,16 @@ struct StreamRetrySuccess {
     session_id: String,
 }
 
+/// 重发请求失败的原因，供负载均衡按与主链路相同的规则处理
+enum StreamRetryFailure {
+    Status {
+        status: u16,
+        retry_after: String,
+        error_text: String,
+    },
+    Network,
+}
+
 async fn execute_stream_retry_request(
     request_backend: &Arc<dyn TransformBackend>,
     http_client: &reqwest::Client,
@@ -3450,8 +3872,9 @@ async fn execute_stream_retry_request(
     retry_label: &str,
     log_tx: &broadcast::Sender<String>,
     logger: &Option<Arc<AppLogger>>,
-) -> Option<StreamRetrySuccess> {
+) -> Result<StreamRetrySuccess, StreamRetryFailure> {
     let retry_session_id = Uuid::new_v4().to_string();
+    crate::metrics::record_retry(retry_label);
     let retry_req = request_backend.build_upstream_request(
         http_client,
         upstream_url,
@@ -3478,12 +3901,18 @@ async fn execute_stream_retry_request(
                         request_id, retry_label, retry_status
                     ),
                 );
-                Some(StreamRetrySuccess {
+                Ok(StreamRetrySuccess {
                     response: retry_response,
                     status: retry_status,
                     session_id: retry_session_id,
                 })
             } else {
+                let retry_after = retry_response
+                    .headers()
+                    .get("retry-after")
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("-")
+                    .to_string();
                 let retry_error = retry_response.text().await.unwrap_or_default();
                 emit_stream_diag(
                     log_tx,
@@ -3493,7 +3922,11 @@ async fn execute_stream_retry_request(
                         request_id, retry_label, retry_status, retry_error
                     ),
                 );
-                None
+                Err(StreamRetryFailure::Status {
+                    status: retry_status,
+                    retry_after,
+                    error_text: retry_error,
+                })
             }
         }
         Err(err) => {
@@ -3505,7 +3938,7 @@ async fn execute_stream_retry_request(
                     request_id, retry_label, err
                 ),
             );
-            None
+            Err(StreamRetryFailure::Network)
         }
     }
 }
@@ -4122,8 +4555,8 @@ fn build_probe_json_payload(model: &str) -> Value {
     })
 }
 
-async fn handle_codex_native_passthrough(
-    req: Request<hyper::body::Incoming>,
+async fn handle_codex_native_passthrough<B>(
+    req: Request<B>,
     request_id: &str,
     routed_path: &str,
     target_url: &str,
@@ -4133,7 +4566,11 @@ async fn handle_codex_native_passthrough(
     image_generation_url: &str,
     image_generation_api_key: Option<String>,
     strip_image_generation_tool: bool,
-) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
+) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible>
+where
+    B: hyper::body::Body<Data = Bytes> + Send + 'static,
+    B::Error: std::fmt::Display,
+{
     let _ = log_tx.send(format!("[NATIVE_PASSTHROUGH] #{} path={}", request_id, routed_path));
     let method = req.method().clone();
     let query = req.uri().query().map(|q| q.to_string());
@@ -4741,6 +5178,27 @@ struct RouteSelection {
     reasoning_effort_override: Option<ReasoningEffort>,
 }
 
+/// 拿到成功响应头的一次上游尝试；循环结束后交给非流式聚合或流式转发
+struct SuccessfulAttempt {
+    /// 经过对冲等待的响应体已转为 `hedged_stream`（含已读到的数据块），此时为 None
+    response: Option<reqwest::Response>,
+    hedged_stream: Option<UpstreamByteStream>,
+    backend: Arc<dyn TransformBackend>,
+    model: String,
+    converter: String,
+    endpoint: String,
+    upstream_status: u16,
+    lb_permit: Option<EndpointPermit>,
+    resolved_target_url: String,
+    api_key: String,
+    upstream_body: Value,
+    parallel_tool_degrade_key: Option<String>,
+    session_id: String,
+    stateful_chain_meta: Option<StatefulChainRequestMeta>,
+    effective_stream: bool,
+    lb_feedback: Option<LbLatencyFeedback>,
+}
+
 fn build_gemini_count_tokens_endpoint(target_url: &str, model: &str) -> String {
     if target_url.contains(":streamGenerateContent") || target_url.contains(":generateContent") {
         let endpoint = target_url
@@ -5072,14 +5530,49 @@ fn resolve_upstream_url(
     }
 }
 
+/// 模型路由命中时优先使用路由指定的上游模型，否则按路由的 slot 解析映射
+/// （映射为空、即透传时保留客户端模型名）；未命中时沿用按模型名推断 slot 的映射
+fn resolve_route_model(
+    converter: &str,
+    input_model: &str,
+    model_route: Option<&ModelRoute>,
+    ctx: &TransformContext,
+) -> String {
+    let mapping_input = match model_route {
+        Some(route) => match route.model.as_ref() {
+            Some(model) => return model.clone(),
+            None => route.slot.as_str(),
+        },
+        None => input_model,
+    };
+    let mapped = resolve_model_for_converter(
+        converter,
+        mapping_input,
+        &ctx.reasoning_mapping,
+        &ctx.codex_model_mapping,
+        &ctx.anthropic_model_mapping,
+        &ctx.openai_model_mapping,
+        &ctx.gemini_reasoning_effort,
+    );
+    if model_route.is_some() && mapped == mapping_input {
+        input_model.to_string()
+    } else {
+        mapped
+    }
+}
+
 fn resolve_route_selection(
     request_id: &str,
     input_model: &str,
     input_slot: ModelSlot,
+    model_route: Option<&ModelRoute>,
     target_url: &str,
     final_api_key: &str,
     ctx: &TransformContext,
     load_balancer_runtime: Option<&LoadBalancerRuntime>,
+    session_hint: Option<&str>,
+    client_identity: Option<&ClientIdentity>,
+    excluded_route: Option<&ResolvedEndpoint>,
     log_tx: &broadcast::Sender<String>,
 ) -> Result<RouteSelection, Response<BoxBody<Bytes, Infallible>>> {
     let mut resolved_target_url = target_url.to_string();
@@ -5087,40 +5580,68 @@ fn resolve_route_selection(
     let mut request_converter = ctx.converter.clone();
     let mut selected_lb_route: Option<ResolvedEndpoint> = None;
     let mut lb_permit: Option<EndpointPermit> = None;
-    let mut request_reasoning_effort_override: Option<ReasoningEffort> = None;
+    // 命名路由的推理强度不再按客户端模型名猜测：未指定时取路由 slot 的映射
+    let mut request_reasoning_effort_override: Option<ReasoningEffort> = model_route.map(|route| {
+        route.reasoning_effort.unwrap_or_else(|| {
+            crate::models::get_reasoning_effort(route.slot.as_str(), &ctx.reasoning_mapping)
+        })
+    });
 
-    let mut model_name = resolve_model_for_converter(
-        &request_converter,
-        input_model,
-        &ctx.reasoning_mapping,
-        &ctx.codex_model_mapping,
-        &ctx.anthropic_model_mapping,
-        &ctx.openai_model_mapping,
-        &ctx.gemini_reasoning_effort,
-    );
+    let mut model_name = resolve_route_model(&request_converter, input_model, model_route, ctx);
 
     if let Some(runtime) = load_balancer_runtime {
-        if let Some((resolved, permit)) = runtime.resolve_and_acquire(input_model) {
+        // 对冲与流内故障转移：换一个端点，不走会话绑定
+        let acquired = if let Some(current) = excluded_route {
+            let route_name = model_route
+                .filter(|route| route.has_endpoints)
+                .map(|route| route.name.as_str());
+            runtime.resolve_alternate_candidate(current, route_name, input_model)
+        } else {
+            match model_route {
+                Some(route) if route.has_endpoints => {
+                    runtime.resolve_and_acquire_for_route(&route.name, input_model, session_hint)
+                }
+                // 降级链跳过客户端无权使用的 slot
+                route => runtime.resolve_and_acquire_in_slot_allowing(
+                    route.map_or(input_slot, |route| route.slot),
+                    input_model,
+                    session_hint,
+                    |slot| client_identity.is_none_or(|identity| identity.allows_slot(slot)),
+                ),
+            }
+        };
+        if let Some((resolved, permit)) = acquired {
             resolved_target_url = resolved.target_url.clone();
             if let Some(key) = resolved.api_key.clone() {
                 resolved_api_key = key;
             }
 
             request_converter = resolved.converter.clone();
+            // 降级到其他 slot 时按该 slot 的映射选择上游模型与推理强度
+            let degraded_route = resolved.degraded_from.map(|_| {
+                let mut route = model_route
+                    .cloned()
+                    .unwrap_or_else(|| ModelRoute::new(resolved.slot.as_str(), resolved.slot));
+                // 命名路由固定的上游模型与推理强度属于原 slot，降级后改按目标 slot 映射
+                route.slot = resolved.slot;
+                route.model = None;
+                route.reasoning_effort = None;
+                route
+            });
+            let mapping_route = degraded_route.as_ref().or(model_route);
             if let Some(overridden_model) = resolved.model.clone() {
                 model_name = overridden_model;
             } else {
-                model_name = resolve_model_for_converter(
-                    &request_converter,
-                    input_model,
-                    &ctx.reasoning_mapping,
-                    &ctx.codex_model_mapping,
-                    &ctx.anthropic_model_mapping,
-                    &ctx.openai_model_mapping,
-                    &ctx.gemini_reasoning_effort,
-                );
+                model_name =
+                    resolve_route_model(&request_converter, input_model, mapping_route, ctx);
             }
 
+            if let Some(route) = degraded_route.as_ref() {
+                let effort = route.reasoning_effort.unwrap_or_else(|| {
+                    crate::models::get_reasoning_effort(route.slot.as_str(), &ctx.reasoning_mapping)
+                });
+                request_reasoning_effort_override = Some(effort);
+            }
             if let Some(custom_effort) = resolved.reasoning_effort.clone() {
                 request_reasoning_effort_override = Some(ReasoningEffort::from_str(&custom_effort));
             }
@@ -5129,11 +5650,16 @@ fn resolve_route_selection(
             lb_permit = Some(permit);
         } else {
             let _ = log_tx.send(format!(
-                "[Warn] #{} lb_unavailable slot={} model={} reason=no_available_candidate",
+                "[Warn] #{} lb_unavailable slot={} route={} model={} reason=no_available_candidate",
                 request_id,
                 input_slot.as_str(),
+                model_route.map(|route| route.name.as_str()).unwrap_or("-"),
                 input_model,
             ));
+            let scope = match model_route {
+                Some(route) => format!("route '{}'", route.name),
+                None => format!("slot '{}'", input_slot.as_str()),
+            };
 
             return Err(Response::builder()
                 .status(StatusCode::SERVICE_UNAVAILABLE)
@@ -5143,8 +5669,8 @@ fn resolve_route_selection(
                         "error": {
                             "type": "service_unavailable",
                             "message": format!(
-                                "No available upstream route in slot '{}' for model '{}'",
-                                input_slot.as_str(),
+                                "No available upstream route in {} for model '{}'",
+                                scope,
                                 input_model
                             )
                         }
@@ -5181,65 +5707,6 @@ fn parse_input_tokens(value: &Value) -> Option<u64> {
         })
 }
 
-fn estimate_input_tokens(request: &AnthropicRequest) -> u64 {
-    let mut chars = 0usize;
-
-    if let Some(system) = &request.system {
-        chars += system.to_string().chars().count();
-    }
-
-    for message in &request.messages {
-        chars += message.role.chars().count();
-        match &message.content {
-            Some(MessageContent::Text(text)) => {
-                chars += text.chars().count();
-            }
-            Some(MessageContent::Blocks(blocks)) => {
-                for block in blocks {
-                    match block {
-                        ContentBlock::Text { text } => chars += text.chars().count(),
-                        ContentBlock::Thinking { thinking, .. } => {
-                            chars += thinking.chars().count()
-                        }
-                        ContentBlock::ToolUse { name, input, .. } => {
-                            chars += name.chars().count();
-                            chars += serde_json::to_string(input)
-                                .unwrap_or_default()
-                                .chars()
-                                .count();
-                        }
-                        ContentBlock::ToolResult { content, .. } => {
-                            chars += content
-                                .as_ref()
-                                .map(|v| {
-                                    serde_json::to_string(v).unwrap_or_default().chars().count()
-                                })
-                                .unwrap_or(0);
-                        }
-                        ContentBlock::Image { .. }
-                        | ContentBlock::ImageUrl { .. }
-                        | ContentBlock::InputImage { .. }
-                        | ContentBlock::Document { .. }
-                        | ContentBlock::OtherValue(_) => {
-                            chars += 64;
-                        }
-                    }
-                }
-            }
-            None => {}
-        }
-    }
-
-    if let Some(tools) = &request.tools {
-        chars += serde_json::to_string(tools)
-            .unwrap_or_default()
-            .chars()
-            .count();
-    }
-
-    ((chars as f64) / 4.0).ceil() as u64
-}
-
 impl ProxyServer {
     pub fn new(port: u16, target_url: String, api_key: Option<String>) -> Self {
         Self {
@@ -5257,6 +5724,7 @@ impl ProxyServer {
             openai_max_tokens_mapping: OpenAIMaxTokensMapping::default(),
             gemini_reasoning_effort: GeminiReasoningEffortMapping::default(),
             max_concurrency: 0,
+            max_queue_wait_ms: 0,
             ignore_probe_requests: false,
             allow_count_tokens_fallback_estimate: true,
             enable_codex_fast_mode: true,
@@ -5286,6 +5754,16 @@ impl ProxyServer {
             enable_stateful_responses_chain: true,
             load_balancer_runtime: None,
             codex_route_config: None,
+            log_dir: None,
+            admin_api: None,
+            client_tokens: Vec::new(),
+            rate_limit: RateLimitConfig::default(),
+            model_routes: ModelRouteTable::default(),
+            usage_ledger: None,
+            token_calibration: TokenCalibrationConfig::default(),
+            session_store: None,
+            pricing: Vec::new(),
+            budgets: BudgetsConfig::default(),
         }
     }
 
@@ -5339,6 +5817,11 @@ impl ProxyServer {
         self
     }
 
+    pub fn with_max_queue_wait_ms(mut self, wait_ms: u64) -> Self {
+        self.max_queue_wait_ms = wait_ms;
+        self
+    }
+
     pub fn with_allow_external_access(mut self, allow: bool) -> Self {
         self.allow_external_access = allow;
         self
@@ -5479,6 +5962,70 @@ impl ProxyServer {
         self
     }
 
+    /// 覆盖日志目录（默认 ~/.codexProxy/logs）
+    pub fn with_log_dir(mut self, log_dir: Option<String>) -> Self {
+        self.log_dir = log_dir;
+        self
+    }
+
+    /// 启用 `/admin` 管理接口（令牌为空时忽略）
+    pub fn with_admin_api(mut self, admin_api: AdminApiConfig) -> Self {
+        self.admin_api = if admin_api.token.trim().is_empty() {
+            None
+        } else {
+            Some(admin_api)
+        };
+        self
+    }
+
+    /// 配置客户端访问令牌（为空时不校验入站令牌）
+    pub fn with_client_tokens(mut self, client_tokens: Vec<ClientTokenConfig>) -> Self {
+        self.client_tokens = client_tokens;
+        self
+    }
+
+    /// 全局限流与 token 配额（各项为 0 表示不限）
+    pub fn with_rate_limit(mut self, rate_limit: RateLimitConfig) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// 本地 token 用量账本（默认不写入）
+    pub fn with_usage_ledger(mut self, usage_ledger: UsageLedgerConfig) -> Self {
+        self.usage_ledger = Some(usage_ledger).filter(|config| config.enabled);
+        self
+    }
+
+    /// 估算校准（默认开启；未指定路径且未启用账本时只保存在内存中）
+    pub fn with_token_calibration(mut self, token_calibration: TokenCalibrationConfig) -> Self {
+        self.token_calibration = token_calibration;
+        self
+    }
+
+    /// 有状态链与技能目录提醒的磁盘持久化（默认只保存在内存中）
+    pub fn with_session_store(mut self, session_store: SessionStoreConfig) -> Self {
+        self.session_store = Some(session_store).filter(|config| config.enabled);
+        self
+    }
+
+    /// 上游模型价目表（美元 / 百万 token）
+    pub fn with_pricing(mut self, pricing: Vec<ModelPriceConfig>) -> Self {
+        self.pricing = pricing;
+        self
+    }
+
+    /// 全局、端点与客户端的花费预算
+    pub fn with_budgets(mut self, budgets: BudgetsConfig) -> Self {
+        self.budgets = budgets;
+        self
+    }
+
+    /// 模型路由表（没有规则时按模型名归入 opus / sonnet / haiku）
+    pub fn with_model_routes(mut self, model_routes: ModelRouteTable) -> Self {
+        self.model_routes = model_routes;
+        self
+    }
+
     pub fn with_load_balancer_runtime(mut self, runtime: LoadBalancerRuntime) -> Self {
         self.load_balancer_runtime = Some(runtime);
         self
@@ -5565,6 +6112,11 @@ impl ProxyServer {
             enable_codex_tool_schema_compaction: self.enable_codex_tool_schema_compaction,
             enable_skill_routing_hint: self.enable_skill_routing_hint,
             enable_stateful_responses_chain: self.enable_stateful_responses_chain,
+            client_tokens: self.client_tokens.clone(),
+            rate_limit: self.rate_limit.clone(),
+            model_routes: self.model_routes.clone(),
+            pricing: self.pricing.clone(),
+            budgets: self.budgets.clone(),
             load_balancer_runtime: self.load_balancer_runtime.clone(),
         }
     }
@@ -5583,7 +6135,7 @@ impl ProxyServer {
         Box<dyn std::error::Error + Send + Sync>,
     > {
         // 初始化全局日志记录器
-        let logger = AppLogger::init(None);
+        let logger = AppLogger::init(self.log_dir.as_deref());
         logger.log("=== Codex Proxy Started ===");
 
         let addr = if self.allow_external_access {
@@ -5613,18 +6165,49 @@ impl ProxyServer {
             Arc::new(Mutex::new(HashSet::new()));
         let skill_catalog_reminders: SkillCatalogReminderStore =
             Arc::new(Mutex::new(HashMap::new()));
-        let runtime_handle = ProxyRuntimeHandle {
-            state: Arc::new(RwLock::new(RuntimeConfigState::from(self.runtime_update()))),
-        };
-        let runtime_handle_for_server = runtime_handle.clone();
+        let session_store = self.session_store.as_ref().map(|config| {
+            let persistence = Arc::new(SessionStorePersistence::new(
+                config,
+                Arc::clone(&stateful_chain_store),
+                Arc::clone(&skill_catalog_reminders),
+            ));
+            match persistence.load() {
+                Ok(report) => {
+                    let _ = log_tx.send(format!(
+                        "[System] Session store: {} chains={} skill_reminders={} dropped={} discarded_file={}",
+                        persistence.path().display(),
+                        report.chains,
+                        report.reminders,
+                        report.dropped,
+                        report.discarded_file
+                    ));
+                }
+                Err(err) => {
+                    let _ = log_tx.send(format!(
+                        "[Warning] session store load failed: {} ({})",
+                        persistence.path().display(),
+                        err
+                    ));
+                }
+            }
+            Arc::clone(&persistence).spawn_periodic_save(log_tx.clone(), shutdown_tx.subscribe());
+            persistence
+        });
+        let runtime_handle = ProxyRuntimeHandle::new(
+            self.runtime_update(),
+            Arc::new(BudgetTracker::new(Some(log_tx.clone()))),
+        );
 
         // 并发控制：0 = 不限制
-        let semaphore: Option<Arc<Semaphore>> = if self.max_concurrency > 0 {
+        let scheduler: Option<Arc<RequestScheduler>> = if self.max_concurrency > 0 {
             let _ = log_tx.send(format!(
-                "[System] Max concurrency: {}",
-                self.max_concurrency
+                "[System] Max concurrency: {} max_queue_wait_ms={}",
+                self.max_concurrency, self.max_queue_wait_ms
             ));
-            Some(Arc::new(Semaphore::new(self.max_concurrency as usize)))
+            Some(Arc::new(RequestScheduler::new(
+                self.max_concurrency,
+                self.max_queue_wait_ms,
+            )))
         } else {
             None
         };
@@ -5637,6 +6220,88 @@ impl ProxyServer {
                 .unwrap(),
         );
 
+        let usage_ledger = self.usage_ledger.as_ref().and_then(|config| {
+            let path = config
+                .path
+                .as_deref()
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .unwrap_or_else(UsageLedger::default_path);
+            match UsageLedger::open(&path) {
+                Ok(ledger) => {
+                    let _ = log_tx.send(format!("[System] Usage ledger: {}", path.display()));
+                    Some(Arc::new(ledger))
+                }
+                Err(err) => {
+                    let _ = log_tx.send(format!(
+                        "[Warning] usage ledger disabled: {} ({})",
+                        path.display(),
+                        err
+                    ));
+                    None
+                }
+            }
+        });
+
+        // 校准系数优先写到配置的路径，否则与账本放在同一目录；两者都没有时只保存在内存中
+        let token_calibration = self.token_calibration.enabled.then(|| {
+            let path = self
+                .token_calibration
+                .path
+                .as_deref()
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| {
+                    usage_ledger
+                        .as_ref()
+                        .map(|ledger| ledger.path().with_file_name(CALIBRATION_FILE_NAME))
+                });
+            Arc::new(path.map(TokenCalibration::open).unwrap_or_default())
+        });
+
+        // 从账本回放当日与当月的花费，重启后预算不清零
+        if let Some(ledger) = usage_ledger.clone() {
+            let budget = Arc::clone(&runtime_handle.budget);
+            tokio::task::spawn_blocking(move || {
+                if let Ok(records) = ledger.records() {
+                    budget.seed(records);
+                }
+            });
+        }
+
+        probe::spawn_health_prober(
+            runtime_handle.clone(),
+            Arc::clone(&http_client),
+            log_tx.clone(),
+            shutdown_tx.subscribe(),
+        );
+
+        let services = RequestServices {
+            runtime_handle: runtime_handle.clone(),
+            http_client,
+            scheduler,
+            model_cooldowns,
+            parallel_tool_degrade_until,
+            stateful_chain_store,
+            stateful_chain_unsupported_endpoints,
+            gemini_explicit_cache_store,
+            gemini_explicit_cache_unsupported_endpoints,
+            codex_v1_unsupported_endpoints,
+            codex_fast_unsupported_endpoints,
+            skill_catalog_reminders,
+            stats: Arc::new(ServerStats::new(self.max_concurrency)),
+            rate_limiter: Arc::new(RateLimiter::default()),
+            usage_ledger,
+            token_calibration,
+            admin: self
+                .admin_api
+                .clone()
+                .map(|admin_api| Arc::new(AdminState::new(admin_api))),
+            log_tx: log_tx.clone(),
+        };
+
         let listen_host = if self.allow_external_access {
             "0.0.0.0"
         } else {
@@ -5647,6 +6312,9 @@ impl ProxyServer {
             listen_host, self.port
         ));
         let _ = log_tx.send(format!("[System] Target: {}", self.target_url));
+        for warning in self.model_routes.warnings() {
+            let _ = log_tx.send(format!("[Warn] model_routes {}", warning));
+        }
         logger.log(&format!(
             "Listening on http://{}:{}",
             listen_host, self.port
@@ -5666,27 +6334,8 @@ impl ProxyServer {
                         match result {
                             Ok((stream, peer_addr)) => {
                                 let io = TokioIo::new(stream);
-                                let http_client = Arc::clone(&http_client);
-                                let semaphore = semaphore.clone();
-                                let model_cooldowns = Arc::clone(&model_cooldowns);
-                                let parallel_tool_degrade_until =
-                                    Arc::clone(&parallel_tool_degrade_until);
-                                let stateful_chain_store = Arc::clone(&stateful_chain_store);
-                                let stateful_chain_unsupported_endpoints =
-                                    Arc::clone(&stateful_chain_unsupported_endpoints);
-                                let gemini_explicit_cache_store =
-                                    Arc::clone(&gemini_explicit_cache_store);
-                                let gemini_explicit_cache_unsupported_endpoints =
-                                    Arc::clone(&gemini_explicit_cache_unsupported_endpoints);
-                                let codex_v1_unsupported_endpoints =
-                                    Arc::clone(&codex_v1_unsupported_endpoints);
-                                let codex_fast_unsupported_endpoints =
-                                    Arc::clone(&codex_fast_unsupported_endpoints);
-                                let skill_catalog_reminders =
-                                    Arc::clone(&skill_catalog_reminders);
-                                let runtime_handle = runtime_handle_for_server.clone();
+                                let services = services.clone();
                                 let log_tx = log_tx.clone();
-                                let log_tx_for_request = log_tx.clone();
                                 let logger_for_conn = logger.clone();
                                 let conn_id: String = Uuid::new_v4()
                                     .simple()
@@ -5696,24 +6345,8 @@ impl ProxyServer {
                                     .collect();
 
                                 conn_tasks.spawn(async move {
-                                    let service = service_fn(move |req| {
-                                        handle_request(
-                                            req,
-                                            runtime_handle.clone(),
-                                            Arc::clone(&http_client),
-                                            semaphore.clone(),
-                                            Arc::clone(&model_cooldowns),
-                                            Arc::clone(&parallel_tool_degrade_until),
-                                            Arc::clone(&stateful_chain_store),
-                                            Arc::clone(&stateful_chain_unsupported_endpoints),
-                                            Arc::clone(&gemini_explicit_cache_store),
-                                            Arc::clone(&gemini_explicit_cache_unsupported_endpoints),
-                                            Arc::clone(&codex_v1_unsupported_endpoints),
-                                            Arc::clone(&codex_fast_unsupported_endpoints),
-                                            Arc::clone(&skill_catalog_reminders),
-                                            log_tx_for_request.clone(),
-                                        )
-                                    });
+                                    let service =
+                                        service_fn(move |req| handle_request(req, services.clone()));
 
                                     if let Err(e) = auto::Builder::new(TokioExecutor::new())
                                         .serve_connection(io, service)
@@ -5760,6 +6393,12 @@ impl ProxyServer {
                     _ = shutdown_rx.recv() => {
                         let _ = log_tx.send("[System] Proxy server shutting down, aborting all connections...".to_string());
                         conn_tasks.abort_all();
+                        if let Some(token_calibration) = services.token_calibration.as_ref() {
+                            let _ = token_calibration.flush();
+                        }
+                        if let Some(session_store) = session_store.as_ref() {
+                            let _ = session_store.save_if_changed();
+                        }
                         break;
                     }
                 }
@@ -5770,11 +6409,12 @@ impl ProxyServer {
     }
 }
 
-async fn handle_request(
-    req: Request<hyper::body::Incoming>,
+/// 每个请求共享的服务端状态：运行时配置句柄、上游 client、并发控制与各类缓存/冷却表
+#[derive(Clone)]
+struct RequestServices {
     runtime_handle: ProxyRuntimeHandle,
     http_client: Arc<reqwest::Client>,
-    semaphore: Option<Arc<Semaphore>>,
+    scheduler: Option<Arc<RequestScheduler>>,
     model_cooldowns: Arc<Mutex<HashMap<String, Instant>>>,
     parallel_tool_degrade_until: Arc<Mutex<HashMap<String, Instant>>>,
     stateful_chain_store: StatefulChainStore,
@@ -5784,43 +6424,495 @@ async fn handle_request(
     codex_v1_unsupported_endpoints: CodexV1UnsupportedEndpointStore,
     codex_fast_unsupported_endpoints: CodexFastUnsupportedEndpointStore,
     skill_catalog_reminders: SkillCatalogReminderStore,
+    stats: Arc<ServerStats>,
+    rate_limiter: Arc<RateLimiter>,
+    usage_ledger: Option<Arc<UsageLedger>>,
+    /// 关闭估算校准时为空
+    token_calibration: Option<Arc<TokenCalibration>>,
+    admin: Option<Arc<AdminState>>,
     log_tx: broadcast::Sender<String>,
-) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
-    let path = req.uri().path().to_string();
-    let normalized_path = path.trim_end_matches('/');
-    let method = req.method().clone();
-    let request_id: String = Uuid::new_v4()
-        .simple()
+}
+
+type HandlerResult = Result<Response<BoxBody<Bytes, Infallible>>, Infallible>;
+
+/// 协议适配入口（如 /v1/chat/completions）改写请求后重新进入主链路。
+/// 单独的函数返回装箱 future，避免 `handle_request` 递归时推导自身的 Send。
+fn dispatch_rewritten_request(
+    req: Request<Full<Bytes>>,
+    services: RequestServices,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = HandlerResult> + Send>> {
+    Box::pin(handle_request(req, services))
+}
+
+fn is_openai_chat_completions_path(routed_path: &str) -> bool {
+    routed_path == "/chat/completions" || routed_path == "/v1/chat/completions"
+}
+
+fn is_openai_responses_path(routed_path: &str) -> bool {
+    routed_path == "/responses" || routed_path == "/v1/responses"
+}
+
+fn openai_error_response(status: StatusCode, kind: &str, message: String) -> HandlerResult {
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(full_body(
+            json!({"error": {"message": message, "type": kind, "param": Value::Null, "code": kind}})
+                .to_string(),
+        ))
+        .unwrap())
+}
+
+/// 读取入站请求体并解析为 JSON；失败时直接给出 OpenAI 风格的 400 响应
+async fn read_inbound_json<B>(body: B) -> Result<Value, HandlerResult>
+where
+    B: hyper::body::Body<Data = Bytes>,
+    B::Error: std::fmt::Display,
+{
+    let body_bytes = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            return Err(openai_error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                format!("Failed to read body: {}", e),
+            ));
+        }
+    };
+    serde_json::from_slice(&body_bytes).map_err(|e| {
+        openai_error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            format!("Invalid JSON: {}", e),
+        )
+    })
+}
+
+/// 以改写后的 Anthropic Messages 请求重新进入 handle_request（沿用原请求头中的认证信息）
+async fn dispatch_inbound_messages(
+    headers: &hyper::HeaderMap,
+    messages_path: &str,
+    anthropic_body: Value,
+    services: RequestServices,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let mut inner = Request::builder().method(Method::POST).uri(messages_path);
+    for (name, value) in headers.iter() {
+        if name == hyper::header::CONTENT_LENGTH || name == hyper::header::CONTENT_TYPE {
+            continue;
+        }
+        inner = inner.header(name, value);
+    }
+    let inner_req = inner
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(anthropic_body.to_string())))
+        .unwrap();
+    let Ok(response) = dispatch_rewritten_request(inner_req, services).await;
+    response
+}
+
+fn is_event_stream_response(parts: &hyper::http::response::Parts) -> bool {
+    parts
+        .headers
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("text/event-stream"))
+        .unwrap_or(false)
+}
+
+/// 主链路输出的 Anthropic SSE 经 encoder 转换后流式返回给客户端
+fn reencode_inbound_stream<E>(body: BoxBody<Bytes, Infallible>, mut encoder: E) -> HandlerResult
+where
+    E: InboundStreamEncoder,
+{
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Frame<Bytes>, Infallible>>(256);
+    tokio::spawn(async move {
+        let mut upstream = body.into_data_stream();
+        while let Some(Ok(chunk)) = upstream.next().await {
+            for frame in encoder.push(&chunk) {
+                if tx.send(Ok(Frame::data(Bytes::from(frame)))).await.is_err() {
+                    return;
+                }
+            }
+        }
+        for frame in encoder.finish() {
+            if tx.send(Ok(Frame::data(Bytes::from(frame)))).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let body = StreamBody::new(tokio_stream::wrappers::ReceiverStream::new(rx));
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("Connection", "keep-alive")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(BoxBody::new(body.map_err(|_: Infallible| unreachable!())))
+        .unwrap())
+}
+
+/// 收集主链路的非流式输出（成功 message 或 Anthropic 错误体）
+async fn collect_inbound_json(body: BoxBody<Bytes, Infallible>) -> Value {
+    let collected = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(never) => match never {},
+    };
+    serde_json::from_slice(&collected).unwrap_or_else(|_| {
+        json!({"error": {"type": "api_error", "message": String::from_utf8_lossy(&collected)}})
+    })
+}
+
+/// 主链路响应中需要带回给入站 OpenAI 客户端的响应头（跨 slot 降级标记、重试等待）
+const INBOUND_FORWARDED_HEADERS: [&str; 2] = [DEGRADED_SLOT_HEADER, "retry-after"];
+
+/// 把主链路响应上的相关响应头复制到重新编码后的响应
+fn forward_inbound_headers(inner: &hyper::HeaderMap, mut result: HandlerResult) -> HandlerResult {
+    if let Ok(response) = result.as_mut() {
+        for name in INBOUND_FORWARDED_HEADERS {
+            if let Some(value) = inner.get(name) {
+                response.headers_mut().insert(name, value.clone());
+            }
+        }
+    }
+    result
+}
+
+fn inbound_json_response(status: StatusCode, payload: Value) -> HandlerResult {
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(full_body(payload.to_string()))
+        .unwrap())
+}
+
+/// 入站 OpenAI Chat Completions：解码为 Anthropic Messages 请求走主链路，再把输出编码回 OpenAI 格式
+async fn handle_openai_chat_completions<B>(
+    req: Request<B>,
+    request_id: &str,
+    services: RequestServices,
+) -> HandlerResult
+where
+    B: hyper::body::Body<Data = Bytes> + Send + 'static,
+    B::Error: std::fmt::Display,
+{
+    let log_tx = services.log_tx.clone();
+    let (parts, body) = req.into_parts();
+    let raw_body = match read_inbound_json(body).await {
+        Ok(value) => value,
+        Err(response) => return response,
+    };
+    let decoded = match decode_chat_completions_request(&raw_body) {
+        Ok(decoded) => decoded,
+        Err(message) => {
+            return openai_error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                message,
+            );
+        }
+    };
+
+    let client_model = decoded.unified.model.clone();
+    let _ = log_tx.send(format!(
+        "[Route] #{} inbound=openai_chat model={} stream={} messages={} tools={} -> /v1/messages",
+        request_id,
+        client_model,
+        decoded.unified.stream,
+        decoded.unified.messages.len(),
+        decoded.unified.tools.as_ref().map(Vec::len).unwrap_or(0),
+    ));
+
+    let response = dispatch_inbound_messages(
+        &parts.headers,
+        "/v1/messages",
+        decoded.to_anthropic_body(),
+        services,
+    )
+    .await;
+    let (response_parts, response_body) = response.into_parts();
+    if is_event_stream_response(&response_parts) && response_parts.status.is_success() {
+        return forward_inbound_headers(
+            &response_parts.headers,
+            reencode_inbound_stream(
+                response_body,
+                ChatCompletionsStreamEncoder::new(&client_model, decoded.include_usage),
+            ),
+        );
+    }
+
+    let payload = collect_inbound_json(response_body).await;
+    let encoded = if response_parts.status.is_success() {
+        encode_chat_completion(&payload, &client_model)
+    } else {
+        encode_chat_completion_error(&payload)
+    };
+    forward_inbound_headers(
+        &response_parts.headers,
+        inbound_json_response(response_parts.status, encoded),
+    )
+}
+
+/// 入站 Responses API（Codex 路由 + 非 Codex 上游）：解码为 Anthropic Messages 请求交给
+/// Gemini / OpenAI / Anthropic 适配器，再把输出编码回 Responses 事件流
+async fn handle_openai_responses<B>(
+    req: Request<B>,
+    request_id: &str,
+    converter: &str,
+    services: RequestServices,
+) -> HandlerResult
+where
+    B: hyper::body::Body<Data = Bytes> + Send + 'static,
+    B::Error: std::fmt::Display,
+{
+    let log_tx = services.log_tx.clone();
+    let (parts, body) = req.into_parts();
+    let raw_body = match read_inbound_json(body).await {
+        Ok(value) => value,
+        Err(response) => return response,
+    };
+    let decoded = match decode_responses_request(&raw_body) {
+        Ok(decoded) => decoded,
+        Err(message) => {
+            return openai_error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                message,
+            );
+        }
+    };
+
+    let client_model = decoded.unified.model.clone();
+    let _ = log_tx.send(format!(
+        "[Route] #{} inbound=responses converter={} model={} stream={} messages={} tools={} custom_tools={} -> /codex/v1/messages",
+        request_id,
+        converter,
+        client_model,
+        decoded.unified.stream,
+        decoded.unified.messages.len(),
+        decoded.unified.tools.as_ref().map(Vec::len).unwrap_or(0),
+        decoded.custom_tools.len(),
+    ));
+    if let Some(previous_response_id) = decoded.previous_response_id.as_deref() {
+        let _ = log_tx.send(format!(
+            "[Warning] #{} previous_response_id={} ignored: converter {} has no server-side response state",
+            request_id, previous_response_id, converter
+        ));
+    }
+
+    let response = dispatch_inbound_messages(
+        &parts.headers,
+        "/codex/v1/messages",
+        decoded.to_anthropic_body(),
+        services,
+    )
+    .await;
+    let (response_parts, response_body) = response.into_parts();
+    if is_event_stream_response(&response_parts) && response_parts.status.is_success() {
+        return forward_inbound_headers(
+            &response_parts.headers,
+            reencode_inbound_stream(
+                response_body,
+                ResponsesStreamEncoder::new(&client_model, decoded.custom_tools),
+            ),
+        );
+    }
+
+    let payload = collect_inbound_json(response_body).await;
+    let encoded = if response_parts.status.is_success() {
+        encode_response(&payload, &client_model, &decoded.custom_tools)
+    } else {
+        encode_response_error(&payload)
+    };
+    forward_inbound_headers(
+        &response_parts.headers,
+        inbound_json_response(response_parts.status, encoded),
+    )
+}
+
+async fn handle_request<B>(
+    req: Request<B>,
+    services: RequestServices,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible>
+where
+    B: hyper::body::Body<Data = Bytes> + Send + 'static,
+    B::Error: std::fmt::Display,
+{
+    let mut observation = RequestObservation::default();
+    let mut result = handle_request_inner(req, services, &mut observation).await;
+    if let Ok(response) = &mut result {
+        observation.apply_headers(response.headers_mut());
+        observation.finish(response.status().as_u16());
+    }
+    result
+}
+
+async fn handle_request_inner<B>(
+    mut req: Request<B>,
+    services: RequestServices,
+    observation: &mut RequestObservation,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible>
+where
+    B: hyper::body::Body<Data = Bytes> + Send + 'static,
+    B::Error: std::fmt::Display,
+{
+    let RequestServices {
+        runtime_handle,
+        http_client,
+        scheduler,
+        model_cooldowns,
+        parallel_tool_degrade_until,
+        stateful_chain_store,
+        stateful_chain_unsupported_endpoints,
+        gemini_explicit_cache_store,
+        gemini_explicit_cache_unsupported_endpoints,
+        codex_v1_unsupported_endpoints,
+        codex_fast_unsupported_endpoints,
+        skill_catalog_reminders,
+        stats,
+        rate_limiter,
+        usage_ledger,
+        token_calibration,
+        admin: _,
+        log_tx,
+    } = services.clone();
+    let path = req.uri().path().to_string();
+    let normalized_path = path.trim_end_matches('/');
+    let method = req.method().clone();
+    let request_id: String = Uuid::new_v4()
+        .simple()
         .to_string()
         .chars()
         .take(8)
         .collect();
     let request_started_at = Instant::now();
 
+    if let Some(admin_path) = normalized_path.strip_prefix("/admin") {
+        if admin_path.is_empty() || admin_path.starts_with('/') {
+            return handle_admin_request(req, admin_path, &services).await;
+        }
+    }
+
+    // 客户端访问令牌：探针与 CORS 预检不校验
+    let skip_client_auth =
+        method == Method::OPTIONS || (method == Method::GET && is_probe_path(normalized_path));
+    let client_identity = if skip_client_auth {
+        None
+    } else {
+        let (auth_route_kind, _) = normalize_client_route_path(normalized_path);
+        match authenticate_client(
+            &runtime_handle.snapshot().client_tokens,
+            req.headers(),
+            auth_route_kind,
+        ) {
+            Ok(identity) => identity,
+            Err(err) => {
+                observation.begin(auth_route_kind, "rejected");
+                return reject_client_request(
+                    &log_tx,
+                    &request_id,
+                    normalized_path,
+                    observation,
+                    err,
+                );
+            }
+        }
+    };
+    // 日志中的请求号带上客户端名，便于按客户端检索
+    let request_id = match client_identity.as_ref() {
+        Some(identity) => {
+            observation.set_client(&identity.name);
+            format!("{}@{}", request_id, identity.name)
+        }
+        None => request_id,
+    };
+    let client_label = client_identity
+        .as_ref()
+        .map(|identity| identity.name.clone())
+        .unwrap_or_else(|| "-".to_string());
+
     // 处理新增的 GET 路由
     if method == Method::GET {
-        match normalized_path {
-            "/v1/models" => {
+        let (models_route_kind, models_path) = normalize_client_route_path(normalized_path);
+        let runtime_state = runtime_handle.snapshot();
+        // Codex 路由走原生透传时 /codex/v1/models 由上游返回
+        let serve_local_models = models_route_kind == ClientRouteKind::Claude
+            || !runtime_state
+                .route_for(models_route_kind)
+                .ctx
+                .converter
+                .eq_ignore_ascii_case("codex");
+        if serve_local_models {
+            if models_path == "/v1/models" {
                 let _ = log_tx.send(format!(
-                    "[System] Processing #{} GET /v1/models",
-                    request_id
+                    "[System] Processing #{} GET {}",
+                    request_id, normalized_path
                 ));
-                return handle_models_list();
+                return handle_models_list(&runtime_state, models_route_kind, &model_cooldowns);
             }
-            path if path.starts_with("/v1/models/") => {
-                let model_id = &path[11..]; // 去掉 "/v1/models/" 前缀
+            if let Some(model_id) = models_path.strip_prefix("/v1/models/") {
                 let _ = log_tx.send(format!(
-                    "[System] Processing #{} GET /v1/models/{}",
-                    request_id, model_id
+                    "[System] Processing #{} GET {}",
+                    request_id, normalized_path
                 ));
-                return handle_model_detail(model_id);
+                return handle_model_detail(
+                    model_id,
+                    &runtime_state,
+                    models_route_kind,
+                    &model_cooldowns,
+                );
             }
+        }
+        match normalized_path {
             "/health" | "/" => {
                 let _ = log_tx.send(format!(
                     "[System] Processing #{} GET {}",
                     request_id, normalized_path
                 ));
-                return handle_health_check();
+                return health_json_response(StatusCode::OK, liveness_report(&stats));
+            }
+            "/metrics" => {
+                let mut text = crate::metrics::global().render();
+                text.push_str(&process_metrics_text(&stats, scheduler.as_deref()));
+                return Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                    .header("Cache-Control", "no-store")
+                    .body(full_body(text))
+                    .unwrap());
+            }
+            "/ready" | "/status" => {
+                let _ = log_tx.send(format!(
+                    "[System] Processing #{} GET {}",
+                    request_id, normalized_path
+                ));
+                let (ready, report) = build_status_report(&services, &runtime_state);
+                // /ready 供探针使用（免令牌）：只返回就绪结论，未就绪时 503；/status 始终 200
+                if normalized_path == "/ready" {
+                    let status = if ready {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    };
+                    return health_json_response(status, readiness_report(&report));
+                }
+                return health_json_response(StatusCode::OK, report);
+            }
+            "/usage" => {
+                let _ = log_tx.send(format!(
+                    "[System] Processing #{} GET {}",
+                    request_id, normalized_path
+                ));
+                let client = client_identity
+                    .as_ref()
+                    .map(|identity| identity.name.as_str());
+                return usage_report_response(
+                    usage_ledger.clone(),
+                    parse_usage_query(req.uri().query(), client),
+                )
+                .await;
             }
             _ => {
                 // 继续到 404 处理
@@ -5838,6 +6930,12 @@ async fn handle_request(
     }
 
     let (client_route_kind, routed_path) = normalize_client_route_path(normalized_path);
+    if method == Method::POST
+        && client_route_kind == ClientRouteKind::Claude
+        && is_openai_chat_completions_path(&routed_path)
+    {
+        return handle_openai_chat_completions(req, &request_id, services).await;
+    }
     let is_messages = routed_path == "/messages" || routed_path == "/v1/messages";
     let is_count_tokens =
         routed_path == "/messages/count_tokens" || routed_path == "/v1/messages/count_tokens";
@@ -5863,11 +6961,27 @@ async fn handle_request(
         request_id, client_route_kind, ctx.converter, routed_path,
         is_codex_native_passthrough_path(&routed_path)));
 
+    if client_route_kind == ClientRouteKind::Codex
+        && !ctx.converter.eq_ignore_ascii_case("codex")
+        && method == Method::POST
+        && is_openai_responses_path(&routed_path)
+    {
+        return handle_openai_responses(req, &request_id, &ctx.converter, services).await;
+    }
+
+    if client_identity.is_some() {
+        // 入站令牌只用于本地鉴权，不能作为上游 key 转发
+        req.headers_mut().remove("authorization");
+        req.headers_mut().remove("x-api-key");
+    }
+
     if client_route_kind == ClientRouteKind::Codex
         && ctx.converter.eq_ignore_ascii_case("codex")
         && is_codex_native_passthrough_path(&routed_path)
     {
         let _ = log_tx.send(format!("[Debug] #{} Entering handle_codex_native_passthrough", request_id));
+        observation.begin(client_route_kind, "passthrough");
+        observation.set_upstream("codex", "single");
         return handle_codex_native_passthrough(
             req,
             &request_id,
@@ -5901,23 +7015,15 @@ async fn handle_request(
         req.method(),
         path
     ));
-
-    // 并发控制：获取许可证，FIFO 排队
-    let permit: Option<OwnedSemaphorePermit> = if let Some(ref sem) = semaphore {
-        let _ = log_tx.send(format!(
-            "[System] #{} waiting for concurrency permit (available: {})",
-            request_id,
-            sem.available_permits(),
-        ));
-        Some(
-            Arc::clone(sem)
-                .acquire_owned()
-                .await
-                .expect("semaphore closed"),
-        )
-    } else {
-        None
-    };
+    let in_flight_guard = InFlightGuard::new(&stats);
+    observation.begin(
+        client_route_kind,
+        if is_count_tokens {
+            "count_tokens"
+        } else {
+            "messages"
+        },
+    );
 
     // 获取认证信息
     let auth_header = req
@@ -6026,6 +7132,10 @@ async fn handle_request(
     let stateful_chain_hint_info =
         resolve_stateful_chain_hint_info(stateful_chain_hint_header, &anthropic_body);
     let stateful_chain_hint = stateful_chain_hint_info.value.clone();
+    // 会话粘性路由使用的会话标识：与有状态链路同源，其次取请求中的 session 提示
+    let lb_session_hint = stateful_chain_hint
+        .clone()
+        .or_else(|| request_hints.session_hint.clone());
     let skill_catalog_cache_key =
         derive_skill_catalog_cache_key(stateful_chain_hint.as_deref(), &anthropic_body);
 
@@ -6071,7 +7181,155 @@ async fn handle_request(
         .unwrap_or("claude-3-5-sonnet-20240620")
         .to_string();
     let input_model = input_model_owned.as_str();
-    let input_slot = ModelSlot::from_model_name(&input_model);
+    let mut model_route = runtime_state
+        .model_routes
+        .resolve(input_model, request_hints.request_kind)
+        .cloned();
+    // 未命中规则的未知模型族按配置走默认路由、拒绝或归入 sonnet slot
+    if model_route.is_none() && detect_model_family(input_model).is_none() {
+        match runtime_state.model_routes.unmatched() {
+            UnmatchedModels::Route(name) => {
+                model_route = runtime_state.model_routes.route(name).cloned();
+            }
+            UnmatchedModels::Reject => {
+                let _ = log_tx.send(format!(
+                    "[Warn] #{} model_route=none model={} unmatched=reject",
+                    request_id, input_model,
+                ));
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .header("Content-Type", "application/json")
+                    .body(full_body(
+                        json!({
+                            "type": "error",
+                            "error": {
+                                "type": "invalid_request_error",
+                                "message": format!(
+                                    "Model '{}' does not match any configured model route",
+                                    input_model
+                                )
+                            }
+                        })
+                        .to_string(),
+                    ))
+                    .unwrap());
+            }
+            UnmatchedModels::SonnetSlot => {
+                let _ = log_tx.send(format!(
+                    "[Route] #{} model_route=none model={} fallback_slot={}",
+                    request_id,
+                    input_model,
+                    ModelSlot::Sonnet.as_str(),
+                ));
+            }
+        }
+    }
+    let input_slot = match model_route.as_ref() {
+        Some(route) => {
+            ctx = route.apply_to_context(&ctx);
+            let _ = log_tx.send(format!(
+                "[Route] #{} model_route={} model={} kind={} slot={} upstream_model={}",
+                request_id,
+                route.name,
+                input_model,
+                request_hints.request_kind.as_str(),
+                route.slot.as_str(),
+                route.model.as_deref().unwrap_or("-"),
+            ));
+            route.slot
+        }
+        None => ModelSlot::from_model_name(input_model),
+    };
+    if let Some(identity) = client_identity.as_ref() {
+        if !identity.allows_slot(input_slot) {
+            return reject_client_request(
+                &log_tx,
+                &request_id,
+                normalized_path,
+                observation,
+                ClientAuthError::SlotNotAllowed {
+                    client: identity.name.clone(),
+                    slot: input_slot.as_str(),
+                },
+            );
+        }
+    }
+
+    // 全局或客户端硬预算：触达后拒绝到周期重置
+    if !is_count_tokens {
+        let client = client_identity
+            .as_ref()
+            .map(|identity| identity.name.as_str());
+        if let Some((scope, breach)) = runtime_handle.budget.admission_breach(client) {
+            let rejection = RateLimitRejection {
+                scope,
+                limit: match breach.period {
+                    BudgetPeriod::Day => "daily_budget",
+                    BudgetPeriod::Month => "monthly_budget",
+                },
+                retry_after_secs: breach.resets_in.as_secs().max(1),
+            };
+            return reject_rate_limited_request(&log_tx, &request_id, rejection);
+        }
+    }
+
+    // 并发控制：按优先级排队，同一优先级内按会话轮转
+    let permit = match scheduler.as_ref() {
+        Some(scheduler) => {
+            let priority = RequestPriority::classify(request_hints.request_kind, input_slot);
+            let session = lb_session_hint.as_deref().unwrap_or(&client_label);
+            match scheduler.acquire(priority, session).await {
+                Ok(admission) => {
+                    crate::metrics::observe_queue_wait(
+                        priority.as_str(),
+                        admission.waited.as_secs_f64(),
+                    );
+                    if admission.queue_depth > 0 {
+                        let _ = log_tx.send(format!(
+                            "[System] #{} concurrency permit acquired priority={} waited_ms={} queue_depth={}",
+                            request_id,
+                            priority.as_str(),
+                            admission.waited.as_millis(),
+                            admission.queue_depth,
+                        ));
+                    }
+                    Some(admission.permit)
+                }
+                Err(timeout) => {
+                    let _ = log_tx.send(format!(
+                        "[Warning] #{} queue_timeout priority={} waited_ms={} queue_depth={}",
+                        request_id,
+                        priority.as_str(),
+                        timeout.waited.as_millis(),
+                        timeout.queue_depth,
+                    ));
+                    crate::metrics::record_queue_timeout(priority.as_str());
+                    return timeout.into_response();
+                }
+            }
+        }
+        None => None,
+    };
+
+    // 限流与配额放在预算与排队之后：被拒绝或排队超时的请求不计入请求数、不占并发流名额
+    // （count_tokens 不消耗上游额度，不计入）
+    let rate_limit_slot = if is_count_tokens {
+        None
+    } else {
+        let client_limits = client_identity
+            .as_ref()
+            .map(|identity| (identity.name.as_str(), &identity.rate_limit));
+        match rate_limiter.admit(
+            client_limits,
+            &runtime_state.rate_limit,
+            anthropic_body.stream,
+        ) {
+            Ok(slot) => slot,
+            Err(rejection) => {
+                return reject_rate_limited_request(&log_tx, &request_id, rejection);
+            }
+        }
+    };
 
     // count_tokens 请求不计入统计
     if !is_count_tokens {
@@ -6111,10 +7369,14 @@ async fn handle_request(
             &request_id,
             input_model,
             input_slot,
+            model_route.as_ref(),
             &target_url,
             &final_api_key,
             &ctx,
             load_balancer_runtime.as_ref(),
+            lb_session_hint.as_deref(),
+            client_identity.as_ref(),
+            None,
             &log_tx,
         ) {
             Ok(selection) => selection,
@@ -6136,6 +7398,7 @@ async fn handle_request(
             .as_ref()
             .map(|route| route.endpoint_id.as_str())
             .unwrap_or("single");
+        observation.set_upstream(&route_selection.converter, route_endpoint);
         let route_key = route_selection
             .route
             .as_ref()
@@ -6318,6 +7581,7 @@ async fn handle_request(
                             if is_codex_v1_responses_path(&count_tokens_endpoint)
                                 && should_retry_codex_v1_path_with_legacy(status, &error_text)
                             {
+                                crate::metrics::record_retry("codex_v1_legacy_path");
                                 let fallback_endpoint =
                                     resolve_upstream_url_with_codex_path_preference(
                                         &route_selection.converter,
@@ -6400,8 +7664,23 @@ async fn handle_request(
         let input_tokens = if let Some(tokens) = token_count {
             tokens
         } else if allow_count_tokens_fallback_estimate {
-            source = "estimate".to_string();
-            estimate_input_tokens(&anthropic_body)
+            let family = TokenizerFamily::for_upstream(
+                &route_selection.converter,
+                &route_selection.model_name,
+            );
+            let estimate = count_request_tokens(&anthropic_body, family);
+            let (tokens, factor) = match token_calibration.as_ref() {
+                Some(calibration) => {
+                    calibration.calibrate(route_endpoint, &route_selection.model_name, estimate)
+                }
+                None => (estimate, None),
+            };
+            source = if factor.is_some() {
+                format!("estimate_{}_calibrated", family.as_str())
+            } else {
+                format!("estimate_{}", family.as_str())
+            };
+            tokens
         } else {
             let _ = log_tx.send(format!(
                 "[Tokens] #{} failed mode={} slot={} endpoint={} route_key={} upstream_status={} fallback=disabled",
@@ -6455,7 +7734,15 @@ async fn handle_request(
 
     let max_lb_attempts = load_balancer_runtime
         .as_ref()
-        .map(|runtime| runtime.candidate_count_for_model(input_model).max(1))
+        .map(|runtime| {
+            match model_route.as_ref() {
+                Some(route) if route.has_endpoints => {
+                    runtime.candidate_count_for_route(&route.name)
+                }
+                _ => runtime.candidate_count_in_slot(input_slot),
+            }
+            .max(1)
+        })
         .unwrap_or(1);
 
     if let Some(ref l) = logger {
@@ -6463,21 +7750,26 @@ async fn handle_request(
     }
 
     let mut attempt_index = 0usize;
-    let mut successful_response: Option<reqwest::Response> = None;
-    let mut successful_backend: Option<Arc<dyn TransformBackend>> = None;
-    let mut successful_model = String::new();
-    let mut successful_converter = String::new();
-    let mut successful_upstream_status: Option<u16> = None;
-    let mut successful_lb_permit: Option<EndpointPermit> = None;
-    let mut successful_resolved_target_url = String::new();
-    let mut successful_api_key = String::new();
-    let mut successful_upstream_body: Option<Value> = None;
-    let mut successful_parallel_tool_degrade_key: Option<String> = None;
-    let mut successful_session_id = String::new();
-    let mut successful_stateful_chain_meta: Option<StatefulChainRequestMeta> = None;
-    let mut successful_effective_stream = anthropic_body.stream;
+    let mut successful: Option<SuccessfulAttempt> = None;
+    let allow_visible_thinking_for_request = !anthropic_body.is_thinking_disabled();
+    // 对冲只用于负载均衡下的对话轮次，会话标题、探测这类请求不值得重复付费
+    let hedge_delay = load_balancer_runtime
+        .as_ref()
+        .and_then(|runtime| runtime.hedge_delay())
+        .filter(|_| request_hints.request_kind.is_conversation());
+    let hedge_race_timeout = Duration::from_millis(stream_opts.stall_timeout_ms.max(1_000));
+    let mut hedge_primary: Option<HedgePrimary> = None;
 
     while attempt_index < max_lb_attempts {
+        if let Some(mut primary) = hedge_primary.take() {
+            // 对冲候选失败并切换到了下一轮：不再继续尝试，直接沿用主候选
+            if primary.hedge_attempted {
+                successful = Some(primary.fall_back(&log_tx, &request_id, "hedge_attempt_failed"));
+                break;
+            }
+            primary.hedge_attempted = true;
+            hedge_primary = Some(primary);
+        }
         attempt_index += 1;
         let _ = log_tx.send(format!(
             "[LB] #{} request_attempt={}/{} slot={}",
@@ -6491,15 +7783,27 @@ async fn handle_request(
             &request_id,
             input_model,
             input_slot,
+            model_route.as_ref(),
             &target_url,
             &final_api_key,
             &ctx,
             load_balancer_runtime.as_ref(),
+            lb_session_hint.as_deref(),
+            client_identity.as_ref(),
+            hedge_primary.as_ref().map(HedgePrimary::route),
             &log_tx,
         ) {
             Ok(selection) => selection,
-            Err(response) => return Ok(response),
+            Err(response) => {
+                if let Some(primary) = hedge_primary.take() {
+                    successful =
+                        Some(primary.fall_back(&log_tx, &request_id, "no_hedge_candidate"));
+                    break;
+                }
+                return Ok(response);
+            }
         };
+        let attempt_started_at = Instant::now();
 
         let route_mode = if route_selection.route.is_some() {
             "lb"
@@ -6516,6 +7820,7 @@ async fn handle_request(
             .as_ref()
             .map(|route| route.endpoint_id.as_str())
             .unwrap_or("single");
+        observation.set_upstream(&route_selection.converter, route_endpoint);
         let route_key = route_selection
             .route
             .as_ref()
@@ -6627,6 +7932,7 @@ async fn handle_request(
             }
 
             if load_balancer_runtime.is_some() && attempt_index < max_lb_attempts {
+                crate::metrics::record_retry("lb_failover");
                 let _ = log_tx.send(format!(
                     "[LB] #{} failover continue reason=local_cooldown from_route={}",
                     request_id, route_key
@@ -6634,6 +7940,11 @@ async fn handle_request(
                 continue;
             }
 
+            if let Some(primary) = hedge_primary.take() {
+                successful = Some(primary.fall_back(&log_tx, &request_id, "local_cooldown"));
+                break;
+            }
+
             let payload = json!({
                 "error": {
                     "type": "rate_limit_error",
@@ -6933,6 +8244,7 @@ async fn handle_request(
                     && load_balancer_runtime.is_some()
                     && attempt_index < max_lb_attempts
                 {
+                    crate::metrics::record_retry("lb_failover");
                     let _ = log_tx.send(format!(
                         "[LB] #{} failover continue reason=network_error from_route={}",
                         request_id, route_key
@@ -6940,6 +8252,11 @@ async fn handle_request(
                     continue;
                 }
 
+                if let Some(primary) = hedge_primary.take() {
+                    successful = Some(primary.fall_back(&log_tx, &request_id, "network_error"));
+                    break;
+                }
+
                 return Ok(Response::builder()
                     .status(StatusCode::BAD_GATEWAY)
                     .header("Content-Type", "application/json")
@@ -6967,6 +8284,7 @@ async fn handle_request(
                 && is_codex_v1_responses_path(&resolved_target_url)
                 && should_retry_codex_v1_path_with_legacy(status, &error_text)
             {
+                crate::metrics::record_retry("codex_v1_legacy_path");
                 let fallback_target_url = resolve_upstream_url_with_codex_path_preference(
                     &route_selection.converter,
                     &route_selection.target_url,
@@ -7076,6 +8394,7 @@ async fn handle_request(
                 if let Some(fallback_body) =
                     remove_priority_service_tier_from_upstream_body(&upstream_body)
                 {
+                    crate::metrics::record_retry("codex_fast_without_service_tier");
                     emit_stream_diag(
                         &log_tx,
                         &logger,
@@ -7169,6 +8488,7 @@ async fn handle_request(
                     && is_previous_response_id_unsupported_error(status, &error_text);
 
             if can_retry_without_previous_response_id {
+                crate::metrics::record_retry("stateful_chain_without_previous_response_id");
                 let mut retry_body = upstream_body.clone();
                 if let Some(obj) = retry_body.as_object_mut() {
                     obj.remove("previous_response_id");
@@ -7279,7 +8599,28 @@ async fn handle_request(
                     ));
                 }
             } else {
-                let action = if let (Some(runtime), Some(route)) = (
+                let cooldown_info = extract_cooldown_info(
+                    status,
+                    &error_text,
+                    &retry_after,
+                    &route_selection.model_name,
+                );
+                // 密钥池端点只冷却当前密钥、换密钥重试，不计入路由健康也不设置模型冷却
+                let key_cooled_down = if let (Some(runtime), Some(route)) = (
+                    load_balancer_runtime.as_ref(),
+                    route_selection.route.as_ref(),
+                ) {
+                    let retry_after_secs = cooldown_info
+                        .as_ref()
+                        .map(|(_, secs, _)| *secs)
+                        .or_else(|| parse_seconds_str(&retry_after));
+                    runtime.cool_down_key(route, status, retry_after_secs)
+                } else {
+                    false
+                };
+                let action = if key_cooled_down {
+                    UpstreamOutcomeAction::RetryNextCandidate
+                } else if let (Some(runtime), Some(route)) = (
                     load_balancer_runtime.as_ref(),
                     route_selection.route.as_ref(),
                 ) {
@@ -7310,12 +8651,9 @@ async fn handle_request(
                 ),
                 );
 
-                if let Some((cooldown_model, cooldown_secs, reason)) = extract_cooldown_info(
-                    status,
-                    &error_text,
-                    &retry_after,
-                    &route_selection.model_name,
-                ) {
+                if let Some((cooldown_model, cooldown_secs, reason)) =
+                    cooldown_info.filter(|_| !key_cooled_down)
+                {
                     set_model_cooldown(&model_cooldowns, &cooldown_model, cooldown_secs);
                     let _ = log_tx.send(format!(
                         "[RateLimit] #{} upstream=429 reason={} model={} retry_after={}s in={} out={} msgs={} summary={}",
@@ -7347,6 +8685,7 @@ async fn handle_request(
                     && load_balancer_runtime.is_some()
                     && attempt_index < max_lb_attempts
                 {
+                    crate::metrics::record_retry("lb_failover");
                     let _ = log_tx.send(format!(
                         "[LB] #{} failover continue reason=upstream_status_{} from_route={}",
                         request_id, status, route_key
@@ -7354,6 +8693,15 @@ async fn handle_request(
                     continue;
                 }
 
+                if let Some(primary) = hedge_primary.take() {
+                    successful = Some(primary.fall_back(
+                        &log_tx,
+                        &request_id,
+                        &format!("upstream_status_{}", status),
+                    ));
+                    break;
+                }
+
                 return Ok(Response::builder()
                     .status(StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY))
                     .header("Content-Type", "application/json")
@@ -7370,38 +8718,142 @@ async fn handle_request(
             runtime.handle_upstream_outcome(route, Some(upstream_status), false, None);
         }
 
-        successful_response = Some(response);
-        successful_backend = Some(request_backend);
-        successful_model = route_selection.model_name.clone();
-        successful_converter = route_selection.converter.clone();
-        successful_upstream_status = Some(upstream_status);
-        successful_lb_permit = route_selection.route_permit.take();
-        successful_resolved_target_url = resolved_target_url.clone();
-        successful_api_key = route_selection.api_key.clone();
-        successful_upstream_body = Some(upstream_body.clone());
-        successful_parallel_tool_degrade_key = Some(parallel_tool_degrade_key);
-        successful_session_id = session_id.clone();
-        successful_stateful_chain_meta = stateful_chain_meta_for_attempt;
-        successful_effective_stream = effective_stream_for_attempt;
+        let mut attempt = SuccessfulAttempt {
+            response: Some(response),
+            hedged_stream: None,
+            backend: request_backend,
+            model: route_selection.model_name.clone(),
+            converter: route_selection.converter.clone(),
+            endpoint: route_endpoint.to_string(),
+            upstream_status,
+            lb_permit: route_selection.route_permit.take(),
+            resolved_target_url: resolved_target_url.clone(),
+            api_key: route_selection.api_key.clone(),
+            upstream_body: upstream_body.clone(),
+            parallel_tool_degrade_key: Some(parallel_tool_degrade_key),
+            session_id: session_id.clone(),
+            stateful_chain_meta: stateful_chain_meta_for_attempt,
+            effective_stream: effective_stream_for_attempt,
+            lb_feedback: load_balancer_runtime
+                .as_ref()
+                .zip(route_selection.route.as_ref())
+                .map(|(runtime, route)| LbLatencyFeedback {
+                    runtime: runtime.clone(),
+                    route: route.clone(),
+                    attempt_started_at,
+                }),
+        };
+
+        if let Some(primary) = hedge_primary.take() {
+            // 对冲候选也拿到了响应头：两路竞速首个业务事件，败者直接断开
+            successful = Some(
+                match PendingStream::from_attempt(
+                    &mut attempt,
+                    allow_visible_thinking_for_request,
+                    &response_transform_request_ctx,
+                    stream_opts.enable_sse_frame_parser,
+                    attempt_started_at,
+                ) {
+                    Some(pending) => {
+                        primary
+                            .settle(attempt, pending, hedge_race_timeout, &log_tx, &request_id)
+                            .await
+                    }
+                    None => primary.fall_back(&log_tx, &request_id, "hedge_not_streaming"),
+                },
+            );
+            break;
+        }
+
+        if let (Some(delay), Some(route)) = (hedge_delay, route_selection.route.clone()) {
+            if effective_stream_for_attempt && attempt_index < max_lb_attempts {
+                if let Some(mut pending) = PendingStream::from_attempt(
+                    &mut attempt,
+                    allow_visible_thinking_for_request,
+                    &response_transform_request_ctx,
+                    stream_opts.enable_sse_frame_parser,
+                    attempt_started_at,
+                ) {
+                    if tokio::time::timeout(delay, pending.wait_business())
+                        .await
+                        .is_err()
+                    {
+                        hedge_primary = Some(HedgePrimary::start(
+                            attempt,
+                            pending,
+                            route,
+                            delay,
+                            &log_tx,
+                            &request_id,
+                        ));
+                        continue;
+                    }
+                    attempt.hedged_stream = Some(pending.into_stream());
+                }
+            }
+        }
+
+        successful = Some(attempt);
         break;
     }
 
-    let response = successful_response.expect("upstream response must exist after successful loop");
-    let request_backend = successful_backend.expect("backend must exist after successful loop");
-    let model = successful_model;
-    let request_converter = successful_converter;
-    let upstream_status =
-        successful_upstream_status.expect("upstream status must exist after successful loop");
-    let resolved_target_url_for_stream = successful_resolved_target_url;
-    let api_key_for_stream = successful_api_key;
-    let upstream_body_for_stream =
-        successful_upstream_body.expect("upstream body must exist after successful loop");
-    let parallel_tool_degrade_key_for_stream = successful_parallel_tool_degrade_key;
-    let session_id_for_request = successful_session_id;
-    let stateful_chain_meta_for_request = successful_stateful_chain_meta;
-    let _lb_permit = successful_lb_permit;
-    let allow_visible_thinking_for_request = !anthropic_body.is_thinking_disabled();
-    let effective_stream = successful_effective_stream;
+    let SuccessfulAttempt {
+        response,
+        hedged_stream,
+        backend: request_backend,
+        model,
+        converter: request_converter,
+        endpoint: request_endpoint,
+        upstream_status,
+        lb_permit: _lb_permit,
+        resolved_target_url: resolved_target_url_for_stream,
+        api_key: api_key_for_stream,
+        upstream_body: upstream_body_for_stream,
+        parallel_tool_degrade_key: parallel_tool_degrade_key_for_stream,
+        session_id: session_id_for_request,
+        stateful_chain_meta: stateful_chain_meta_for_request,
+        effective_stream,
+        lb_feedback,
+    } = successful.expect("upstream response must exist after successful loop");
+    // 对冲胜出者可能不是最后一次尝试
+    observation.set_upstream(&request_converter, &request_endpoint);
+    let estimate_family = TokenizerFamily::for_upstream(&request_converter, &model);
+    let usage_tags = UsageTags {
+        ledger: usage_ledger.clone(),
+        budget: Arc::clone(&runtime_handle.budget),
+        request_id: request_id.clone(),
+        session: lb_session_hint.clone(),
+        kind: request_hints.request_kind.as_str(),
+        slot: lb_feedback
+            .as_ref()
+            .map_or(input_slot, |feedback| feedback.route.slot)
+            .as_str(),
+        model: model.clone(),
+        // 系数只用于 count_tokens 的回退估算，两者都开启时才需要估算本次输入
+        estimate: token_calibration
+            .as_ref()
+            .filter(|_| allow_count_tokens_fallback_estimate)
+            .map(|calibration| InputEstimate {
+                calibration: Arc::clone(calibration),
+                family: estimate_family,
+                request: Arc::new(anthropic_body.clone()),
+                log_tx: log_tx.clone(),
+            }),
+    };
+    if let Some(feedback) = lb_feedback.as_ref() {
+        if let Some(degraded_from) = feedback.route.degraded_from {
+            let _ = log_tx.send(format!(
+                "[Route] #{} degraded_slot from={} to={} endpoint={}",
+                request_id,
+                degraded_from.as_str(),
+                feedback.route.slot.as_str(),
+                feedback.route.endpoint_id,
+            ));
+            if feedback.runtime.degradation_header_enabled() {
+                observation.set_degraded_slot(degraded_from.as_str(), feedback.route.slot.as_str());
+            }
+        }
+    }
 
     let _ = log_tx.send(format!(
         "[System] #{} Request transformed and forwarding to upstream API",
@@ -7416,6 +8868,7 @@ async fn handle_request(
     }
 
     if request_converter.eq_ignore_ascii_case("anthropic") && !effective_stream {
+        let response = response.expect("non-stream responses are never hedged");
         let content_type = response
             .headers()
             .get("content-type")
@@ -7441,6 +8894,7 @@ async fn handle_request(
 
     // 非流式：把上游 SSE 聚合成 Anthropic JSON
     if !effective_stream {
+        let response = response.expect("non-stream responses are never hedged");
         let response_content_type = response
             .headers()
             .get("content-type")
@@ -7489,6 +8943,21 @@ async fn handle_request(
                 parsed.get("usage"),
                 "codex_non_stream",
             );
+            let mut metrics = StreamMetrics::new(request_started_at)
+                .with_export(
+                    client_route_kind,
+                    &client_label,
+                    &request_converter,
+                    &request_endpoint,
+                    false,
+                )
+                .with_rate_limiter(&rate_limiter)
+                .with_lb_feedback(lb_feedback.clone())
+                .with_usage_tags(usage_tags.clone());
+            if let Some(usage) = parsed.get("usage") {
+                metrics.mark_usage(usage);
+            }
+            metrics.export();
             log_prompt_cache_observation(
                 &log_tx,
                 &request_id,
@@ -7543,7 +9012,17 @@ async fn handle_request(
         let mut transformer =
             request_backend.create_response_transformer(&model, allow_visible_thinking_for_request);
         transformer.configure_request_context(&response_transform_request_ctx);
-        let mut metrics = StreamMetrics::new(request_started_at);
+        let mut metrics = StreamMetrics::new(request_started_at)
+            .with_export(
+                client_route_kind,
+                &client_label,
+                &request_converter,
+                &request_endpoint,
+                false,
+            )
+            .with_rate_limiter(&rate_limiter)
+            .with_lb_feedback(lb_feedback.clone())
+            .with_usage_tags(usage_tags.clone());
 
         let mut message_state: Option<Value> = None;
         let mut blocks: BTreeMap<usize, Value> = BTreeMap::new();
@@ -7789,6 +9268,9 @@ async fn handle_request(
             latest_codex_terminal_snapshot.as_ref(),
             &model,
         );
+        if let Some(usage) = payload.get("usage") {
+            metrics.mark_usage(usage);
+        }
         log_prompt_cache_observation(
             &log_tx,
             &request_id,
@@ -7893,28 +9375,55 @@ async fn handle_request(
     let stream_opts_for_task = stream_opts;
     let request_started_at_for_stream = request_started_at;
     drop(permit);
-    let request_backend_for_stream = request_backend.clone();
-    let model_for_stream = model.clone();
-    let upstream_url_for_stream = resolved_target_url_for_stream.clone();
-    let upstream_api_key_for_stream = api_key_for_stream.clone();
+    let mut request_backend_for_stream = request_backend.clone();
+    let mut model_for_stream = model.clone();
+    let mut upstream_url_for_stream = resolved_target_url_for_stream.clone();
+    let mut upstream_api_key_for_stream = api_key_for_stream.clone();
     let upstream_body_for_stream = upstream_body_for_stream.clone();
     let session_id_for_stream = session_id_for_request.clone();
-    let serial_fallback_upstream_body_for_stream =
+    let mut serial_fallback_upstream_body_for_stream =
         disable_parallel_tool_calls_in_upstream_body(&upstream_body_for_stream);
     let http_client_for_stream = http_client.clone();
     let anthropic_version_for_stream = anthropic_version.clone();
     let anthropic_beta_for_stream = anthropic_beta.clone();
-    let is_codex_stream_for_task = request_converter.eq_ignore_ascii_case("codex");
+    let mut is_codex_stream_for_task = request_converter.eq_ignore_ascii_case("codex");
+    let converter_for_stream_metrics = request_converter.clone();
     let stateful_chain_enabled_for_stream =
         enable_stateful_responses_chain && request_converter.eq_ignore_ascii_case("codex");
-    let stateful_chain_meta_for_stream = stateful_chain_meta_for_request.clone();
+    let mut stateful_chain_meta_for_stream = stateful_chain_meta_for_request.clone();
     let stateful_chain_store_for_stream = stateful_chain_store.clone();
     let parallel_tool_degrade_until_for_stream = parallel_tool_degrade_until.clone();
-    let parallel_tool_degrade_key_for_stream = parallel_tool_degrade_key_for_stream.clone();
+    let mut parallel_tool_degrade_key_for_stream = parallel_tool_degrade_key_for_stream.clone();
+    // 负载均衡下，首个业务事件之前的卡顿 / 中断改由其他端点重试
+    let mut stream_failover = lb_feedback.as_ref().map(|feedback| StreamFailover {
+        runtime: feedback.runtime.clone(),
+        route: feedback.route.clone(),
+        request_id: request_id.clone(),
+        input_model: input_model_owned.clone(),
+        input_slot,
+        model_route: model_route.clone(),
+        target_url: target_url.clone(),
+        api_key: final_api_key.clone(),
+        ctx: ctx.clone(),
+        anthropic_body: anthropic_body.clone(),
+        raw_request_body: raw_request_body.clone(),
+        prefer_codex_v1_path,
+        codex_v1_unsupported_endpoints: codex_v1_unsupported_endpoints.clone(),
+        log_tx: log_tx.clone(),
+    });
     let response_transform_request_ctx_for_stream = response_transform_request_ctx.clone();
     let downstream_path_for_stream = normalized_path.to_string();
     tokio::spawn(async move {
-        let mut stream = response.bytes_stream();
+        // 在途计数持续到流转发结束
+        let _in_flight_guard = in_flight_guard;
+        let _rate_limit_slot = rate_limit_slot;
+        let mut stream: UpstreamByteStream = match hedged_stream {
+            Some(stream) => stream,
+            None => response
+                .expect("upstream response must exist when not hedged")
+                .bytes_stream()
+                .boxed(),
+        };
         let mut transformer = request_backend_for_stream
             .create_response_transformer(&model_for_stream, allow_visible_thinking_for_request);
         transformer.configure_request_context(&response_transform_request_ctx_for_stream);
@@ -7924,7 +9433,17 @@ async fn handle_request(
         let mut frame_parser = SseFrameParser::default();
         let mut upstream_log_counter = 0u64;
         let mut downstream_log_counter = 0u64;
-        let mut metrics = StreamMetrics::new(request_started_at_for_stream);
+        let mut metrics = StreamMetrics::new(request_started_at_for_stream)
+            .with_export(
+                client_route_kind,
+                &client_label,
+                &converter_for_stream_metrics,
+                &request_endpoint,
+                true,
+            )
+            .with_rate_limiter(&rate_limiter)
+            .with_lb_feedback(lb_feedback)
+            .with_usage_tags(usage_tags);
         let mut event_counters = StreamEventCounters::default();
         let hard_timeout = Duration::from_secs(600);
         let stream_idle_timeout =
@@ -7947,6 +9466,7 @@ async fn handle_request(
         let mut silence_warn_logged = false;
         let mut silence_error_logged = false;
         let mut endpoint_parallel_degrade_marked = false;
+        let mut _failover_permit: Option<EndpointPermit> = None;
 
         'stream_attempt: loop {
             loop {
@@ -8438,7 +9958,7 @@ async fn handle_request(
                     ),
                 );
 
-                if let Some(retry) = execute_stream_retry_request(
+                if let Ok(retry) = execute_stream_retry_request(
                     &request_backend_for_stream,
                     &http_client_for_stream,
                     &upstream_url_for_stream,
@@ -8455,7 +9975,7 @@ async fn handle_request(
                 {
                     active_session_id_for_stream = retry.session_id;
                     current_upstream_status = retry.status;
-                    stream = retry.response.bytes_stream();
+                    stream = retry.response.bytes_stream().boxed();
                     transformer = request_backend_for_stream.create_response_transformer(
                         &model_for_stream,
                         allow_visible_thinking_for_request,
@@ -8542,7 +10062,7 @@ async fn handle_request(
                     ),
                 );
 
-                if let Some(retry) = execute_stream_retry_request(
+                if let Ok(retry) = execute_stream_retry_request(
                     &request_backend_for_stream,
                     &http_client_for_stream,
                     &upstream_url_for_stream,
@@ -8559,7 +10079,7 @@ async fn handle_request(
                 {
                     active_session_id_for_stream = retry.session_id;
                     current_upstream_status = retry.status;
-                    stream = retry.response.bytes_stream();
+                    stream = retry.response.bytes_stream().boxed();
                     transformer = request_backend_for_stream.create_response_transformer(
                         &model_for_stream,
                         allow_visible_thinking_for_request,
@@ -8603,7 +10123,60 @@ async fn handle_request(
                     ),
                 );
 
-                if let Some(retry) = execute_stream_retry_request(
+                let failover = match stream_failover
+                    .as_mut()
+                    .filter(|_| decision.allow_endpoint_failover())
+                {
+                    Some(failover) => {
+                        failover
+                            .next_attempt(
+                                decision.stream_close_cause.unwrap_or("stream_incomplete"),
+                                &http_client_for_stream,
+                                &anthropic_version_for_stream,
+                                anthropic_beta_for_stream.as_deref(),
+                                &logger_for_stream,
+                            )
+                            .await
+                    }
+                    None => None,
+                };
+                if let Some(attempt) = failover {
+                    // 换端点后原有的有状态链路与并行工具降级记录都不再适用
+                    stateful_chain_meta_for_stream = None;
+                    parallel_tool_degrade_key_for_stream = None;
+                    is_codex_stream_for_task = attempt.converter.eq_ignore_ascii_case("codex");
+                    serial_fallback_upstream_body_for_stream =
+                        disable_parallel_tool_calls_in_upstream_body(&attempt.upstream_body);
+                    active_upstream_body_for_stream = attempt.upstream_body;
+                    active_session_id_for_stream = attempt.session_id;
+                    request_backend_for_stream = attempt.backend;
+                    model_for_stream = attempt.model;
+                    upstream_url_for_stream = attempt.upstream_url;
+                    upstream_api_key_for_stream = attempt.api_key;
+                    _failover_permit = attempt.permit;
+                    metrics.set_failover_upstream(
+                        &attempt.converter,
+                        &model_for_stream,
+                        attempt.feedback,
+                    );
+                    decision.incomplete_stream_retry_succeeded = true;
+                    current_upstream_status = attempt.status;
+                    stream = attempt.response.bytes_stream().boxed();
+                    transformer = request_backend_for_stream.create_response_transformer(
+                        &model_for_stream,
+                        allow_visible_thinking_for_request,
+                    );
+                    transformer
+                        .configure_request_context(&response_transform_request_ctx_for_stream);
+                    line_buffer.clear();
+                    frame_parser = SseFrameParser::default();
+                    decision.on_retry_success_reset();
+                    decision.emitted_non_heartbeat_event = false;
+                    last_upstream_activity = Instant::now();
+                    continue 'stream_attempt;
+                }
+
+                if let Ok(retry) = execute_stream_retry_request(
                     &request_backend_for_stream,
                     &http_client_for_stream,
                     &upstream_url_for_stream,
@@ -8621,7 +10194,7 @@ async fn handle_request(
                     active_session_id_for_stream = retry.session_id;
                     decision.incomplete_stream_retry_succeeded = true;
                     current_upstream_status = retry.status;
-                    stream = retry.response.bytes_stream();
+                    stream = retry.response.bytes_stream().boxed();
                     line_buffer.clear();
                     frame_parser = SseFrameParser::default();
                     decision.on_retry_success_reset();
@@ -8830,6 +10403,7 @@ async fn handle_request(
             decision.saw_response_failed,
             decision.saw_message_stop,
         );
+        metrics.set_close_cause(&close_cause);
         let stream_outcome = decision.stream_outcome();
         emit_stream_diag(
             &log_tx_clone,
@@ -8888,6 +10462,12 @@ async fn handle_request(
                 .and_then(|snapshot| snapshot.get("usage")),
             "codex_stream",
         );
+        if let Some(usage) = latest_codex_terminal_snapshot
+            .as_ref()
+            .and_then(|snapshot| snapshot.get("usage"))
+        {
+            metrics.mark_usage(usage);
+        }
 
         if stateful_chain_enabled_for_stream
             && decision.saw_response_completed
@@ -8954,13 +10534,14 @@ async fn handle_request(
         .unwrap())
 }
 
-/// 处理 GET /v1/models 请求
-fn handle_models_list() -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
-    let models = generate_model_list();
-    let response_body = json!({
-        "object": "list",
-        "data": models
-    });
+/// 处理 GET /v1/models 请求：按当前路由配置列出 slot → 上游模型
+fn handle_models_list(
+    runtime_state: &RuntimeConfigState,
+    route_kind: ClientRouteKind,
+    model_cooldowns: &Arc<Mutex<HashMap<String, Instant>>>,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
+    let entries = build_slot_models(&runtime_state.route_for(route_kind), model_cooldowns);
+    let response_body = encode_model_list(&entries, runtime_state.applied_at);
 
     Ok(Response::builder()
         .status(StatusCode::OK)
@@ -8971,10 +10552,15 @@ fn handle_models_list() -> Result<Response<BoxBody<Bytes, Infallible>>, Infallib
 }
 
 /// 处理 GET /v1/models/{model_id} 请求
-fn handle_model_detail(model_id: &str) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
-    let supported_models = ["opus", "sonnet", "haiku"];
+fn handle_model_detail(
+    model_id: &str,
+    runtime_state: &RuntimeConfigState,
+    route_kind: ClientRouteKind,
+    model_cooldowns: &Arc<Mutex<HashMap<String, Instant>>>,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
+    let entries = build_slot_models(&runtime_state.route_for(route_kind), model_cooldowns);
 
-    if !supported_models.contains(&model_id) {
+    let Some(entry) = find_slot_model(&entries, model_id) else {
         let error_response = json!({
             "error": {
                 "type": "not_found",
@@ -8988,17 +10574,9 @@ fn handle_model_detail(model_id: &str) -> Result<Response<BoxBody<Bytes, Infalli
             .header("Access-Control-Allow-Origin", "*")
             .body(full_body(error_response.to_string()))
             .unwrap());
-    }
+    };
 
-    let model_info = json!({
-        "id": model_id,
-        "object": "model",
-        "created": 1677610602,
-        "owned_by": "codex-proxy",
-        "permission": [],
-        "root": model_id,
-        "parent": null
-    });
+    let model_info = encode_model_entry(entry, model_id, runtime_state.applied_at);
 
     Ok(Response::builder()
         .status(StatusCode::OK)
@@ -9008,21 +10586,57 @@ fn handle_model_detail(model_id: &str) -> Result<Response<BoxBody<Bytes, Infalli
         .unwrap())
 }
 
-/// 处理健康检查请求 GET /health 和 GET /
-fn handle_health_check() -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
-    let health_response = json!({
-        "status": "ok",
-        "version": "0.1.3"
-    });
-
+/// 健康检查类端点（/health、/ready、/status）的 JSON 响应，禁止缓存
+fn health_json_response(
+    status: StatusCode,
+    body: Value,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
     Ok(Response::builder()
-        .status(StatusCode::OK)
+        .status(status)
         .header("Content-Type", "application/json")
+        .header("Cache-Control", "no-store")
         .header("Access-Control-Allow-Origin", "*")
-        .body(full_body(health_response.to_string()))
+        .body(full_body(body.to_string()))
         .unwrap())
 }
 
+/// 超出限流配额：记录日志并返回 429
+fn reject_rate_limited_request(
+    log_tx: &broadcast::Sender<String>,
+    request_id: &str,
+    rejection: RateLimitRejection,
+) -> HandlerResult {
+    let _ = log_tx.send(format!(
+        "[Warning] #{} rate_limited scope={} limit={} retry_after={}s",
+        request_id, rejection.scope, rejection.limit, rejection.retry_after_secs
+    ));
+    rejection.into_response()
+}
+
+/// 不需要客户端令牌的探针路径
+fn is_probe_path(path: &str) -> bool {
+    matches!(path, "" | "/" | "/health" | "/ready")
+}
+
+/// 客户端令牌校验失败：记录日志并返回 401/403
+fn reject_client_request(
+    log_tx: &broadcast::Sender<String>,
+    request_id: &str,
+    path: &str,
+    observation: &mut RequestObservation,
+    err: ClientAuthError,
+) -> HandlerResult {
+    observation.set_client(err.client_name());
+    let _ = log_tx.send(format!(
+        "[Warning] #{} client_auth_rejected client={} path={} reason={}",
+        request_id,
+        err.client_name(),
+        path,
+        err.reason()
+    ));
+    err.into_response()
+}
+
 /// 处理 OPTIONS 请求（CORS 预检）
 fn handle_cors_preflight() -> Result<Response<BoxBody<Bytes, Infallible>>, Infallible> {
     Ok(Response::builder()
@@ -9038,26 +10652,6 @@ fn handle_cors_preflight() -> Result<Response<BoxBody<Bytes, Infallible>>, Infal
         .unwrap())
 }
 
-/// 生成模型列表
-fn generate_model_list() -> Vec<Value> {
-    let models = ["opus", "sonnet", "haiku"];
-
-    models
-        .iter()
-        .map(|&model_id| {
-            json!({
-                "id": model_id,
-                "object": "model",
-                "created": 1677610602,
-                "owned_by": "codex-proxy",
-                "permission": [],
-                "root": model_id,
-                "parent": null
-            })
-        })
-        .collect()
-}
-
 fn full_body(s: String) -> BoxBody<Bytes, Infallible> {
     BoxBody::new(Full::new(Bytes::from(s)).map_err(|_: Infallible| unreachable!()))
 }
@@ -9097,9 +10691,12 @@ mod tests {
         GeminiExplicitCacheUnsupportedEndpointStore, RuntimeConfigState, RuntimeConfigUpdate,
         RuntimeRouteUpdate, SkillCatalogReminderStore, SseFrameParser, StatefulChainEntry,
         StatefulChainRequestMeta, StatefulChainStore, StatefulChainUnsupportedEndpointStore,
-        StreamEventCounters, StreamRuntimeOptions, UpstreamOperation,
+        StreamEventCounters, StreamMetrics, StreamRuntimeOptions, UpstreamOperation,
+        InputEstimate, UsageTags,
     };
     use crate::models::AnthropicRequest;
+    use crate::token_calibration::TokenCalibration;
+    use crate::tokenizer::{count_request_tokens, TokenizerFamily};
     use crate::transform::{request_envelope_hints_from_anthropic, RequestEnvelopeHints};
     use serde_json::{json, Value};
     use std::collections::{HashMap, HashSet};
@@ -9284,6 +10881,11 @@ mod tests {
             enable_codex_tool_schema_compaction: true,
             enable_skill_routing_hint: false,
             enable_stateful_responses_chain: true,
+            client_tokens: Vec::new(),
+            rate_limit: crate::config::RateLimitConfig::default(),
+            model_routes: crate::model_routes::ModelRouteTable::default(),
+            pricing: Vec::new(),
+            budgets: crate::config::BudgetsConfig::default(),
             load_balancer_runtime: None,
         });
 
@@ -11395,6 +12997,202 @@ data: {"type":"response.completed","response":{"id":"resp_123","status":"complet
         );
     }
 
+    #[test]
+    fn test_stream_metrics_export_appends_usage_ledger_record_once() {
+        let path = std::env::temp_dir()
+            .join(format!("usage-ledger-{}", uuid::Uuid::new_v4()))
+            .join("usage-ledger.jsonl");
+        let ledger = Arc::new(crate::usage_ledger::UsageLedger::open(&path).expect("ledger"));
+        let budget = Arc::new(crate::budget::BudgetTracker::new(None));
+        budget.configure(
+            &[crate::config::ModelPriceConfig {
+                pattern: "gpt-5*".to_string(),
+                input: 1.0,
+                cached_input: Some(0.1),
+                output: 10.0,
+                ..Default::default()
+            }],
+            crate::config::BudgetsConfig::default(),
+        );
+        let mut metrics = StreamMetrics::new(Instant::now())
+            .with_export(ClientRouteKind::Claude, "alice", "Codex", "ep-a", true)
+            .with_usage_tags(UsageTags {
+                ledger: Some(Arc::clone(&ledger)),
+                budget: Arc::clone(&budget),
+                request_id: "abc@alice".to_string(),
+                session: Some("sess-1".to_string()),
+                kind: "subagent",
+                slot: "sonnet",
+                model: "gpt-5-codex".to_string(),
+                estimate: None,
+            });
+        metrics.mark_usage(&json!({
+            "input_tokens": 1200,
+            "output_tokens": 80,
+            "cache_read_input_tokens": 1000,
+            "output_tokens_details": {"reasoning_tokens": 30}
+        }));
+        metrics.export();
+        metrics.export();
+
+        let records = ledger.records().expect("records");
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.client, "alice");
+        assert_eq!(record.session.as_deref(), Some("sess-1"));
+        assert_eq!(record.kind, "subagent");
+        assert_eq!(record.converter, "codex");
+        assert_eq!(record.endpoint, "ep-a");
+        assert_eq!(record.model, "gpt-5-codex");
+        assert_eq!(record.input_tokens, 1200);
+        assert_eq!(record.output_tokens, 80);
+        assert_eq!(record.cached_input_tokens, 1000);
+        assert_eq!(record.reasoning_tokens, 30);
+        // 200 * 1.0 + 1000 * 0.1 + 80 * 10.0（推理 token 未单独定价时按 output 计）
+        let cost = record.cost_usd.expect("priced model");
+        assert!((cost - 0.0011).abs() < 1e-12, "cost={}", cost);
+        let _ = std::fs::remove_dir_all(path.parent().expect("ledger dir"));
+    }
+
+    #[test]
+    fn test_stream_metrics_export_calibrates_anthropic_estimate_with_cache_tokens() {
+        let calibration = Arc::new(TokenCalibration::default());
+        let (log_tx, mut log_rx) = broadcast::channel(16);
+        let request: AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{"role": "user", "content": "hello ".repeat(600)}]
+        }))
+        .expect("request");
+        let estimate = count_request_tokens(&request, TokenizerFamily::Claude);
+        let request = Arc::new(request);
+        for _ in 0..3 {
+            let mut metrics = StreamMetrics::new(Instant::now())
+                .with_export(ClientRouteKind::Claude, "-", "Anthropic", "ep-a", false)
+                .with_usage_tags(UsageTags {
+                    ledger: None,
+                    budget: Arc::new(crate::budget::BudgetTracker::new(None)),
+                    request_id: "abc".to_string(),
+                    session: None,
+                    kind: "conversation_turn",
+                    slot: "sonnet",
+                    model: "claude-sonnet-4-5".to_string(),
+                    estimate: Some(InputEstimate {
+                        calibration: Arc::clone(&calibration),
+                        family: TokenizerFamily::Claude,
+                        request: Arc::clone(&request),
+                        log_tx: log_tx.clone(),
+                    }),
+                });
+            // Anthropic 原生上游：实际输入 = input + 缓存读 + 缓存写
+            metrics.mark_usage(&json!({
+                "input_tokens": 100,
+                "cache_read_input_tokens": 900,
+                "cache_creation_input_tokens": 100,
+                "output_tokens": 10
+            }));
+            metrics.export();
+        }
+
+        let factor = calibration
+            .factor("ep-a", "claude-sonnet-4-5")
+            .expect("factor after 3 samples");
+        let expected = 1100.0 / estimate as f64;
+        assert!((factor - expected).abs() < 1e-9, "factor={}", factor);
+        let log = log_rx.try_recv().expect("estimate log");
+        assert!(log.starts_with(&format!(
+            "[Tokens] #abc estimate family=claude raw={}",
+            estimate
+        )));
+        assert!(log.contains("actual=1100"));
+    }
+
+    #[test]
+    fn test_request_envelope_hints_classify_claude_code_request_kinds() {
+        let classify = |value: Value| {
+            let request: AnthropicRequest = serde_json::from_value(value).expect("request");
+            request_envelope_hints_from_anthropic(&request).request_kind
+        };
+        let main_system = json!([
+            {"type":"text","text":"You are Claude Code, Anthropic's official CLI for Claude."}
+        ]);
+
+        assert_eq!(
+            classify(json!({
+                "model": "claude-opus-4-6",
+                "messages": [{"role":"user","content":"修复登录页"}],
+                "system": main_system,
+            })),
+            crate::transform::ClaudeCodeRequestKind::ConversationTurn
+        );
+        assert_eq!(
+            classify(json!({
+                "model": "claude-sonnet-4-6",
+                "messages": [{"role":"user","content":"Find all callers of parse_config"}],
+                "system": [
+                    {"type":"text","text":"You are Claude Code, Anthropic's official CLI for Claude."},
+                    {"type":"text","text":"You are an agent for Claude Code, Anthropic's official CLI for Claude. Given the user's message, you should use the tools available to complete the task."}
+                ],
+            })),
+            crate::transform::ClaudeCodeRequestKind::Subagent
+        );
+        assert_eq!(
+            classify(json!({
+                "model": "claude-opus-4-6",
+                "messages": [
+                    {"role":"user","content":"修复登录页"},
+                    {"role":"assistant","content":"已修复"},
+                    {"role":"user","content":[{"type":"text","text":"Your task is to create a detailed summary of the conversation so far, paying close attention to the user's explicit requests."}]}
+                ],
+                "system": [
+                    {"type":"text","text":"You are a helpful AI assistant tasked with summarizing conversations."}
+                ],
+            })),
+            crate::transform::ClaudeCodeRequestKind::Compaction
+        );
+        assert_eq!(
+            classify(json!({
+                "model": "claude-haiku-4-5-20251001",
+                "messages": [{"role":"user","content":"帮我看下这个报错"}],
+                "system": [
+                    {"type":"text","text":"Analyze if this message indicates a new conversation topic. If it does, extract a 2-3 word title."}
+                ],
+            })),
+            crate::transform::ClaudeCodeRequestKind::SessionTitle
+        );
+        assert_eq!(
+            classify(json!({
+                "model": "claude-haiku-4-5-20251001",
+                "max_tokens": 1,
+                "messages": [{"role":"user","content":"quota"}],
+            })),
+            crate::transform::ClaudeCodeRequestKind::ConversationTurn
+        );
+        assert_eq!(
+            classify(json!({
+                "model": "claude-sonnet-4-6",
+                "messages": [{"role":"user","content":"foo"}],
+            })),
+            crate::transform::ClaudeCodeRequestKind::ConversationTurn
+        );
+        assert_eq!(
+            classify(json!({
+                "model": "claude-sonnet-4-6",
+                "max_tokens": 1,
+                "messages": [{"role":"user","content":"foo"}],
+                "tools": [{"name":"Read","input_schema":{"type":"object"}}],
+            })),
+            crate::transform::ClaudeCodeRequestKind::ConversationTurn
+        );
+        assert_eq!(
+            classify(json!({
+                "model": "claude-sonnet-4-6",
+                "max_tokens": 1,
+                "messages": [{"role":"user","content":"Count"}],
+            })),
+            crate::transform::ClaudeCodeRequestKind::Probe
+        );
+    }
+
     #[test]
     fn test_stateful_chain_key_separates_title_and_conversation_requests() {
         let title_request: AnthropicRequest = serde_json::from_value(json!({
@@ -11837,4 +13635,225 @@ data: {"type":"ping"}
         }
         assert!(get_parallel_tool_degrade_remaining_seconds(&degrade_map, &key).is_none());
     }
+
+    #[test]
+    fn test_only_liveness_and_readiness_probes_skip_client_auth() {
+        for path in ["", "/", "/health", "/ready"] {
+            assert!(super::is_probe_path(path), "{path}");
+        }
+        for path in ["/status", "/metrics", "/usage", "/v1/messages"] {
+            assert!(!super::is_probe_path(path), "{path}");
+        }
+    }
+
+    /// opus 唯一的端点已不可用、降级链 opus → sonnet 的运行时
+    fn exhausted_opus_runtime() -> crate::load_balancer::LoadBalancerRuntime {
+        use crate::load_balancer::{
+            test_support, DegradationStep, LoadBalancerProfile, ModelSlot, SlotMapping,
+        };
+
+        let step = |slot| DegradationStep {
+            slot,
+            reasoning_effort: None,
+        };
+        let runtime = test_support::runtime(
+            LoadBalancerProfile {
+                id: "p".to_string(),
+                model_mapping: SlotMapping {
+                    opus: vec![test_support::slot_ref("ep-opus")],
+                    sonnet: vec![test_support::slot_ref("ep-sonnet")],
+                    haiku: vec![],
+                },
+                degradation_chain: vec![step(ModelSlot::Opus), step(ModelSlot::Sonnet)],
+                degradation_header: true,
+                ..LoadBalancerProfile::default()
+            },
+            &[("ep-opus", "codex"), ("ep-sonnet", "codex")],
+            HashMap::new(),
+        );
+        let (primary, permit) = runtime.resolve_and_acquire("claude-opus-4-6").unwrap();
+        drop(permit);
+        runtime.mark_unavailable(&primary, "quota");
+        runtime
+    }
+
+    #[test]
+    fn test_degraded_named_route_drops_pinned_model_and_effort() {
+        use crate::load_balancer::ModelSlot;
+        use crate::model_routes::ModelRoute;
+
+        let runtime = exhausted_opus_runtime();
+        let ctx = test_transform_context("codex");
+        let pinned = ModelRoute::new("pinned", ModelSlot::Opus)
+            .with_model("gpt-5-pinned")
+            .with_reasoning_effort(crate::models::ReasoningEffort::Xhigh);
+        let (log_tx, _log_rx) = broadcast::channel(16);
+        let selection = super::resolve_route_selection(
+            "req-1",
+            "claude-opus-4-6",
+            ModelSlot::Opus,
+            Some(&pinned),
+            "",
+            "",
+            &ctx,
+            Some(&runtime),
+            None,
+            None,
+            None,
+            &log_tx,
+        )
+        .expect("should degrade to sonnet");
+
+        let route = selection.route.as_ref().unwrap();
+        assert_eq!(route.endpoint_id, "ep-sonnet");
+        assert_eq!(route.degraded_from, Some(ModelSlot::Opus));
+        let sonnet_route = ModelRoute::new("sonnet", ModelSlot::Sonnet);
+        assert_eq!(
+            selection.model_name,
+            super::resolve_route_model("codex", "claude-opus-4-6", Some(&sonnet_route), &ctx)
+        );
+        assert_ne!(selection.model_name, "gpt-5-pinned");
+        assert_eq!(
+            selection.reasoning_effort_override,
+            Some(crate::models::get_reasoning_effort(
+                "sonnet",
+                &ctx.reasoning_mapping
+            ))
+        );
+    }
+
+    #[test]
+    fn test_degradation_skips_slots_the_client_may_not_use() {
+        use crate::load_balancer::ModelSlot;
+
+        let tokens: Vec<crate::config::ClientTokenConfig> = serde_json::from_value(json!([
+            {"name": "opus-only", "token": "tok-opus", "allowedSlots": ["opus"]}
+        ]))
+        .unwrap();
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("x-api-key", "tok-opus".parse().unwrap());
+        let identity =
+            super::authenticate_client(&tokens, &headers, super::ClientRouteKind::Claude)
+                .unwrap()
+                .expect("client identity");
+
+        let runtime = exhausted_opus_runtime();
+        let ctx = test_transform_context("codex");
+        let (log_tx, _log_rx) = broadcast::channel(16);
+        let select = |client| {
+            super::resolve_route_selection(
+                "req-1",
+                "claude-opus-4-6",
+                ModelSlot::Opus,
+                None,
+                "",
+                "",
+                &ctx,
+                Some(&runtime),
+                None,
+                client,
+                None,
+                &log_tx,
+            )
+        };
+
+        // 客户端只允许 opus：不能借降级链用上 sonnet
+        assert!(select(Some(&identity)).is_err());
+        let selection = select(None).expect("unrestricted request degrades to sonnet");
+        assert_eq!(
+            selection.route.as_ref().map(|route| route.slot),
+            Some(ModelSlot::Sonnet)
+        );
+    }
+
+    #[test]
+    fn test_forward_inbound_headers_keeps_degraded_slot_and_retry_after() {
+        let mut inner = hyper::HeaderMap::new();
+        inner.insert(super::DEGRADED_SLOT_HEADER, "opus->sonnet".parse().unwrap());
+        inner.insert("retry-after", "7".parse().unwrap());
+        inner.insert("x-unrelated", "1".parse().unwrap());
+
+        let response = super::forward_inbound_headers(
+            &inner,
+            super::inbound_json_response(
+                hyper::StatusCode::TOO_MANY_REQUESTS,
+                json!({"error": {}}),
+            ),
+        )
+        .unwrap();
+        let headers = response.headers();
+        assert_eq!(headers[super::DEGRADED_SLOT_HEADER], "opus->sonnet");
+        assert_eq!(headers["retry-after"], "7");
+        assert!(headers.get("x-unrelated").is_none());
+        assert_eq!(headers["content-type"], "application/json");
+    }
+
+    #[tokio::test]
+    async fn test_stream_retry_request_reports_upstream_status_and_retry_after() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 16 * 1024];
+            let _ = socket.read(&mut buf).await;
+            let body = r#"{"error":{"type":"rate_limit_error"}}"#;
+            let response = format!(
+                "HTTP/1.1 429 Too Many Requests\r\nretry-after: 12\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+
+        let (log_tx, _log_rx) = broadcast::channel(16);
+        let backend = super::build_backend_by_converter("codex");
+        let result = super::execute_stream_retry_request(
+            &backend,
+            &reqwest::Client::new(),
+            &format!("http://{}/v1/responses", addr),
+            "test-key",
+            &json!({"model": "gpt-5", "input": []}),
+            "2023-06-01",
+            None,
+            "req-1",
+            "stream_failover",
+            &log_tx,
+            &None,
+        )
+        .await;
+        match result {
+            Err(super::StreamRetryFailure::Status {
+                status,
+                retry_after,
+                error_text,
+            }) => {
+                assert_eq!(status, 429);
+                assert_eq!(retry_after, "12");
+                assert!(error_text.contains("rate_limit_error"));
+            }
+            _ => panic!("expected an upstream status failure"),
+        }
+
+        // 连接被拒绝时按网络错误返回
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        let result = super::execute_stream_retry_request(
+            &backend,
+            &reqwest::Client::new(),
+            &format!("http://{}/v1/responses", closed_addr),
+            "test-key",
+            &json!({"model": "gpt-5", "input": []}),
+            "2023-06-01",
+            None,
+            "req-2",
+            "stream_failover",
+            &log_tx,
+            &None,
+        )
+        .await;
+        assert!(matches!(result, Err(super::StreamRetryFailure::Network)));
+    }
 }
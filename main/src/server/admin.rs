@@ -0,0 +1,729 @@
+//! `/admin` 管理接口：查看与热更新运行时配置、切换 LB profile、启停端点、清理冷却与缓存
+//!
+//! 以 `ProxyConfig` 为唯一数据源：每次修改后重新走 `build_runtime_update` 并
+//! `ProxyRuntimeHandle::apply_update`，与桌面端热更新路径一致；配置了文件路径时同步落盘。
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::{Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use super::{full_body, HandlerResult, RequestServices, RuntimeConfigState, RuntimeRouteState};
+use crate::config::{build_runtime_update, save_config_file, LbEndpointConfig, ProxyConfig};
+use crate::logger::mask_secret;
+
+/// 配置中需要脱敏的字段名
+const SECRET_KEYS: &[&str] = &["apiKey", "apiKeys", "adminToken", "token"];
+
+/// 未显式配置 `lbEndpointConfigs` 时端点的默认并发上限（与 `build_lb_runtime` 一致）
+const DEFAULT_ENDPOINT_MAX_CONCURRENCY: u32 = 16;
+
+/// 启用管理接口所需的配置
+#[derive(Debug, Clone)]
+pub struct AdminApiConfig {
+    /// 请求需携带 `Authorization: Bearer <token>` 或 `X-Admin-Token: <token>`
+    pub token: String,
+    /// 配置文件中的代理配置（不含命令行覆盖，修改后原样写回）
+    pub proxy_config: ProxyConfig,
+    /// 修改后写回的配置文件；为 None 时只修改内存
+    pub config_path: Option<PathBuf>,
+}
+
+pub(crate) struct AdminState {
+    /// 修改配置中的 `adminToken` 后随之更新
+    token: Mutex<String>,
+    /// 读取、修改与写回都在同一把锁内完成，并发修改不会互相覆盖
+    config: Mutex<ProxyConfig>,
+    config_path: Option<PathBuf>,
+}
+
+impl AdminState {
+    pub fn new(config: AdminApiConfig) -> Self {
+        Self {
+            token: Mutex::new(config.token),
+            config: Mutex::new(config.proxy_config),
+            config_path: config.config_path,
+        }
+    }
+
+    fn authorized(&self, headers: &hyper::HeaderMap) -> bool {
+        let provided = headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or_else(|| {
+                headers
+                    .get("x-admin-token")
+                    .and_then(|value| value.to_str().ok())
+            })
+            .map(str::trim)
+            .unwrap_or("");
+        let token = self
+            .token
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        constant_time_eq(provided.as_bytes(), token.as_bytes())
+    }
+
+    /// 配置中的 `adminToken` 改为非空新值时替换当前令牌；清空时保留原令牌，避免把自己锁在外面
+    fn rotate_token(&self, previous: &ProxyConfig, next: &ProxyConfig) -> bool {
+        let Some(token) = next
+            .admin_token
+            .as_deref()
+            .map(str::trim)
+            .filter(|token| !token.is_empty() && next.admin_token != previous.admin_token)
+        else {
+            return false;
+        };
+        *self
+            .token
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = token.to_string();
+        true
+    }
+
+    fn lock_config(&self) -> std::sync::MutexGuard<'_, ProxyConfig> {
+        self.config
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() || b.is_empty() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn mask_optional_secret(secret: Option<&str>) -> Value {
+    match secret {
+        Some(value) => Value::String(mask_secret(value)),
+        None => Value::Null,
+    }
+}
+
+/// 递归脱敏 JSON 中的密钥字段
+pub(crate) fn mask_secrets(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                if SECRET_KEYS.contains(&key.as_str()) {
+                    mask_secret_value(item);
+                } else {
+                    mask_secrets(item);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(mask_secrets),
+        _ => {}
+    }
+}
+
+/// 密钥字段可以是单个字符串，也可以是字符串数组（端点密钥池）
+fn mask_secret_value(value: &mut Value) {
+    match value {
+        Value::String(secret) => *secret = mask_secret(secret),
+        Value::Array(items) => items.iter_mut().for_each(mask_secret_value),
+        _ => {}
+    }
+}
+
+fn restore_secret_value(next: &mut Value, previous: &Value) {
+    match (next, previous) {
+        (Value::String(secret), Value::String(original))
+            if secret != original && *secret == mask_secret(original) =>
+        {
+            *secret = original.clone();
+        }
+        // 密钥池增删后下标会错位，按脱敏值在原数组中查找
+        (Value::Array(next_items), Value::Array(previous_items)) => {
+            for item in next_items.iter_mut() {
+                let Value::String(secret) = item else {
+                    continue;
+                };
+                let original = previous_items
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .find(|original| original != secret && *secret == mask_secret(original));
+                if let Some(original) = original {
+                    *secret = original.to_string();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 把客户端回传的脱敏值还原为原始密钥（GET 后整段回写时不会覆盖真实密钥）
+fn restore_masked_secrets(next: &mut Value, previous: &Value) {
+    match (next, previous) {
+        (Value::Object(next_map), Value::Object(previous_map)) => {
+            for (key, item) in next_map.iter_mut() {
+                let Some(previous_item) = previous_map.get(key) else {
+                    continue;
+                };
+                if SECRET_KEYS.contains(&key.as_str()) {
+                    restore_secret_value(item, previous_item);
+                } else {
+                    restore_masked_secrets(item, previous_item);
+                }
+            }
+        }
+        (Value::Array(next_items), Value::Array(previous_items)) => {
+            for (index, item) in next_items.iter_mut().enumerate() {
+                // 端点列表按 id 对齐，其余数组按下标
+                let previous_item = item
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .and_then(|id| {
+                        previous_items.iter().find(|candidate| {
+                            candidate.get("id").and_then(Value::as_str) == Some(id)
+                        })
+                    })
+                    .or_else(|| previous_items.get(index));
+                if let Some(previous_item) = previous_item {
+                    restore_masked_secrets(item, previous_item);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// RFC 7396 JSON Merge Patch
+pub(crate) fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch_map) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(serde_json::Map::new());
+    }
+    let target_map = target.as_object_mut().expect("target is object");
+    for (key, value) in patch_map {
+        if value.is_null() {
+            target_map.remove(key);
+        } else {
+            merge_patch(target_map.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+fn admin_json(status: StatusCode, body: Value) -> HandlerResult {
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(full_body(body.to_string()))
+        .unwrap())
+}
+
+fn admin_error(status: StatusCode, message: impl Into<String>) -> HandlerResult {
+    admin_json(
+        status,
+        json!({"error": {"type": "admin_error", "message": message.into()}}),
+    )
+}
+
+fn masked_config(config: &ProxyConfig) -> Value {
+    let mut value = serde_json::to_value(config).unwrap_or(Value::Null);
+    mask_secrets(&mut value);
+    value
+}
+
+fn describe_route(route: &RuntimeRouteState) -> Value {
+    json!({
+        "targetUrl": route.target_url,
+        "apiKey": mask_optional_secret(route.api_key.as_deref()),
+        "converter": route.ctx.converter,
+        "codexModel": route.ctx.codex_model,
+        "reasoningEffort": route.ctx.reasoning_mapping,
+        "codexModelMapping": route.ctx.codex_model_mapping,
+        "anthropicModelMapping": route.ctx.anthropic_model_mapping,
+        "openaiModelMapping": route.ctx.openai_model_mapping,
+        "openaiMaxTokensMapping": route.ctx.openai_max_tokens_mapping,
+        "geminiReasoningEffort": route.ctx.gemini_reasoning_effort,
+        "loadBalancer": route.load_balancer_runtime.as_ref().map(|runtime| json!({
+            "selectedProfileId": runtime.selected_profile_id(),
+        })),
+        "imageGenerationUrl": route.image_generation_url,
+        "imageGenerationApiKey": mask_optional_secret(route.image_generation_api_key.as_deref()),
+        "stripImageGenerationTool": route.strip_image_generation_tool,
+    })
+}
+
+/// 当前生效的 `RuntimeConfigState`（密钥脱敏）
+pub(crate) fn describe_runtime_state(state: &RuntimeConfigState) -> Value {
+    json!({
+        "appliedAt": state.applied_at,
+        "claudeRoute": describe_route(&state.claude_route),
+        "codexRoute": describe_route(&state.codex_route),
+        "ignoreProbeRequests": state.ignore_probe_requests,
+        "allowCountTokensFallbackEstimate": state.allow_count_tokens_fallback_estimate,
+        "enableCodexFastMode": state.enable_codex_fast_mode,
+        "forceStreamForCodex": state.force_stream_for_codex,
+        "enableSseFrameParser": state.enable_sse_frame_parser,
+        "enableStreamHeartbeat": state.enable_stream_heartbeat,
+        "streamHeartbeatIntervalMs": state.stream_heartbeat_interval_ms,
+        "enableStreamLogSampling": state.enable_stream_log_sampling,
+        "streamLogSampleEveryN": state.stream_log_sample_every_n,
+        "streamLogMaxChars": state.stream_log_max_chars,
+        "enableStreamMetrics": state.enable_stream_metrics,
+        "enableStreamEventMetrics": state.enable_stream_event_metrics,
+        "streamSilenceWarnMs": state.stream_silence_warn_ms,
+        "streamSilenceErrorMs": state.stream_silence_error_ms,
+        "stallTimeoutMs": state.stall_timeout_ms,
+        "enableIncompleteStreamRetry": state.enable_incomplete_stream_retry,
+        "incompleteStreamRetryMaxAttempts": state.incomplete_stream_retry_max_attempts,
+        "enableSiblingToolErrorRetry": state.enable_sibling_tool_error_retry,
+        "preferCodexV1Path": state.prefer_codex_v1_path,
+        "enableCodexToolSchemaCompaction": state.enable_codex_tool_schema_compaction,
+        "enableSkillRoutingHint": state.enable_skill_routing_hint,
+        "enableStatefulResponsesChain": state.enable_stateful_responses_chain,
+    })
+}
+
+/// 对配置应用 JSON Merge Patch 并校验能反序列化回 `ProxyConfig`
+pub(crate) fn apply_config_patch(
+    config: &ProxyConfig,
+    patch: &Value,
+) -> Result<ProxyConfig, String> {
+    if !patch.is_object() {
+        return Err("Patch body must be a JSON object".to_string());
+    }
+    let previous = serde_json::to_value(config).map_err(|e| e.to_string())?;
+    let mut next = previous.clone();
+    merge_patch(&mut next, patch);
+    restore_masked_secrets(&mut next, &previous);
+    serde_json::from_value::<ProxyConfig>(next).map_err(|e| format!("Invalid config: {}", e))
+}
+
+/// 启停端点：写入 `lbEndpointConfigs`，未配置过的端点按默认并发补齐
+pub(crate) fn set_endpoint_enabled(
+    config: &mut ProxyConfig,
+    endpoint_id: &str,
+    enabled: bool,
+) -> Result<(), String> {
+    if !config
+        .endpoint_options
+        .iter()
+        .any(|endpoint| endpoint.id == endpoint_id)
+    {
+        return Err(format!("Unknown endpoint: {}", endpoint_id));
+    }
+    config
+        .load_balancer
+        .lb_endpoint_configs
+        .entry(endpoint_id.to_string())
+        .or_insert_with(|| LbEndpointConfig {
+            endpoint_id: endpoint_id.to_string(),
+            enabled: true,
+            max_concurrency: DEFAULT_ENDPOINT_MAX_CONCURRENCY,
+            priority: 0,
+            weight: 1,
+        })
+        .enabled = enabled;
+    Ok(())
+}
+
+pub(crate) fn select_lb_profile(config: &mut ProxyConfig, profile_id: &str) -> Result<(), String> {
+    if !config
+        .load_balancer
+        .lb_profiles
+        .iter()
+        .any(|profile| profile.id == profile_id)
+    {
+        return Err(format!("Unknown load balancer profile: {}", profile_id));
+    }
+    config.load_balancer.selected_lb_profile_id = Some(profile_id.to_string());
+    Ok(())
+}
+
+fn clear_store<T>(store: &std::sync::Arc<Mutex<T>>, clear: impl Fn(&mut T) -> usize) -> usize {
+    store.lock().map(|mut guard| clear(&mut guard)).unwrap_or(0)
+}
+
+/// 清空指定缓存；`stores` 为空时清空全部
+fn flush_stores(services: &RequestServices, stores: &[String]) -> Result<Value, String> {
+    const KNOWN: &[&str] = &["stateful_chain", "skill_catalog", "gemini_cache"];
+    if let Some(unknown) = stores.iter().find(|name| !KNOWN.contains(&name.as_str())) {
+        return Err(format!(
+            "Unknown store: {} (expected one of {})",
+            unknown,
+            KNOWN.join(", ")
+        ));
+    }
+    let wants = |name: &str| stores.is_empty() || stores.iter().any(|item| item == name);
+    let mut flushed = serde_json::Map::new();
+    if wants("stateful_chain") {
+        let entries = clear_store(&services.stateful_chain_store, |map| {
+            let len = map.len();
+            map.clear();
+            len
+        });
+        clear_store(&services.stateful_chain_unsupported_endpoints, |set| {
+            let len = set.len();
+            set.clear();
+            len
+        });
+        flushed.insert("stateful_chain".to_string(), json!(entries));
+    }
+    if wants("skill_catalog") {
+        let entries = clear_store(&services.skill_catalog_reminders, |map| {
+            let len = map.len();
+            map.clear();
+            len
+        });
+        flushed.insert("skill_catalog".to_string(), json!(entries));
+    }
+    if wants("gemini_cache") {
+        let entries = clear_store(&services.gemini_explicit_cache_store, |map| {
+            let len = map.len();
+            map.clear();
+            len
+        });
+        clear_store(
+            &services.gemini_explicit_cache_unsupported_endpoints,
+            |set| {
+                let len = set.len();
+                set.clear();
+                len
+            },
+        );
+        flushed.insert("gemini_cache".to_string(), json!(entries));
+    }
+    Ok(Value::Object(flushed))
+}
+
+async fn read_admin_json<B>(body: B) -> Result<Value, HandlerResult>
+where
+    B: hyper::body::Body<Data = Bytes>,
+    B::Error: std::fmt::Display,
+{
+    let bytes = body
+        .collect()
+        .await
+        .map_err(|e| {
+            admin_error(
+                StatusCode::BAD_REQUEST,
+                format!("Failed to read body: {}", e),
+            )
+        })?
+        .to_bytes();
+    if bytes.iter().all(u8::is_ascii_whitespace) {
+        return Ok(Value::Null);
+    }
+    serde_json::from_slice(&bytes)
+        .map_err(|e| admin_error(StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)))
+}
+
+/// 在配置锁内修改、热更新并写回；`modify` 失败时不做任何改动
+fn commit_config(
+    admin: &AdminState,
+    services: &RequestServices,
+    action: &str,
+    modify: impl FnOnce(&ProxyConfig) -> Result<ProxyConfig, (StatusCode, String)>,
+) -> HandlerResult {
+    let mut current = admin.lock_config();
+    let config = match modify(&current) {
+        Ok(config) => config,
+        Err((status, message)) => return admin_error(status, message),
+    };
+    services
+        .runtime_handle
+        .apply_update(build_runtime_update(&config, Some(services.log_tx.clone())));
+    let persisted = match admin.config_path.as_ref() {
+        Some(path) => match save_config_file(path, &config) {
+            Ok(()) => true,
+            Err(e) => {
+                let _ = services.log_tx.send(format!(
+                    "[Warning] Admin {} applied but failed to save {}: {}",
+                    action,
+                    path.display(),
+                    e
+                ));
+                false
+            }
+        },
+        None => false,
+    };
+    if admin.rotate_token(&current, &config) {
+        let _ = services
+            .log_tx
+            .send("[System] Admin token rotated".to_string());
+    }
+    let _ = services.log_tx.send(format!(
+        "[System] Admin {} applied (persisted={})",
+        action, persisted
+    ));
+    let body = json!({
+        "applied": true,
+        "persisted": persisted,
+        "config": masked_config(&config),
+    });
+    *current = config;
+    admin_json(StatusCode::OK, body)
+}
+
+/// 处理 `/admin/*`；`admin_path` 为去掉 `/admin` 前缀后的路径
+pub(crate) async fn handle_admin_request<B>(
+    req: Request<B>,
+    admin_path: &str,
+    services: &RequestServices,
+) -> HandlerResult
+where
+    B: hyper::body::Body<Data = Bytes>,
+    B::Error: std::fmt::Display,
+{
+    let Some(admin) = services.admin.as_ref() else {
+        return admin_error(StatusCode::NOT_FOUND, "Admin API is not enabled");
+    };
+    if !admin.authorized(req.headers()) {
+        let _ = services.log_tx.send(format!(
+            "[Warning] Admin request rejected: {} /admin{}",
+            req.method(),
+            admin_path
+        ));
+        return admin_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+    }
+
+    let method = req.method().clone();
+    let segments: Vec<&str> = admin_path
+        .trim_matches('/')
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    match (&method, segments.as_slice()) {
+        (&Method::GET, ["config"]) => admin_json(
+            StatusCode::OK,
+            json!({
+                "config": masked_config(&admin.lock_config()),
+                "configPath": admin.config_path.as_ref().map(|path| path.display().to_string()),
+            }),
+        ),
+        (&Method::PATCH, ["config"]) => {
+            let patch = match read_admin_json(req.into_body()).await {
+                Ok(value) => value,
+                Err(response) => return response,
+            };
+            commit_config(admin, services, "config patch", |config| {
+                apply_config_patch(config, &patch)
+                    .map_err(|message| (StatusCode::BAD_REQUEST, message))
+            })
+        }
+        (&Method::GET, ["runtime"]) => admin_json(
+            StatusCode::OK,
+            describe_runtime_state(&services.runtime_handle.snapshot()),
+        ),
+        (&Method::PUT, ["lb", "profile"]) | (&Method::POST, ["lb", "profile"]) => {
+            let body = match read_admin_json(req.into_body()).await {
+                Ok(value) => value,
+                Err(response) => return response,
+            };
+            let Some(profile_id) = body.get("profileId").and_then(Value::as_str) else {
+                return admin_error(StatusCode::BAD_REQUEST, "Missing profileId");
+            };
+            commit_config(admin, services, "lb profile switch", |config| {
+                let mut next = config.clone();
+                select_lb_profile(&mut next, profile_id)
+                    .map(|()| next)
+                    .map_err(|message| (StatusCode::NOT_FOUND, message))
+            })
+        }
+        (&Method::POST, ["endpoints", endpoint_id, action @ ("enable" | "disable")]) => {
+            commit_config(admin, services, &format!("endpoint {}", action), |config| {
+                let mut next = config.clone();
+                set_endpoint_enabled(&mut next, endpoint_id, *action == "enable")
+                    .map(|()| next)
+                    .map_err(|message| (StatusCode::NOT_FOUND, message))
+            })
+        }
+        (&Method::POST, ["cooldowns", "clear"]) => {
+            let model_cooldowns = clear_store(&services.model_cooldowns, |map| {
+                let len = map.len();
+                map.clear();
+                len
+            });
+            let state = services.runtime_handle.snapshot();
+            let lb_entries: usize = [&state.claude_route, &state.codex_route]
+                .iter()
+                .filter_map(|route| route.load_balancer_runtime.as_ref())
+                .map(|runtime| runtime.clear_cooldowns())
+                .sum();
+            let _ = services.log_tx.send(format!(
+                "[System] Admin cooldowns cleared: model_cooldowns={} lb_entries={}",
+                model_cooldowns, lb_entries
+            ));
+            admin_json(
+                StatusCode::OK,
+                json!({"model_cooldowns": model_cooldowns, "lb_entries": lb_entries}),
+            )
+        }
+        (&Method::POST, ["stores", "flush"]) => {
+            let body = match read_admin_json(req.into_body()).await {
+                Ok(value) => value,
+                Err(response) => return response,
+            };
+            let stores: Vec<String> = body
+                .get("stores")
+                .and_then(Value::as_array)
+                .map(|items| {
+                    items
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default();
+            match flush_stores(services, &stores) {
+                Ok(flushed) => {
+                    let _ = services
+                        .log_tx
+                        .send(format!("[System] Admin stores flushed: {}", flushed));
+                    admin_json(StatusCode::OK, json!({ "flushed": flushed }))
+                }
+                Err(message) => admin_error(StatusCode::BAD_REQUEST, message),
+            }
+        }
+        _ => admin_error(
+            StatusCode::NOT_FOUND,
+            format!("Unknown admin route: {} /admin{}", method, admin_path),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{default_proxy_config, LoadBalancerProfile};
+
+    fn config_with_key() -> ProxyConfig {
+        let mut config = default_proxy_config();
+        config.api_key = "sk-live-0123456789abcdef".to_string();
+        if let Some(endpoint) = config.endpoint_options.first_mut() {
+            endpoint.api_key = "sk-endpoint-0123456789".to_string();
+            endpoint.api_keys = vec![
+                "sk-pool-a-0123456789".to_string(),
+                "sk-pool-b-9876543210".to_string(),
+            ];
+        }
+        config
+    }
+
+    #[test]
+    fn masked_config_hides_every_api_key() {
+        let masked = masked_config(&config_with_key());
+        assert_eq!(masked["apiKey"], "sk-****cdef");
+        assert_eq!(masked["endpointOptions"][0]["apiKey"], "sk-****6789");
+        assert_eq!(
+            masked["endpointOptions"][0]["apiKeys"],
+            json!(["sk-****6789", "sk-****3210"])
+        );
+        assert!(!masked.to_string().contains("0123456789abcdef"));
+        assert!(!masked.to_string().contains("pool"));
+    }
+
+    #[test]
+    fn config_patch_merges_fields_and_keeps_masked_secrets() {
+        let config = config_with_key();
+        let roundtrip = masked_config(&config);
+        let patch = json!({
+            "maxConcurrency": 4,
+            "apiKey": roundtrip["apiKey"].clone(),
+            "endpointOptions": roundtrip["endpointOptions"].clone(),
+        });
+
+        let next = apply_config_patch(&config, &patch).unwrap();
+        assert_eq!(next.max_concurrency, 4);
+        assert_eq!(next.api_key, config.api_key);
+        assert_eq!(
+            next.endpoint_options[0].api_key,
+            config.endpoint_options[0].api_key
+        );
+        assert_eq!(
+            next.endpoint_options[0].api_keys,
+            config.endpoint_options[0].api_keys
+        );
+
+        // 删除池中第一个密钥后，剩余的脱敏值仍还原为原密钥
+        let mut endpoints = roundtrip["endpointOptions"].clone();
+        endpoints[0]["apiKeys"] = json!([endpoints[0]["apiKeys"][1].clone(), "sk-pool-c"]);
+        let next = apply_config_patch(&config, &json!({"endpointOptions": endpoints})).unwrap();
+        assert_eq!(
+            next.endpoint_options[0].api_keys,
+            ["sk-pool-b-9876543210", "sk-pool-c"]
+        );
+
+        let next = apply_config_patch(&config, &json!({"apiKey": "sk-new"})).unwrap();
+        assert_eq!(next.api_key, "sk-new");
+        assert!(apply_config_patch(&config, &json!({"port": "not-a-port"})).is_err());
+    }
+
+    #[test]
+    fn endpoint_toggle_and_profile_switch_validate_ids() {
+        let mut config = default_proxy_config();
+        let endpoint_id = config.endpoint_options[0].id.clone();
+        set_endpoint_enabled(&mut config, &endpoint_id, false).unwrap();
+        let entry = &config.load_balancer.lb_endpoint_configs[&endpoint_id];
+        assert!(!entry.enabled);
+        assert_eq!(entry.max_concurrency, DEFAULT_ENDPOINT_MAX_CONCURRENCY);
+        assert!(set_endpoint_enabled(&mut config, "missing", true).is_err());
+
+        config.load_balancer.lb_profiles.push(LoadBalancerProfile {
+            id: "backup".to_string(),
+            name: "Backup".to_string(),
+            ..Default::default()
+        });
+        select_lb_profile(&mut config, "backup").unwrap();
+        assert_eq!(
+            config.load_balancer.selected_lb_profile_id.as_deref(),
+            Some("backup")
+        );
+        assert!(select_lb_profile(&mut config, "missing").is_err());
+    }
+
+    #[test]
+    fn admin_token_must_match_exactly() {
+        let admin = AdminState::new(AdminApiConfig {
+            token: "secret-token".to_string(),
+            proxy_config: default_proxy_config(),
+            config_path: None,
+        });
+        let mut headers = hyper::HeaderMap::new();
+        assert!(!admin.authorized(&headers));
+        headers.insert("authorization", "Bearer secret-token".parse().unwrap());
+        assert!(admin.authorized(&headers));
+        headers.insert("authorization", "Bearer secret-tokem".parse().unwrap());
+        assert!(!admin.authorized(&headers));
+        headers.remove("authorization");
+        headers.insert("x-admin-token", "secret-token".parse().unwrap());
+        assert!(admin.authorized(&headers));
+    }
+
+    #[test]
+    fn changing_admin_token_in_config_rotates_the_token() {
+        let admin = AdminState::new(AdminApiConfig {
+            token: "old-token".to_string(),
+            proxy_config: default_proxy_config(),
+            config_path: None,
+        });
+        let previous = admin.lock_config().clone();
+        let next = apply_config_patch(&previous, &json!({"adminToken": "new-token"})).unwrap();
+        assert!(admin.rotate_token(&previous, &next));
+
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("x-admin-token", "old-token".parse().unwrap());
+        assert!(!admin.authorized(&headers));
+        headers.insert("x-admin-token", "new-token".parse().unwrap());
+        assert!(admin.authorized(&headers));
+
+        // 未改动或清空 adminToken 时保留当前令牌
+        assert!(!admin.rotate_token(&next, &next));
+        assert!(!admin.rotate_token(&next, &previous));
+        assert!(admin.authorized(&headers));
+    }
+}
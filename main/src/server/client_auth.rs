@@ -0,0 +1,263 @@
+//! 客户端访问令牌：对外开放监听时校验入站 `x-api-key` / `Authorization`
+//!
+//! 未配置任何令牌时保持原有行为（入站 key 可作为上游 key 透传）；配置后每个请求都必须携带
+//! 已启用的令牌，且令牌本身不会被转发到上游。
+
+use hyper::{HeaderMap, Response, StatusCode};
+use serde_json::json;
+
+use super::admin::constant_time_eq;
+use super::{extract_bearer_token, full_body, ClientRouteKind, HandlerResult};
+use crate::config::{ClientTokenConfig, RateLimitConfig};
+use crate::load_balancer::ModelSlot;
+
+/// 通过校验的客户端
+#[derive(Debug, Clone)]
+pub(crate) struct ClientIdentity {
+    pub name: String,
+    pub rate_limit: RateLimitConfig,
+    allowed_slots: Vec<String>,
+}
+
+impl ClientIdentity {
+    pub fn allows_slot(&self, slot: ModelSlot) -> bool {
+        allows(&self.allowed_slots, slot.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ClientAuthError {
+    Missing,
+    Invalid,
+    Disabled(String),
+    RouteNotAllowed { client: String, route: &'static str },
+    SlotNotAllowed { client: String, slot: &'static str },
+}
+
+impl ClientAuthError {
+    pub fn client_name(&self) -> &str {
+        match self {
+            ClientAuthError::Missing | ClientAuthError::Invalid => "-",
+            ClientAuthError::Disabled(client)
+            | ClientAuthError::RouteNotAllowed { client, .. }
+            | ClientAuthError::SlotNotAllowed { client, .. } => client,
+        }
+    }
+
+    /// 写入日志的简短原因
+    pub fn reason(&self) -> String {
+        match self {
+            ClientAuthError::Missing => "missing_token".to_string(),
+            ClientAuthError::Invalid => "invalid_token".to_string(),
+            ClientAuthError::Disabled(_) => "token_disabled".to_string(),
+            ClientAuthError::RouteNotAllowed { route, .. } => {
+                format!("route_not_allowed route={}", route)
+            }
+            ClientAuthError::SlotNotAllowed { slot, .. } => {
+                format!("slot_not_allowed slot={}", slot)
+            }
+        }
+    }
+
+    /// Anthropic 格式错误：令牌问题 401 `authentication_error`，越权 403 `permission_error`
+    pub fn into_response(self) -> HandlerResult {
+        let (status, error_type, message) = match &self {
+            ClientAuthError::Missing => (
+                StatusCode::UNAUTHORIZED,
+                "authentication_error",
+                "x-api-key header is required".to_string(),
+            ),
+            ClientAuthError::Invalid => (
+                StatusCode::UNAUTHORIZED,
+                "authentication_error",
+                "invalid x-api-key".to_string(),
+            ),
+            ClientAuthError::Disabled(_) => (
+                StatusCode::UNAUTHORIZED,
+                "authentication_error",
+                "this API key has been disabled".to_string(),
+            ),
+            ClientAuthError::RouteNotAllowed { route, .. } => (
+                StatusCode::FORBIDDEN,
+                "permission_error",
+                format!("this API key is not allowed to use the '{}' route", route),
+            ),
+            ClientAuthError::SlotNotAllowed { slot, .. } => (
+                StatusCode::FORBIDDEN,
+                "permission_error",
+                format!("this API key is not allowed to use '{}' models", slot),
+            ),
+        };
+        Ok(Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(full_body(
+                json!({
+                    "type": "error",
+                    "error": {"type": error_type, "message": message}
+                })
+                .to_string(),
+            ))
+            .unwrap())
+    }
+}
+
+fn allows(allowed: &[String], value: &str) -> bool {
+    allowed.is_empty()
+        || allowed
+            .iter()
+            .any(|item| item.trim().eq_ignore_ascii_case(value))
+}
+
+/// 入站令牌：`x-api-key` 优先，其次 `Authorization: Bearer`
+fn incoming_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .or_else(|| {
+            headers
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(extract_bearer_token)
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        })
+}
+
+/// 校验入站令牌；未配置令牌时返回 `Ok(None)`
+pub(crate) fn authenticate_client(
+    tokens: &[ClientTokenConfig],
+    headers: &HeaderMap,
+    route: ClientRouteKind,
+) -> Result<Option<ClientIdentity>, ClientAuthError> {
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+    let provided = incoming_token(headers).ok_or(ClientAuthError::Missing)?;
+    let matched = tokens
+        .iter()
+        .filter(|entry| !entry.token.is_empty())
+        .find(|entry| constant_time_eq(provided.as_bytes(), entry.token.as_bytes()))
+        .ok_or(ClientAuthError::Invalid)?;
+    if !matched.enabled {
+        return Err(ClientAuthError::Disabled(matched.name.clone()));
+    }
+    if !allows(&matched.allowed_routes, route.as_str()) {
+        return Err(ClientAuthError::RouteNotAllowed {
+            client: matched.name.clone(),
+            route: route.as_str(),
+        });
+    }
+    Ok(Some(ClientIdentity {
+        name: matched.name.clone(),
+        rate_limit: matched.rate_limit.clone(),
+        allowed_slots: matched.allowed_slots.clone(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(name: &str, token: &str) -> ClientTokenConfig {
+        ClientTokenConfig {
+            name: name.to_string(),
+            token: token.to_string(),
+            enabled: true,
+            allowed_routes: Vec::new(),
+            allowed_slots: Vec::new(),
+            rate_limit: RateLimitConfig::default(),
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn no_configured_tokens_allows_anonymous_requests() {
+        let result = authenticate_client(&[], &HeaderMap::new(), ClientRouteKind::Claude);
+        assert!(matches!(result, Ok(None)));
+    }
+
+    #[test]
+    fn accepts_x_api_key_and_bearer_tokens() {
+        let tokens = vec![token("alice", "tok-alice"), token("bob", "tok-bob")];
+        let by_key = authenticate_client(
+            &tokens,
+            &headers(&[("x-api-key", "tok-alice")]),
+            ClientRouteKind::Claude,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(by_key.name, "alice");
+
+        let by_bearer = authenticate_client(
+            &tokens,
+            &headers(&[("authorization", "Bearer tok-bob")]),
+            ClientRouteKind::Codex,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(by_bearer.name, "bob");
+    }
+
+    #[test]
+    fn rejects_missing_unknown_and_disabled_tokens() {
+        let mut disabled = token("carol", "tok-carol");
+        disabled.enabled = false;
+        let tokens = vec![token("alice", "tok-alice"), disabled];
+
+        assert_eq!(
+            authenticate_client(&tokens, &HeaderMap::new(), ClientRouteKind::Claude).unwrap_err(),
+            ClientAuthError::Missing
+        );
+        assert_eq!(
+            authenticate_client(
+                &tokens,
+                &headers(&[("x-api-key", "tok-mallory")]),
+                ClientRouteKind::Claude
+            )
+            .unwrap_err(),
+            ClientAuthError::Invalid
+        );
+        assert_eq!(
+            authenticate_client(
+                &tokens,
+                &headers(&[("x-api-key", "tok-carol")]),
+                ClientRouteKind::Claude
+            )
+            .unwrap_err(),
+            ClientAuthError::Disabled("carol".to_string())
+        );
+    }
+
+    #[test]
+    fn enforces_allowed_routes_and_slots() {
+        let mut limited = token("ci", "tok-ci");
+        limited.allowed_routes = vec!["codex".to_string()];
+        limited.allowed_slots = vec!["Haiku".to_string()];
+        let tokens = vec![limited];
+        let request_headers = headers(&[("x-api-key", "tok-ci")]);
+
+        assert_eq!(
+            authenticate_client(&tokens, &request_headers, ClientRouteKind::Claude).unwrap_err(),
+            ClientAuthError::RouteNotAllowed {
+                client: "ci".to_string(),
+                route: "claude",
+            }
+        );
+        let identity = authenticate_client(&tokens, &request_headers, ClientRouteKind::Codex)
+            .unwrap()
+            .unwrap();
+        assert!(identity.allows_slot(ModelSlot::Haiku));
+        assert!(!identity.allows_slot(ModelSlot::Opus));
+    }
+}
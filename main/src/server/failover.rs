@@ -0,0 +1,201 @@
+//! 流内故障转移：负载均衡下的流式请求在向客户端输出任何业务事件之前卡住、空完成或中途断开时，
+//! 不再原样重发到同一上游，而是记一次失败并向负载均衡器要另一个端点的候选
+//!
+//! 候选的 converter 与原端点不同时，按新 converter 重新构建请求体；响应转换器由流式转发任务重建。
+//! 转移请求的结果按主链路的规则反馈给负载均衡（密钥冷却、不可用标记、错误计数）；
+//! 没有其他可用候选或转移请求失败时返回 None，由调用方退回同端点重试。
+
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::broadcast;
+
+use super::{
+    build_backend_by_converter, build_codex_v1_endpoint_key, execute_stream_retry_request,
+    extract_cooldown_info, is_codex_v1_endpoint_unsupported, parse_seconds_str,
+    resolve_route_selection, resolve_upstream_url_with_codex_path_preference,
+    transform_request_with_optional_codex_effort_override, CodexV1UnsupportedEndpointStore,
+    LbLatencyFeedback, StreamRetryFailure, UpstreamOperation,
+};
+use crate::load_balancer::{EndpointPermit, LoadBalancerRuntime, ModelSlot, ResolvedEndpoint};
+use crate::logger::AppLogger;
+use crate::model_routes::ModelRoute;
+use crate::models::AnthropicRequest;
+use crate::transform::anthropic::build_raw_passthrough_body;
+use crate::transform::{TransformBackend, TransformContext};
+
+/// 为一次流式请求重新选路所需的请求上下文
+pub(crate) struct StreamFailover {
+    pub runtime: LoadBalancerRuntime,
+    /// 当前正在转发的路由；转移成功后更新为新路由
+    pub route: ResolvedEndpoint,
+    pub request_id: String,
+    pub input_model: String,
+    pub input_slot: ModelSlot,
+    pub model_route: Option<ModelRoute>,
+    pub target_url: String,
+    pub api_key: String,
+    pub ctx: TransformContext,
+    pub anthropic_body: AnthropicRequest,
+    /// anthropic 透传时使用的原始请求 JSON
+    pub raw_request_body: Value,
+    pub prefer_codex_v1_path: bool,
+    pub codex_v1_unsupported_endpoints: CodexV1UnsupportedEndpointStore,
+    pub log_tx: broadcast::Sender<String>,
+}
+
+/// 已拿到成功响应头的转移请求
+pub(crate) struct FailoverAttempt {
+    pub response: reqwest::Response,
+    pub status: u16,
+    pub session_id: String,
+    pub backend: Arc<dyn TransformBackend>,
+    pub converter: String,
+    pub model: String,
+    pub upstream_url: String,
+    pub api_key: String,
+    pub upstream_body: Value,
+    /// 新路由的在途占用，随流式转发结束释放
+    pub permit: Option<EndpointPermit>,
+    pub feedback: LbLatencyFeedback,
+}
+
+impl StreamFailover {
+    /// 记录当前路由的失败，并向另一个端点发出同样的请求
+    pub async fn next_attempt(
+        &mut self,
+        cause: &str,
+        http_client: &reqwest::Client,
+        anthropic_version: &str,
+        anthropic_beta: Option<&str>,
+        logger: &Option<Arc<AppLogger>>,
+    ) -> Option<FailoverAttempt> {
+        self.runtime.record_stream_failure(&self.route, cause);
+        let mut selection = resolve_route_selection(
+            &self.request_id,
+            &self.input_model,
+            self.input_slot,
+            self.model_route.as_ref(),
+            &self.target_url,
+            &self.api_key,
+            &self.ctx,
+            Some(&self.runtime),
+            None,
+            // 换端点只在原候选的 slot 内进行，不经过降级链
+            None,
+            Some(&self.route),
+            &self.log_tx,
+        )
+        .ok()?;
+        let route = selection.route.take()?;
+        let _ = self.log_tx.send(format!(
+            "[LB] #{} stream_failover cause={} from_route={} to_route={} converter={}->{}",
+            self.request_id,
+            cause,
+            self.route.route_key,
+            route.route_key,
+            self.route.converter,
+            selection.converter,
+        ));
+
+        let backend = build_backend_by_converter(&selection.converter);
+        let upstream_body = if selection.converter.eq_ignore_ascii_case("anthropic") {
+            build_raw_passthrough_body(&self.raw_request_body, Some(&selection.model_name))
+        } else {
+            transform_request_with_optional_codex_effort_override(
+                &selection.converter,
+                &backend,
+                &self.anthropic_body,
+                &self.log_tx,
+                &self.ctx,
+                &selection.model_name,
+                selection.reasoning_effort_override,
+                true,
+            )
+            .0
+        };
+        let is_codex = selection.converter.eq_ignore_ascii_case("codex");
+        let prefer_codex_v1_path = is_codex
+            && self.prefer_codex_v1_path
+            && !is_codex_v1_endpoint_unsupported(
+                &self.codex_v1_unsupported_endpoints,
+                &build_codex_v1_endpoint_key(
+                    &selection.converter,
+                    &selection.target_url,
+                    &selection.api_key,
+                ),
+            );
+        let upstream_url = resolve_upstream_url_with_codex_path_preference(
+            &selection.converter,
+            &selection.target_url,
+            UpstreamOperation::Messages,
+            &selection.model_name,
+            prefer_codex_v1_path,
+        );
+
+        let attempt_started_at = Instant::now();
+        let retry = match execute_stream_retry_request(
+            &backend,
+            http_client,
+            &upstream_url,
+            &selection.api_key,
+            &upstream_body,
+            anthropic_version,
+            anthropic_beta,
+            &self.request_id,
+            "stream_failover",
+            &self.log_tx,
+            logger,
+        )
+        .await
+        {
+            Ok(retry) => retry,
+            Err(StreamRetryFailure::Network) => {
+                self.runtime
+                    .handle_upstream_outcome(&route, None, true, None);
+                return None;
+            }
+            Err(StreamRetryFailure::Status {
+                status,
+                retry_after,
+                error_text,
+            }) => {
+                // 与主链路一致：密钥池端点只冷却当前密钥，否则按路由级结果计入
+                let retry_after_secs =
+                    extract_cooldown_info(status, &error_text, &retry_after, &selection.model_name)
+                        .map(|(_, secs, _)| secs)
+                        .or_else(|| parse_seconds_str(&retry_after));
+                if !self.runtime.cool_down_key(&route, status, retry_after_secs) {
+                    self.runtime.handle_upstream_outcome(
+                        &route,
+                        Some(status),
+                        false,
+                        Some(&error_text),
+                    );
+                }
+                return None;
+            }
+        };
+        self.runtime
+            .handle_upstream_outcome(&route, Some(retry.status), false, None);
+        self.route = route.clone();
+
+        Some(FailoverAttempt {
+            response: retry.response,
+            status: retry.status,
+            session_id: retry.session_id,
+            backend,
+            converter: selection.converter,
+            model: selection.model_name,
+            upstream_url,
+            api_key: selection.api_key,
+            upstream_body,
+            permit: selection.route_permit.take(),
+            feedback: LbLatencyFeedback {
+                runtime: self.runtime.clone(),
+                route,
+                attempt_started_at,
+            },
+        })
+    }
+}
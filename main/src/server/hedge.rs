@@ -0,0 +1,397 @@
+//! 对冲请求：流式请求的首个候选在 `hedgeAfterMs` 内没有产出业务事件时，向下一个候选发出
+//! 同样的请求，两路中先产出 message_start + 内容者胜出，另一路直接断开
+//!
+//! 等待期间读到的上游数据块会缓存下来，胜出后原样回放给流式转发任务，
+//! 因此这里的转换器只用于判断"是否出现业务事件"，不会向客户端输出。
+
+use bytes::Bytes;
+use futures_util::stream::{self, Stream, StreamExt};
+use futures_util::FutureExt;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use super::{drain_complete_lines, is_business_stream_output, SseFrameParser, SuccessfulAttempt};
+use crate::load_balancer::ResolvedEndpoint;
+use crate::transform::{ResponseTransformRequestContext, ResponseTransformer};
+
+/// 流式转发任务读取的上游字节流
+pub(crate) type UpstreamByteStream = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PeekOutcome {
+    /// 已出现首个业务事件（文本 / 工具调用 / thinking 内容）
+    Business,
+    /// 业务事件出现前流已结束或出错，交给原有的流式重试逻辑处理
+    Ended,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HedgeWinner {
+    Primary,
+    Hedge,
+}
+
+/// 等待首个业务事件的上游流
+pub(crate) struct PendingStream {
+    stream: UpstreamByteStream,
+    buffered: Vec<reqwest::Result<Bytes>>,
+    transformer: Box<dyn ResponseTransformer>,
+    use_frame_parser: bool,
+    frame_parser: SseFrameParser,
+    line_buffer: String,
+    outcome: Option<PeekOutcome>,
+    pub started_at: Instant,
+}
+
+impl PendingStream {
+    /// `transformer` 需与转发任务使用同一 backend / 模型创建，`use_frame_parser` 与
+    /// `enableSseFrameParser` 一致，保证判断结果与实际输出相同
+    pub fn new(
+        stream: UpstreamByteStream,
+        transformer: Box<dyn ResponseTransformer>,
+        use_frame_parser: bool,
+        started_at: Instant,
+    ) -> Self {
+        Self {
+            stream,
+            buffered: Vec::new(),
+            transformer,
+            use_frame_parser,
+            frame_parser: SseFrameParser::default(),
+            line_buffer: String::new(),
+            outcome: None,
+            started_at,
+        }
+    }
+
+    /// 接管一次成功尝试的响应体；响应体已被接管时返回 None
+    pub fn from_attempt(
+        attempt: &mut SuccessfulAttempt,
+        allow_visible_thinking: bool,
+        request_ctx: &ResponseTransformRequestContext,
+        use_frame_parser: bool,
+        started_at: Instant,
+    ) -> Option<Self> {
+        let response = attempt.response.take()?;
+        let mut transformer = attempt
+            .backend
+            .create_response_transformer(&attempt.model, allow_visible_thinking);
+        transformer.configure_request_context(request_ctx);
+        Some(Self::new(
+            response.bytes_stream().boxed(),
+            transformer,
+            use_frame_parser,
+            started_at,
+        ))
+    }
+
+    /// 读取直到出现业务事件、流结束或出错；可安全地被超时或 select 取消
+    pub async fn wait_business(&mut self) -> PeekOutcome {
+        if let Some(outcome) = self.outcome {
+            return outcome;
+        }
+        loop {
+            let Some(item) = self.stream.next().await else {
+                return self.finish(PeekOutcome::Ended);
+            };
+            let chunk = match &item {
+                Ok(chunk) => String::from_utf8_lossy(chunk).to_string(),
+                Err(_) => {
+                    self.buffered.push(item);
+                    return self.finish(PeekOutcome::Ended);
+                }
+            };
+            self.buffered.push(item);
+            if self.chunk_has_business_output(&chunk) {
+                return self.finish(PeekOutcome::Business);
+            }
+        }
+    }
+
+    /// 不再等待，只读取上游已到达的数据：返回已确定的结果，仍需等待时返回 None
+    fn settled_outcome(&mut self) -> Option<PeekOutcome> {
+        self.wait_business().now_or_never()
+    }
+
+    fn finish(&mut self, outcome: PeekOutcome) -> PeekOutcome {
+        self.outcome = Some(outcome);
+        outcome
+    }
+
+    fn chunk_has_business_output(&mut self, chunk: &str) -> bool {
+        let outputs: Vec<String> = if self.use_frame_parser {
+            self.frame_parser
+                .push_chunk(chunk)
+                .iter()
+                .flat_map(|frame| self.transformer.transform_event(frame))
+                .collect()
+        } else {
+            self.line_buffer.push_str(chunk);
+            drain_complete_lines(&mut self.line_buffer)
+                .iter()
+                .flat_map(|line| self.transformer.transform_line(line))
+                .collect()
+        };
+        outputs
+            .iter()
+            .any(|output| is_business_stream_output(output))
+    }
+
+    /// 已缓存的数据块在前、剩余上游流在后
+    pub fn into_stream(self) -> UpstreamByteStream {
+        stream::iter(self.buffered).chain(self.stream).boxed()
+    }
+}
+
+/// 两路竞速首个业务事件；返回胜者以及败者是否已失败（结束或出错）。
+/// 两路都在业务事件前结束时判主候选胜出，由原有流程处理其错误
+pub(crate) async fn race_pending_streams(
+    primary: &mut PendingStream,
+    hedge: &mut PendingStream,
+) -> (HedgeWinner, bool) {
+    let mut primary_ended = false;
+    let mut hedge_ended = false;
+    loop {
+        tokio::select! {
+            outcome = primary.wait_business(), if !primary_ended => match outcome {
+                PeekOutcome::Business => return (HedgeWinner::Primary, hedge_ended),
+                PeekOutcome::Ended => primary_ended = true,
+            },
+            outcome = hedge.wait_business(), if !hedge_ended => match outcome {
+                PeekOutcome::Business => return (HedgeWinner::Hedge, primary_ended),
+                PeekOutcome::Ended => hedge_ended = true,
+            },
+        }
+        if primary_ended && hedge_ended {
+            return (HedgeWinner::Primary, true);
+        }
+    }
+}
+
+/// 超过对冲等待时长仍无业务事件、正在等待对冲候选的主候选
+pub(crate) struct HedgePrimary {
+    attempt: SuccessfulAttempt,
+    pending: PendingStream,
+    route: ResolvedEndpoint,
+    /// 已为它发起过对冲尝试；再次回到尝试循环开头说明对冲候选失败
+    pub hedge_attempted: bool,
+}
+
+impl HedgePrimary {
+    pub fn start(
+        attempt: SuccessfulAttempt,
+        pending: PendingStream,
+        route: ResolvedEndpoint,
+        delay: Duration,
+        log_tx: &broadcast::Sender<String>,
+        request_id: &str,
+    ) -> Self {
+        let _ = log_tx.send(format!(
+            "[LB] #{} hedge_start after_ms={} primary_route={}",
+            request_id,
+            delay.as_millis(),
+            route.route_key
+        ));
+        crate::metrics::record_hedge(route.slot.as_str(), "started");
+        Self {
+            attempt,
+            pending,
+            route,
+            hedge_attempted: false,
+        }
+    }
+
+    pub fn route(&self) -> &ResolvedEndpoint {
+        &self.route
+    }
+
+    /// 没有可用的对冲候选或对冲请求失败：继续使用主候选
+    pub fn fall_back(
+        self,
+        log_tx: &broadcast::Sender<String>,
+        request_id: &str,
+        reason: &str,
+    ) -> SuccessfulAttempt {
+        let _ = log_tx.send(format!(
+            "[LB] #{} hedge_fallback reason={} primary_route={}",
+            request_id, reason, self.route.route_key
+        ));
+        crate::metrics::record_hedge(self.route.slot.as_str(), "hedge_unavailable");
+        let mut attempt = self.attempt;
+        attempt.hedged_stream = Some(self.pending.into_stream());
+        attempt
+    }
+
+    /// 与已拿到响应头的对冲候选竞速，返回胜者；败者的连接与并发名额随之释放。
+    /// 败者已失败按网络错误、已产出业务事件按其状态码计入 `record_result`；
+    /// 仍在等待而被取消的不计结果，只以已等待时长作为 TTFB 下限计入延迟
+    pub async fn settle(
+        mut self,
+        hedge: SuccessfulAttempt,
+        mut hedge_pending: PendingStream,
+        race_timeout: Duration,
+        log_tx: &broadcast::Sender<String>,
+        request_id: &str,
+    ) -> SuccessfulAttempt {
+        let race = tokio::time::timeout(
+            race_timeout,
+            race_pending_streams(&mut self.pending, &mut hedge_pending),
+        )
+        .await;
+        // 两路都迟迟没有业务事件时按主候选继续，由流式转发的停滞检测接手
+        let race_timed_out = race.is_err();
+        let (winner, _) = race.unwrap_or((HedgeWinner::Primary, false));
+        let (mut winner_attempt, winner_pending, loser_attempt, mut loser_pending) = match winner {
+            HedgeWinner::Primary => (self.attempt, self.pending, hedge, hedge_pending),
+            HedgeWinner::Hedge => (hedge, hedge_pending, self.attempt, self.pending),
+        };
+        let loser_outcome = loser_pending.settled_outcome();
+
+        let route_key = |attempt: &SuccessfulAttempt| {
+            attempt
+                .lb_feedback
+                .as_ref()
+                .map(|feedback| feedback.route.route_key.clone())
+                .unwrap_or_else(|| attempt.endpoint.clone())
+        };
+        let _ = log_tx.send(format!(
+            "[LB] #{} hedge_settled winner={} route={} loser_route={} loser={} race_timeout={}",
+            request_id,
+            match winner {
+                HedgeWinner::Primary => "primary",
+                HedgeWinner::Hedge => "hedge",
+            },
+            route_key(&winner_attempt),
+            route_key(&loser_attempt),
+            match loser_outcome {
+                Some(PeekOutcome::Business) => "completed",
+                Some(PeekOutcome::Ended) => "failed",
+                None => "cancelled",
+            },
+            race_timed_out,
+        ));
+        crate::metrics::record_hedge(
+            self.route.slot.as_str(),
+            match winner {
+                HedgeWinner::Primary => "primary_won",
+                HedgeWinner::Hedge => "hedge_won",
+            },
+        );
+
+        if let Some(feedback) = loser_attempt.lb_feedback.as_ref() {
+            match loser_outcome {
+                Some(PeekOutcome::Ended) => {
+                    feedback.runtime.record_result(&feedback.route, None, true);
+                }
+                Some(PeekOutcome::Business) => feedback.runtime.record_result(
+                    &feedback.route,
+                    Some(loser_attempt.upstream_status),
+                    false,
+                ),
+                None => {}
+            }
+            if loser_outcome != Some(PeekOutcome::Ended) {
+                feedback.runtime.record_latency(
+                    &feedback.route,
+                    loser_pending.started_at.elapsed(),
+                    None,
+                );
+            }
+        }
+        drop(loser_pending);
+        drop(loser_attempt);
+
+        winner_attempt.hedged_stream = Some(winner_pending.into_stream());
+        winner_attempt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::{AnthropicBackend, TransformBackend};
+    use std::time::Duration;
+
+    const MESSAGE_START: &str = "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"type\":\"message\",\"role\":\"assistant\",\"content\":[],\"model\":\"m\"}}\n\n";
+    const TEXT_DELTA: &str = "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"hi\"}}\n\n";
+
+    fn pending(chunks: Vec<&'static str>, delay: Duration) -> PendingStream {
+        let stream = stream::iter(chunks)
+            .then(move |chunk| async move {
+                tokio::time::sleep(delay).await;
+                Ok(Bytes::from(chunk))
+            })
+            .boxed();
+        let transformer = AnthropicBackend.create_response_transformer("m", true);
+        PendingStream::new(stream, transformer, true, Instant::now())
+    }
+
+    fn ready(chunks: Vec<&'static str>) -> PendingStream {
+        let stream = stream::iter(chunks.into_iter().map(|chunk| Ok(Bytes::from(chunk)))).boxed();
+        let transformer = AnthropicBackend.create_response_transformer("m", true);
+        PendingStream::new(stream, transformer, true, Instant::now())
+    }
+
+    async fn collect(stream: UpstreamByteStream) -> String {
+        stream
+            .map(|item| String::from_utf8_lossy(&item.unwrap()).to_string())
+            .collect::<Vec<_>>()
+            .await
+            .concat()
+    }
+
+    #[tokio::test]
+    async fn buffered_chunks_are_replayed_before_the_rest_of_the_stream() {
+        let mut pending = pending(
+            vec![MESSAGE_START, TEXT_DELTA, "event: message_stop\n\n"],
+            Duration::ZERO,
+        );
+        assert_eq!(pending.wait_business().await, PeekOutcome::Business);
+        assert_eq!(pending.buffered.len(), 2);
+        let replayed = collect(pending.into_stream()).await;
+        assert_eq!(
+            replayed,
+            format!("{}{}event: message_stop\n\n", MESSAGE_START, TEXT_DELTA)
+        );
+    }
+
+    #[tokio::test]
+    async fn message_start_alone_is_not_a_business_event() {
+        let mut pending = pending(vec![MESSAGE_START], Duration::ZERO);
+        assert_eq!(pending.wait_business().await, PeekOutcome::Ended);
+        // 结果会被记住，不会再次读取
+        assert_eq!(pending.wait_business().await, PeekOutcome::Ended);
+    }
+
+    #[tokio::test]
+    async fn faster_stream_wins_and_failed_loser_is_reported() {
+        let mut primary = pending(vec![MESSAGE_START, TEXT_DELTA], Duration::from_millis(200));
+        let mut hedge = pending(vec![MESSAGE_START, TEXT_DELTA], Duration::from_millis(5));
+        assert_eq!(
+            race_pending_streams(&mut primary, &mut hedge).await,
+            (HedgeWinner::Hedge, false)
+        );
+
+        let mut primary = pending(vec![MESSAGE_START, TEXT_DELTA], Duration::from_millis(20));
+        let mut hedge = pending(vec![MESSAGE_START], Duration::from_millis(1));
+        assert_eq!(
+            race_pending_streams(&mut primary, &mut hedge).await,
+            (HedgeWinner::Primary, true)
+        );
+    }
+
+    #[tokio::test]
+    async fn loser_outcome_distinguishes_completed_failed_and_cancelled() {
+        assert_eq!(
+            ready(vec![MESSAGE_START, TEXT_DELTA]).settled_outcome(),
+            Some(PeekOutcome::Business)
+        );
+        assert_eq!(
+            ready(vec![MESSAGE_START]).settled_outcome(),
+            Some(PeekOutcome::Ended)
+        );
+        let mut slow = pending(vec![MESSAGE_START, TEXT_DELTA], Duration::from_secs(60));
+        assert_eq!(slow.settled_outcome(), None);
+    }
+}
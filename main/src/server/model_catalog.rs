@@ -0,0 +1,340 @@
+//! `/v1/models` 动态模型目录：按当前运行时配置展示每个 slot 实际映射到的上游模型
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::{get_active_cooldown_seconds, resolve_model_for_converter, RuntimeRouteState};
+use crate::load_balancer::ModelSlot;
+
+const MODEL_SLOTS: [ModelSlot; 3] = [ModelSlot::Opus, ModelSlot::Sonnet, ModelSlot::Haiku];
+
+/// 单个 slot 的当前路由结果
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SlotModelEntry {
+    pub slot: ModelSlot,
+    pub converter: String,
+    pub upstream_model: String,
+    pub reasoning_effort: Option<String>,
+    pub endpoint_id: Option<String>,
+    pub candidates: usize,
+    pub available: bool,
+    pub cooldown_remaining_secs: Option<u64>,
+}
+
+/// 只有 codex / gemini 按 reasoning effort 选择模型或档位；openai / anthropic 直接透传
+fn slot_reasoning_effort(
+    route: &RuntimeRouteState,
+    converter: &str,
+    slot: ModelSlot,
+) -> Option<String> {
+    if !converter.eq_ignore_ascii_case("codex") && !converter.eq_ignore_ascii_case("gemini") {
+        return None;
+    }
+    let mapping = &route.ctx.reasoning_mapping;
+    let effort = match slot {
+        ModelSlot::Opus => mapping.opus,
+        ModelSlot::Sonnet => mapping.sonnet,
+        ModelSlot::Haiku => mapping.haiku,
+    };
+    Some(effort.as_str().to_string())
+}
+
+fn slot_upstream_model(route: &RuntimeRouteState, converter: &str, slot: ModelSlot) -> String {
+    let ctx = &route.ctx;
+    resolve_model_for_converter(
+        converter,
+        slot.as_str(),
+        &ctx.reasoning_mapping,
+        &ctx.codex_model_mapping,
+        &ctx.anthropic_model_mapping,
+        &ctx.openai_model_mapping,
+        &ctx.gemini_reasoning_effort,
+    )
+}
+
+pub(crate) fn build_slot_models(
+    route: &RuntimeRouteState,
+    model_cooldowns: &Arc<Mutex<HashMap<String, Instant>>>,
+) -> Vec<SlotModelEntry> {
+    MODEL_SLOTS
+        .iter()
+        .map(|&slot| {
+            let mut entry = match route.load_balancer_runtime.as_ref() {
+                Some(runtime) => {
+                    let candidates = runtime.slot_status(slot);
+                    let primary = candidates
+                        .iter()
+                        .find(|candidate| candidate.is_available())
+                        .or_else(|| candidates.first());
+                    let converter = primary
+                        .map(|candidate| candidate.converter.clone())
+                        .unwrap_or_else(|| route.ctx.converter.clone());
+                    SlotModelEntry {
+                        slot,
+                        upstream_model: primary
+                            .and_then(|candidate| candidate.model.clone())
+                            .unwrap_or_else(|| slot_upstream_model(route, &converter, slot)),
+                        reasoning_effort: primary
+                            .and_then(|candidate| candidate.reasoning_effort.clone())
+                            .or_else(|| slot_reasoning_effort(route, &converter, slot)),
+                        endpoint_id: primary.map(|candidate| candidate.endpoint_id.clone()),
+                        candidates: candidates.len(),
+                        available: candidates.iter().any(|candidate| candidate.is_available()),
+                        cooldown_remaining_secs: if candidates
+                            .iter()
+                            .any(|candidate| candidate.is_available())
+                        {
+                            None
+                        } else {
+                            candidates
+                                .iter()
+                                .filter_map(|candidate| {
+                                    candidate
+                                        .cooldown_remaining_secs
+                                        .or(candidate.backoff_remaining_secs)
+                                })
+                                .min()
+                        },
+                        converter,
+                    }
+                }
+                None => SlotModelEntry {
+                    slot,
+                    upstream_model: slot_upstream_model(route, &route.ctx.converter, slot),
+                    reasoning_effort: slot_reasoning_effort(route, &route.ctx.converter, slot),
+                    converter: route.ctx.converter.clone(),
+                    endpoint_id: None,
+                    candidates: 1,
+                    available: true,
+                    cooldown_remaining_secs: None,
+                },
+            };
+
+            // 本地模型冷却（上游 429 Retry-After）期间该 slot 的请求会被直接拒绝
+            if let Some(remaining) =
+                get_active_cooldown_seconds(model_cooldowns, &entry.upstream_model)
+            {
+                entry.available = false;
+                entry.cooldown_remaining_secs = Some(
+                    entry
+                        .cooldown_remaining_secs
+                        .map_or(remaining, |current| current.max(remaining)),
+                );
+            }
+            entry
+        })
+        .collect()
+}
+
+/// 按客户端传入的模型 id 匹配 slot（`opus` / `claude-opus-4-6` / 上游模型名均可）
+pub(crate) fn find_slot_model<'a>(
+    entries: &'a [SlotModelEntry],
+    model_id: &str,
+) -> Option<&'a SlotModelEntry> {
+    let lower = model_id.to_ascii_lowercase();
+    entries
+        .iter()
+        .find(|entry| entry.upstream_model.eq_ignore_ascii_case(model_id))
+        .or_else(|| {
+            entries
+                .iter()
+                .find(|entry| lower.contains(entry.slot.as_str()))
+        })
+}
+
+fn display_name(entry: &SlotModelEntry) -> String {
+    let slot = entry.slot.as_str();
+    let mut name = format!(
+        "{}{} → {}",
+        slot[..1].to_ascii_uppercase(),
+        &slot[1..],
+        entry.upstream_model
+    );
+    if let Some(effort) = entry.reasoning_effort.as_deref() {
+        name.push_str(&format!(" ({})", effort));
+    }
+    name
+}
+
+/// 同时满足 Anthropic（type / display_name / created_at）与 OpenAI（object / created / owned_by）两种格式
+pub(crate) fn encode_model_entry(entry: &SlotModelEntry, id: &str, created: i64) -> Value {
+    let created_at = chrono::DateTime::from_timestamp(created, 0)
+        .map(|value| value.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_default();
+    json!({
+        "id": id,
+        "type": "model",
+        "display_name": display_name(entry),
+        "created_at": created_at,
+        "object": "model",
+        "created": created,
+        "owned_by": entry.converter,
+        "root": entry.upstream_model,
+        "parent": null,
+        "permission": [],
+        "slot": entry.slot.as_str(),
+        "upstream_model": entry.upstream_model,
+        "converter": entry.converter,
+        "reasoning_effort": entry.reasoning_effort,
+        "endpoint_id": entry.endpoint_id,
+        "candidates": entry.candidates,
+        "available": entry.available,
+        "cooldown_remaining_secs": entry.cooldown_remaining_secs,
+    })
+}
+
+pub(crate) fn encode_model_list(entries: &[SlotModelEntry], created: i64) -> Value {
+    let data: Vec<Value> = entries
+        .iter()
+        .map(|entry| encode_model_entry(entry, entry.slot.as_str(), created))
+        .collect();
+    json!({
+        "object": "list",
+        "has_more": false,
+        "first_id": data.first().and_then(|item| item.get("id")).cloned(),
+        "last_id": data.last().and_then(|item| item.get("id")).cloned(),
+        "data": data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_balancer::{
+        test_support, EndpointPolicy, LoadBalancerProfile, LoadBalancerRuntime, SlotEndpointRef,
+        SlotMapping,
+    };
+    use crate::models::{
+        AnthropicModelMapping, CodexModelMapping, GeminiReasoningEffortMapping,
+        OpenAIMaxTokensMapping, OpenAIModelMapping, ReasoningEffortMapping,
+    };
+    use crate::transform::TransformContext;
+
+    fn route(converter: &str, runtime: Option<LoadBalancerRuntime>) -> RuntimeRouteState {
+        RuntimeRouteState {
+            target_url: "https://upstream.example".to_string(),
+            api_key: None,
+            ctx: TransformContext {
+                reasoning_mapping: ReasoningEffortMapping::default(),
+                codex_model_mapping: CodexModelMapping::default(),
+                anthropic_model_mapping: AnthropicModelMapping::default(),
+                openai_model_mapping: OpenAIModelMapping {
+                    opus: "gpt-4.1".to_string(),
+                    sonnet: String::new(),
+                    haiku: String::new(),
+                },
+                openai_max_tokens_mapping: OpenAIMaxTokensMapping::default(),
+                custom_injection_prompt: String::new(),
+                converter: converter.to_string(),
+                codex_model: "gpt-5.3-codex".to_string(),
+                gemini_reasoning_effort: GeminiReasoningEffortMapping::default(),
+                enable_codex_tool_schema_compaction: true,
+                enable_codex_fast_mode: true,
+                enable_skill_routing_hint: false,
+            },
+            load_balancer_runtime: runtime,
+            image_generation_url: String::new(),
+            image_generation_api_key: None,
+            strip_image_generation_tool: false,
+        }
+    }
+
+    fn slot_ref(endpoint_id: &str, model: Option<&str>) -> SlotEndpointRef {
+        SlotEndpointRef {
+            custom_model_name: model.map(str::to_string),
+            ..test_support::slot_ref(endpoint_id)
+        }
+    }
+
+    #[test]
+    fn single_mode_reports_mapped_model_per_slot() {
+        let cooldowns = Arc::new(Mutex::new(HashMap::new()));
+        let entries = build_slot_models(&route("gemini", None), &cooldowns);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].upstream_model, "gemini-3-pro-preview");
+        assert_eq!(entries[0].reasoning_effort.as_deref(), Some("xhigh"));
+        assert_eq!(entries[2].upstream_model, "gemini-3-flash-preview");
+        assert!(entries.iter().all(|entry| entry.available));
+
+        let openai = build_slot_models(&route("openai", None), &cooldowns);
+        assert_eq!(openai[0].upstream_model, "gpt-4.1");
+        assert_eq!(openai[0].reasoning_effort, None);
+        // 未配置映射时原样透传 slot 名
+        assert_eq!(openai[1].upstream_model, "sonnet");
+    }
+
+    #[test]
+    fn model_cooldown_marks_slot_unavailable() {
+        let cooldowns = Arc::new(Mutex::new(HashMap::new()));
+        cooldowns.lock().unwrap().insert(
+            "gpt-5.3-codex".to_string(),
+            Instant::now() + std::time::Duration::from_secs(30),
+        );
+        let entries = build_slot_models(&route("codex", None), &cooldowns);
+        assert!(!entries[0].available);
+        assert!(entries[0].cooldown_remaining_secs.unwrap() > 0);
+        assert!(entries[1].available);
+    }
+
+    #[test]
+    fn load_balancer_mode_uses_slot_endpoint_model_and_health() {
+        let runtime = test_support::runtime(
+            LoadBalancerProfile {
+                id: "p".to_string(),
+                model_mapping: SlotMapping {
+                    opus: vec![
+                        slot_ref("ep-a", Some("gpt-4.1")),
+                        slot_ref("ep-b", Some("gemini-2.5-pro")),
+                    ],
+                    sonnet: vec![slot_ref("ep-b", None)],
+                    haiku: vec![],
+                },
+                ..LoadBalancerProfile::default()
+            },
+            &[("ep-a", "openai"), ("ep-b", "gemini")],
+            [(
+                "ep-a".to_string(),
+                EndpointPolicy {
+                    enabled: false,
+                    ..EndpointPolicy::default()
+                },
+            )]
+            .into_iter()
+            .collect(),
+        );
+
+        let cooldowns = Arc::new(Mutex::new(HashMap::new()));
+        let entries = build_slot_models(&route("codex", Some(runtime)), &cooldowns);
+
+        assert_eq!(entries[0].endpoint_id.as_deref(), Some("ep-b"));
+        assert_eq!(entries[0].upstream_model, "gemini-2.5-pro");
+        assert_eq!(entries[0].converter, "gemini");
+        assert_eq!(entries[0].candidates, 2);
+        assert!(entries[0].available);
+        assert_eq!(entries[1].upstream_model, "gemini-3-flash-preview");
+        assert_eq!(entries[2].candidates, 0);
+        assert!(!entries[2].available);
+    }
+
+    #[test]
+    fn model_list_is_readable_as_anthropic_and_openai() {
+        let cooldowns = Arc::new(Mutex::new(HashMap::new()));
+        let entries = build_slot_models(&route("codex", None), &cooldowns);
+        let list = encode_model_list(&entries, 1_700_000_000);
+        assert_eq!(list["object"], "list");
+        assert_eq!(list["first_id"], "opus");
+        assert_eq!(list["last_id"], "haiku");
+        let opus = &list["data"][0];
+        assert_eq!(opus["type"], "model");
+        assert_eq!(opus["created"], 1_700_000_000);
+        assert_eq!(opus["created_at"], "2023-11-14T22:13:20Z");
+        assert_eq!(opus["display_name"], "Opus → gpt-5.3-codex (xhigh)");
+
+        let detail = find_slot_model(&entries, "claude-haiku-4-5").expect("haiku");
+        assert_eq!(detail.slot, ModelSlot::Haiku);
+        assert!(find_slot_model(&entries, "gpt-5.2-codex").is_some());
+        assert!(find_slot_model(&entries, "unknown").is_none());
+    }
+}
@@ -0,0 +1,131 @@
+//! 负载均衡后台探测：定期向冷却中、试探中及已停用的路由发送最小测试请求
+//!
+//! 只在 `lbProbeIntervalSeconds > 0` 时生效；探测结果交给 `LoadBalancerRuntime::record_probe_result`
+//! 推进 Cooldown -> HalfOpen -> Healthy，并沿用 `[LB]` / `[LBStatus]` 日志。
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use super::{
+    build_backend_by_converter, resolve_model_for_converter,
+    resolve_upstream_url_with_codex_path_preference, should_retry_codex_v1_path_with_legacy,
+    ProxyRuntimeHandle, RuntimeRouteState, UpstreamOperation,
+};
+use crate::endpoint_test::{
+    build_endpoint_test_request_for_model, run_endpoint_test_attempt, EndpointTestAttempt,
+};
+use crate::load_balancer::ProbeTarget;
+
+/// 检查到期探测目标的周期（探测间隔本身由配置决定）
+const PROBE_TICK: Duration = Duration::from_secs(5);
+/// 单次探测（含等待首个数据块）的总超时
+const PROBE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 启动后台探测任务，随 shutdown 信号退出
+pub(crate) fn spawn_health_prober(
+    runtime_handle: ProxyRuntimeHandle,
+    http_client: Arc<reqwest::Client>,
+    log_tx: broadcast::Sender<String>,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(PROBE_TICK) => {}
+                _ = shutdown_rx.recv() => break,
+            }
+            let snapshot = runtime_handle.snapshot();
+            let prefer_codex_v1_path = snapshot.prefer_codex_v1_path;
+            for route in [snapshot.claude_route, snapshot.codex_route] {
+                let Some(runtime) = route.load_balancer_runtime.clone() else {
+                    continue;
+                };
+                for target in runtime.due_probe_targets() {
+                    let route = route.clone();
+                    let runtime = runtime.clone();
+                    let http_client = Arc::clone(&http_client);
+                    let log_tx = log_tx.clone();
+                    tokio::spawn(async move {
+                        let _ = log_tx.send(format!(
+                            "[LB] probe route={} health={} enabled={}",
+                            target.route.route_key,
+                            target.health.as_str(),
+                            target.enabled
+                        ));
+                        let (success, detail) =
+                            probe_route(&http_client, &route, prefer_codex_v1_path, &target).await;
+                        runtime.record_probe_result(&target.route, success, &detail);
+                    });
+                }
+            }
+        }
+    });
+}
+
+async fn probe_route(
+    client: &reqwest::Client,
+    route: &RuntimeRouteState,
+    prefer_codex_v1_path: bool,
+    target: &ProbeTarget,
+) -> (bool, String) {
+    let resolved = &target.route;
+    let converter = resolved.converter.as_str();
+    let ctx = &route.ctx;
+    let input_model = format!("claude-{}-4-6", resolved.slot.as_str());
+    let model = resolved.model.clone().unwrap_or_else(|| {
+        resolve_model_for_converter(
+            converter,
+            &input_model,
+            &ctx.reasoning_mapping,
+            &ctx.codex_model_mapping,
+            &ctx.anthropic_model_mapping,
+            &ctx.openai_model_mapping,
+            &ctx.gemini_reasoning_effort,
+        )
+    });
+    let api_key = resolved
+        .api_key
+        .clone()
+        .or_else(|| route.api_key.clone())
+        .unwrap_or_default();
+    let backend = build_backend_by_converter(converter);
+    let request = build_endpoint_test_request_for_model(&input_model);
+    let (body, session_id) =
+        backend.transform_request(&request, None, ctx, true, Some(model.clone()));
+
+    let mut prefer_v1 = prefer_codex_v1_path;
+    loop {
+        let url = resolve_upstream_url_with_codex_path_preference(
+            converter,
+            &resolved.target_url,
+            UpstreamOperation::Messages,
+            &model,
+            prefer_v1,
+        );
+        let attempt = match tokio::time::timeout(
+            PROBE_TIMEOUT,
+            run_endpoint_test_attempt(client, &backend, &url, &api_key, &body, &session_id),
+        )
+        .await
+        {
+            Ok(attempt) => attempt,
+            Err(_) => return (false, "probe timed out".to_string()),
+        };
+        if let EndpointTestAttempt::HttpFailure {
+            http_status,
+            body_text,
+            ..
+        } = &attempt
+        {
+            if prefer_v1
+                && converter.eq_ignore_ascii_case("codex")
+                && should_retry_codex_v1_path_with_legacy(*http_status, body_text)
+            {
+                prefer_v1 = false;
+                continue;
+            }
+        }
+        return (attempt.is_success(), attempt.summary());
+    }
+}
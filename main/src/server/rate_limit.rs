@@ -0,0 +1,416 @@
+//! 限流与配额：每分钟请求数、并发流数、每小时/每天输入输出 token
+//!
+//! 限额来自运行时配置（全局 `rateLimit` 与各客户端令牌的 `rateLimit`，随 `RuntimeConfigUpdate`
+//! 热更新）；用量窗口保存在这里，热更新不会清零。
+
+use hyper::{Response, StatusCode};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{full_body, HandlerResult};
+use crate::config::RateLimitConfig;
+
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(3_600);
+const DAY: Duration = Duration::from_secs(86_400);
+/// 并发流满时建议的重试间隔
+const STREAM_RETRY_AFTER_SECS: u64 = 1;
+
+/// 用量窗口的键；全局与客户端分开存放，客户端名取任何值都不会与全局窗口混用
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Scope {
+    Global,
+    Client(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Default)]
+struct UsageWindow {
+    requests: VecDeque<Instant>,
+    /// (时间, 输入 token, 输出 token)
+    tokens: VecDeque<(Instant, u64, u64)>,
+    active_streams: u32,
+}
+
+impl UsageWindow {
+    fn prune(&mut self, now: Instant) {
+        while let Some(at) = self.requests.front() {
+            if now.duration_since(*at) < MINUTE {
+                break;
+            }
+            self.requests.pop_front();
+        }
+        while let Some((at, _, _)) = self.tokens.front() {
+            if now.duration_since(*at) < DAY {
+                break;
+            }
+            self.tokens.pop_front();
+        }
+    }
+
+    /// 窗口内的 token 用量达到上限时，返回用量回落到上限以下还需等待的秒数
+    fn token_retry_after(
+        &self,
+        kind: TokenKind,
+        window: Duration,
+        limit: u64,
+        now: Instant,
+    ) -> Option<u64> {
+        if limit == 0 {
+            return None;
+        }
+        let in_window: Vec<(Instant, u64)> = self
+            .tokens
+            .iter()
+            .filter(|(at, _, _)| now.duration_since(*at) < window)
+            .map(|(at, input, output)| match kind {
+                TokenKind::Input => (*at, *input),
+                TokenKind::Output => (*at, *output),
+            })
+            .collect();
+        let mut used: u64 = in_window.iter().map(|(_, value)| value).sum();
+        if used < limit {
+            return None;
+        }
+        for (at, value) in in_window {
+            used = used.saturating_sub(value);
+            if used < limit {
+                return Some(retry_after_secs(at + window, now));
+            }
+        }
+        Some(window.as_secs())
+    }
+
+    fn check(
+        &self,
+        limits: &RateLimitConfig,
+        streaming: bool,
+        now: Instant,
+    ) -> Result<(), (&'static str, u64)> {
+        if limits.requests_per_minute > 0
+            && self.requests.len() >= limits.requests_per_minute as usize
+        {
+            let oldest = self.requests.front().copied().unwrap_or(now);
+            return Err((
+                "requests_per_minute",
+                retry_after_secs(oldest + MINUTE, now),
+            ));
+        }
+        if streaming
+            && limits.max_concurrent_streams > 0
+            && self.active_streams >= limits.max_concurrent_streams
+        {
+            return Err(("max_concurrent_streams", STREAM_RETRY_AFTER_SECS));
+        }
+        for (name, kind, window, limit) in [
+            (
+                "input_tokens_per_hour",
+                TokenKind::Input,
+                HOUR,
+                limits.input_tokens_per_hour,
+            ),
+            (
+                "output_tokens_per_hour",
+                TokenKind::Output,
+                HOUR,
+                limits.output_tokens_per_hour,
+            ),
+            (
+                "input_tokens_per_day",
+                TokenKind::Input,
+                DAY,
+                limits.input_tokens_per_day,
+            ),
+            (
+                "output_tokens_per_day",
+                TokenKind::Output,
+                DAY,
+                limits.output_tokens_per_day,
+            ),
+        ] {
+            if let Some(retry_after) = self.token_retry_after(kind, window, limit, now) {
+                return Err((name, retry_after));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn retry_after_secs(available_at: Instant, now: Instant) -> u64 {
+    let wait = available_at.saturating_duration_since(now);
+    // 向上取整，至少 1 秒
+    (wait.as_secs() + u64::from(wait.subsec_nanos() > 0)).max(1)
+}
+
+/// 超限拒绝；`scope` 为客户端名或 `global`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RateLimitRejection {
+    pub scope: String,
+    pub limit: &'static str,
+    pub retry_after_secs: u64,
+}
+
+impl RateLimitRejection {
+    /// Anthropic 格式的 429 `rate_limit_error`，附带 `retry-after`
+    pub fn into_response(self) -> HandlerResult {
+        let message = format!(
+            "{} rate limit exceeded ({}), retry after {}s",
+            self.scope, self.limit, self.retry_after_secs
+        );
+        Ok(Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .header("retry-after", self.retry_after_secs.to_string())
+            .body(full_body(
+                json!({
+                    "type": "error",
+                    "error": {"type": "rate_limit_error", "message": message}
+                })
+                .to_string(),
+            ))
+            .unwrap())
+    }
+}
+
+/// 流式请求占用的并发名额；随流结束（drop）归还
+#[derive(Debug)]
+pub(crate) struct StreamSlot {
+    limiter: Arc<RateLimiter>,
+    scopes: Vec<Scope>,
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        if let Ok(mut windows) = self.limiter.windows.lock() {
+            for scope in &self.scopes {
+                if let Some(window) = windows.get_mut(scope) {
+                    window.active_streams = window.active_streams.saturating_sub(1);
+                }
+            }
+        }
+    }
+}
+
+/// 按全局与客户端两级统计用量（随 RequestServices 共享）
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    windows: Mutex<HashMap<Scope, UsageWindow>>,
+}
+
+impl RateLimiter {
+    fn scopes(client: Option<&str>) -> Vec<Scope> {
+        let mut scopes = vec![Scope::Global];
+        if let Some(client) = client {
+            scopes.push(Scope::Client(client.to_string()));
+        }
+        scopes
+    }
+
+    /// 检查并登记一次请求；流式请求额外占用一个并发名额
+    pub fn admit(
+        self: &Arc<Self>,
+        client: Option<(&str, &RateLimitConfig)>,
+        global: &RateLimitConfig,
+        streaming: bool,
+    ) -> Result<Option<StreamSlot>, RateLimitRejection> {
+        self.admit_at(client, global, streaming, Instant::now())
+    }
+
+    fn admit_at(
+        self: &Arc<Self>,
+        client: Option<(&str, &RateLimitConfig)>,
+        global: &RateLimitConfig,
+        streaming: bool,
+        now: Instant,
+    ) -> Result<Option<StreamSlot>, RateLimitRejection> {
+        let scopes = Self::scopes(client.map(|(name, _)| name));
+        let mut windows = match self.windows.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let labels = std::iter::once("global").chain(client.map(|(name, _)| name));
+        let limits = std::iter::once(global).chain(client.map(|(_, limits)| limits));
+        for ((label, limits), scope) in labels.zip(limits).zip(&scopes) {
+            let window = windows.entry(scope.clone()).or_default();
+            window.prune(now);
+            if let Err((limit, retry_after_secs)) = window.check(limits, streaming, now) {
+                return Err(RateLimitRejection {
+                    scope: label.to_string(),
+                    limit,
+                    retry_after_secs,
+                });
+            }
+        }
+        for scope in &scopes {
+            let window = windows.entry(scope.clone()).or_default();
+            window.requests.push_back(now);
+            if streaming {
+                window.active_streams += 1;
+            }
+        }
+        drop(windows);
+        Ok(streaming.then(|| StreamSlot {
+            limiter: Arc::clone(self),
+            scopes,
+        }))
+    }
+
+    /// 记录上游 usage 中的 token 数（请求结束后计入配额）
+    pub fn record_tokens(&self, client: Option<&str>, input_tokens: u64, output_tokens: u64) {
+        self.record_tokens_at(client, input_tokens, output_tokens, Instant::now());
+    }
+
+    fn record_tokens_at(
+        &self,
+        client: Option<&str>,
+        input_tokens: u64,
+        output_tokens: u64,
+        now: Instant,
+    ) {
+        if input_tokens == 0 && output_tokens == 0 {
+            return;
+        }
+        if let Ok(mut windows) = self.windows.lock() {
+            for scope in Self::scopes(client) {
+                let window = windows.entry(scope).or_default();
+                window.prune(now);
+                window.tokens.push_back((now, input_tokens, output_tokens));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unlimited() -> RateLimitConfig {
+        RateLimitConfig::default()
+    }
+
+    #[test]
+    fn requests_per_minute_is_enforced_per_client_and_resets_after_window() {
+        let limiter = Arc::new(RateLimiter::default());
+        let limits = RateLimitConfig {
+            requests_per_minute: 2,
+            ..RateLimitConfig::default()
+        };
+        let start = Instant::now();
+        for _ in 0..2 {
+            assert!(limiter
+                .admit_at(Some(("alice", &limits)), &unlimited(), false, start)
+                .is_ok());
+        }
+        let rejection = limiter
+            .admit_at(
+                Some(("alice", &limits)),
+                &unlimited(),
+                false,
+                start + Duration::from_secs(20),
+            )
+            .unwrap_err();
+        assert_eq!(rejection.scope, "alice");
+        assert_eq!(rejection.limit, "requests_per_minute");
+        assert_eq!(rejection.retry_after_secs, 40);
+
+        // 其他客户端不受影响
+        assert!(limiter
+            .admit_at(Some(("bob", &limits)), &unlimited(), false, start)
+            .is_ok());
+        assert!(limiter
+            .admit_at(
+                Some(("alice", &limits)),
+                &unlimited(),
+                false,
+                start + MINUTE
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn concurrent_streams_are_released_when_slot_drops() {
+        let limiter = Arc::new(RateLimiter::default());
+        let global = RateLimitConfig {
+            max_concurrent_streams: 1,
+            ..RateLimitConfig::default()
+        };
+        let now = Instant::now();
+        let slot = limiter.admit_at(None, &global, true, now).unwrap();
+        assert!(slot.is_some());
+        // 非流式请求不占并发名额
+        assert!(limiter.admit_at(None, &global, false, now).is_ok());
+        let rejection = limiter.admit_at(None, &global, true, now).unwrap_err();
+        assert_eq!(rejection.scope, "global");
+        assert_eq!(rejection.limit, "max_concurrent_streams");
+
+        drop(slot);
+        assert!(limiter.admit_at(None, &global, true, now).is_ok());
+    }
+
+    #[test]
+    fn client_named_like_a_wildcard_does_not_share_the_global_window() {
+        let limiter = Arc::new(RateLimiter::default());
+        let limits = RateLimitConfig {
+            requests_per_minute: 1,
+            ..RateLimitConfig::default()
+        };
+        let now = Instant::now();
+        assert!(limiter.admit_at(None, &unlimited(), false, now).is_ok());
+        // 匿名请求只计入全局窗口，不占用名为 `*` 的客户端的额度
+        assert!(limiter
+            .admit_at(Some(("*", &limits)), &unlimited(), false, now)
+            .is_ok());
+        assert!(limiter
+            .admit_at(Some(("*", &limits)), &unlimited(), false, now)
+            .is_err());
+    }
+
+    #[test]
+    fn token_quota_blocks_until_enough_usage_expires() {
+        let limiter = Arc::new(RateLimiter::default());
+        let limits = RateLimitConfig {
+            output_tokens_per_hour: 1_000,
+            ..RateLimitConfig::default()
+        };
+        let start = Instant::now();
+        limiter.record_tokens_at(Some("alice"), 10, 600, start);
+        limiter.record_tokens_at(Some("alice"), 10, 500, start + Duration::from_secs(600));
+
+        let rejection = limiter
+            .admit_at(
+                Some(("alice", &limits)),
+                &unlimited(),
+                false,
+                start + Duration::from_secs(900),
+            )
+            .unwrap_err();
+        assert_eq!(rejection.limit, "output_tokens_per_hour");
+        // 最早一笔 600 过期后用量降到 500，低于上限
+        assert_eq!(rejection.retry_after_secs, 2_700);
+
+        assert!(limiter
+            .admit_at(Some(("alice", &limits)), &unlimited(), false, start + HOUR)
+            .is_ok());
+    }
+
+    #[test]
+    fn rejection_uses_anthropic_rate_limit_error_with_retry_after() {
+        let response = RateLimitRejection {
+            scope: "global".to_string(),
+            limit: "requests_per_minute",
+            retry_after_secs: 7,
+        }
+        .into_response()
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "7");
+    }
+}
@@ -0,0 +1,474 @@
+//! 请求调度：在 `maxConcurrency` 之下按优先级与会话公平地放行上游请求（替代全局信号量）
+//!
+//! 优先级由 Claude Code 请求类型与 slot 决定：交互轮次优先，会话标题与 haiku 这类后台请求垫后。
+//! 同一优先级内按会话轮转出队，单个会话的突发请求不会占满并发；排队超过 `maxQueueWaitMs`
+//! 的请求返回 Anthropic `overloaded_error`。
+
+use hyper::{Response, StatusCode};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use super::{full_body, HandlerResult};
+use crate::load_balancer::ModelSlot;
+use crate::transform::ClaudeCodeRequestKind;
+
+/// 排队超时时建议的重试间隔
+const QUEUE_RETRY_AFTER_SECS: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RequestPriority {
+    /// opus / sonnet 上的主对话轮次与上下文压缩
+    Interactive,
+    Normal,
+    /// 会话标题、子代理、探测与 haiku slot 的请求
+    Background,
+}
+
+impl RequestPriority {
+    /// 出队顺序
+    const ALL: [RequestPriority; 3] = [
+        RequestPriority::Interactive,
+        RequestPriority::Normal,
+        RequestPriority::Background,
+    ];
+
+    pub fn classify(kind: ClaudeCodeRequestKind, slot: ModelSlot) -> Self {
+        match (kind, slot) {
+            (
+                ClaudeCodeRequestKind::SessionTitle
+                | ClaudeCodeRequestKind::Subagent
+                | ClaudeCodeRequestKind::Probe,
+                _,
+            )
+            | (_, ModelSlot::Haiku) => Self::Background,
+            (ClaudeCodeRequestKind::ConversationTurn | ClaudeCodeRequestKind::Compaction, _) => {
+                Self::Interactive
+            }
+            _ => Self::Normal,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Interactive => "interactive",
+            Self::Normal => "normal",
+            Self::Background => "background",
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Self::Interactive => 0,
+            Self::Normal => 1,
+            Self::Background => 2,
+        }
+    }
+}
+
+struct Waiter {
+    id: u64,
+    enqueued_at: Instant,
+    grant: oneshot::Sender<()>,
+}
+
+/// 单个优先级的队列：会话按轮转顺序排列，会话内先进先出
+#[derive(Default)]
+struct PriorityQueue {
+    sessions: VecDeque<(String, VecDeque<Waiter>)>,
+}
+
+impl PriorityQueue {
+    fn push(&mut self, session: &str, waiter: Waiter) {
+        match self.sessions.iter_mut().find(|(key, _)| key == session) {
+            Some((_, waiters)) => waiters.push_back(waiter),
+            None => self
+                .sessions
+                .push_back((session.to_string(), VecDeque::from([waiter]))),
+        }
+    }
+
+    /// 取队首会话的第一个请求，该会话还有排队请求时移到队尾
+    fn pop(&mut self) -> Option<Waiter> {
+        let (session, mut waiters) = self.sessions.pop_front()?;
+        let waiter = waiters.pop_front();
+        if !waiters.is_empty() {
+            self.sessions.push_back((session, waiters));
+        }
+        waiter
+    }
+
+    fn remove(&mut self, id: u64) -> bool {
+        for index in 0..self.sessions.len() {
+            let waiters = &mut self.sessions[index].1;
+            if let Some(position) = waiters.iter().position(|waiter| waiter.id == id) {
+                waiters.remove(position);
+                if waiters.is_empty() {
+                    self.sessions.remove(index);
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    fn len(&self) -> usize {
+        self.sessions.iter().map(|(_, waiters)| waiters.len()).sum()
+    }
+
+    fn oldest_wait(&self, now: Instant) -> Option<Duration> {
+        self.sessions
+            .iter()
+            .filter_map(|(_, waiters)| waiters.front())
+            .map(|waiter| now.saturating_duration_since(waiter.enqueued_at))
+            .max()
+    }
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    in_flight: usize,
+    next_waiter_id: u64,
+    queues: [PriorityQueue; 3],
+}
+
+impl SchedulerState {
+    fn queued(&self) -> usize {
+        self.queues.iter().map(PriorityQueue::len).sum()
+    }
+
+    /// 把一个并发名额交给下一个仍在等待的请求；没有等待者时归还名额
+    fn release(&mut self) {
+        for priority in RequestPriority::ALL {
+            while let Some(waiter) = self.queues[priority.index()].pop() {
+                if waiter.grant.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+        self.in_flight = self.in_flight.saturating_sub(1);
+    }
+}
+
+pub(crate) struct RequestScheduler {
+    max_concurrency: usize,
+    /// None 表示不限制排队时长
+    max_queue_wait: Option<Duration>,
+    state: Mutex<SchedulerState>,
+}
+
+/// 已获得的并发名额；随请求（流式为转发任务）结束释放
+pub(crate) struct SchedulerPermit {
+    scheduler: Arc<RequestScheduler>,
+}
+
+impl Drop for SchedulerPermit {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.scheduler.state.lock() {
+            state.release();
+        }
+    }
+}
+
+/// 排队等待中的请求；被取消（客户端断开）时从队列移除，已被分配的名额转交下一个
+struct QueuedTicket<'a> {
+    scheduler: &'a RequestScheduler,
+    priority: RequestPriority,
+    id: u64,
+    armed: bool,
+}
+
+impl Drop for QueuedTicket<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        if let Ok(mut state) = self.scheduler.state.lock() {
+            if !state.queues[self.priority.index()].remove(self.id) {
+                state.release();
+            }
+        }
+    }
+}
+
+pub(crate) struct Admission {
+    pub permit: SchedulerPermit,
+    pub waited: Duration,
+    /// 入队时前面排着的请求数；直接放行时为 0
+    pub queue_depth: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct QueueTimeout {
+    pub priority: RequestPriority,
+    pub waited: Duration,
+    pub queue_depth: usize,
+}
+
+impl QueueTimeout {
+    /// Anthropic 格式的 529 `overloaded_error`
+    pub fn into_response(self) -> HandlerResult {
+        let message = format!(
+            "Proxy is overloaded: request waited {}ms in the {} queue",
+            self.waited.as_millis(),
+            self.priority.as_str()
+        );
+        Ok(Response::builder()
+            .status(StatusCode::from_u16(529).unwrap())
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", "*")
+            .header("retry-after", QUEUE_RETRY_AFTER_SECS.to_string())
+            .body(full_body(
+                json!({
+                    "type": "error",
+                    "error": {"type": "overloaded_error", "message": message}
+                })
+                .to_string(),
+            ))
+            .unwrap())
+    }
+}
+
+impl RequestScheduler {
+    /// `max_queue_wait_ms` 为 0 时不限制排队时长
+    pub fn new(max_concurrency: u32, max_queue_wait_ms: u64) -> Self {
+        Self {
+            max_concurrency: max_concurrency.max(1) as usize,
+            max_queue_wait: (max_queue_wait_ms > 0)
+                .then(|| Duration::from_millis(max_queue_wait_ms)),
+            state: Mutex::new(SchedulerState::default()),
+        }
+    }
+
+    /// 有空闲名额且无人排队时直接放行，否则按优先级与会话排队
+    pub async fn acquire(
+        self: &Arc<Self>,
+        priority: RequestPriority,
+        session: &str,
+    ) -> Result<Admission, QueueTimeout> {
+        let enqueued_at = Instant::now();
+        let (mut receiver, mut ticket, queue_depth) = {
+            let mut state = self
+                .state
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let queue_depth = state.queued();
+            if queue_depth == 0 && state.in_flight < self.max_concurrency {
+                state.in_flight += 1;
+                return Ok(Admission {
+                    permit: SchedulerPermit {
+                        scheduler: Arc::clone(self),
+                    },
+                    waited: Duration::ZERO,
+                    queue_depth: 0,
+                });
+            }
+            let (grant, receiver) = oneshot::channel();
+            state.next_waiter_id += 1;
+            let id = state.next_waiter_id;
+            state.queues[priority.index()].push(
+                session,
+                Waiter {
+                    id,
+                    enqueued_at,
+                    grant,
+                },
+            );
+            let ticket = QueuedTicket {
+                scheduler: self,
+                priority,
+                id,
+                armed: true,
+            };
+            (receiver, ticket, queue_depth)
+        };
+
+        let granted = match self.max_queue_wait {
+            Some(limit) => tokio::time::timeout(limit, &mut receiver).await.is_ok(),
+            None => (&mut receiver).await.is_ok(),
+        };
+        // 超时与放行可能同时发生：名额已交过来就照常使用
+        let granted = granted || receiver.try_recv().is_ok();
+        if !granted {
+            return Err(QueueTimeout {
+                priority,
+                waited: enqueued_at.elapsed(),
+                queue_depth,
+            });
+        }
+        ticket.armed = false;
+        Ok(Admission {
+            permit: SchedulerPermit {
+                scheduler: Arc::clone(self),
+            },
+            waited: enqueued_at.elapsed(),
+            queue_depth,
+        })
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state
+            .lock()
+            .map(|state| self.max_concurrency.saturating_sub(state.in_flight))
+            .unwrap_or(0)
+    }
+
+    /// 各优先级的排队数
+    pub fn queue_depths(&self) -> Vec<(RequestPriority, usize)> {
+        let Ok(state) = self.state.lock() else {
+            return Vec::new();
+        };
+        RequestPriority::ALL
+            .into_iter()
+            .map(|priority| (priority, state.queues[priority.index()].len()))
+            .collect()
+    }
+
+    /// `/status` 中的调度状态
+    pub fn status_json(&self) -> Value {
+        let Ok(state) = self.state.lock() else {
+            return Value::Null;
+        };
+        let now = Instant::now();
+        let queues: serde_json::Map<String, Value> = RequestPriority::ALL
+            .into_iter()
+            .map(|priority| {
+                let queue = &state.queues[priority.index()];
+                (
+                    priority.as_str().to_string(),
+                    json!({
+                        "depth": queue.len(),
+                        "sessions": queue.sessions.len(),
+                        "oldest_wait_ms": queue
+                            .oldest_wait(now)
+                            .map(|wait| wait.as_millis() as u64),
+                    }),
+                )
+            })
+            .collect();
+        json!({
+            "in_flight": state.in_flight,
+            "max_concurrency": self.max_concurrency,
+            "max_queue_wait_ms": self.max_queue_wait.map(|wait| wait.as_millis() as u64),
+            "queued": state.queued(),
+            "queues": Value::Object(queues),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(max_concurrency: u32, max_queue_wait_ms: u64) -> Arc<RequestScheduler> {
+        Arc::new(RequestScheduler::new(max_concurrency, max_queue_wait_ms))
+    }
+
+    #[test]
+    fn priority_follows_request_kind_and_slot() {
+        let classify = RequestPriority::classify;
+        assert_eq!(
+            classify(ClaudeCodeRequestKind::ConversationTurn, ModelSlot::Opus),
+            RequestPriority::Interactive
+        );
+        assert_eq!(
+            classify(ClaudeCodeRequestKind::ConversationTurn, ModelSlot::Haiku),
+            RequestPriority::Background
+        );
+        assert_eq!(
+            classify(ClaudeCodeRequestKind::SessionTitle, ModelSlot::Sonnet),
+            RequestPriority::Background
+        );
+        assert_eq!(
+            classify(ClaudeCodeRequestKind::Subagent, ModelSlot::Opus),
+            RequestPriority::Background
+        );
+        assert_eq!(
+            classify(ClaudeCodeRequestKind::Compaction, ModelSlot::Sonnet),
+            RequestPriority::Interactive
+        );
+        assert_eq!(
+            classify(ClaudeCodeRequestKind::Unknown, ModelSlot::Sonnet),
+            RequestPriority::Normal
+        );
+    }
+
+    #[tokio::test]
+    async fn higher_priority_and_other_sessions_are_served_first() {
+        let scheduler = scheduler(1, 0);
+        let held = scheduler
+            .acquire(RequestPriority::Normal, "s0")
+            .await
+            .unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for (priority, session, label) in [
+            (RequestPriority::Background, "bg", "bg"),
+            (RequestPriority::Interactive, "a", "a1"),
+            (RequestPriority::Interactive, "a", "a2"),
+            (RequestPriority::Interactive, "b", "b1"),
+        ] {
+            let scheduler = Arc::clone(&scheduler);
+            let order = Arc::clone(&order);
+            tasks.push(tokio::spawn(async move {
+                let admission = scheduler.acquire(priority, session).await.unwrap();
+                order.lock().unwrap().push(label);
+                drop(admission);
+            }));
+            // 保证入队顺序
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(scheduler.status_json()["queued"], 4);
+
+        drop(held);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), ["a1", "b1", "a2", "bg"]);
+        assert_eq!(scheduler.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn queue_wait_limit_returns_overloaded_and_frees_the_queue() {
+        let scheduler = scheduler(1, 20);
+        let _held = scheduler
+            .acquire(RequestPriority::Interactive, "s")
+            .await
+            .unwrap();
+
+        let timeout = scheduler
+            .acquire(RequestPriority::Background, "s")
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(timeout.priority, RequestPriority::Background);
+        assert_eq!(timeout.queue_depth, 0);
+        assert_eq!(scheduler.status_json()["queued"], 0);
+
+        let response = timeout.into_response().unwrap();
+        assert_eq!(response.status().as_u16(), 529);
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiter_does_not_leak_the_slot() {
+        let scheduler = scheduler(1, 0);
+        let held = scheduler
+            .acquire(RequestPriority::Normal, "s")
+            .await
+            .unwrap();
+        let waiting = {
+            let scheduler = Arc::clone(&scheduler);
+            tokio::spawn(async move { scheduler.acquire(RequestPriority::Normal, "t").await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        waiting.abort();
+        let _ = waiting.await;
+        assert_eq!(scheduler.status_json()["queued"], 0);
+
+        drop(held);
+        assert_eq!(scheduler.available_permits(), 1);
+    }
+}
@@ -0,0 +1,491 @@
+//! 有状态链与技能目录提醒的磁盘持久化
+//!
+//! 内存中的 `StatefulChainStore` / `SkillCatalogReminderStore` 仍是唯一的读写入口；这里只在启动时
+//! 把文件载回内存，运行中按间隔把有变化的内容整体写回（临时文件 + rename），关闭时再写一次。
+//! 文件带格式版本与哈希探针：版本不符或端点键 / 指纹的哈希算法变化时整体丢弃，
+//! 缺少非 input 指纹的链条目也不载入，避免重启后把旧链接到已变化的端点或请求参数上。
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+use super::{
+    hash_to_u64, SkillCatalogCacheEntry, SkillCatalogReminderStore, StatefulChainEntry,
+    StatefulChainStore,
+};
+use crate::config::SessionStoreConfig;
+
+/// 存储文件格式版本；字段含义变化时递增，旧文件直接丢弃
+const SESSION_STORE_FORMAT_VERSION: u32 = 1;
+/// 默认文件名
+const SESSION_STORE_FILE_NAME: &str = "session-store.json";
+/// 两次检查落盘的间隔
+const SESSION_STORE_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PersistedSessionStore {
+    version: u32,
+    /// 端点键与指纹所用哈希的探针；标准库哈希实现变化后旧键不可比
+    hash_probe: String,
+    #[serde(default)]
+    stateful_chains: Vec<PersistedChainEntry>,
+    #[serde(default)]
+    skill_catalog_reminders: Vec<PersistedReminderEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PersistedChainEntry {
+    key: String,
+    response_id: String,
+    endpoint_key: String,
+    full_input: Vec<Value>,
+    output_items: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    static_prefix_summary: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    non_input_fingerprint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    turn_state: Option<String>,
+    /// 最后更新时间（Unix 秒）
+    updated_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PersistedReminderEntry {
+    key: String,
+    reminder_text: String,
+    updated_at: u64,
+}
+
+/// 载入结果，用于启动日志
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SessionStoreLoadReport {
+    pub(crate) chains: usize,
+    pub(crate) reminders: usize,
+    /// 因过期或缺少指纹被丢弃的条目
+    pub(crate) dropped: usize,
+    /// 版本或哈希探针不符，整个文件被丢弃
+    pub(crate) discarded_file: bool,
+}
+
+/// 两个内存表的变更标记：条目数与最近一次更新时间
+type StoreGeneration = (usize, Option<Instant>, usize, Option<Instant>);
+
+pub(crate) struct SessionStorePersistence {
+    path: PathBuf,
+    ttl: Duration,
+    chain_store: StatefulChainStore,
+    reminder_store: SkillCatalogReminderStore,
+    saved_generation: Mutex<Option<StoreGeneration>>,
+}
+
+impl SessionStorePersistence {
+    pub(crate) fn new(
+        config: &SessionStoreConfig,
+        chain_store: StatefulChainStore,
+        reminder_store: SkillCatalogReminderStore,
+    ) -> Self {
+        let path = config
+            .path
+            .as_deref()
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(default_session_store_path);
+        Self {
+            path,
+            ttl: Duration::from_secs(config.ttl_secs),
+            chain_store,
+            reminder_store,
+            saved_generation: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 把文件中未过期的条目载入内存表；文件不存在时什么也不做
+    pub(crate) fn load(&self) -> std::io::Result<SessionStoreLoadReport> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(SessionStoreLoadReport::default())
+            }
+            Err(err) => return Err(err),
+        };
+        let persisted = match serde_json::from_slice::<PersistedSessionStore>(&bytes) {
+            Ok(persisted)
+                if persisted.version == SESSION_STORE_FORMAT_VERSION
+                    && persisted.hash_probe == hash_probe() =>
+            {
+                persisted
+            }
+            _ => {
+                return Ok(SessionStoreLoadReport {
+                    discarded_file: true,
+                    ..SessionStoreLoadReport::default()
+                })
+            }
+        };
+
+        let now_unix = unix_now();
+        let now = Instant::now();
+        let mut report = SessionStoreLoadReport::default();
+        let mut chains = lock_store(&self.chain_store);
+        for entry in persisted.stateful_chains {
+            let Some(updated_at) = self.restore_instant(now, now_unix, entry.updated_at) else {
+                report.dropped += 1;
+                continue;
+            };
+            // 无指纹的条目在运行时会放行非 input 字段校验，重启后无法确认参数未变，不载入
+            if entry.non_input_fingerprint.is_none() {
+                report.dropped += 1;
+                continue;
+            }
+            chains.insert(
+                entry.key,
+                StatefulChainEntry {
+                    response_id: entry.response_id,
+                    endpoint_key: entry.endpoint_key,
+                    full_input: entry.full_input,
+                    output_items: entry.output_items,
+                    static_prefix_summary: entry.static_prefix_summary,
+                    non_input_fingerprint: entry.non_input_fingerprint,
+                    turn_state: entry.turn_state,
+                    updated_at,
+                },
+            );
+            report.chains += 1;
+        }
+        drop(chains);
+
+        let mut reminders = lock_store(&self.reminder_store);
+        for entry in persisted.skill_catalog_reminders {
+            let Some(updated_at) = self.restore_instant(now, now_unix, entry.updated_at) else {
+                report.dropped += 1;
+                continue;
+            };
+            reminders.insert(
+                entry.key,
+                SkillCatalogCacheEntry {
+                    reminder_text: entry.reminder_text,
+                    updated_at,
+                },
+            );
+            report.reminders += 1;
+        }
+        drop(reminders);
+
+        // 刚载入的内容与文件一致，不必立即写回
+        *self
+            .saved_generation
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(self.generation());
+        Ok(report)
+    }
+
+    /// 内存表有变化时写回文件；返回是否写入
+    pub(crate) fn save_if_changed(&self) -> std::io::Result<bool> {
+        let generation = self.generation();
+        let mut saved = self
+            .saved_generation
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if *saved == Some(generation) {
+            return Ok(false);
+        }
+        self.save()?;
+        *saved = Some(generation);
+        Ok(true)
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let now_unix = unix_now();
+        let stateful_chains = lock_store(&self.chain_store)
+            .iter()
+            .filter(|(_, entry)| entry.updated_at.elapsed() <= self.ttl)
+            .map(|(key, entry)| PersistedChainEntry {
+                key: key.clone(),
+                response_id: entry.response_id.clone(),
+                endpoint_key: entry.endpoint_key.clone(),
+                full_input: entry.full_input.clone(),
+                output_items: entry.output_items.clone(),
+                static_prefix_summary: entry.static_prefix_summary.clone(),
+                non_input_fingerprint: entry.non_input_fingerprint.clone(),
+                turn_state: entry.turn_state.clone(),
+                updated_at: now_unix.saturating_sub(entry.updated_at.elapsed().as_secs()),
+            })
+            .collect();
+        let skill_catalog_reminders = lock_store(&self.reminder_store)
+            .iter()
+            .filter(|(_, entry)| entry.updated_at.elapsed() <= self.ttl)
+            .map(|(key, entry)| PersistedReminderEntry {
+                key: key.clone(),
+                reminder_text: entry.reminder_text.clone(),
+                updated_at: now_unix.saturating_sub(entry.updated_at.elapsed().as_secs()),
+            })
+            .collect();
+        let bytes = serde_json::to_vec(&PersistedSessionStore {
+            version: SESSION_STORE_FORMAT_VERSION,
+            hash_probe: hash_probe(),
+            stateful_chains,
+            skill_catalog_reminders,
+        })?;
+
+        if let Some(parent) = self
+            .path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        write_private_file(&tmp, &bytes)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// 后台定期落盘，直到收到关闭信号；关闭时的最后一次写入由调用方负责
+    pub(crate) fn spawn_periodic_save(
+        self: Arc<Self>,
+        log_tx: broadcast::Sender<String>,
+        mut shutdown_rx: broadcast::Receiver<()>,
+    ) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SESSION_STORE_SAVE_INTERVAL);
+            interval.tick().await;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let persistence = Arc::clone(&self);
+                        let result =
+                            tokio::task::spawn_blocking(move || persistence.save_if_changed()).await;
+                        if let Ok(Err(err)) = result {
+                            let _ = log_tx.send(format!(
+                                "[Warning] session store save failed: {} ({})",
+                                self.path.display(),
+                                err
+                            ));
+                        }
+                    }
+                    _ = shutdown_rx.recv() => break,
+                }
+            }
+        });
+    }
+
+    fn generation(&self) -> StoreGeneration {
+        let chains = lock_store(&self.chain_store);
+        let reminders = lock_store(&self.reminder_store);
+        (
+            chains.len(),
+            chains.values().map(|entry| entry.updated_at).max(),
+            reminders.len(),
+            reminders.values().map(|entry| entry.updated_at).max(),
+        )
+    }
+
+    /// 把持久化的 Unix 时间换回 `Instant`；已过期时为空
+    fn restore_instant(&self, now: Instant, now_unix: u64, updated_at: u64) -> Option<Instant> {
+        let age = Duration::from_secs(now_unix.saturating_sub(updated_at));
+        if age > self.ttl {
+            return None;
+        }
+        Some(now.checked_sub(age).unwrap_or(now))
+    }
+}
+
+/// 默认存储路径：`~/.codexProxy/sessions/session-store.json`
+pub(crate) fn default_session_store_path() -> PathBuf {
+    let base = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    base.join(".codexProxy")
+        .join("sessions")
+        .join(SESSION_STORE_FILE_NAME)
+}
+
+fn hash_probe() -> String {
+    format!("{:016x}", hash_to_u64(&["codex-proxy-session-store"]))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+fn lock_store<T>(store: &Arc<Mutex<T>>) -> std::sync::MutexGuard<'_, T> {
+    store
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// 文件包含完整的对话内容，Unix 上只允许当前用户读写
+fn write_private_file(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn stores() -> (StatefulChainStore, SkillCatalogReminderStore) {
+        (
+            Arc::new(Mutex::new(HashMap::new())),
+            Arc::new(Mutex::new(HashMap::new())),
+        )
+    }
+
+    fn chain_entry(fingerprint: Option<&str>, age: Duration) -> StatefulChainEntry {
+        StatefulChainEntry {
+            response_id: "resp_1".to_string(),
+            endpoint_key: "codex:0000000000000001".to_string(),
+            full_input: vec![json!({"role":"user","content":"hi"})],
+            output_items: vec![json!({"type":"message","role":"assistant"})],
+            static_prefix_summary: None,
+            non_input_fingerprint: fingerprint.map(str::to_string),
+            turn_state: Some("ts-1".to_string()),
+            updated_at: Instant::now().checked_sub(age).unwrap_or_else(Instant::now),
+        }
+    }
+
+    fn config(path: &Path, ttl_secs: u64) -> SessionStoreConfig {
+        SessionStoreConfig {
+            enabled: true,
+            path: Some(path.display().to_string()),
+            ttl_secs,
+        }
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("session-store-{}", uuid::Uuid::new_v4().simple()))
+            .join(SESSION_STORE_FILE_NAME)
+    }
+
+    #[test]
+    fn test_session_store_round_trips_chains_and_reminders() {
+        let path = temp_path();
+        let (chains, reminders) = stores();
+        chains.lock().unwrap().insert(
+            "chain-a".to_string(),
+            chain_entry(Some("fp"), Duration::ZERO),
+        );
+        reminders.lock().unwrap().insert(
+            "skills-a".to_string(),
+            SkillCatalogCacheEntry {
+                reminder_text: "<system-reminder>skills</system-reminder>".to_string(),
+                updated_at: Instant::now(),
+            },
+        );
+        let persistence = SessionStorePersistence::new(&config(&path, 3600), chains, reminders);
+        assert!(persistence.save_if_changed().expect("save"));
+        assert!(!persistence.save_if_changed().expect("unchanged"));
+
+        let (chains, reminders) = stores();
+        let restored = SessionStorePersistence::new(
+            &config(&path, 3600),
+            Arc::clone(&chains),
+            Arc::clone(&reminders),
+        );
+        let report = restored.load().expect("load");
+        assert_eq!(report.chains, 1);
+        assert_eq!(report.reminders, 1);
+        let entry = chains
+            .lock()
+            .unwrap()
+            .get("chain-a")
+            .cloned()
+            .expect("chain restored");
+        assert_eq!(entry.response_id, "resp_1");
+        assert_eq!(entry.turn_state.as_deref(), Some("ts-1"));
+        assert_eq!(entry.non_input_fingerprint.as_deref(), Some("fp"));
+        assert!(reminders.lock().unwrap().contains_key("skills-a"));
+        // 载入后内容未变，不需要写回
+        assert!(!restored.save_if_changed().expect("unchanged"));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_session_store_drops_expired_and_unfingerprinted_entries() {
+        let path = temp_path();
+        let (chains, reminders) = stores();
+        {
+            let mut guard = chains.lock().unwrap();
+            guard.insert("fresh".to_string(), chain_entry(Some("fp"), Duration::ZERO));
+            guard.insert("legacy".to_string(), chain_entry(None, Duration::ZERO));
+            guard.insert(
+                "stale".to_string(),
+                chain_entry(Some("fp"), Duration::from_secs(7200)),
+            );
+        }
+        let persistence = SessionStorePersistence::new(&config(&path, 3600), chains, reminders);
+        persistence.save_if_changed().expect("save");
+
+        let (chains, reminders) = stores();
+        let report =
+            SessionStorePersistence::new(&config(&path, 3600), Arc::clone(&chains), reminders)
+                .load()
+                .expect("load");
+        assert_eq!(report.chains, 1);
+        assert_eq!(report.dropped, 1);
+        let guard = chains.lock().unwrap();
+        assert!(guard.contains_key("fresh"));
+        assert!(!guard.contains_key("legacy"));
+        assert!(!guard.contains_key("stale"));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_session_store_discards_other_format_versions() {
+        let path = temp_path();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            json!({
+                "version": SESSION_STORE_FORMAT_VERSION + 1,
+                "hashProbe": hash_probe(),
+                "statefulChains": [{
+                    "key": "chain-a",
+                    "responseId": "resp_1",
+                    "endpointKey": "codex:1",
+                    "fullInput": [],
+                    "outputItems": [],
+                    "nonInputFingerprint": "fp",
+                    "updatedAt": unix_now()
+                }]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let (chains, reminders) = stores();
+        let report =
+            SessionStorePersistence::new(&config(&path, 3600), Arc::clone(&chains), reminders)
+                .load()
+                .expect("load");
+        assert!(report.discarded_file);
+        assert!(chains.lock().unwrap().is_empty());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
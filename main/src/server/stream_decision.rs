@@ -127,6 +127,11 @@ impl StreamDecisionState {
             && self.incomplete_stream_retry_attempts < opts.incomplete_stream_retry_max_attempts
     }
 
+    /// 客户端尚未收到业务事件时，重试可以透明地换到其他端点
+    pub fn allow_endpoint_failover(&self) -> bool {
+        !self.emitted_business_event && !self.saw_message_stop
+    }
+
     pub fn incomplete_retry_skip_reason(&self, opts: StreamRuntimeOptions) -> &'static str {
         if !opts.enable_incomplete_stream_retry {
             "disabled"
@@ -256,6 +261,19 @@ data: {"type":"content_block_delta","delta":{"type":"text_delta","text":"late"}}
         assert!(!state.allow_incomplete_retry(opts()));
     }
 
+    #[test]
+    fn endpoint_failover_requires_no_business_output() {
+        let mut state = StreamDecisionState {
+            sent_message_start_to_client: true,
+            emitted_non_heartbeat_event: true,
+            ..Default::default()
+        };
+        assert!(state.allow_endpoint_failover());
+
+        state.emitted_business_event = true;
+        assert!(!state.allow_endpoint_failover());
+    }
+
     #[test]
     fn sibling_tool_retry_guard_requires_clean_failed_state() {
         let mut state = StreamDecisionState {
@@ -0,0 +1,119 @@
+//! `/usage`：汇总本地用量账本
+//!
+//! 查询参数：`group_by`（day / endpoint / model / session / client / kind / slot，默认 day）、
+//! `since` / `until`（`YYYY-MM-DD`，含边界）、`session`、`client`、`limit`。
+//! 携带客户端令牌的请求只能看到该客户端自己的用量。
+
+use hyper::StatusCode;
+use serde_json::json;
+use std::sync::Arc;
+
+use super::{health_json_response, HandlerResult};
+use crate::usage_ledger::{UsageGroupBy, UsageLedger, UsageQuery};
+
+/// 解析 `/usage` 的查询串；`client` 为已认证的客户端名，覆盖查询中的 `client`
+pub(crate) fn parse_usage_query(
+    query: Option<&str>,
+    client: Option<&str>,
+) -> Result<UsageQuery, String> {
+    let mut parsed = UsageQuery::default();
+    let url = reqwest::Url::parse(&format!("http://localhost/usage?{}", query.unwrap_or("")))
+        .map_err(|err| format!("invalid query: {}", err))?;
+    for (key, value) in url.query_pairs() {
+        let value = value.trim().to_string();
+        if value.is_empty() {
+            continue;
+        }
+        match key.as_ref() {
+            "group_by" | "groupBy" => {
+                parsed.group_by = UsageGroupBy::parse(&value)
+                    .ok_or_else(|| format!("unknown group_by '{}'", value))?;
+            }
+            "since" | "until" => {
+                chrono::NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+                    .map_err(|_| format!("{} must be YYYY-MM-DD", key))?;
+                if key == "since" {
+                    parsed.since = Some(value);
+                } else {
+                    parsed.until = Some(value);
+                }
+            }
+            "session" => parsed.session = Some(value),
+            "client" => parsed.client = Some(value),
+            "limit" => {
+                parsed.limit = value
+                    .parse()
+                    .map_err(|_| format!("invalid limit '{}'", value))?;
+            }
+            _ => {}
+        }
+    }
+    if let Some(client) = client {
+        parsed.client = Some(client.to_string());
+    }
+    Ok(parsed)
+}
+
+pub(crate) async fn usage_report_response(
+    ledger: Option<Arc<UsageLedger>>,
+    query: Result<UsageQuery, String>,
+) -> HandlerResult {
+    let query = match query {
+        Ok(query) => query,
+        Err(message) => {
+            return health_json_response(
+                StatusCode::BAD_REQUEST,
+                json!({"error": {"message": message}}),
+            );
+        }
+    };
+    let Some(ledger) = ledger else {
+        return health_json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": {"message": "usage ledger is disabled"}}),
+        );
+    };
+
+    let result = tokio::task::spawn_blocking(move || ledger.query(&query)).await;
+    match result {
+        Ok(Ok(report)) => health_json_response(StatusCode::OK, report.to_json()),
+        Ok(Err(err)) => health_json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": {"message": format!("failed to read usage ledger: {}", err)}}),
+        ),
+        Err(err) => health_json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": {"message": err.to_string()}}),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_query_and_pins_authenticated_client() {
+        let query = parse_usage_query(
+            Some("group_by=session&since=2026-10-01&client=bob&limit=5&session=a%20b"),
+            Some("alice"),
+        )
+        .expect("valid query");
+        assert_eq!(query.group_by, UsageGroupBy::Session);
+        assert_eq!(query.since.as_deref(), Some("2026-10-01"));
+        assert_eq!(query.client.as_deref(), Some("alice"));
+        assert_eq!(query.session.as_deref(), Some("a b"));
+        assert_eq!(query.limit, 5);
+
+        let query = parse_usage_query(None, None).expect("empty query");
+        assert_eq!(query.group_by, UsageGroupBy::Day);
+        assert!(query.client.is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_query_values() {
+        assert!(parse_usage_query(Some("group_by=week"), None).is_err());
+        assert!(parse_usage_query(Some("since=10/01/2026"), None).is_err());
+        assert!(parse_usage_query(Some("limit=-1"), None).is_err());
+    }
+}
@@ -0,0 +1,324 @@
+//! 本地 token 估算的自校准：按 端点 / 模型 对比估算值与上游 usage 回报的实际输入，学习修正系数
+//!
+//! 每个完成的请求都按上游 tokenizer 族估算一次输入，与上游实际计入的输入（含缓存读写）比较；
+//! 比值先取算术平均、样本多了之后按 EWMA 平滑，得到的系数在 count_tokens 回退估算时乘上。
+//! 指定了持久化路径时系数写入 JSON 文件（节流写入，临时文件 + rename），重启后继续使用。
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 校准文件名；与用量账本放在同一目录
+pub const CALIBRATION_FILE_NAME: &str = "token-calibration.json";
+
+/// 样本数达到该值前系数不生效
+const MIN_SAMPLES: u64 = 3;
+/// 前 `1 / EWMA_ALPHA` 个样本取算术平均，之后按 EWMA 平滑
+const EWMA_ALPHA: f64 = 0.1;
+/// 估算值过小时比值噪声太大（纯脚手架 token 占比高），不计入
+const MIN_ESTIMATE_TOKENS: u64 = 64;
+/// 单个样本的比值限制在该范围内，避免异常 usage 把系数带偏
+const MIN_FACTOR: f64 = 0.5;
+const MAX_FACTOR: f64 = 2.0;
+/// 两次落盘之间的最短间隔
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 某个 端点 / 模型 的校准状态
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CalibrationEntry {
+    /// 实际输入 / 原始估算
+    pub factor: f64,
+    pub samples: u64,
+    /// 校准后估算的相对误差绝对值（EWMA）
+    pub mean_abs_error: f64,
+}
+
+/// 一次对比的结果，供日志与指标使用
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationSample {
+    /// 原始估算
+    pub estimate: u64,
+    /// 按本次更新前的系数校准后的估算，即 count_tokens 此时会返回的值
+    pub calibrated: u64,
+    pub actual: u64,
+    /// (calibrated - actual) / actual
+    pub error_ratio: f64,
+    /// 更新后的系数与样本数
+    pub factor: f64,
+    pub samples: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PersistedEntry {
+    endpoint: String,
+    model: String,
+    #[serde(flatten)]
+    entry: CalibrationEntry,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PersistedCalibration {
+    #[serde(default)]
+    entries: Vec<PersistedEntry>,
+}
+
+#[derive(Default)]
+struct CalibrationState {
+    entries: BTreeMap<(String, String), CalibrationEntry>,
+    dirty: bool,
+    last_saved_at: Option<Instant>,
+}
+
+/// 修正系数存储；`path` 为空时只保存在内存中
+#[derive(Default)]
+pub struct TokenCalibration {
+    path: Option<PathBuf>,
+    state: Mutex<CalibrationState>,
+}
+
+impl TokenCalibration {
+    /// 从文件加载已有系数；文件不存在或损坏时从空白开始
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let entries = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<PersistedCalibration>(&bytes).ok())
+            .map(|persisted| {
+                persisted
+                    .entries
+                    .into_iter()
+                    .filter(|item| item.entry.factor.is_finite() && item.entry.factor > 0.0)
+                    .map(|item| ((item.endpoint, item.model), item.entry))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            path: Some(path),
+            state: Mutex::new(CalibrationState {
+                entries,
+                ..CalibrationState::default()
+            }),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// 已生效的修正系数；样本不足时为空
+    pub fn factor(&self, endpoint: &str, model: &str) -> Option<f64> {
+        let state = self.lock();
+        state
+            .entries
+            .get(&(endpoint.to_string(), model.to_string()))
+            .filter(|entry| entry.samples >= MIN_SAMPLES)
+            .map(|entry| entry.factor)
+    }
+
+    /// 对原始估算应用修正系数，返回 (校准后的估算, 生效的系数)
+    pub fn calibrate(&self, endpoint: &str, model: &str, estimate: u64) -> (u64, Option<f64>) {
+        match self.factor(endpoint, model) {
+            Some(factor) => (apply_factor(estimate, factor), Some(factor)),
+            None => (estimate, None),
+        }
+    }
+
+    /// 用一次请求的原始估算与上游实际输入更新系数；样本无效时返回 `None`
+    pub fn observe(
+        &self,
+        endpoint: &str,
+        model: &str,
+        estimate: u64,
+        actual: u64,
+    ) -> Option<CalibrationSample> {
+        if estimate < MIN_ESTIMATE_TOKENS || actual == 0 {
+            return None;
+        }
+        let sample = {
+            let mut state = self.lock();
+            let entry = state
+                .entries
+                .entry((endpoint.to_string(), model.to_string()))
+                .or_insert(CalibrationEntry {
+                    factor: 1.0,
+                    samples: 0,
+                    mean_abs_error: 0.0,
+                });
+            let calibrated = if entry.samples >= MIN_SAMPLES {
+                apply_factor(estimate, entry.factor)
+            } else {
+                estimate
+            };
+            let error_ratio = (calibrated as f64 - actual as f64) / actual as f64;
+            let ratio = (actual as f64 / estimate as f64).clamp(MIN_FACTOR, MAX_FACTOR);
+            entry.samples += 1;
+            let weight = (1.0 / entry.samples as f64).max(EWMA_ALPHA);
+            entry.factor += (ratio - entry.factor) * weight;
+            entry.mean_abs_error += (error_ratio.abs() - entry.mean_abs_error) * weight;
+            let sample = CalibrationSample {
+                estimate,
+                calibrated,
+                actual,
+                error_ratio,
+                factor: entry.factor,
+                samples: entry.samples,
+            };
+            state.dirty = true;
+            sample
+        };
+        self.save_if_due();
+        Some(sample)
+    }
+
+    /// 距上次落盘超过间隔时写入文件
+    fn save_if_due(&self) {
+        let due = {
+            let state = self.lock();
+            state.dirty
+                && state
+                    .last_saved_at
+                    .is_none_or(|saved_at| saved_at.elapsed() >= SAVE_INTERVAL)
+        };
+        if due {
+            let _ = self.flush();
+        }
+    }
+
+    /// 立即写入文件（有未保存的变更时）
+    pub fn flush(&self) -> std::io::Result<()> {
+        let Some(path) = self.path.as_ref() else {
+            return Ok(());
+        };
+        let bytes = {
+            let mut state = self.lock();
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+            state.last_saved_at = Some(Instant::now());
+            serde_json::to_vec_pretty(&PersistedCalibration {
+                entries: state
+                    .entries
+                    .iter()
+                    .map(|((endpoint, model), entry)| PersistedEntry {
+                        endpoint: endpoint.clone(),
+                        model: model.clone(),
+                        entry: *entry,
+                    })
+                    .collect(),
+            })?
+        };
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// 状态页展示的全部系数
+    pub fn status_json(&self) -> serde_json::Value {
+        let state = self.lock();
+        serde_json::Value::Array(
+            state
+                .entries
+                .iter()
+                .map(|((endpoint, model), entry)| {
+                    serde_json::json!({
+                        "endpoint": endpoint,
+                        "model": model,
+                        "factor": entry.factor,
+                        "samples": entry.samples,
+                        "meanAbsError": entry.mean_abs_error,
+                        "active": entry.samples >= MIN_SAMPLES,
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CalibrationState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn apply_factor(estimate: u64, factor: f64) -> u64 {
+    (estimate as f64 * factor).round().max(1.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_factor_applies_after_min_samples_and_tracks_ratio() {
+        let calibration = TokenCalibration::default();
+        for _ in 0..2 {
+            let sample = calibration
+                .observe("ep-a", "gpt-5", 1000, 1200)
+                .expect("sample");
+            assert_eq!(sample.calibrated, 1000);
+        }
+        assert_eq!(calibration.calibrate("ep-a", "gpt-5", 1000), (1000, None));
+
+        let sample = calibration
+            .observe("ep-a", "gpt-5", 1000, 1200)
+            .expect("sample");
+        assert_eq!(sample.samples, 3);
+        assert!((sample.factor - 1.2).abs() < 1e-9);
+        let (calibrated, factor) = calibration.calibrate("ep-a", "gpt-5", 1000);
+        assert_eq!(calibrated, 1200);
+        assert!(factor.is_some());
+
+        // 其它端点 / 模型互不影响
+        assert_eq!(calibration.calibrate("ep-b", "gpt-5", 1000), (1000, None));
+        assert_eq!(calibration.calibrate("ep-a", "gpt-4o", 1000), (1000, None));
+
+        let sample = calibration
+            .observe("ep-a", "gpt-5", 1000, 1200)
+            .expect("sample");
+        assert_eq!(sample.calibrated, 1200);
+        assert!(sample.error_ratio.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_observe_skips_small_estimates_and_clamps_outliers() {
+        let calibration = TokenCalibration::default();
+        assert!(calibration.observe("ep", "m", 10, 500).is_none());
+        assert!(calibration.observe("ep", "m", 1000, 0).is_none());
+
+        let sample = calibration
+            .observe("ep", "m", 1000, 50_000)
+            .expect("sample");
+        assert!((sample.factor - MAX_FACTOR).abs() < 1e-9);
+        assert!((sample.error_ratio - (1000.0 - 50_000.0) / 50_000.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_factors_persist_across_reopen() {
+        let dir = std::env::temp_dir().join(format!(
+            "codex-proxy-calibration-{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let path = dir.join(CALIBRATION_FILE_NAME);
+        let calibration = TokenCalibration::open(&path);
+        for _ in 0..3 {
+            calibration.observe("ep-a", "claude-sonnet", 2000, 1800);
+        }
+        calibration.flush().expect("flush");
+
+        let reopened = TokenCalibration::open(&path);
+        let factor = reopened.factor("ep-a", "claude-sonnet").expect("factor");
+        assert!((factor - 0.9).abs() < 1e-9);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,522 @@
+//! 本地 token 计数：上游没有 count_tokens 接口（或调用失败）时的估算
+//!
+//! 文本用内置的 o200k 词表（tiktoken）做真实的 BPE 分词；Claude / Gemini 没有公开词表，在 o200k
+//! 计数上按经验系数换算，误差由 `token_calibration` 按上游 usage 校正。图片按解码出的宽高套用
+//! 各家的计费公式，PDF 按页数估算。
+
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use base64::Engine;
+use serde_json::Value;
+use tiktoken_rs::o200k_base_singleton;
+
+use crate::models::{
+    AnthropicRequest, ContentBlock, ImageSource, ImageUrlValue, MessageContent, SystemBlock,
+    SystemContent,
+};
+
+/// 每条消息的角色与分隔符开销（与 OpenAI chat 计数一致）
+const TOKENS_PER_MESSAGE: u64 = 3;
+/// 助手回复的引导 token
+const REPLY_PRIMING_TOKENS: u64 = 3;
+/// 无法读出尺寸的图片按 1024x1024 计
+const DEFAULT_IMAGE_SIDE: u32 = 1024;
+/// PDF 每页（文本 + 页面图像）的估算值
+const PDF_PAGE_TOKENS: u64 = 1_500;
+/// 读取图片尺寸时先解码的 base64 前缀长度（约 48KB）；JPEG 的 SOF 不在其中时再整体解码
+const IMAGE_HEADER_BASE64_CHARS: usize = 64 * 1024;
+/// 相对 o200k 的文本换算系数（经验值）：Claude 词表更小，Gemini 与 o200k 接近
+const CLAUDE_TEXT_SCALE: f64 = 1.2;
+const GEMINI_TEXT_SCALE: f64 = 1.05;
+
+/// 计数所参照的上游分词器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenizerFamily {
+    /// Codex / OpenAI（gpt-4o、gpt-5 系列）
+    O200k,
+    Claude,
+    Gemini,
+}
+
+impl TokenizerFamily {
+    /// 按上游转换器与模型名选择；OpenAI 兼容上游上跑的 Claude / Gemini 模型按模型名识别
+    pub fn for_upstream(converter: &str, model: &str) -> Self {
+        let model = model.to_ascii_lowercase();
+        if converter.eq_ignore_ascii_case("gemini") || model.contains("gemini") {
+            Self::Gemini
+        } else if converter.eq_ignore_ascii_case("anthropic") || model.contains("claude") {
+            Self::Claude
+        } else {
+            Self::O200k
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::O200k => "o200k",
+            Self::Claude => "claude",
+            Self::Gemini => "gemini",
+        }
+    }
+
+    fn text_scale(&self) -> f64 {
+        match self {
+            Self::O200k => 1.0,
+            Self::Claude => CLAUDE_TEXT_SCALE,
+            Self::Gemini => GEMINI_TEXT_SCALE,
+        }
+    }
+
+    /// 单张图片的 token 数；`low_detail` 仅对 OpenAI 的 `detail: low` 生效
+    pub fn image_tokens(&self, width: u32, height: u32, low_detail: bool) -> u64 {
+        let (width, height) = (width.max(1) as f64, height.max(1) as f64);
+        match self {
+            // 先缩放到 2048 见方以内，再把短边缩到 768，按 512 切块
+            Self::O200k => {
+                if low_detail {
+                    return 85;
+                }
+                let fit = (2048.0 / width.max(height)).min(1.0);
+                let (width, height) = (width * fit, height * fit);
+                let shrink = (768.0 / width.min(height)).min(1.0);
+                let tiles = ((width * shrink) / 512.0).ceil() * ((height * shrink) / 512.0).ceil();
+                85 + 170 * tiles as u64
+            }
+            // 长边缩到 1568 以内后按 宽 x 高 / 750 计，上限约 1600
+            Self::Claude => {
+                let fit = (1568.0 / width.max(height)).min(1.0);
+                (((width * fit) * (height * fit)) / 750.0)
+                    .ceil()
+                    .min(1600.0) as u64
+            }
+            // 两边都不超过 384 时按 258；否则按短边 / 1.5（256..=768）切块，每块 258
+            Self::Gemini => {
+                if width <= 384.0 && height <= 384.0 {
+                    return 258;
+                }
+                let unit = (width.min(height) / 1.5).clamp(256.0, 768.0);
+                let tiles = (width / unit).ceil() * (height / unit).ceil();
+                258 * tiles as u64
+            }
+        }
+    }
+}
+
+/// 文本的 o200k token 数（未按分词器换算）
+pub fn count_text_tokens(text: &str) -> u64 {
+    if text.is_empty() {
+        return 0;
+    }
+    o200k_base_singleton().encode_ordinary(text).len() as u64
+}
+
+/// 估算一次 Anthropic Messages 请求的输入 token 数
+pub fn count_request_tokens(request: &AnthropicRequest, family: TokenizerFamily) -> u64 {
+    let mut counter = TokenCounter::default();
+
+    if let Some(system) = &request.system {
+        counter.fixed += TOKENS_PER_MESSAGE;
+        match system {
+            SystemContent::Text(text) => counter.text(text),
+            SystemContent::Blocks(blocks) => {
+                for block in blocks {
+                    match block {
+                        SystemBlock::Text { text } | SystemBlock::PlainString(text) => {
+                            counter.text(text)
+                        }
+                        SystemBlock::Other(value) => counter.value(value, family),
+                    }
+                }
+            }
+        }
+    }
+
+    for message in &request.messages {
+        counter.fixed += TOKENS_PER_MESSAGE;
+        counter.text(&message.role);
+        match &message.content {
+            Some(MessageContent::Text(text)) => counter.text(text),
+            Some(MessageContent::Blocks(blocks)) => {
+                for block in blocks {
+                    counter.block(block, family);
+                }
+            }
+            None => {}
+        }
+    }
+
+    if let Some(tools) = &request.tools {
+        for tool in tools {
+            counter.json(tool);
+        }
+    }
+
+    counter.fixed += REPLY_PRIMING_TOKENS;
+    counter.total(family)
+}
+
+/// 文本按 o200k 累计，最后统一换算；图片等按分词器直接计入 `fixed`
+#[derive(Default)]
+struct TokenCounter {
+    text: u64,
+    fixed: u64,
+}
+
+impl TokenCounter {
+    fn text(&mut self, text: &str) {
+        self.text += count_text_tokens(text);
+    }
+
+    fn json(&mut self, value: &Value) {
+        self.text(&serde_json::to_string(value).unwrap_or_default());
+    }
+
+    fn block(&mut self, block: &ContentBlock, family: TokenizerFamily) {
+        match block {
+            ContentBlock::Text { text } => self.text(text),
+            ContentBlock::Thinking { thinking, .. } => self.text(thinking),
+            ContentBlock::ToolUse { name, input, .. } => {
+                self.text(name);
+                self.json(input);
+            }
+            ContentBlock::ToolResult { content, .. } => {
+                if let Some(content) = content {
+                    self.value(content, family);
+                }
+            }
+            ContentBlock::Image {
+                source,
+                source_raw,
+                image_url,
+            } => {
+                let bytes = source
+                    .as_ref()
+                    .and_then(image_source_bytes)
+                    .or_else(|| {
+                        source_raw
+                            .as_ref()
+                            .and_then(|raw| raw.get("data"))
+                            .and_then(Value::as_str)
+                            .and_then(decode_image_base64)
+                    })
+                    .or_else(|| image_url.as_ref().and_then(image_url_bytes));
+                self.fixed += image_tokens_for(bytes.as_deref(), family, false);
+            }
+            ContentBlock::ImageUrl { image_url } => {
+                let bytes = image_url_bytes(image_url);
+                self.fixed += image_tokens_for(bytes.as_deref(), family, false);
+            }
+            ContentBlock::InputImage {
+                image_url,
+                url,
+                detail,
+            } => {
+                let bytes = image_url
+                    .as_ref()
+                    .and_then(image_url_bytes)
+                    .or_else(|| url.as_deref().and_then(data_url_bytes));
+                let low_detail = detail
+                    .as_deref()
+                    .is_some_and(|detail| detail.eq_ignore_ascii_case("low"));
+                self.fixed += image_tokens_for(bytes.as_deref(), family, low_detail);
+            }
+            ContentBlock::Document { source, .. } => match source {
+                Some(source) => self.document(source),
+                None => self.fixed += PDF_PAGE_TOKENS,
+            },
+            ContentBlock::OtherValue(value) => self.value(value, family),
+        }
+    }
+
+    /// tool_result 内容或未建模的块：文本、图片与文档分别计数，其余按 JSON 文本
+    fn value(&mut self, value: &Value, family: TokenizerFamily) {
+        match value {
+            Value::String(text) => self.text(text),
+            Value::Array(items) => {
+                for item in items {
+                    self.value(item, family);
+                }
+            }
+            Value::Object(map) => match map.get("type").and_then(Value::as_str) {
+                Some("text") => self.text(map.get("text").and_then(Value::as_str).unwrap_or("")),
+                Some("image") => {
+                    let bytes = map
+                        .get("source")
+                        .and_then(|source| source.get("data"))
+                        .and_then(Value::as_str)
+                        .and_then(decode_image_base64);
+                    self.fixed += image_tokens_for(bytes.as_deref(), family, false);
+                }
+                Some("document") => match map.get("source") {
+                    Some(source) => self.document(source),
+                    None => self.fixed += PDF_PAGE_TOKENS,
+                },
+                _ => self.json(value),
+            },
+            Value::Null => {}
+            other => self.json(other),
+        }
+    }
+
+    /// 文本来源按内容计；base64 PDF 按页数计
+    fn document(&mut self, source: &Value) {
+        let data = source.get("data").and_then(Value::as_str).unwrap_or("");
+        match source.get("type").and_then(Value::as_str) {
+            Some("text") => self.text(data),
+            Some("base64") => {
+                let pages = BASE64_STANDARD
+                    .decode(data.trim())
+                    .map(|bytes| pdf_page_count(&bytes))
+                    .unwrap_or(1);
+                self.fixed += PDF_PAGE_TOKENS * pages.max(1);
+            }
+            _ => self.fixed += PDF_PAGE_TOKENS,
+        }
+    }
+
+    fn total(&self, family: TokenizerFamily) -> u64 {
+        (self.text as f64 * family.text_scale()).ceil() as u64 + self.fixed
+    }
+}
+
+fn image_tokens_for(bytes: Option<&[u8]>, family: TokenizerFamily, low_detail: bool) -> u64 {
+    let (width, height) = bytes
+        .and_then(image_dimensions)
+        .unwrap_or((DEFAULT_IMAGE_SIDE, DEFAULT_IMAGE_SIDE));
+    family.image_tokens(width, height, low_detail)
+}
+
+fn image_source_bytes(source: &ImageSource) -> Option<Vec<u8>> {
+    source
+        .data
+        .as_deref()
+        .and_then(decode_image_base64)
+        .or_else(|| source.url.as_deref().and_then(data_url_bytes))
+}
+
+fn image_url_bytes(value: &ImageUrlValue) -> Option<Vec<u8>> {
+    match value {
+        ImageUrlValue::Str(url) | ImageUrlValue::ObjUrl { url } => data_url_bytes(url),
+        ImageUrlValue::ObjUri { uri } => data_url_bytes(uri),
+    }
+}
+
+/// 只有 `data:` URL 能读出尺寸；远程图片按默认尺寸计
+fn data_url_bytes(url: &str) -> Option<Vec<u8>> {
+    let (_, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+    decode_image_base64(data)
+}
+
+/// 先解码前缀，读不出尺寸（JPEG 的 SOF 在很后面）时再整体解码
+fn decode_image_base64(data: &str) -> Option<Vec<u8>> {
+    let data = data.trim();
+    if data.len() > IMAGE_HEADER_BASE64_CHARS {
+        if let Ok(prefix) = BASE64_STANDARD.decode(&data[..IMAGE_HEADER_BASE64_CHARS]) {
+            if image_dimensions(&prefix).is_some() {
+                return Some(prefix);
+            }
+        }
+    }
+    BASE64_STANDARD.decode(data).ok()
+}
+
+/// 从 PNG / GIF / JPEG / WebP 文件头读出 (宽, 高)
+pub fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |at: usize| Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let le16 = |at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let le24 = |at: usize| {
+        let b = bytes.get(at..at + 3)?;
+        Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16)
+    };
+
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let width = u32::from_be_bytes(bytes.get(16..20)?.try_into().ok()?);
+        let height = u32::from_be_bytes(bytes.get(20..24)?.try_into().ok()?);
+        return Some((width, height));
+    }
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        return Some((le16(6)?, le16(8)?));
+    }
+    if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        return match bytes.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3fff, le16(28)? & 0x3fff)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(bytes.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            }
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            _ => None,
+        };
+    }
+    if bytes.starts_with(&[0xff, 0xd8]) {
+        let mut at = 2;
+        while at + 4 <= bytes.len() {
+            if bytes[at] != 0xff {
+                at += 1;
+                continue;
+            }
+            let marker = bytes[at + 1];
+            // SOF0..SOF15，排除 DHT(C4) / JPG(C8) / DAC(CC)
+            if (0xc0..=0xcf).contains(&marker) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+                return Some((be16(at + 7)?, be16(at + 5)?));
+            }
+            if marker == 0xd8 || marker == 0x01 || (0xd0..=0xd7).contains(&marker) {
+                at += 2;
+                continue;
+            }
+            at += 2 + be16(at + 2)? as usize;
+        }
+    }
+    None
+}
+
+/// 统计 PDF 中的页面对象（`/Type /Page`，不含 `/Pages`）
+fn pdf_page_count(bytes: &[u8]) -> u64 {
+    let mut pages = 0;
+    let mut at = 0;
+    while let Some(offset) = find(&bytes[at..], b"/Type") {
+        let mut cursor = at + offset + b"/Type".len();
+        while bytes.get(cursor).is_some_and(|b| b.is_ascii_whitespace()) {
+            cursor += 1;
+        }
+        if bytes[cursor..].starts_with(b"/Page") && bytes.get(cursor + 5) != Some(&b's') {
+            pages += 1;
+        }
+        at = cursor;
+    }
+    pages
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        bytes.extend(width.to_be_bytes());
+        bytes.extend(height.to_be_bytes());
+        bytes.extend([8, 6, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn text_counts_match_o200k() {
+        let bpe = o200k_base_singleton();
+        assert_eq!(bpe.encode_ordinary("Hello world"), [13225, 2375]);
+        assert_eq!(count_text_tokens(""), 0);
+        assert_eq!(
+            count_text_tokens("The quick brown fox jumps over the lazy dog."),
+            10
+        );
+        assert_eq!(count_text_tokens("Hello world"), 2);
+        // 特殊 token 的字面文本按普通文本计
+        assert!(count_text_tokens("<|endoftext|>") > 1);
+    }
+
+    #[test]
+    fn long_runs_do_not_swallow_the_rest_of_the_text() {
+        let dashes = "-".repeat(300);
+        let text = format!("{} tail", dashes);
+        let bpe = o200k_base_singleton();
+        let tokens = bpe.encode_ordinary(&text);
+        assert_eq!(bpe.decode(tokens.clone()).expect("decode"), text);
+        assert_eq!(
+            count_text_tokens(&text),
+            count_text_tokens(&dashes) + count_text_tokens(" tail")
+        );
+    }
+
+    #[test]
+    fn reads_image_dimensions_from_headers() {
+        assert_eq!(image_dimensions(&png(640, 480)), Some((640, 480)));
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend([0x20, 0x03, 0x58, 0x02]);
+        assert_eq!(image_dimensions(&gif), Some((800, 600)));
+        let jpeg = [
+            0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x02,
+            0xd0, 0x05, 0x00,
+        ];
+        assert_eq!(image_dimensions(&jpeg), Some((1280, 720)));
+        assert_eq!(image_dimensions(b"not an image"), None);
+    }
+
+    #[test]
+    fn image_tokens_follow_provider_formulas() {
+        // 1024x1024 -> 768x768 -> 4 块
+        assert_eq!(TokenizerFamily::O200k.image_tokens(1024, 1024, false), 765);
+        assert_eq!(TokenizerFamily::O200k.image_tokens(4096, 8192, true), 85);
+        // 2048x4096 -> 1024x2048 -> 768x1536 -> 2x3 块
+        assert_eq!(TokenizerFamily::O200k.image_tokens(2048, 4096, false), 1105);
+        assert_eq!(TokenizerFamily::Claude.image_tokens(1000, 750, false), 1000);
+        assert_eq!(
+            TokenizerFamily::Claude.image_tokens(4000, 4000, false),
+            1600
+        );
+        assert_eq!(TokenizerFamily::Gemini.image_tokens(300, 200, false), 258);
+        assert_eq!(
+            TokenizerFamily::Gemini.image_tokens(1536, 768, false),
+            258 * 6
+        );
+    }
+
+    #[test]
+    fn request_count_uses_image_size_and_family_scale() {
+        let image = BASE64_STANDARD.encode(png(512, 512));
+        let request: AnthropicRequest = serde_json::from_value(json!({
+            "model": "claude-sonnet-4",
+            "system": "You are a helpful assistant.",
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "What is in this picture?"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": image}},
+                    {"type": "tool_result", "tool_use_id": "t1", "content": [
+                        {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": image}}
+                    ]}
+                ]
+            }]
+        }))
+        .expect("request");
+
+        let o200k = count_request_tokens(&request, TokenizerFamily::O200k);
+        let text = count_text_tokens("You are a helpful assistant.")
+            + count_text_tokens("user")
+            + count_text_tokens("What is in this picture?");
+        // 512x512 单块：85 + 170
+        assert_eq!(
+            o200k,
+            text + 2 * TOKENS_PER_MESSAGE + REPLY_PRIMING_TOKENS + 2 * 255
+        );
+
+        let gemini = count_request_tokens(&request, TokenizerFamily::Gemini);
+        assert_eq!(
+            gemini,
+            (text as f64 * GEMINI_TEXT_SCALE).ceil() as u64
+                + 2 * TOKENS_PER_MESSAGE
+                + REPLY_PRIMING_TOKENS
+                + 2 * TokenizerFamily::Gemini.image_tokens(512, 512, false)
+        );
+    }
+
+    #[test]
+    fn family_follows_converter_and_model() {
+        assert_eq!(
+            TokenizerFamily::for_upstream("codex", "gpt-5-codex"),
+            TokenizerFamily::O200k
+        );
+        assert_eq!(
+            TokenizerFamily::for_upstream("openai", "gemini-2.5-pro"),
+            TokenizerFamily::Gemini
+        );
+        assert_eq!(
+            TokenizerFamily::for_upstream("anthropic", "glm-4.6"),
+            TokenizerFamily::Claude
+        );
+    }
+}
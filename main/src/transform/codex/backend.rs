@@ -30,9 +30,7 @@ pub(crate) fn build_codex_unified_request(
     let stripped_skill_scaffolding =
         strip_skill_scaffolding_user_messages(&mut unified, !extracted_skills.is_empty());
 
-    if hints.request_kind == crate::transform::ClaudeCodeRequestKind::ConversationTurn
-        && unified.has_system_text()
-    {
+    if hints.request_kind.is_conversation() && unified.has_system_text() {
         unified.append_system_texts(crate::prompts::codex_system_prompt_extensions());
     }
 
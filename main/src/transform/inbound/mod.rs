@@ -0,0 +1,63 @@
+//! 入站协议解码/编码：把非 Anthropic 客户端协议接入现有的 Anthropic Messages 主链路
+pub mod openai_chat;
+pub mod responses;
+
+use serde_json::Value;
+
+/// 把主链路输出的 Anthropic SSE 重新编码为客户端协议的流式编码器
+pub trait InboundStreamEncoder: Send + 'static {
+    /// 喂入一段 Anthropic SSE 字节（可能包含半个事件或半个 UTF-8 字符），返回编码后的 SSE 帧
+    fn push(&mut self, chunk: &[u8]) -> Vec<String>;
+
+    /// 上游流结束：补齐收尾事件
+    fn finish(&mut self) -> Vec<String>;
+}
+
+/// Anthropic SSE 字节流的事件切分缓冲
+#[derive(Debug, Default)]
+pub struct AnthropicSseBuffer {
+    buffer: Vec<u8>,
+}
+
+impl AnthropicSseBuffer {
+    /// 追加字节并取出所有完整事件
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Value> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(idx) = self.buffer.windows(2).position(|window| window == b"\n\n") {
+            let event: Vec<u8> = self.buffer.drain(..idx + 2).collect();
+            events.extend(parse_anthropic_sse_event(&String::from_utf8_lossy(&event)));
+        }
+        events
+    }
+
+    /// 取出缓冲区中残留的最后一个（无结尾空行的）事件
+    pub fn flush(&mut self) -> Option<Value> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let event = std::mem::take(&mut self.buffer);
+        parse_anthropic_sse_event(&String::from_utf8_lossy(&event))
+    }
+}
+
+/// 解析单个 SSE 事件的 data；payload 缺少 `type` 时用 `event:` 行补齐
+pub fn parse_anthropic_sse_event(event: &str) -> Option<Value> {
+    let mut event_name = None;
+    let mut data = String::new();
+    for line in event.lines() {
+        if let Some(name) = line.strip_prefix("event:") {
+            event_name = Some(name.trim().to_string());
+        } else if let Some(payload) = line.strip_prefix("data:") {
+            data.push_str(payload.trim_start());
+        }
+    }
+    if data.is_empty() {
+        return None;
+    }
+    let mut payload = serde_json::from_str::<Value>(&data).ok()?;
+    if payload.get("type").and_then(Value::as_str).is_none() {
+        payload["type"] = Value::String(event_name?);
+    }
+    Some(payload)
+}
@@ -0,0 +1,784 @@
+//! OpenAI Chat Completions 入站协议
+//!
+//! - 请求：`/v1/chat/completions` body → `UnifiedChatRequest` → Anthropic Messages body
+//! - 响应：各 `ResponseTransformer` 产出的 Anthropic SSE / JSON → `chat.completion(.chunk)`
+
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use super::{AnthropicSseBuffer, InboundStreamEncoder};
+use crate::transform::providers::encode_anthropic_body;
+use crate::transform::unified::{
+    UnifiedChatRequest, UnifiedContent, UnifiedFunctionCall, UnifiedMessage, UnifiedMessageRole,
+    UnifiedReasoning, UnifiedThinking, UnifiedTool, UnifiedToolCall, UnifiedToolChoice,
+    UnifiedToolDefinition,
+};
+
+/// 解码后的 Chat Completions 请求（附带只在响应编码阶段需要的选项）
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedChatCompletionsRequest {
+    pub unified: UnifiedChatRequest,
+    pub include_usage: bool,
+    pub top_p: Option<f32>,
+    pub stop: Vec<String>,
+    pub user: Option<String>,
+}
+
+impl DecodedChatCompletionsRequest {
+    /// 转为内部 Anthropic Messages 请求体，交给现有的 slot 映射 / 负载均衡 / backend 链路
+    pub fn to_anthropic_body(&self) -> Value {
+        let mut body = encode_anthropic_body(&self.unified, &self.unified.model);
+        if let Some(obj) = body.as_object_mut() {
+            if obj.get("max_tokens").map(Value::is_null).unwrap_or(true) {
+                obj.remove("max_tokens");
+            }
+            if obj.get("temperature").map(Value::is_null).unwrap_or(true) {
+                obj.remove("temperature");
+            }
+            if let Some(top_p) = self.top_p {
+                obj.insert("top_p".to_string(), json!(top_p));
+            }
+            if !self.stop.is_empty() {
+                obj.insert("stop_sequences".to_string(), json!(self.stop));
+            }
+            if let Some(user) = self.user.as_ref() {
+                obj.insert("metadata".to_string(), json!({ "user_id": user }));
+            }
+        }
+        body
+    }
+}
+
+pub fn decode_chat_completions_request(
+    body: &Value,
+) -> Result<DecodedChatCompletionsRequest, String> {
+    let obj = body
+        .as_object()
+        .ok_or_else(|| "request body must be a JSON object".to_string())?;
+    let raw_messages = obj
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or_else(|| "'messages' is required".to_string())?;
+
+    let mut messages = Vec::new();
+    for raw in raw_messages {
+        if let Some(message) = decode_message(raw)? {
+            messages.push(message);
+        }
+    }
+
+    let max_tokens = obj
+        .get("max_completion_tokens")
+        .or_else(|| obj.get("max_tokens"))
+        .and_then(Value::as_u64)
+        .map(|value| value as u32);
+    let temperature = obj
+        .get("temperature")
+        .and_then(Value::as_f64)
+        .map(|value| value as f32);
+    let top_p = obj
+        .get("top_p")
+        .and_then(Value::as_f64)
+        .map(|value| value as f32);
+    let stream = obj.get("stream").and_then(Value::as_bool).unwrap_or(false);
+    let include_usage = obj
+        .get("stream_options")
+        .and_then(|value| value.get("include_usage"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let stop = match obj.get("stop") {
+        Some(Value::String(text)) => vec![text.clone()],
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(|item| item.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let reasoning = obj
+        .get("reasoning_effort")
+        .and_then(Value::as_str)
+        .map(|effort| UnifiedReasoning {
+            enabled: !effort.eq_ignore_ascii_case("none"),
+            effort: Some(effort.to_string()),
+            max_tokens: None,
+        });
+
+    Ok(DecodedChatCompletionsRequest {
+        unified: UnifiedChatRequest {
+            messages,
+            model: obj
+                .get("model")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            max_tokens,
+            temperature,
+            stream,
+            tools: decode_tools(obj.get("tools")),
+            tool_choice: decode_tool_choice(obj.get("tool_choice")),
+            reasoning,
+        },
+        include_usage,
+        top_p,
+        stop,
+        user: obj.get("user").and_then(Value::as_str).map(str::to_string),
+    })
+}
+
+fn decode_message(raw: &Value) -> Result<Option<UnifiedMessage>, String> {
+    let role = raw
+        .get("role")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "message.role is required".to_string())?;
+    let role = match role {
+        "system" | "developer" => UnifiedMessageRole::System,
+        "user" => UnifiedMessageRole::User,
+        "assistant" => UnifiedMessageRole::Assistant,
+        "tool" | "function" => UnifiedMessageRole::Tool,
+        other => return Err(format!("unsupported message role '{}'", other)),
+    };
+
+    let content = decode_content(raw.get("content"));
+    let tool_calls: Vec<UnifiedToolCall> = raw
+        .get("tool_calls")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .filter_map(|(idx, call)| {
+            let function = call.get("function")?;
+            Some(UnifiedToolCall {
+                id: call
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("call_{}", idx)),
+                function: UnifiedFunctionCall {
+                    name: function.get("name").and_then(Value::as_str)?.to_string(),
+                    arguments: match function.get("arguments") {
+                        Some(Value::String(text)) => text.clone(),
+                        Some(other) => other.to_string(),
+                        None => "{}".to_string(),
+                    },
+                },
+            })
+        })
+        .collect();
+    let thinking = raw
+        .get("reasoning_content")
+        .and_then(Value::as_str)
+        .filter(|text| !text.is_empty())
+        .map(|text| UnifiedThinking {
+            content: text.to_string(),
+            signature: None,
+        });
+    let tool_call_id = raw
+        .get("tool_call_id")
+        .or_else(|| raw.get("name"))
+        .and_then(Value::as_str)
+        .filter(|_| role == UnifiedMessageRole::Tool)
+        .map(str::to_string);
+
+    if role != UnifiedMessageRole::Tool
+        && content.is_empty()
+        && tool_calls.is_empty()
+        && thinking.is_none()
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(UnifiedMessage {
+        role,
+        content,
+        tool_calls,
+        tool_call_id,
+        thinking,
+    }))
+}
+
+fn decode_content(content: Option<&Value>) -> Vec<UnifiedContent> {
+    match content {
+        Some(Value::String(text)) if !text.is_empty() => {
+            vec![UnifiedContent::Text { text: text.clone() }]
+        }
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| match part.get("type").and_then(Value::as_str) {
+                Some("text") | Some("input_text") => part
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .filter(|text| !text.is_empty())
+                    .map(|text| UnifiedContent::Text {
+                        text: text.to_string(),
+                    }),
+                Some("image_url") => {
+                    let url = match part.get("image_url") {
+                        Some(Value::String(url)) => Some(url.clone()),
+                        Some(value) => value.get("url").and_then(Value::as_str).map(str::to_string),
+                        None => None,
+                    }?;
+                    Some(UnifiedContent::ImageUrl {
+                        media_type: data_url_media_type(&url),
+                        url,
+                    })
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn data_url_media_type(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("data:")?;
+    let media_type = rest.split([';', ',']).next()?;
+    if media_type.is_empty() {
+        None
+    } else {
+        Some(media_type.to_string())
+    }
+}
+
+fn decode_tools(tools: Option<&Value>) -> Option<Vec<UnifiedTool>> {
+    let converted: Vec<UnifiedTool> = tools
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|tool| {
+            let function = tool.get("function").unwrap_or(tool);
+            let name = function.get("name").and_then(Value::as_str)?;
+            Some(UnifiedTool {
+                function: UnifiedToolDefinition {
+                    name: name.to_string(),
+                    description: function
+                        .get("description")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string(),
+                    parameters: function
+                        .get("parameters")
+                        .cloned()
+                        .unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+                },
+            })
+        })
+        .collect();
+
+    if converted.is_empty() {
+        None
+    } else {
+        Some(converted)
+    }
+}
+
+fn decode_tool_choice(choice: Option<&Value>) -> Option<UnifiedToolChoice> {
+    match choice? {
+        Value::String(kind) => match kind.as_str() {
+            "auto" => Some(UnifiedToolChoice::Auto),
+            "none" => Some(UnifiedToolChoice::None),
+            "required" => Some(UnifiedToolChoice::Required),
+            _ => None,
+        },
+        value => value
+            .get("function")
+            .and_then(|function| function.get("name"))
+            .and_then(Value::as_str)
+            .map(|name| UnifiedToolChoice::Function {
+                name: name.to_string(),
+            }),
+    }
+}
+
+pub fn map_stop_reason_to_finish_reason(stop_reason: Option<&str>) -> &'static str {
+    match stop_reason {
+        Some("max_tokens") => "length",
+        Some("tool_use") => "tool_calls",
+        Some("refusal") => "content_filter",
+        _ => "stop",
+    }
+}
+
+fn encode_usage(usage: &Value) -> Value {
+    let input = usage
+        .get("input_tokens")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let cache_read = usage
+        .get("cache_read_input_tokens")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let cache_creation = usage
+        .get("cache_creation_input_tokens")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let output = usage
+        .get("output_tokens")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let prompt_tokens = input + cache_read + cache_creation;
+    json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": output,
+        "total_tokens": prompt_tokens + output,
+        "prompt_tokens_details": { "cached_tokens": cache_read },
+    })
+}
+
+fn chat_completion_id(message_id: Option<&str>) -> String {
+    let raw = message_id.unwrap_or_default();
+    let suffix = raw.strip_prefix("msg_").unwrap_or(raw);
+    if suffix.is_empty() {
+        format!("chatcmpl-{}", uuid::Uuid::new_v4().simple())
+    } else {
+        format!("chatcmpl-{}", suffix)
+    }
+}
+
+/// 将非流式 Anthropic message JSON 编码为 `chat.completion`
+pub fn encode_chat_completion(message: &Value, model: &str) -> Value {
+    let mut text = String::new();
+    let mut reasoning = String::new();
+    let mut tool_calls = Vec::new();
+
+    for block in message
+        .get("content")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        match block.get("type").and_then(Value::as_str) {
+            Some("text") => {
+                text.push_str(block.get("text").and_then(Value::as_str).unwrap_or(""));
+            }
+            Some("thinking") => {
+                reasoning.push_str(block.get("thinking").and_then(Value::as_str).unwrap_or(""));
+            }
+            Some("tool_use") => {
+                let input = block.get("input").cloned().unwrap_or_else(|| json!({}));
+                tool_calls.push(json!({
+                    "id": block.get("id").and_then(Value::as_str).unwrap_or_default(),
+                    "type": "function",
+                    "function": {
+                        "name": block.get("name").and_then(Value::as_str).unwrap_or_default(),
+                        "arguments": input.to_string(),
+                    }
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    let mut assistant = Map::new();
+    assistant.insert("role".to_string(), json!("assistant"));
+    assistant.insert(
+        "content".to_string(),
+        if text.is_empty() && !tool_calls.is_empty() {
+            Value::Null
+        } else {
+            json!(text)
+        },
+    );
+    if !reasoning.is_empty() {
+        assistant.insert("reasoning_content".to_string(), json!(reasoning));
+    }
+    if !tool_calls.is_empty() {
+        assistant.insert("tool_calls".to_string(), json!(tool_calls));
+    }
+
+    let mut completion = json!({
+        "id": chat_completion_id(message.get("id").and_then(Value::as_str)),
+        "object": "chat.completion",
+        "created": chrono::Utc::now().timestamp(),
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "message": Value::Object(assistant),
+            "finish_reason": map_stop_reason_to_finish_reason(
+                message.get("stop_reason").and_then(Value::as_str),
+            ),
+        }],
+    });
+    if let Some(usage) = message.get("usage") {
+        completion["usage"] = encode_usage(usage);
+    }
+    completion
+}
+
+/// 将 Anthropic 错误体转换为 OpenAI 错误格式
+pub fn encode_chat_completion_error(error_body: &Value) -> Value {
+    let error = error_body.get("error").unwrap_or(error_body);
+    let message = error
+        .get("message")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .unwrap_or_else(|| error_body.to_string());
+    let kind = error
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or("api_error");
+    json!({
+        "error": {
+            "message": message,
+            "type": kind,
+            "param": Value::Null,
+            "code": kind,
+        }
+    })
+}
+
+/// Anthropic SSE → `chat.completion.chunk` SSE 的有状态编码器
+pub struct ChatCompletionsStreamEncoder {
+    model: String,
+    include_usage: bool,
+    id: String,
+    created: i64,
+    buffer: AnthropicSseBuffer,
+    role_sent: bool,
+    finished: bool,
+    /// Anthropic content block index → OpenAI tool_calls index
+    tool_indices: HashMap<u64, usize>,
+    next_tool_index: usize,
+    usage: Map<String, Value>,
+    stop_reason: Option<String>,
+}
+
+impl ChatCompletionsStreamEncoder {
+    pub fn new(model: &str, include_usage: bool) -> Self {
+        Self {
+            model: model.to_string(),
+            include_usage,
+            id: chat_completion_id(None),
+            created: chrono::Utc::now().timestamp(),
+            buffer: AnthropicSseBuffer::default(),
+            role_sent: false,
+            finished: false,
+            tool_indices: HashMap::new(),
+            next_tool_index: 0,
+            usage: Map::new(),
+            stop_reason: None,
+        }
+    }
+
+    fn chunk(&self, delta: Value, finish_reason: Option<&str>) -> String {
+        let payload = json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{
+                "index": 0,
+                "delta": delta,
+                "finish_reason": finish_reason,
+            }],
+        });
+        format!("data: {}\n\n", payload)
+    }
+
+    fn delta(&mut self, mut delta: Value) -> String {
+        if !self.role_sent {
+            self.role_sent = true;
+            delta["role"] = json!("assistant");
+        }
+        self.chunk(delta, None)
+    }
+
+    fn emit_finish(&mut self) -> Vec<String> {
+        if self.finished {
+            return Vec::new();
+        }
+        self.finished = true;
+        let mut output = Vec::new();
+        if !self.role_sent {
+            output.push(self.delta(json!({ "content": "" })));
+        }
+        let finish_reason = map_stop_reason_to_finish_reason(self.stop_reason.as_deref());
+        output.push(self.chunk(json!({}), Some(finish_reason)));
+        if self.include_usage {
+            let payload = json!({
+                "id": self.id,
+                "object": "chat.completion.chunk",
+                "created": self.created,
+                "model": self.model,
+                "choices": [],
+                "usage": encode_usage(&Value::Object(self.usage.clone())),
+            });
+            output.push(format!("data: {}\n\n", payload));
+        }
+        output
+    }
+
+    fn merge_usage(&mut self, usage: Option<&Value>) {
+        if let Some(Value::Object(fields)) = usage {
+            for (key, value) in fields {
+                if !value.is_null() {
+                    self.usage.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    fn encode_event(&mut self, payload: &Value) -> Vec<String> {
+        let kind = payload
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+
+        match kind {
+            "message_start" => {
+                let message = payload.get("message");
+                if let Some(id) = message.and_then(|m| m.get("id")).and_then(Value::as_str) {
+                    self.id = chat_completion_id(Some(id));
+                }
+                self.merge_usage(message.and_then(|m| m.get("usage")));
+                vec![self.delta(json!({ "content": "" }))]
+            }
+            "content_block_start" => {
+                let block = payload.get("content_block");
+                if block.and_then(|b| b.get("type")).and_then(Value::as_str) != Some("tool_use") {
+                    return Vec::new();
+                }
+                let block_index = payload.get("index").and_then(Value::as_u64).unwrap_or(0);
+                let tool_index = self.next_tool_index;
+                self.next_tool_index += 1;
+                self.tool_indices.insert(block_index, tool_index);
+                let block = block.cloned().unwrap_or_default();
+                vec![self.delta(json!({
+                    "tool_calls": [{
+                        "index": tool_index,
+                        "id": block.get("id").and_then(Value::as_str).unwrap_or_default(),
+                        "type": "function",
+                        "function": {
+                            "name": block.get("name").and_then(Value::as_str).unwrap_or_default(),
+                            "arguments": "",
+                        }
+                    }]
+                }))]
+            }
+            "content_block_delta" => {
+                let Some(delta) = payload.get("delta") else {
+                    return Vec::new();
+                };
+                match delta.get("type").and_then(Value::as_str) {
+                    Some("text_delta") => {
+                        let text = delta.get("text").and_then(Value::as_str).unwrap_or("");
+                        vec![self.delta(json!({ "content": text }))]
+                    }
+                    Some("thinking_delta") => {
+                        let text = delta.get("thinking").and_then(Value::as_str).unwrap_or("");
+                        vec![self.delta(json!({ "reasoning_content": text }))]
+                    }
+                    Some("input_json_delta") => {
+                        let block_index = payload.get("index").and_then(Value::as_u64).unwrap_or(0);
+                        let Some(tool_index) = self.tool_indices.get(&block_index).copied() else {
+                            return Vec::new();
+                        };
+                        let partial = delta
+                            .get("partial_json")
+                            .and_then(Value::as_str)
+                            .unwrap_or("");
+                        vec![self.delta(json!({
+                            "tool_calls": [{
+                                "index": tool_index,
+                                "function": { "arguments": partial }
+                            }]
+                        }))]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            "message_delta" => {
+                if let Some(reason) = payload
+                    .get("delta")
+                    .and_then(|delta| delta.get("stop_reason"))
+                    .and_then(Value::as_str)
+                {
+                    self.stop_reason = Some(reason.to_string());
+                }
+                self.merge_usage(payload.get("usage"));
+                Vec::new()
+            }
+            "message_stop" => self.emit_finish(),
+            "ping" => vec![": ping\n\n".to_string()],
+            "error" => {
+                self.finished = true;
+                vec![format!(
+                    "data: {}\n\n",
+                    encode_chat_completion_error(payload)
+                )]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl InboundStreamEncoder for ChatCompletionsStreamEncoder {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        let events = self.buffer.push(chunk);
+        events
+            .iter()
+            .flat_map(|event| self.encode_event(event))
+            .collect()
+    }
+
+    /// 补齐 finish_reason / usage / [DONE]
+    fn finish(&mut self) -> Vec<String> {
+        let mut output = Vec::new();
+        if let Some(event) = self.buffer.flush() {
+            output.extend(self.encode_event(&event));
+        }
+        output.extend(self.emit_finish());
+        output.push("data: [DONE]\n\n".to_string());
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AnthropicRequest;
+
+    fn parse_chunks(frames: &[String]) -> Vec<Value> {
+        frames
+            .iter()
+            .filter_map(|frame| frame.strip_prefix("data: "))
+            .filter(|data| !data.starts_with("[DONE]"))
+            .map(|data| serde_json::from_str(data.trim()).expect("chunk json"))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_chat_request_with_tools_into_anthropic_body() {
+        let body = json!({
+            "model": "claude-sonnet-4-6",
+            "stream": true,
+            "stream_options": { "include_usage": true },
+            "max_completion_tokens": 512,
+            "stop": "END",
+            "messages": [
+                { "role": "system", "content": "be brief" },
+                { "role": "user", "content": [
+                    { "type": "text", "text": "weather?" },
+                    { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } }
+                ]},
+                { "role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" }
+                }]},
+                { "role": "tool", "tool_call_id": "call_1", "content": "sunny" }
+            ],
+            "tools": [{ "type": "function", "function": {
+                "name": "get_weather", "description": "Weather", "parameters": { "type": "object" }
+            }}],
+            "tool_choice": { "type": "function", "function": { "name": "get_weather" } }
+        });
+
+        let decoded = decode_chat_completions_request(&body).expect("decode");
+        assert!(decoded.include_usage);
+        assert_eq!(decoded.unified.max_tokens, Some(512));
+        assert_eq!(decoded.unified.messages.len(), 4);
+        assert_eq!(
+            decoded.unified.tool_choice,
+            Some(UnifiedToolChoice::Function {
+                name: "get_weather".to_string()
+            })
+        );
+
+        let anthropic_body = decoded.to_anthropic_body();
+        assert_eq!(anthropic_body["system"], "be brief");
+        assert_eq!(anthropic_body["stop_sequences"], json!(["END"]));
+        assert_eq!(
+            anthropic_body["messages"][1]["content"][0]["type"],
+            "tool_use"
+        );
+        assert_eq!(
+            anthropic_body["messages"][1]["content"][0]["input"]["city"],
+            "Paris"
+        );
+        assert_eq!(
+            anthropic_body["messages"][2]["content"][0]["tool_use_id"],
+            "call_1"
+        );
+        let request: AnthropicRequest =
+            serde_json::from_value(anthropic_body).expect("anthropic request");
+        assert!(request.stream);
+        assert_eq!(request.messages.len(), 3);
+    }
+
+    #[test]
+    fn rejects_request_without_messages() {
+        let err = decode_chat_completions_request(&json!({ "model": "x" })).unwrap_err();
+        assert!(err.contains("messages"));
+    }
+
+    #[test]
+    fn stream_encoder_emits_text_tool_calls_usage_and_done() {
+        let mut encoder = ChatCompletionsStreamEncoder::new("claude-sonnet-4-6", true);
+        let input = concat!(
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_abc\",\"usage\":{\"input_tokens\":10,\"cache_read_input_tokens\":4}}}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"Read\",\"input\":{}}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"path\\\":\"}}\n\n",
+            "event: ping\ndata: {\"type\": \"ping\"}\n\n",
+            "event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":7}}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        );
+
+        // 按不完整的分片喂入，验证跨帧缓冲
+        let (head, tail) = input.split_at(97);
+        let mut frames = encoder.push(head.as_bytes());
+        frames.extend(encoder.push(tail.as_bytes()));
+        frames.extend(encoder.finish());
+
+        assert!(frames.contains(&": ping\n\n".to_string()));
+        assert_eq!(frames.last().map(String::as_str), Some("data: [DONE]\n\n"));
+        let chunks = parse_chunks(&frames);
+        assert!(chunks.iter().all(|c| c["id"] == "chatcmpl-abc"));
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "Hi");
+        let tool_start = &chunks[2]["choices"][0]["delta"]["tool_calls"][0];
+        assert_eq!(tool_start["id"], "toolu_1");
+        assert_eq!(tool_start["function"]["name"], "Read");
+        assert_eq!(
+            chunks[3]["choices"][0]["delta"]["tool_calls"][0]["function"]["arguments"],
+            "{\"path\":"
+        );
+        assert_eq!(chunks[4]["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(chunks[5]["usage"]["prompt_tokens"], 14);
+        assert_eq!(chunks[5]["usage"]["completion_tokens"], 7);
+        assert_eq!(
+            chunks[5]["usage"]["prompt_tokens_details"]["cached_tokens"],
+            4
+        );
+        assert_eq!(chunks.len(), 6, "finish must be emitted exactly once");
+    }
+
+    #[test]
+    fn encodes_non_stream_completion_with_tool_calls() {
+        let message = json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                { "type": "thinking", "thinking": "hmm" },
+                { "type": "text", "text": "Let me check." },
+                { "type": "tool_use", "id": "toolu_9", "name": "Bash", "input": { "cmd": "ls" } }
+            ],
+            "stop_reason": "tool_use",
+            "usage": { "input_tokens": 3, "output_tokens": 5 }
+        });
+
+        let completion = encode_chat_completion(&message, "gpt-client-model");
+        assert_eq!(completion["object"], "chat.completion");
+        assert_eq!(completion["model"], "gpt-client-model");
+        let choice = &completion["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(choice["message"]["content"], "Let me check.");
+        assert_eq!(choice["message"]["reasoning_content"], "hmm");
+        assert_eq!(
+            choice["message"]["tool_calls"][0]["function"]["arguments"],
+            "{\"cmd\":\"ls\"}"
+        );
+        assert_eq!(completion["usage"]["total_tokens"], 8);
+    }
+}
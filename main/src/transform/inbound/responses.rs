@@ -0,0 +1,1140 @@
+//! OpenAI Responses API 入站协议（`/codex/v1/responses` 对接非 Codex 上游）
+//!
+//! - 请求：Responses body（input items / function_call / reasoning / tools）→ `UnifiedChatRequest`
+//!   → Anthropic Messages body，交给 Gemini / OpenAI / Anthropic 适配器链路
+//! - 响应：Anthropic SSE / JSON → Responses API 事件流（`response.output_item.added` ...）/ response 对象
+
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+
+use super::openai_chat::encode_chat_completion_error;
+use super::{AnthropicSseBuffer, InboundStreamEncoder};
+use crate::transform::providers::encode_anthropic_body;
+use crate::transform::unified::{
+    UnifiedChatRequest, UnifiedContent, UnifiedFunctionCall, UnifiedMessage, UnifiedMessageRole,
+    UnifiedReasoning, UnifiedThinking, UnifiedTool, UnifiedToolCall, UnifiedToolChoice,
+    UnifiedToolDefinition,
+};
+
+/// 解码后的 Responses 请求
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedResponsesRequest {
+    pub unified: UnifiedChatRequest,
+    /// `type: custom` 的自由文本工具（如 apply_patch），上游以 `{input}` 函数形式调用，回写时还原
+    pub custom_tools: HashSet<String>,
+    pub top_p: Option<f32>,
+    pub user: Option<String>,
+    pub previous_response_id: Option<String>,
+}
+
+impl DecodedResponsesRequest {
+    /// 转为内部 Anthropic Messages 请求体
+    pub fn to_anthropic_body(&self) -> Value {
+        let mut body = encode_anthropic_body(&self.unified, &self.unified.model);
+        if let Some(obj) = body.as_object_mut() {
+            if obj.get("max_tokens").map(Value::is_null).unwrap_or(true) {
+                obj.remove("max_tokens");
+            }
+            if obj.get("temperature").map(Value::is_null).unwrap_or(true) {
+                obj.remove("temperature");
+            }
+            if let Some(top_p) = self.top_p {
+                obj.insert("top_p".to_string(), json!(top_p));
+            }
+            if let Some(user) = self.user.as_ref() {
+                obj.insert("metadata".to_string(), json!({ "user_id": user }));
+            }
+        }
+        body
+    }
+}
+
+pub fn decode_responses_request(body: &Value) -> Result<DecodedResponsesRequest, String> {
+    let obj = body
+        .as_object()
+        .ok_or_else(|| "request body must be a JSON object".to_string())?;
+
+    let mut messages = Vec::new();
+    if let Some(instructions) = obj
+        .get("instructions")
+        .and_then(Value::as_str)
+        .filter(|text| !text.trim().is_empty())
+    {
+        messages.push(UnifiedMessage {
+            role: UnifiedMessageRole::System,
+            content: vec![UnifiedContent::Text {
+                text: instructions.to_string(),
+            }],
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            thinking: None,
+        });
+    }
+
+    match obj.get("input") {
+        Some(Value::String(text)) => messages.push(UnifiedMessage {
+            role: UnifiedMessageRole::User,
+            content: vec![UnifiedContent::Text { text: text.clone() }],
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            thinking: None,
+        }),
+        Some(Value::Array(items)) => decode_input_items(items, &mut messages)?,
+        Some(_) => return Err("'input' must be a string or an array".to_string()),
+        None => return Err("'input' is required".to_string()),
+    }
+
+    let (tools, custom_tools) = decode_tools(obj.get("tools"));
+    let reasoning = obj
+        .get("reasoning")
+        .and_then(|value| value.get("effort"))
+        .and_then(Value::as_str)
+        .map(|effort| UnifiedReasoning {
+            enabled: !effort.eq_ignore_ascii_case("none"),
+            effort: Some(effort.to_string()),
+            max_tokens: None,
+        });
+
+    Ok(DecodedResponsesRequest {
+        unified: UnifiedChatRequest {
+            messages,
+            model: obj
+                .get("model")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            max_tokens: obj
+                .get("max_output_tokens")
+                .and_then(Value::as_u64)
+                .map(|value| value as u32),
+            temperature: obj
+                .get("temperature")
+                .and_then(Value::as_f64)
+                .map(|value| value as f32),
+            stream: obj.get("stream").and_then(Value::as_bool).unwrap_or(false),
+            tools,
+            tool_choice: decode_tool_choice(obj.get("tool_choice")),
+            reasoning,
+        },
+        custom_tools,
+        top_p: obj
+            .get("top_p")
+            .and_then(Value::as_f64)
+            .map(|value| value as f32),
+        user: obj.get("user").and_then(Value::as_str).map(str::to_string),
+        previous_response_id: obj
+            .get("previous_response_id")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    })
+}
+
+fn empty_message(role: UnifiedMessageRole) -> UnifiedMessage {
+    UnifiedMessage {
+        role,
+        content: Vec::new(),
+        tool_calls: Vec::new(),
+        tool_call_id: None,
+        thinking: None,
+    }
+}
+
+/// 连续的 reasoning / assistant message / function_call 合并为同一条 assistant 消息，
+/// 以满足 Anthropic「tool_use 与 tool_result 成对相邻」的约束
+fn decode_input_items(items: &[Value], messages: &mut Vec<UnifiedMessage>) -> Result<(), String> {
+    let mut pending_assistant: Option<UnifiedMessage> = None;
+
+    for item in items {
+        let kind = item
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("message");
+        match kind {
+            "message" => {
+                let role = item
+                    .get("role")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| "input message role is required".to_string())?;
+                let content = decode_content(item.get("content"));
+                match role {
+                    "assistant" => {
+                        let assistant = pending_assistant
+                            .get_or_insert_with(|| empty_message(UnifiedMessageRole::Assistant));
+                        if !assistant.tool_calls.is_empty() {
+                            messages.push(pending_assistant.take().unwrap());
+                            pending_assistant = Some(UnifiedMessage {
+                                content,
+                                ..empty_message(UnifiedMessageRole::Assistant)
+                            });
+                        } else {
+                            assistant.content.extend(content);
+                        }
+                    }
+                    "user" | "system" | "developer" => {
+                        messages.extend(pending_assistant.take());
+                        if content.is_empty() {
+                            continue;
+                        }
+                        messages.push(UnifiedMessage {
+                            content,
+                            ..empty_message(if role == "user" {
+                                UnifiedMessageRole::User
+                            } else {
+                                UnifiedMessageRole::System
+                            })
+                        });
+                    }
+                    other => return Err(format!("unsupported input role '{}'", other)),
+                }
+            }
+            "reasoning" => {
+                let summary: Vec<&str> = item
+                    .get("summary")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|part| part.get("text").and_then(Value::as_str))
+                    .collect();
+                let signature = item
+                    .get("encrypted_content")
+                    .and_then(Value::as_str)
+                    .map(str::to_string);
+                if summary.is_empty() && signature.is_none() {
+                    continue;
+                }
+                if pending_assistant
+                    .as_ref()
+                    .map(|message| !message.tool_calls.is_empty() || !message.content.is_empty())
+                    .unwrap_or(false)
+                {
+                    messages.extend(pending_assistant.take());
+                }
+                let assistant = pending_assistant
+                    .get_or_insert_with(|| empty_message(UnifiedMessageRole::Assistant));
+                assistant.thinking = Some(UnifiedThinking {
+                    content: summary.join("\n\n"),
+                    signature,
+                });
+            }
+            "function_call" | "custom_tool_call" => {
+                let name = item
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| format!("{} name is required", kind))?;
+                let call_id = item
+                    .get("call_id")
+                    .or_else(|| item.get("id"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let arguments = if kind == "custom_tool_call" {
+                    json!({ "input": item.get("input").and_then(Value::as_str).unwrap_or("") })
+                        .to_string()
+                } else {
+                    match item.get("arguments") {
+                        Some(Value::String(text)) => text.clone(),
+                        Some(other) => other.to_string(),
+                        None => "{}".to_string(),
+                    }
+                };
+                pending_assistant
+                    .get_or_insert_with(|| empty_message(UnifiedMessageRole::Assistant))
+                    .tool_calls
+                    .push(UnifiedToolCall {
+                        id: call_id.to_string(),
+                        function: UnifiedFunctionCall {
+                            name: name.to_string(),
+                            arguments,
+                        },
+                    });
+            }
+            "function_call_output" | "custom_tool_call_output" => {
+                messages.extend(pending_assistant.take());
+                let output = match item.get("output") {
+                    Some(Value::String(text)) => text.clone(),
+                    Some(Value::Array(parts)) => parts
+                        .iter()
+                        .filter_map(|part| part.get("text").and_then(Value::as_str))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    Some(other) => other.to_string(),
+                    None => String::new(),
+                };
+                messages.push(UnifiedMessage {
+                    content: vec![UnifiedContent::Text { text: output }],
+                    tool_call_id: item
+                        .get("call_id")
+                        .and_then(Value::as_str)
+                        .map(str::to_string),
+                    ..empty_message(UnifiedMessageRole::Tool)
+                });
+            }
+            // web_search_call / local_shell_call 等内置工具项无法在非 Codex 上游重放，直接跳过
+            _ => {}
+        }
+    }
+
+    messages.extend(pending_assistant);
+    Ok(())
+}
+
+fn decode_content(content: Option<&Value>) -> Vec<UnifiedContent> {
+    match content {
+        Some(Value::String(text)) if !text.is_empty() => {
+            vec![UnifiedContent::Text { text: text.clone() }]
+        }
+        Some(Value::Array(parts)) => {
+            parts
+                .iter()
+                .filter_map(|part| match part.get("type").and_then(Value::as_str) {
+                    Some("input_text") | Some("output_text") | Some("text") => part
+                        .get("text")
+                        .and_then(Value::as_str)
+                        .filter(|text| !text.is_empty())
+                        .map(|text| UnifiedContent::Text {
+                            text: text.to_string(),
+                        }),
+                    Some("refusal") => part.get("refusal").and_then(Value::as_str).map(|text| {
+                        UnifiedContent::Text {
+                            text: text.to_string(),
+                        }
+                    }),
+                    Some("input_image") => {
+                        let url = part.get("image_url").and_then(Value::as_str)?;
+                        Some(UnifiedContent::ImageUrl {
+                            media_type: url
+                                .strip_prefix("data:")
+                                .and_then(|rest| rest.split([';', ',']).next())
+                                .filter(|media_type| !media_type.is_empty())
+                                .map(str::to_string),
+                            url: url.to_string(),
+                        })
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn decode_tools(tools: Option<&Value>) -> (Option<Vec<UnifiedTool>>, HashSet<String>) {
+    let mut custom_tools = HashSet::new();
+    let converted: Vec<UnifiedTool> = tools
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|tool| {
+            let name = tool.get("name").and_then(Value::as_str)?;
+            let description = tool
+                .get("description")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            match tool.get("type").and_then(Value::as_str) {
+                Some("function") => Some(UnifiedTool {
+                    function: UnifiedToolDefinition {
+                        name: name.to_string(),
+                        description: description.to_string(),
+                        parameters: tool
+                            .get("parameters")
+                            .cloned()
+                            .unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+                    },
+                }),
+                Some("custom") => {
+                    custom_tools.insert(name.to_string());
+                    let format = tool.get("format");
+                    let description = match format
+                        .and_then(|format| format.get("definition"))
+                        .and_then(Value::as_str)
+                    {
+                        Some(definition) => format!(
+                            "{}\n\nThe `input` string must follow this {} grammar:\n{}",
+                            description,
+                            format
+                                .and_then(|format| format.get("syntax"))
+                                .and_then(Value::as_str)
+                                .unwrap_or("lark"),
+                            definition
+                        ),
+                        None => description.to_string(),
+                    };
+                    Some(UnifiedTool {
+                        function: UnifiedToolDefinition {
+                            name: name.to_string(),
+                            description,
+                            parameters: json!({
+                                "type": "object",
+                                "properties": { "input": { "type": "string" } },
+                                "required": ["input"],
+                            }),
+                        },
+                    })
+                }
+                _ => None,
+            }
+        })
+        .collect();
+
+    let tools = if converted.is_empty() {
+        None
+    } else {
+        Some(converted)
+    };
+    (tools, custom_tools)
+}
+
+fn decode_tool_choice(choice: Option<&Value>) -> Option<UnifiedToolChoice> {
+    match choice? {
+        Value::String(kind) => match kind.as_str() {
+            "auto" => Some(UnifiedToolChoice::Auto),
+            "none" => Some(UnifiedToolChoice::None),
+            "required" => Some(UnifiedToolChoice::Required),
+            _ => None,
+        },
+        value => {
+            value
+                .get("name")
+                .and_then(Value::as_str)
+                .map(|name| UnifiedToolChoice::Function {
+                    name: name.to_string(),
+                })
+        }
+    }
+}
+
+fn encode_usage(usage: &Value) -> Value {
+    let field = |name: &str| usage.get(name).and_then(Value::as_u64).unwrap_or(0);
+    let cache_read = field("cache_read_input_tokens");
+    let input_tokens = field("input_tokens") + cache_read + field("cache_creation_input_tokens");
+    let output_tokens = field("output_tokens");
+    json!({
+        "input_tokens": input_tokens,
+        "input_tokens_details": { "cached_tokens": cache_read },
+        "output_tokens": output_tokens,
+        "output_tokens_details": { "reasoning_tokens": 0 },
+        "total_tokens": input_tokens + output_tokens,
+    })
+}
+
+fn new_item_id(prefix: &str) -> String {
+    format!("{}_{}", prefix, uuid::Uuid::new_v4().simple())
+}
+
+fn response_id(message_id: Option<&str>) -> String {
+    let raw = message_id.unwrap_or_default();
+    let suffix = raw.strip_prefix("msg_").unwrap_or(raw);
+    if suffix.is_empty() {
+        new_item_id("resp")
+    } else {
+        format!("resp_{}", suffix)
+    }
+}
+
+/// Anthropic stop_reason → (status, incomplete_details)
+fn response_status(stop_reason: Option<&str>) -> (&'static str, Value) {
+    match stop_reason {
+        Some("max_tokens") => ("incomplete", json!({ "reason": "max_output_tokens" })),
+        Some("refusal") => ("incomplete", json!({ "reason": "content_filter" })),
+        _ => ("completed", Value::Null),
+    }
+}
+
+fn message_item(id: &str, text: &str, status: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "message",
+        "role": "assistant",
+        "status": status,
+        "content": [{ "type": "output_text", "text": text, "annotations": [] }],
+    })
+}
+
+fn reasoning_item(id: &str, text: &str, signature: Option<&str>) -> Value {
+    let mut item = json!({
+        "id": id,
+        "type": "reasoning",
+        "summary": [{ "type": "summary_text", "text": text }],
+    });
+    if let Some(signature) = signature.filter(|value| !value.is_empty()) {
+        item["encrypted_content"] = json!(signature);
+    }
+    item
+}
+
+fn tool_call_item(
+    id: &str,
+    call_id: &str,
+    name: &str,
+    arguments: &str,
+    custom: bool,
+    status: &str,
+) -> Value {
+    if custom {
+        let input = serde_json::from_str::<Value>(arguments)
+            .ok()
+            .and_then(|value| {
+                value
+                    .get("input")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            })
+            .unwrap_or_default();
+        json!({
+            "id": id,
+            "type": "custom_tool_call",
+            "status": status,
+            "call_id": call_id,
+            "name": name,
+            "input": input,
+        })
+    } else {
+        json!({
+            "id": id,
+            "type": "function_call",
+            "status": status,
+            "call_id": call_id,
+            "name": name,
+            "arguments": arguments,
+        })
+    }
+}
+
+/// 将非流式 Anthropic message JSON 编码为 Responses API `response` 对象
+pub fn encode_response(message: &Value, model: &str, custom_tools: &HashSet<String>) -> Value {
+    let mut output = Vec::new();
+    for block in message
+        .get("content")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        match block.get("type").and_then(Value::as_str) {
+            Some("text") => output.push(message_item(
+                &new_item_id("msg"),
+                block.get("text").and_then(Value::as_str).unwrap_or(""),
+                "completed",
+            )),
+            Some("thinking") => output.push(reasoning_item(
+                &new_item_id("rs"),
+                block.get("thinking").and_then(Value::as_str).unwrap_or(""),
+                block.get("signature").and_then(Value::as_str),
+            )),
+            Some("tool_use") => {
+                let name = block
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let input = block.get("input").cloned().unwrap_or_else(|| json!({}));
+                output.push(tool_call_item(
+                    &new_item_id("fc"),
+                    block.get("id").and_then(Value::as_str).unwrap_or_default(),
+                    name,
+                    &input.to_string(),
+                    custom_tools.contains(name),
+                    "completed",
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    let (status, incomplete_details) =
+        response_status(message.get("stop_reason").and_then(Value::as_str));
+    let mut response = json!({
+        "id": response_id(message.get("id").and_then(Value::as_str)),
+        "object": "response",
+        "created_at": chrono::Utc::now().timestamp(),
+        "status": status,
+        "incomplete_details": incomplete_details,
+        "model": model,
+        "output": output,
+    });
+    if let Some(usage) = message.get("usage") {
+        response["usage"] = encode_usage(usage);
+    }
+    response
+}
+
+/// 将 Anthropic 错误体转换为 Responses API 错误格式（与 Chat Completions 一致）
+pub fn encode_response_error(error_body: &Value) -> Value {
+    encode_chat_completion_error(error_body)
+}
+
+enum OpenItemKind {
+    Text {
+        text: String,
+    },
+    Reasoning {
+        text: String,
+        signature: String,
+    },
+    ToolCall {
+        call_id: String,
+        name: String,
+        arguments: String,
+        custom: bool,
+    },
+}
+
+struct OpenItem {
+    output_index: usize,
+    item_id: String,
+    kind: OpenItemKind,
+}
+
+/// Anthropic SSE → Responses API SSE 的有状态编码器
+pub struct ResponsesStreamEncoder {
+    model: String,
+    custom_tools: HashSet<String>,
+    id: String,
+    created_at: i64,
+    buffer: AnthropicSseBuffer,
+    sequence_number: u64,
+    started: bool,
+    finished: bool,
+    /// Anthropic content block index → 进行中的 output item
+    open_items: HashMap<u64, OpenItem>,
+    output: Vec<(usize, Value)>,
+    next_output_index: usize,
+    usage: Map<String, Value>,
+    stop_reason: Option<String>,
+}
+
+impl ResponsesStreamEncoder {
+    pub fn new(model: &str, custom_tools: HashSet<String>) -> Self {
+        Self {
+            model: model.to_string(),
+            custom_tools,
+            id: response_id(None),
+            created_at: chrono::Utc::now().timestamp(),
+            buffer: AnthropicSseBuffer::default(),
+            sequence_number: 0,
+            started: false,
+            finished: false,
+            open_items: HashMap::new(),
+            output: Vec::new(),
+            next_output_index: 0,
+            usage: Map::new(),
+            stop_reason: None,
+        }
+    }
+
+    fn event(&mut self, kind: &str, mut payload: Value) -> String {
+        payload["type"] = json!(kind);
+        payload["sequence_number"] = json!(self.sequence_number);
+        self.sequence_number += 1;
+        format!("event: {}\ndata: {}\n\n", kind, payload)
+    }
+
+    fn response_object(&self, status: &str) -> Value {
+        let mut output = self.output.clone();
+        output.sort_by_key(|(index, _)| *index);
+        json!({
+            "id": self.id,
+            "object": "response",
+            "created_at": self.created_at,
+            "status": status,
+            "model": self.model,
+            "output": output.into_iter().map(|(_, item)| item).collect::<Vec<_>>(),
+        })
+    }
+
+    fn ensure_started(&mut self) -> Vec<String> {
+        if self.started {
+            return Vec::new();
+        }
+        self.started = true;
+        let response = self.response_object("in_progress");
+        vec![
+            self.event("response.created", json!({ "response": response })),
+            self.event("response.in_progress", json!({ "response": response })),
+        ]
+    }
+
+    fn open_item(&mut self, block_index: u64, block: &Value) -> Vec<String> {
+        let output_index = self.next_output_index;
+        let (item_id, kind, item) = match block.get("type").and_then(Value::as_str) {
+            Some("text") => {
+                let item_id = new_item_id("msg");
+                let mut item = message_item(&item_id, "", "in_progress");
+                item["content"] = json!([]);
+                (
+                    item_id,
+                    OpenItemKind::Text {
+                        text: String::new(),
+                    },
+                    item,
+                )
+            }
+            Some("thinking") => {
+                let item_id = new_item_id("rs");
+                let mut item = reasoning_item(&item_id, "", None);
+                item["summary"] = json!([]);
+                (
+                    item_id,
+                    OpenItemKind::Reasoning {
+                        text: String::new(),
+                        signature: String::new(),
+                    },
+                    item,
+                )
+            }
+            Some("tool_use") => {
+                let name = block
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let call_id = block.get("id").and_then(Value::as_str).unwrap_or_default();
+                let custom = self.custom_tools.contains(name);
+                let item_id = new_item_id(if custom { "ctc" } else { "fc" });
+                let item = tool_call_item(&item_id, call_id, name, "", custom, "in_progress");
+                (
+                    item_id,
+                    OpenItemKind::ToolCall {
+                        call_id: call_id.to_string(),
+                        name: name.to_string(),
+                        arguments: String::new(),
+                        custom,
+                    },
+                    item,
+                )
+            }
+            _ => return Vec::new(),
+        };
+        self.next_output_index += 1;
+
+        let mut frames = vec![self.event(
+            "response.output_item.added",
+            json!({ "output_index": output_index, "item": item }),
+        )];
+        match kind {
+            OpenItemKind::Text { .. } => frames.push(self.event(
+                "response.content_part.added",
+                json!({
+                    "item_id": item_id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": { "type": "output_text", "text": "", "annotations": [] },
+                }),
+            )),
+            OpenItemKind::Reasoning { .. } => frames.push(self.event(
+                "response.reasoning_summary_part.added",
+                json!({
+                    "item_id": item_id,
+                    "output_index": output_index,
+                    "summary_index": 0,
+                    "part": { "type": "summary_text", "text": "" },
+                }),
+            )),
+            OpenItemKind::ToolCall { .. } => {}
+        }
+        self.open_items.insert(
+            block_index,
+            OpenItem {
+                output_index,
+                item_id,
+                kind,
+            },
+        );
+        frames
+    }
+
+    fn apply_delta(&mut self, block_index: u64, delta: &Value) -> Vec<String> {
+        let Some(open) = self.open_items.get_mut(&block_index) else {
+            return Vec::new();
+        };
+        let item_id = open.item_id.clone();
+        let output_index = open.output_index;
+        let (kind, payload) = match (&mut open.kind, delta.get("type").and_then(Value::as_str)) {
+            (OpenItemKind::Text { text }, Some("text_delta")) => {
+                let piece = delta.get("text").and_then(Value::as_str).unwrap_or("");
+                text.push_str(piece);
+                (
+                    "response.output_text.delta",
+                    json!({ "item_id": item_id, "output_index": output_index, "content_index": 0, "delta": piece }),
+                )
+            }
+            (OpenItemKind::Reasoning { text, .. }, Some("thinking_delta")) => {
+                let piece = delta.get("thinking").and_then(Value::as_str).unwrap_or("");
+                text.push_str(piece);
+                (
+                    "response.reasoning_summary_text.delta",
+                    json!({ "item_id": item_id, "output_index": output_index, "summary_index": 0, "delta": piece }),
+                )
+            }
+            (OpenItemKind::Reasoning { signature, .. }, Some("signature_delta")) => {
+                signature.push_str(delta.get("signature").and_then(Value::as_str).unwrap_or(""));
+                return Vec::new();
+            }
+            (
+                OpenItemKind::ToolCall {
+                    arguments, custom, ..
+                },
+                Some("input_json_delta"),
+            ) => {
+                let piece = delta
+                    .get("partial_json")
+                    .and_then(Value::as_str)
+                    .unwrap_or("");
+                arguments.push_str(piece);
+                // custom 工具的 input 需要完整 JSON 才能还原，只在 output_item.done 时一次性给出
+                if *custom {
+                    return Vec::new();
+                }
+                (
+                    "response.function_call_arguments.delta",
+                    json!({ "item_id": item_id, "output_index": output_index, "delta": piece }),
+                )
+            }
+            _ => return Vec::new(),
+        };
+        vec![self.event(kind, payload)]
+    }
+
+    fn close_item(&mut self, block_index: u64) -> Vec<String> {
+        let Some(open) = self.open_items.remove(&block_index) else {
+            return Vec::new();
+        };
+        let item_id = open.item_id;
+        let output_index = open.output_index;
+        let mut frames = Vec::new();
+        let item = match open.kind {
+            OpenItemKind::Text { text } => {
+                frames.push(self.event(
+                    "response.output_text.done",
+                    json!({ "item_id": item_id, "output_index": output_index, "content_index": 0, "text": text }),
+                ));
+                frames.push(self.event(
+                    "response.content_part.done",
+                    json!({
+                        "item_id": item_id,
+                        "output_index": output_index,
+                        "content_index": 0,
+                        "part": { "type": "output_text", "text": text, "annotations": [] },
+                    }),
+                ));
+                message_item(&item_id, &text, "completed")
+            }
+            OpenItemKind::Reasoning { text, signature } => {
+                frames.push(self.event(
+                    "response.reasoning_summary_text.done",
+                    json!({ "item_id": item_id, "output_index": output_index, "summary_index": 0, "text": text }),
+                ));
+                frames.push(self.event(
+                    "response.reasoning_summary_part.done",
+                    json!({
+                        "item_id": item_id,
+                        "output_index": output_index,
+                        "summary_index": 0,
+                        "part": { "type": "summary_text", "text": text },
+                    }),
+                ));
+                reasoning_item(&item_id, &text, Some(&signature))
+            }
+            OpenItemKind::ToolCall {
+                call_id,
+                name,
+                arguments,
+                custom,
+            } => {
+                let arguments = if arguments.trim().is_empty() {
+                    "{}".to_string()
+                } else {
+                    arguments
+                };
+                if !custom {
+                    frames.push(self.event(
+                        "response.function_call_arguments.done",
+                        json!({ "item_id": item_id, "output_index": output_index, "arguments": arguments }),
+                    ));
+                }
+                tool_call_item(&item_id, &call_id, &name, &arguments, custom, "completed")
+            }
+        };
+        frames.push(self.event(
+            "response.output_item.done",
+            json!({ "output_index": output_index, "item": item }),
+        ));
+        self.output.push((output_index, item));
+        frames
+    }
+
+    fn emit_finish(&mut self) -> Vec<String> {
+        if self.finished {
+            return Vec::new();
+        }
+        let mut frames = self.ensure_started();
+        self.finished = true;
+        let mut open_indices: Vec<u64> = self.open_items.keys().copied().collect();
+        open_indices.sort_unstable();
+        for block_index in open_indices {
+            frames.extend(self.close_item(block_index));
+        }
+
+        let (status, incomplete_details) = response_status(self.stop_reason.as_deref());
+        let mut response = self.response_object(status);
+        response["incomplete_details"] = incomplete_details;
+        response["usage"] = encode_usage(&Value::Object(self.usage.clone()));
+        let kind = if status == "completed" {
+            "response.completed"
+        } else {
+            "response.incomplete"
+        };
+        frames.push(self.event(kind, json!({ "response": response })));
+        frames
+    }
+
+    fn merge_usage(&mut self, usage: Option<&Value>) {
+        if let Some(Value::Object(fields)) = usage {
+            for (key, value) in fields {
+                if !value.is_null() {
+                    self.usage.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    fn encode_event(&mut self, payload: &Value) -> Vec<String> {
+        if self.finished {
+            return Vec::new();
+        }
+        let kind = payload
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let block_index = payload.get("index").and_then(Value::as_u64).unwrap_or(0);
+
+        match kind {
+            "message_start" => {
+                let message = payload.get("message");
+                if !self.started {
+                    if let Some(id) = message.and_then(|m| m.get("id")).and_then(Value::as_str) {
+                        self.id = response_id(Some(id));
+                    }
+                }
+                self.merge_usage(message.and_then(|m| m.get("usage")));
+                self.ensure_started()
+            }
+            "content_block_start" => {
+                let mut frames = self.ensure_started();
+                let block = payload.get("content_block").cloned().unwrap_or_default();
+                frames.extend(self.open_item(block_index, &block));
+                frames
+            }
+            "content_block_delta" => match payload.get("delta") {
+                Some(delta) => self.apply_delta(block_index, delta),
+                None => Vec::new(),
+            },
+            "content_block_stop" => self.close_item(block_index),
+            "message_delta" => {
+                if let Some(reason) = payload
+                    .get("delta")
+                    .and_then(|delta| delta.get("stop_reason"))
+                    .and_then(Value::as_str)
+                {
+                    self.stop_reason = Some(reason.to_string());
+                }
+                self.merge_usage(payload.get("usage"));
+                Vec::new()
+            }
+            "message_stop" => self.emit_finish(),
+            "error" => {
+                let mut frames = self.ensure_started();
+                self.finished = true;
+                let error = encode_response_error(payload);
+                let mut response = self.response_object("failed");
+                response["error"] = json!({
+                    "code": error["error"]["code"],
+                    "message": error["error"]["message"],
+                });
+                frames.push(self.event("response.failed", json!({ "response": response })));
+                frames
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl InboundStreamEncoder for ResponsesStreamEncoder {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        let events = self.buffer.push(chunk);
+        events
+            .iter()
+            .flat_map(|event| self.encode_event(event))
+            .collect()
+    }
+
+    /// 补齐未关闭的 output item 与 response.completed
+    fn finish(&mut self) -> Vec<String> {
+        let mut output = Vec::new();
+        if let Some(event) = self.buffer.flush() {
+            output.extend(self.encode_event(&event));
+        }
+        output.extend(self.emit_finish());
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AnthropicRequest;
+
+    fn parse_events(frames: &[String]) -> Vec<Value> {
+        frames
+            .iter()
+            .filter_map(|frame| frame.lines().find_map(|line| line.strip_prefix("data: ")))
+            .map(|data| serde_json::from_str(data).expect("event json"))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_codex_input_items_into_paired_tool_turns() {
+        let body = json!({
+            "model": "gpt-5-codex",
+            "instructions": "You are Codex.",
+            "stream": true,
+            "reasoning": { "effort": "high", "summary": "auto" },
+            "input": [
+                { "type": "message", "role": "developer", "content": [{ "type": "input_text", "text": "sandbox: workspace-write" }] },
+                { "type": "message", "role": "user", "content": [{ "type": "input_text", "text": "list files" }] },
+                { "type": "reasoning", "summary": [{ "type": "summary_text", "text": "need ls" }], "encrypted_content": "sig-1" },
+                { "type": "function_call", "call_id": "call_1", "name": "shell", "arguments": "{\"command\":[\"ls\"]}" },
+                { "type": "custom_tool_call", "call_id": "call_2", "name": "apply_patch", "input": "*** Begin Patch" },
+                { "type": "function_call_output", "call_id": "call_1", "output": "a.rs" },
+                { "type": "custom_tool_call_output", "call_id": "call_2", "output": "Done" },
+                { "type": "message", "role": "assistant", "content": [{ "type": "output_text", "text": "a.rs" }] }
+            ],
+            "tools": [
+                { "type": "function", "name": "shell", "description": "Run", "parameters": { "type": "object" } },
+                { "type": "custom", "name": "apply_patch", "description": "Patch", "format": { "type": "grammar", "syntax": "lark", "definition": "start: x" } },
+                { "type": "web_search" }
+            ],
+            "tool_choice": "auto"
+        });
+
+        let decoded = decode_responses_request(&body).expect("decode");
+        assert!(decoded.custom_tools.contains("apply_patch"));
+        let tools = decoded.unified.tools.as_ref().expect("tools");
+        assert_eq!(tools.len(), 2);
+        assert!(tools[1].function.description.contains("start: x"));
+        assert_eq!(
+            decoded
+                .unified
+                .reasoning
+                .as_ref()
+                .and_then(|r| r.effort.as_deref()),
+            Some("high")
+        );
+
+        let anthropic_body = decoded.to_anthropic_body();
+        assert!(anthropic_body["system"]
+            .as_str()
+            .unwrap()
+            .contains("sandbox: workspace-write"));
+        let messages = anthropic_body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 5);
+        let assistant = &messages[1]["content"];
+        assert_eq!(assistant[0]["type"], "thinking");
+        assert_eq!(assistant[0]["signature"], "sig-1");
+        assert_eq!(assistant[1]["input"]["command"][0], "ls");
+        assert_eq!(assistant[2]["input"]["input"], "*** Begin Patch");
+        assert_eq!(messages[2]["content"][0]["tool_use_id"], "call_1");
+        assert_eq!(messages[3]["content"][0]["tool_use_id"], "call_2");
+        assert_eq!(messages[4]["role"], "assistant");
+
+        let request: AnthropicRequest =
+            serde_json::from_value(anthropic_body).expect("anthropic request");
+        assert!(request.stream);
+    }
+
+    #[test]
+    fn rejects_request_without_input() {
+        let err = decode_responses_request(&json!({ "model": "x" })).unwrap_err();
+        assert!(err.contains("input"));
+    }
+
+    #[test]
+    fn stream_encoder_emits_responses_events() {
+        let mut custom_tools = HashSet::new();
+        custom_tools.insert("apply_patch".to_string());
+        let mut encoder = ResponsesStreamEncoder::new("gpt-5-codex", custom_tools);
+        let input = concat!(
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_abc\",\"usage\":{\"input_tokens\":10}}}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"thinking\",\"thinking\":\"\"}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"plan\"}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"signature_delta\",\"signature\":\"sig\"}}\n\n",
+            "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n",
+            "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":1}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":2,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"shell\",\"input\":{}}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":2,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"command\\\":[]}\"}}\n\n",
+            "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":2}\n\n",
+            "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":3,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_2\",\"name\":\"apply_patch\",\"input\":{}}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":3,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"input\\\":\\\"*** Begin Patch\\\"}\"}}\n\n",
+            "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":3}\n\n",
+            "event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":7}}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        );
+
+        let (head, tail) = input.split_at(131);
+        let mut frames = encoder.push(head.as_bytes());
+        frames.extend(encoder.push(tail.as_bytes()));
+        frames.extend(encoder.finish());
+
+        let events = parse_events(&frames);
+        let kinds: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+        assert_eq!(kinds[0], "response.created");
+        assert_eq!(kinds[1], "response.in_progress");
+        assert!(kinds.contains(&"response.reasoning_summary_text.delta"));
+        assert!(kinds.contains(&"response.output_text.delta"));
+        assert!(kinds.contains(&"response.function_call_arguments.delta"));
+        assert_eq!(
+            kinds.iter().filter(|k| **k == "response.completed").count(),
+            1
+        );
+        assert_eq!(kinds.last(), Some(&"response.completed"));
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[0]["sequence_number"].as_u64() < pair[1]["sequence_number"].as_u64()));
+
+        let completed = events.last().unwrap();
+        assert_eq!(completed["response"]["id"], "resp_abc");
+        let output = completed["response"]["output"].as_array().unwrap();
+        assert_eq!(output.len(), 4);
+        assert_eq!(output[0]["type"], "reasoning");
+        assert_eq!(output[0]["encrypted_content"], "sig");
+        assert_eq!(output[1]["content"][0]["text"], "Hi");
+        assert_eq!(output[2]["type"], "function_call");
+        assert_eq!(output[2]["call_id"], "toolu_1");
+        assert_eq!(output[2]["arguments"], "{\"command\":[]}");
+        assert_eq!(output[3]["type"], "custom_tool_call");
+        assert_eq!(output[3]["input"], "*** Begin Patch");
+        assert_eq!(completed["response"]["usage"]["total_tokens"], 17);
+    }
+
+    #[test]
+    fn encodes_non_stream_response_and_max_tokens_as_incomplete() {
+        let message = json!({
+            "id": "msg_1",
+            "content": [
+                { "type": "text", "text": "partial" },
+                { "type": "tool_use", "id": "toolu_9", "name": "shell", "input": { "command": ["ls"] } }
+            ],
+            "stop_reason": "max_tokens",
+            "usage": { "input_tokens": 3, "output_tokens": 5 }
+        });
+
+        let response = encode_response(&message, "gpt-5-codex", &HashSet::new());
+        assert_eq!(response["object"], "response");
+        assert_eq!(response["status"], "incomplete");
+        assert_eq!(
+            response["incomplete_details"]["reason"],
+            "max_output_tokens"
+        );
+        assert_eq!(response["output"][0]["content"][0]["text"], "partial");
+        assert_eq!(response["output"][1]["call_id"], "toolu_9");
+        assert_eq!(response["usage"]["total_tokens"], 8);
+    }
+}
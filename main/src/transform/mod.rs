@@ -1,6 +1,7 @@
 pub mod anthropic;
 pub mod codex;
 pub mod gemini;
+pub mod inbound;
 pub mod openai;
 pub(crate) mod processor;
 pub mod providers;
@@ -76,8 +77,16 @@ pub struct CanonicalToolResult {
 
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum ClaudeCodeRequestKind {
+    /// 会话标题 / 话题判断
     SessionTitle,
+    /// 主对话轮次
     ConversationTurn,
+    /// Task / Agent 工具拉起的子代理
+    Subagent,
+    /// 上下文压缩（/compact 与自动压缩）
+    Compaction,
+    /// 启动时的连通性 / 配额探测
+    Probe,
     #[default]
     Unknown,
 }
@@ -87,9 +96,20 @@ impl ClaudeCodeRequestKind {
         match self {
             Self::SessionTitle => "session_title",
             Self::ConversationTurn => "conversation_turn",
+            Self::Subagent => "subagent",
+            Self::Compaction => "compaction",
+            Self::Probe => "probe",
             Self::Unknown => "unknown",
         }
     }
+
+    /// 是否为携带完整对话上下文的轮次（主对话、子代理与压缩）
+    pub const fn is_conversation(self) -> bool {
+        matches!(
+            self,
+            Self::ConversationTurn | Self::Subagent | Self::Compaction
+        )
+    }
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
@@ -115,10 +135,66 @@ fn classify_claude_code_request_kind(request: &AnthropicRequest) -> ClaudeCodeRe
         .unwrap_or_default();
     let has_title_prompt = system_text.contains("Generate a concise, sentence-case title")
         && system_text.contains("Return JSON with a single \"title\" field.");
-    if has_title_prompt {
-        ClaudeCodeRequestKind::SessionTitle
-    } else {
-        ClaudeCodeRequestKind::ConversationTurn
+    let has_topic_prompt =
+        system_text.contains("Analyze if this message indicates a new conversation topic");
+    if has_title_prompt || has_topic_prompt {
+        return ClaudeCodeRequestKind::SessionTitle;
+    }
+    if is_probe_request(request) {
+        return ClaudeCodeRequestKind::Probe;
+    }
+    if system_text.contains("tasked with summarizing conversations")
+        || last_user_text(request)
+            .is_some_and(|text| text.contains("create a detailed summary of the conversation"))
+    {
+        return ClaudeCodeRequestKind::Compaction;
+    }
+    if system_text.contains("You are an agent for Claude Code") {
+        return ClaudeCodeRequestKind::Subagent;
+    }
+    ClaudeCodeRequestKind::ConversationTurn
+}
+
+/// `max_tokens=1`、没有工具与系统提示词、只有一条 `foo` / `count` 用户消息的请求
+fn is_probe_request(request: &AnthropicRequest) -> bool {
+    if request.messages.len() != 1 || request.max_tokens != Some(1) {
+        return false;
+    }
+    let has_tools = request
+        .tools
+        .as_ref()
+        .is_some_and(|tools| !tools.is_empty());
+    let has_system = request
+        .system
+        .as_ref()
+        .is_some_and(|system| !system.to_string().trim().is_empty());
+    if has_tools || has_system {
+        return false;
+    }
+    last_user_text(request).is_some_and(|text| {
+        let text = text.trim();
+        text.eq_ignore_ascii_case("foo") || text.eq_ignore_ascii_case("count")
+    })
+}
+
+fn last_user_text(request: &AnthropicRequest) -> Option<String> {
+    let message = request
+        .messages
+        .iter()
+        .rev()
+        .find(|message| message.role.eq_ignore_ascii_case("user"))?;
+    match message.content.as_ref()? {
+        MessageContent::Text(text) => Some(text.clone()),
+        MessageContent::Blocks(blocks) => Some(
+            blocks
+                .iter()
+                .filter_map(|block| match block {
+                    ContentBlock::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
     }
 }
 
@@ -233,7 +233,7 @@ impl GeminiAdapter {
     }
 }
 
-fn encode_anthropic_body(unified: &UnifiedChatRequest, route_model: &str) -> Value {
+pub(crate) fn encode_anthropic_body(unified: &UnifiedChatRequest, route_model: &str) -> Value {
     let system = system_text(unified);
     let messages: Vec<Value> = unified
         .messages
@@ -1334,6 +1334,9 @@ fn prompt_cache_request_kind_segment(hints: &RequestEnvelopeHints) -> &'static s
     match hints.request_kind {
         super::ClaudeCodeRequestKind::SessionTitle => "st",
         super::ClaudeCodeRequestKind::ConversationTurn => "ct",
+        super::ClaudeCodeRequestKind::Subagent => "sa",
+        super::ClaudeCodeRequestKind::Compaction => "cp",
+        super::ClaudeCodeRequestKind::Probe => "pb",
         super::ClaudeCodeRequestKind::Unknown => "uk",
     }
 }
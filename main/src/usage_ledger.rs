@@ -0,0 +1,399 @@
+//! 本地 token 用量账本：每个完成的请求追加一行 JSON，按日期 / 端点 / 模型 / 会话等维度汇总
+//!
+//! 账本是纯追加的 JSONL 文件（默认 `~/.codexProxy/usage/usage-ledger.jsonl`），
+//! 写入与 `AppLogger` 一样在锁内同步追加；查询时整文件读取并跳过损坏的行。
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// 账本文件名
+const LEDGER_FILE_NAME: &str = "usage-ledger.jsonl";
+
+/// 一个已完成请求的用量记录
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// 本地时间的 RFC 3339 时间戳
+    pub ts: String,
+    pub request_id: String,
+    /// 客户端名；未配置客户端令牌时为 `-`
+    pub client: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Claude Code 请求类型（`conversation_turn` / `subagent` / `session_title` ...）
+    pub kind: String,
+    /// 入口路由（`claude` / `codex`）
+    pub route: String,
+    pub slot: String,
+    pub endpoint: String,
+    pub converter: String,
+    /// 上游模型
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cached_input_tokens: u64,
+    /// 包含在 `output_tokens` 中的推理 token
+    #[serde(default)]
+    pub reasoning_tokens: u64,
+    /// 按价目表折算的费用（美元）；模型未配置单价时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+    pub streaming: bool,
+}
+
+impl UsageRecord {
+    /// 记录所在日期（`YYYY-MM-DD`）
+    pub fn day(&self) -> &str {
+        self.ts.get(..10).unwrap_or(&self.ts)
+    }
+}
+
+/// 汇总维度
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UsageGroupBy {
+    #[default]
+    Day,
+    Endpoint,
+    Model,
+    Session,
+    Client,
+    Kind,
+    Slot,
+}
+
+impl UsageGroupBy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "day" | "date" => Some(Self::Day),
+            "endpoint" => Some(Self::Endpoint),
+            "model" => Some(Self::Model),
+            "session" => Some(Self::Session),
+            "client" => Some(Self::Client),
+            "kind" | "request_kind" => Some(Self::Kind),
+            "slot" => Some(Self::Slot),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Endpoint => "endpoint",
+            Self::Model => "model",
+            Self::Session => "session",
+            Self::Client => "client",
+            Self::Kind => "kind",
+            Self::Slot => "slot",
+        }
+    }
+
+    fn key<'a>(&self, record: &'a UsageRecord) -> &'a str {
+        match self {
+            Self::Day => record.day(),
+            Self::Endpoint => &record.endpoint,
+            Self::Model => &record.model,
+            Self::Session => record.session.as_deref().unwrap_or("-"),
+            Self::Client => &record.client,
+            Self::Kind => &record.kind,
+            Self::Slot => &record.slot,
+        }
+    }
+}
+
+/// 查询条件；日期均为 `YYYY-MM-DD` 且包含边界
+#[derive(Debug, Clone, Default)]
+pub struct UsageQuery {
+    pub group_by: UsageGroupBy,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub client: Option<String>,
+    pub session: Option<String>,
+    /// 最多返回的分组数；0 表示不限
+    pub limit: usize,
+}
+
+impl UsageQuery {
+    fn matches(&self, record: &UsageRecord) -> bool {
+        let day = record.day();
+        self.since.as_deref().is_none_or(|since| day >= since)
+            && self.until.as_deref().is_none_or(|until| day <= until)
+            && self
+                .client
+                .as_deref()
+                .is_none_or(|client| record.client == client)
+            && self
+                .session
+                .as_deref()
+                .is_none_or(|session| record.session.as_deref() == Some(session))
+    }
+}
+
+/// 一个分组的累计用量
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cached_input_tokens: u64,
+    pub reasoning_tokens: u64,
+    pub cost_usd: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, record: &UsageRecord) {
+        self.requests += 1;
+        self.input_tokens += record.input_tokens;
+        self.output_tokens += record.output_tokens;
+        self.cached_input_tokens += record.cached_input_tokens;
+        self.reasoning_tokens += record.reasoning_tokens;
+        self.cost_usd += record.cost_usd.unwrap_or(0.0);
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageBucket {
+    pub key: String,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageReport {
+    pub group_by: UsageGroupBy,
+    pub buckets: Vec<UsageBucket>,
+    pub total: UsageTotals,
+}
+
+impl UsageReport {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "group_by": self.group_by.as_str(),
+            "total": self.total,
+            "buckets": self.buckets,
+        })
+    }
+}
+
+/// 按查询条件汇总：按日期分组时按日期升序，其余按 token 总量降序
+pub fn aggregate<I>(records: I, query: &UsageQuery) -> UsageReport
+where
+    I: IntoIterator<Item = UsageRecord>,
+{
+    let mut total = UsageTotals::default();
+    let mut groups: HashMap<String, UsageTotals> = HashMap::new();
+    for record in records {
+        if !query.matches(&record) {
+            continue;
+        }
+        total.add(&record);
+        groups
+            .entry(query.group_by.key(&record).to_string())
+            .or_default()
+            .add(&record);
+    }
+
+    let mut buckets: Vec<UsageBucket> = groups
+        .into_iter()
+        .map(|(key, totals)| UsageBucket { key, totals })
+        .collect();
+    if query.group_by == UsageGroupBy::Day {
+        buckets.sort_by(|a, b| a.key.cmp(&b.key));
+    } else {
+        buckets.sort_by(|a, b| {
+            b.totals
+                .total_tokens()
+                .cmp(&a.totals.total_tokens())
+                .then_with(|| a.key.cmp(&b.key))
+        });
+    }
+    if query.limit > 0 {
+        buckets.truncate(query.limit);
+    }
+
+    UsageReport {
+        group_by: query.group_by,
+        buckets,
+        total,
+    }
+}
+
+/// 追加写入的用量账本
+pub struct UsageLedger {
+    path: PathBuf,
+    write_lock: Mutex<()>,
+}
+
+impl UsageLedger {
+    /// 默认账本路径：`~/.codexProxy/usage/usage-ledger.jsonl`
+    pub fn default_path() -> PathBuf {
+        let base = std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(PathBuf::from)
+            .unwrap_or_default();
+        base.join(".codexProxy")
+            .join("usage")
+            .join(LEDGER_FILE_NAME)
+    }
+
+    /// 打开（必要时创建）账本所在目录
+    pub fn open(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Self {
+            path,
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, record: &UsageRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let _guard = self
+            .write_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+
+    /// 读出全部记录；文件不存在时为空
+    pub fn records(&self) -> std::io::Result<Vec<UsageRecord>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if let Ok(record) = serde_json::from_str::<UsageRecord>(&line) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    pub fn query(&self, query: &UsageQuery) -> std::io::Result<UsageReport> {
+        Ok(aggregate(self.records()?, query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(ts: &str, session: &str, endpoint: &str, input: u64, output: u64) -> UsageRecord {
+        UsageRecord {
+            ts: ts.to_string(),
+            request_id: "r".to_string(),
+            client: "alice".to_string(),
+            session: Some(session.to_string()),
+            kind: "conversation_turn".to_string(),
+            route: "claude".to_string(),
+            slot: "opus".to_string(),
+            endpoint: endpoint.to_string(),
+            converter: "codex".to_string(),
+            model: "gpt-5-codex".to_string(),
+            input_tokens: input,
+            output_tokens: output,
+            cached_input_tokens: input / 2,
+            reasoning_tokens: output / 2,
+            cost_usd: Some(input as f64 / 1000.0),
+            streaming: true,
+        }
+    }
+
+    #[test]
+    fn aggregates_by_day_and_filters_by_date_range() {
+        let records = vec![
+            record("2026-10-01T10:00:00+08:00", "s1", "ep-a", 100, 10),
+            record("2026-10-01T11:00:00+08:00", "s2", "ep-b", 200, 20),
+            record("2026-10-02T09:00:00+08:00", "s1", "ep-a", 300, 30),
+            record("2026-10-03T09:00:00+08:00", "s1", "ep-a", 400, 40),
+        ];
+        let report = aggregate(
+            records,
+            &UsageQuery {
+                since: Some("2026-10-01".to_string()),
+                until: Some("2026-10-02".to_string()),
+                ..UsageQuery::default()
+            },
+        );
+        let days: Vec<_> = report.buckets.iter().map(|b| b.key.as_str()).collect();
+        assert_eq!(days, ["2026-10-01", "2026-10-02"]);
+        assert_eq!(report.buckets[0].totals.requests, 2);
+        assert_eq!(report.buckets[0].totals.input_tokens, 300);
+        assert_eq!(report.total.output_tokens, 60);
+        assert_eq!(report.total.cached_input_tokens, 300);
+        assert_eq!(report.total.reasoning_tokens, 30);
+        assert!((report.buckets[0].totals.cost_usd - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn non_day_groups_sort_by_total_tokens_and_respect_limit() {
+        let records = vec![
+            record("2026-10-01T10:00:00+08:00", "s1", "ep-a", 100, 10),
+            record("2026-10-01T11:00:00+08:00", "s2", "ep-b", 500, 50),
+            record("2026-10-02T09:00:00+08:00", "s1", "ep-a", 100, 10),
+        ];
+        let report = aggregate(
+            records,
+            &UsageQuery {
+                group_by: UsageGroupBy::Session,
+                limit: 1,
+                ..UsageQuery::default()
+            },
+        );
+        assert_eq!(report.buckets.len(), 1);
+        assert_eq!(report.buckets[0].key, "s2");
+        assert_eq!(report.total.requests, 3);
+    }
+
+    #[test]
+    fn ledger_appends_and_reads_back_skipping_corrupt_lines() {
+        let dir = std::env::temp_dir().join(format!("usage-ledger-{}", uuid::Uuid::new_v4()));
+        let ledger = UsageLedger::open(dir.join(LEDGER_FILE_NAME)).expect("open ledger");
+        assert!(ledger.records().expect("empty ledger").is_empty());
+
+        ledger
+            .append(&record("2026-10-01T10:00:00+08:00", "s1", "ep-a", 100, 10))
+            .expect("append");
+        OpenOptions::new()
+            .append(true)
+            .open(ledger.path())
+            .and_then(|mut file| file.write_all(b"{not json\n"))
+            .expect("write corrupt line");
+        ledger
+            .append(&record("2026-10-01T11:00:00+08:00", "s2", "ep-b", 200, 20))
+            .expect("append");
+
+        let report = ledger
+            .query(&UsageQuery {
+                group_by: UsageGroupBy::Endpoint,
+                ..UsageQuery::default()
+            })
+            .expect("query");
+        assert_eq!(report.total.requests, 2);
+        assert_eq!(report.buckets[0].key, "ep-b");
+        let _ = fs::remove_dir_all(dir);
+    }
+}
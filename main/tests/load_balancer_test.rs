@@ -1,9 +1,16 @@
+use codex_proxy_core::budget::{BudgetTracker, TokenUsage};
+use codex_proxy_core::config::{BudgetLimits, BudgetsConfig, ModelPriceConfig};
 use codex_proxy_core::load_balancer::*;
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::thread::sleep;
 use std::time::Duration;
 
 fn create_test_runtime() -> LoadBalancerRuntime {
+    create_test_runtime_with_named_routes(Vec::new())
+}
+
+fn create_test_runtime_with_named_routes(named_routes: Vec<NamedRoute>) -> LoadBalancerRuntime {
     let endpoint_directory: HashMap<String, LoadBalancerEndpoint> = [
         (
             "ep-1".to_string(),
@@ -12,6 +19,8 @@ fn create_test_runtime() -> LoadBalancerRuntime {
                 target_url: "https://api1.example.com".to_string(),
                 api_key: Some("key1".to_string()),
                 converter: "codex".to_string(),
+                api_keys: Vec::new(),
+                key_rotation: KeyRotation::default(),
             },
         ),
         (
@@ -21,6 +30,8 @@ fn create_test_runtime() -> LoadBalancerRuntime {
                 target_url: "https://api2.example.com".to_string(),
                 api_key: Some("key2".to_string()),
                 converter: "gemini".to_string(),
+                api_keys: Vec::new(),
+                key_rotation: KeyRotation::default(),
             },
         ),
     ]
@@ -58,6 +69,11 @@ fn create_test_runtime() -> LoadBalancerRuntime {
                 converter_override: None,
             }],
         },
+        selection: SelectionStrategy::Priority,
+        session_affinity_ttl_seconds: 0,
+        hedge_after_ms: 0,
+        degradation_chain: Vec::new(),
+        degradation_header: false,
     }];
 
     let endpoint_policies: HashMap<String, EndpointPolicy> = [(
@@ -70,6 +86,10 @@ fn create_test_runtime() -> LoadBalancerRuntime {
             cooldown_seconds: 5,
             degraded_concurrency: 1,
             transient_backoff_seconds: 1,
+            priority: 0,
+            weight: 1,
+            latency_threshold_ms: 0,
+            probe_interval_seconds: 0,
         },
     )]
     .into_iter()
@@ -80,6 +100,7 @@ fn create_test_runtime() -> LoadBalancerRuntime {
             selected_profile_id: Some("profile-1".to_string()),
             profiles,
             endpoint_policies,
+            named_routes,
         },
         endpoint_directory,
         None,
@@ -144,6 +165,33 @@ fn test_error_threshold_to_constrained() {
     drop(permit);
 }
 
+#[test]
+fn test_slot_status_reports_cooldown_and_in_flight_without_acquiring() {
+    let runtime = create_test_runtime();
+
+    let (_resolved, permit) = runtime.resolve_and_acquire("claude-opus").unwrap();
+    let status = runtime.slot_status(ModelSlot::Opus);
+    assert_eq!(status.len(), 1);
+    assert_eq!(status[0].endpoint_id, "ep-1");
+    assert_eq!(status[0].in_flight, 1);
+    assert_eq!(status[0].health, EndpointHealth::Healthy);
+    assert!(status[0].is_available());
+    drop(permit);
+
+    let resolved = resolve_opus_route(&runtime);
+    runtime.mark_unavailable(&resolved, "auth");
+    let status = runtime.slot_status(ModelSlot::Opus);
+    assert_eq!(status[0].health, EndpointHealth::Cooldown);
+    assert!(status[0].cooldown_remaining_secs.unwrap() <= 5);
+    assert!(!status[0].is_available());
+    assert_eq!(status[0].in_flight, 0);
+
+    // 只读快照不影响其它 slot
+    let sonnet = runtime.slot_status(ModelSlot::Sonnet);
+    assert_eq!(sonnet.len(), 2);
+    assert!(sonnet.iter().all(|candidate| candidate.is_available()));
+}
+
 #[test]
 fn test_network_error_counted() {
     let runtime = create_test_runtime();
@@ -210,6 +258,8 @@ fn test_model_unavailable_signal_immediately_fallbacks_to_next_route_in_same_slo
                 target_url: "http://bad.example.com".to_string(),
                 api_key: Some("bad".to_string()),
                 converter: "anthropic".to_string(),
+                api_keys: Vec::new(),
+                key_rotation: KeyRotation::default(),
             },
         ),
         (
@@ -219,6 +269,8 @@ fn test_model_unavailable_signal_immediately_fallbacks_to_next_route_in_same_slo
                 target_url: "https://good.example.com".to_string(),
                 api_key: Some("good".to_string()),
                 converter: "codex".to_string(),
+                api_keys: Vec::new(),
+                key_rotation: KeyRotation::default(),
             },
         ),
     ]
@@ -246,6 +298,11 @@ fn test_model_unavailable_signal_immediately_fallbacks_to_next_route_in_same_slo
             sonnet: vec![],
             haiku: vec![],
         },
+        selection: SelectionStrategy::Priority,
+        session_affinity_ttl_seconds: 0,
+        hedge_after_ms: 0,
+        degradation_chain: Vec::new(),
+        degradation_header: false,
     }];
 
     let endpoint_policies: HashMap<String, EndpointPolicy> = [
@@ -259,6 +316,10 @@ fn test_model_unavailable_signal_immediately_fallbacks_to_next_route_in_same_slo
                 cooldown_seconds: 300,
                 degraded_concurrency: 2,
                 transient_backoff_seconds: 1,
+                priority: 0,
+                weight: 1,
+                latency_threshold_ms: 0,
+                probe_interval_seconds: 0,
             },
         ),
         (
@@ -271,6 +332,10 @@ fn test_model_unavailable_signal_immediately_fallbacks_to_next_route_in_same_slo
                 cooldown_seconds: 300,
                 degraded_concurrency: 2,
                 transient_backoff_seconds: 1,
+                priority: 0,
+                weight: 1,
+                latency_threshold_ms: 0,
+                probe_interval_seconds: 0,
             },
         ),
     ]
@@ -282,6 +347,7 @@ fn test_model_unavailable_signal_immediately_fallbacks_to_next_route_in_same_slo
             selected_profile_id: Some("profile-1".to_string()),
             profiles,
             endpoint_policies,
+            named_routes: Vec::new(),
         },
         endpoint_directory,
         None,
@@ -319,6 +385,8 @@ fn test_route_not_found_404_immediately_fallbacks_to_next_route_in_same_slot() {
                 target_url: "https://bad.example.com/openai".to_string(),
                 api_key: Some("bad".to_string()),
                 converter: "codex".to_string(),
+                api_keys: Vec::new(),
+                key_rotation: KeyRotation::default(),
             },
         ),
         (
@@ -328,6 +396,8 @@ fn test_route_not_found_404_immediately_fallbacks_to_next_route_in_same_slot() {
                 target_url: "https://good.example.com/openai/responses".to_string(),
                 api_key: Some("good".to_string()),
                 converter: "codex".to_string(),
+                api_keys: Vec::new(),
+                key_rotation: KeyRotation::default(),
             },
         ),
     ]
@@ -355,6 +425,11 @@ fn test_route_not_found_404_immediately_fallbacks_to_next_route_in_same_slot() {
             sonnet: vec![],
             haiku: vec![],
         },
+        selection: SelectionStrategy::Priority,
+        session_affinity_ttl_seconds: 0,
+        hedge_after_ms: 0,
+        degradation_chain: Vec::new(),
+        degradation_header: false,
     }];
 
     let endpoint_policies: HashMap<String, EndpointPolicy> = [
@@ -368,6 +443,10 @@ fn test_route_not_found_404_immediately_fallbacks_to_next_route_in_same_slot() {
                 cooldown_seconds: 300,
                 degraded_concurrency: 2,
                 transient_backoff_seconds: 1,
+                priority: 0,
+                weight: 1,
+                latency_threshold_ms: 0,
+                probe_interval_seconds: 0,
             },
         ),
         (
@@ -380,6 +459,10 @@ fn test_route_not_found_404_immediately_fallbacks_to_next_route_in_same_slot() {
                 cooldown_seconds: 300,
                 degraded_concurrency: 2,
                 transient_backoff_seconds: 1,
+                priority: 0,
+                weight: 1,
+                latency_threshold_ms: 0,
+                probe_interval_seconds: 0,
             },
         ),
     ]
@@ -391,6 +474,7 @@ fn test_route_not_found_404_immediately_fallbacks_to_next_route_in_same_slot() {
             selected_profile_id: Some("profile-1".to_string()),
             profiles,
             endpoint_policies,
+            named_routes: Vec::new(),
         },
         endpoint_directory,
         None,
@@ -431,3 +515,834 @@ fn test_generic_5xx_is_retryable_for_same_request_failover() {
     );
     assert_eq!(action, UpstreamOutcomeAction::RetryNextCandidate);
 }
+
+#[test]
+fn test_forced_cooldown_is_counted_as_lb_transition_metric() {
+    let labels = [
+        ("slot", "opus"),
+        ("endpoint", "ep-1"),
+        ("from", "Healthy"),
+        ("to", "Cooldown"),
+    ];
+    let before = codex_proxy_core::metrics::global()
+        .counter_value("codex_proxy_lb_transitions_total", &labels);
+
+    let runtime = create_test_runtime();
+    let resolved = resolve_opus_route(&runtime);
+    runtime.mark_unavailable(&resolved, "quota_exhausted");
+
+    let after = codex_proxy_core::metrics::global()
+        .counter_value("codex_proxy_lb_transitions_total", &labels);
+    assert!(after > before);
+    assert!(codex_proxy_core::metrics::global()
+        .render()
+        .contains("# TYPE codex_proxy_lb_transitions_total counter"));
+}
+
+fn endpoint(id: &str, converter: &str) -> LoadBalancerEndpoint {
+    LoadBalancerEndpoint {
+        id: id.to_string(),
+        target_url: format!("https://{}.example.com", id),
+        converter: converter.to_string(),
+        ..LoadBalancerEndpoint::default()
+    }
+}
+
+fn slot_ref(endpoint_id: &str) -> SlotEndpointRef {
+    SlotEndpointRef {
+        endpoint_id: endpoint_id.to_string(),
+        ..SlotEndpointRef::default()
+    }
+}
+
+/// 只有一个 profile 的运行时
+fn build_runtime(
+    profile: LoadBalancerProfile,
+    endpoints: Vec<LoadBalancerEndpoint>,
+    endpoint_policies: HashMap<String, EndpointPolicy>,
+) -> LoadBalancerRuntime {
+    LoadBalancerRuntime::new(
+        LoadBalancerConfig {
+            selected_profile_id: Some(profile.id.clone()),
+            profiles: vec![profile],
+            endpoint_policies,
+            named_routes: Vec::new(),
+        },
+        endpoints
+            .into_iter()
+            .map(|endpoint| (endpoint.id.clone(), endpoint))
+            .collect(),
+        None,
+    )
+}
+
+/// 三个同 slot 端点：(id, priority, weight)
+fn create_strategy_runtime(
+    selection: SelectionStrategy,
+    endpoints: &[(&str, u32, u32)],
+) -> LoadBalancerRuntime {
+    create_strategy_runtime_with_policy(selection, endpoints, EndpointPolicy::default())
+}
+
+fn create_strategy_runtime_with_policy(
+    selection: SelectionStrategy,
+    endpoints: &[(&str, u32, u32)],
+    base_policy: EndpointPolicy,
+) -> LoadBalancerRuntime {
+    let profile = LoadBalancerProfile {
+        id: "profile-1".to_string(),
+        model_mapping: SlotMapping {
+            sonnet: endpoints.iter().map(|(id, _, _)| slot_ref(id)).collect(),
+            ..SlotMapping::default()
+        },
+        selection,
+        session_affinity_ttl_seconds: 600,
+        ..LoadBalancerProfile::default()
+    };
+    let endpoint_policies = endpoints
+        .iter()
+        .map(|(id, priority, weight)| {
+            (
+                id.to_string(),
+                EndpointPolicy {
+                    priority: *priority,
+                    weight: *weight,
+                    ..base_policy.clone()
+                },
+            )
+        })
+        .collect();
+    build_runtime(
+        profile,
+        endpoints
+            .iter()
+            .map(|(id, _, _)| endpoint(id, "codex"))
+            .collect(),
+        endpoint_policies,
+    )
+}
+
+fn pick_counts(runtime: &LoadBalancerRuntime, rounds: usize) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for _ in 0..rounds {
+        let (resolved, _permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+        *counts.entry(resolved.endpoint_id).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[test]
+fn test_selection_strategy_parses_config_values() {
+    assert_eq!(
+        SelectionStrategy::from_config("round_robin"),
+        SelectionStrategy::RoundRobin
+    );
+    assert_eq!(
+        SelectionStrategy::from_config("Weighted_Random"),
+        SelectionStrategy::WeightedRandom
+    );
+    assert_eq!(
+        SelectionStrategy::from_config("least_in_flight"),
+        SelectionStrategy::LeastInFlight
+    );
+    assert_eq!(
+        SelectionStrategy::from_config(""),
+        SelectionStrategy::Priority
+    );
+}
+
+#[test]
+fn test_priority_tiers_are_honoured_before_weights() {
+    // ep-a 在后备层，即使权重更高也不应被选中
+    let runtime = create_strategy_runtime(
+        SelectionStrategy::RoundRobin,
+        &[("ep-a", 1, 10), ("ep-b", 0, 1), ("ep-c", 0, 1)],
+    );
+    let counts = pick_counts(&runtime, 20);
+    assert_eq!(counts.get("ep-a"), None);
+    assert_eq!(counts.get("ep-b"), Some(&10));
+    assert_eq!(counts.get("ep-c"), Some(&10));
+}
+
+#[test]
+fn test_lower_tier_takes_over_when_primary_tier_is_unavailable() {
+    let runtime = create_strategy_runtime(
+        SelectionStrategy::WeightedRandom,
+        &[("ep-a", 0, 1), ("ep-b", 1, 1)],
+    );
+    let (primary, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    assert_eq!(primary.endpoint_id, "ep-a");
+    drop(permit);
+    runtime.mark_unavailable(&primary, "quota");
+
+    let (fallback, _permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    assert_eq!(fallback.endpoint_id, "ep-b");
+}
+
+#[test]
+fn test_budgets_demote_soft_and_skip_hard_breached_endpoints() {
+    let budget = Arc::new(BudgetTracker::new(None));
+    let limits = |daily_soft, daily_hard| BudgetLimits {
+        daily_soft,
+        daily_hard,
+        ..BudgetLimits::default()
+    };
+    let budgets = BudgetsConfig {
+        endpoints: [
+            ("ep-a".to_string(), limits(1.0, 0.0)),
+            ("ep-b".to_string(), limits(0.0, 1.0)),
+        ]
+        .into_iter()
+        .collect(),
+        ..BudgetsConfig::default()
+    };
+    budget.configure(
+        &[ModelPriceConfig {
+            pattern: "gpt-5".to_string(),
+            input: 1.0,
+            ..ModelPriceConfig::default()
+        }],
+        budgets,
+    );
+    let runtime = create_strategy_runtime(
+        SelectionStrategy::Priority,
+        &[("ep-a", 0, 1), ("ep-b", 1, 1), ("ep-c", 2, 1)],
+    )
+    .with_budget_tracker(Arc::clone(&budget));
+    let million = TokenUsage {
+        input: 1_000_000,
+        ..TokenUsage::default()
+    };
+
+    let (first, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    assert_eq!(first.endpoint_id, "ep-a");
+    drop(permit);
+
+    // 软预算：ep-a 排到最后，ep-b 接手
+    budget.record("r1", "gpt-5", "ep-a", None, &million);
+    let (second, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    assert_eq!(second.endpoint_id, "ep-b");
+    drop(permit);
+
+    // 硬预算：ep-b 视同冷却直到次日
+    budget.record("r2", "gpt-5", "ep-b", None, &million);
+    let (third, _permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    assert_eq!(third.endpoint_id, "ep-c");
+
+    let status = runtime.slot_status(ModelSlot::Sonnet);
+    let ep_b = status.iter().find(|c| c.endpoint_id == "ep-b").unwrap();
+    assert_eq!(ep_b.health, EndpointHealth::Cooldown);
+    assert_eq!(ep_b.budget, Some("hard"));
+    assert!(ep_b.cooldown_remaining_secs.is_some());
+    assert!(!ep_b.is_available());
+    let ep_a = status.iter().find(|c| c.endpoint_id == "ep-a").unwrap();
+    assert_eq!(ep_a.budget, Some("soft"));
+    assert!(ep_a.is_available());
+}
+
+#[test]
+fn test_smooth_weighted_round_robin_follows_weights() {
+    let runtime = create_strategy_runtime(
+        SelectionStrategy::RoundRobin,
+        &[("ep-a", 0, 3), ("ep-b", 0, 1)],
+    );
+    let order: Vec<String> = (0..4)
+        .map(|_| {
+            let (resolved, _permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+            resolved.endpoint_id
+        })
+        .collect();
+    // 平滑轮询不会连续打满高权重端点
+    assert_eq!(order, vec!["ep-a", "ep-a", "ep-b", "ep-a"]);
+}
+
+#[test]
+fn test_weighted_random_spreads_load_roughly_by_weight() {
+    let runtime = create_strategy_runtime(
+        SelectionStrategy::WeightedRandom,
+        &[("ep-a", 0, 3), ("ep-b", 0, 1)],
+    );
+    let counts = pick_counts(&runtime, 400);
+    let a = *counts.get("ep-a").unwrap_or(&0);
+    let b = *counts.get("ep-b").unwrap_or(&0);
+    assert_eq!(a + b, 400);
+    assert!(a > 220 && b > 40, "a={} b={}", a, b);
+}
+
+#[test]
+fn test_least_in_flight_prefers_idle_endpoint() {
+    let runtime = create_strategy_runtime(
+        SelectionStrategy::LeastInFlight,
+        &[("ep-a", 0, 1), ("ep-b", 0, 1)],
+    );
+    let (first, _first_permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    let (second, second_permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    assert_eq!(first.endpoint_id, "ep-a");
+    assert_eq!(second.endpoint_id, "ep-b");
+
+    // ep-b 释放后 in_flight 更少，下一次应选 ep-b
+    drop(second_permit);
+    let (third, _third_permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    assert_eq!(third.endpoint_id, "ep-b");
+}
+
+fn status_of(runtime: &LoadBalancerRuntime, endpoint_id: &str) -> SlotCandidateStatus {
+    runtime
+        .slot_status(ModelSlot::Sonnet)
+        .into_iter()
+        .find(|status| status.endpoint_id == endpoint_id)
+        .unwrap()
+}
+
+#[test]
+fn test_slow_route_is_constrained_and_recovers_after_latency_drops() {
+    let runtime = create_strategy_runtime_with_policy(
+        SelectionStrategy::Priority,
+        &[("ep-a", 0, 1)],
+        EndpointPolicy {
+            latency_threshold_ms: 500,
+            ..EndpointPolicy::default()
+        },
+    );
+    let (route, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    drop(permit);
+
+    // 样本不足时不降级
+    runtime.record_latency(&route, Duration::from_millis(2_000), None);
+    runtime.record_latency(&route, Duration::from_millis(2_000), None);
+    assert_eq!(status_of(&runtime, "ep-a").health, EndpointHealth::Healthy);
+
+    runtime.record_latency(&route, Duration::from_millis(2_000), Some(40.0));
+    let status = status_of(&runtime, "ep-a");
+    assert_eq!(status.health, EndpointHealth::Constrained);
+    assert_eq!(status.ttfb_ewma_ms, Some(2_000));
+    assert_eq!(status.output_tokens_per_sec, Some(40.0));
+
+    for _ in 0..10 {
+        runtime.record_latency(&route, Duration::from_millis(100), None);
+    }
+    assert_eq!(status_of(&runtime, "ep-a").health, EndpointHealth::Healthy);
+}
+
+#[test]
+fn test_latency_threshold_zero_never_demotes() {
+    let runtime = create_strategy_runtime(SelectionStrategy::Priority, &[("ep-a", 0, 1)]);
+    let (route, _permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    for _ in 0..5 {
+        runtime.record_latency(&route, Duration::from_secs(30), None);
+    }
+    assert_eq!(status_of(&runtime, "ep-a").health, EndpointHealth::Healthy);
+}
+
+#[test]
+fn test_lowest_latency_prefers_faster_route_within_tier() {
+    let runtime = create_strategy_runtime(
+        SelectionStrategy::LowestLatency,
+        &[("ep-a", 0, 1), ("ep-b", 0, 1), ("ep-c", 1, 1)],
+    );
+    // 尚无样本的路由优先被测量
+    let (slow, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    drop(permit);
+    runtime.record_latency(&slow, Duration::from_millis(1_500), Some(20.0));
+    let (fast, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    drop(permit);
+    assert_ne!(fast.endpoint_id, slow.endpoint_id);
+    runtime.record_latency(&fast, Duration::from_millis(300), Some(80.0));
+
+    let counts = pick_counts(&runtime, 100);
+    let fast_picks = *counts.get(&fast.endpoint_id).unwrap_or(&0);
+    assert!(fast_picks >= 80, "fast picks={}", fast_picks);
+    // 后备层不参与同层探测
+    assert_eq!(counts.get("ep-c"), None);
+}
+
+#[test]
+fn test_latency_breaks_ties_within_tier_for_other_strategies() {
+    for selection in [
+        SelectionStrategy::RoundRobin,
+        SelectionStrategy::WeightedRandom,
+    ] {
+        let runtime = create_strategy_runtime_with_policy(
+            selection,
+            &[("ep-a", 0, 1), ("ep-b", 0, 1), ("ep-c", 1, 1)],
+            EndpointPolicy {
+                max_concurrency: 1,
+                ..EndpointPolicy::default()
+            },
+        );
+        // 并发上限为 1，同时持有两个许可即可拿到同层的两条路由
+        let (first, first_permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+        let (second, second_permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+        let (slow, fast) = if first.endpoint_id == "ep-a" {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        assert_eq!(fast.endpoint_id, "ep-b");
+        drop(first_permit);
+        drop(second_permit);
+        for _ in 0..3 {
+            runtime.record_latency(&slow, Duration::from_millis(2_000), Some(20.0));
+            runtime.record_latency(&fast, Duration::from_millis(300), Some(80.0));
+        }
+
+        // 明显更慢的 ep-a 让位给同层的 ep-b，后备层不受影响
+        let counts = pick_counts(&runtime, 20);
+        assert_eq!(counts.get("ep-b"), Some(&20), "{:?}", selection);
+
+        // 慢路由不可用前仍先于后备层被尝试
+        runtime.mark_unavailable(&fast, "quota");
+        let (fallback, _permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+        assert_eq!(fallback.endpoint_id, "ep-a", "{:?}", selection);
+    }
+}
+
+#[test]
+fn test_priority_strategy_ignores_latency() {
+    let runtime = create_strategy_runtime_with_policy(
+        SelectionStrategy::Priority,
+        &[("ep-a", 0, 1), ("ep-b", 0, 1)],
+        EndpointPolicy {
+            max_concurrency: 1,
+            ..EndpointPolicy::default()
+        },
+    );
+    let (slow, slow_permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    let (fast, fast_permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    assert_eq!(slow.endpoint_id, "ep-a");
+    assert_eq!(fast.endpoint_id, "ep-b");
+    drop(slow_permit);
+    drop(fast_permit);
+    for _ in 0..5 {
+        runtime.record_latency(&slow, Duration::from_millis(5_000), Some(10.0));
+        runtime.record_latency(&fast, Duration::from_millis(200), Some(100.0));
+    }
+
+    // 严格按配置顺序：ep-a 即使明显更慢也始终排在第一
+    let counts = pick_counts(&runtime, 10);
+    assert_eq!(counts.get("ep-a"), Some(&10));
+}
+
+#[test]
+fn test_comparable_latency_keeps_strategy_order() {
+    let runtime = create_strategy_runtime(
+        SelectionStrategy::RoundRobin,
+        &[("ep-a", 0, 1), ("ep-b", 0, 1)],
+    );
+    let (a, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    drop(permit);
+    let (b, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    drop(permit);
+    for _ in 0..3 {
+        runtime.record_latency(&a, Duration::from_millis(400), Some(60.0));
+        runtime.record_latency(&b, Duration::from_millis(500), Some(50.0));
+    }
+    let counts = pick_counts(&runtime, 20);
+    assert_eq!(counts.get("ep-a"), Some(&10));
+    assert_eq!(counts.get("ep-b"), Some(&10));
+}
+
+fn probe_policy() -> EndpointPolicy {
+    EndpointPolicy {
+        cooldown_seconds: 3600,
+        probe_interval_seconds: 1,
+        ..EndpointPolicy::default()
+    }
+}
+
+#[test]
+fn test_probe_moves_cooled_route_through_half_open_to_healthy() {
+    let runtime = create_strategy_runtime_with_policy(
+        SelectionStrategy::Priority,
+        &[("ep-a", 0, 1)],
+        probe_policy(),
+    );
+    let (route, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    drop(permit);
+    runtime.mark_unavailable(&route, "quota");
+
+    // 刚进入冷却，未满一个探测间隔
+    assert!(runtime.due_probe_targets().is_empty());
+    sleep(Duration::from_millis(1_100));
+    let targets = runtime.due_probe_targets();
+    assert_eq!(targets.len(), 1);
+    assert_eq!(targets[0].health, EndpointHealth::Cooldown);
+    assert!(targets[0].enabled);
+    // 已派发的目标在下一个间隔前不会重复返回
+    assert!(runtime.due_probe_targets().is_empty());
+
+    runtime.record_probe_result(&targets[0].route, true, "ok");
+    let status = status_of(&runtime, "ep-a");
+    assert_eq!(status.health, EndpointHealth::HalfOpen);
+    assert_eq!(status.max_concurrency, 1);
+
+    // 试探期只放行一个并发
+    let (half_open_route, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    assert!(runtime.resolve_and_acquire("claude-sonnet-4").is_none());
+    runtime.record_result(&half_open_route, Some(200), false);
+    drop(permit);
+    assert_eq!(status_of(&runtime, "ep-a").health, EndpointHealth::Healthy);
+}
+
+#[test]
+fn test_probe_failure_keeps_cooldown_and_half_open_failure_reopens_it() {
+    let runtime = create_strategy_runtime_with_policy(
+        SelectionStrategy::Priority,
+        &[("ep-a", 0, 1)],
+        probe_policy(),
+    );
+    let (route, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    drop(permit);
+    runtime.mark_unavailable(&route, "quota");
+
+    runtime.record_probe_result(&route, false, "HTTP 503");
+    assert_eq!(status_of(&runtime, "ep-a").health, EndpointHealth::Cooldown);
+
+    runtime.record_probe_result(&route, true, "ok");
+    assert_eq!(status_of(&runtime, "ep-a").health, EndpointHealth::HalfOpen);
+    let action = runtime.handle_upstream_outcome(&route, Some(500), false, Some("boom"));
+    assert_eq!(action, UpstreamOutcomeAction::RetryNextCandidate);
+    let status = status_of(&runtime, "ep-a");
+    assert_eq!(status.health, EndpointHealth::Cooldown);
+    assert!(status.cooldown_remaining_secs.is_some());
+}
+
+#[test]
+fn test_disabled_endpoint_is_probed_but_not_reenabled() {
+    let runtime = create_strategy_runtime_with_policy(
+        SelectionStrategy::Priority,
+        &[("ep-a", 0, 1)],
+        EndpointPolicy {
+            enabled: false,
+            ..probe_policy()
+        },
+    );
+    let targets = runtime.due_probe_targets();
+    assert_eq!(targets.len(), 1);
+    assert!(!targets[0].enabled);
+
+    runtime.record_probe_result(&targets[0].route, true, "ok");
+    assert!(!status_of(&runtime, "ep-a").enabled);
+    assert!(runtime.resolve_and_acquire("claude-sonnet-4").is_none());
+}
+
+#[test]
+fn test_probing_is_disabled_by_default() {
+    let runtime = create_strategy_runtime(SelectionStrategy::Priority, &[("ep-a", 0, 1)]);
+    let (route, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    drop(permit);
+    runtime.mark_unavailable(&route, "quota");
+    assert!(runtime.due_probe_targets().is_empty());
+}
+
+fn pick_for_session(runtime: &LoadBalancerRuntime, session: &str) -> String {
+    let (resolved, _permit) = runtime
+        .resolve_and_acquire_for_session("claude-sonnet-4", Some(session))
+        .unwrap();
+    resolved.endpoint_id
+}
+
+#[test]
+fn test_session_sticks_to_bound_route_across_round_robin() {
+    let runtime = create_strategy_runtime(
+        SelectionStrategy::RoundRobin,
+        &[("ep-a", 0, 1), ("ep-b", 0, 1)],
+    );
+    let first = pick_for_session(&runtime, "session-1");
+    for _ in 0..5 {
+        assert_eq!(pick_for_session(&runtime, "session-1"), first);
+    }
+    // 无会话提示的请求照常轮询
+    let counts = pick_counts(&runtime, 4);
+    assert_eq!(counts.len(), 2);
+}
+
+#[test]
+fn test_session_rebinds_only_when_bound_route_becomes_unavailable() {
+    let runtime = create_strategy_runtime(
+        SelectionStrategy::Priority,
+        &[("ep-a", 0, 1), ("ep-b", 0, 1)],
+    );
+    let (bound, permit) = runtime
+        .resolve_and_acquire_for_session("claude-sonnet-4", Some("session-1"))
+        .unwrap();
+    drop(permit);
+    assert_eq!(bound.endpoint_id, "ep-a");
+
+    runtime.mark_unavailable(&bound, "quota");
+    assert_eq!(pick_for_session(&runtime, "session-1"), "ep-b");
+
+    // ep-a 恢复后会话仍留在新绑定的 ep-b，新会话按策略回到 ep-a
+    runtime.clear_cooldowns();
+    assert_eq!(pick_for_session(&runtime, "session-1"), "ep-b");
+    assert_eq!(pick_for_session(&runtime, "session-2"), "ep-a");
+}
+
+#[test]
+fn test_busy_bound_route_overflows_without_rebinding() {
+    let runtime = create_strategy_runtime_with_policy(
+        SelectionStrategy::Priority,
+        &[("ep-a", 0, 1), ("ep-b", 0, 1)],
+        EndpointPolicy {
+            max_concurrency: 1,
+            ..EndpointPolicy::default()
+        },
+    );
+    let (bound, permit) = runtime
+        .resolve_and_acquire_for_session("claude-sonnet-4", Some("session-1"))
+        .unwrap();
+    assert_eq!(bound.endpoint_id, "ep-a");
+    assert_eq!(pick_for_session(&runtime, "session-1"), "ep-b");
+
+    drop(permit);
+    assert_eq!(pick_for_session(&runtime, "session-1"), "ep-a");
+}
+
+#[test]
+fn test_named_route_resolves_its_own_endpoints() {
+    let runtime = create_test_runtime_with_named_routes(vec![NamedRoute {
+        name: "codex-fast".to_string(),
+        slot: ModelSlot::Sonnet,
+        endpoints: vec![SlotEndpointRef {
+            endpoint_id: "ep-2".to_string(),
+            custom_model_name: Some("gpt-5-codex".to_string()),
+            custom_reasoning_effort: None,
+            converter_override: None,
+        }],
+    }]);
+
+    assert_eq!(runtime.named_route_names(), vec!["codex-fast"]);
+    let (resolved, permit) = runtime
+        .resolve_and_acquire_for_route("codex-fast", "gpt-5-codex", None)
+        .expect("named route should resolve");
+    drop(permit);
+    assert_eq!(resolved.endpoint_id, "ep-2");
+    assert_eq!(resolved.slot, ModelSlot::Sonnet);
+    assert_eq!(resolved.model.as_deref(), Some("gpt-5-codex"));
+    assert_eq!(runtime.named_route_status("codex-fast").len(), 1);
+    assert_eq!(runtime.candidate_count_for_route("codex-fast"), 1);
+    assert_eq!(runtime.candidate_count_for_route("missing"), 0);
+
+    assert!(runtime
+        .resolve_and_acquire_for_route("missing", "gpt-5-codex", None)
+        .is_none());
+
+    // 没有端点列表的路由沿用 slot 候选，不再依赖模型名推断
+    let (in_slot, _permit) = runtime
+        .resolve_and_acquire_in_slot(ModelSlot::Opus, "gpt-5-codex", None)
+        .unwrap();
+    assert_eq!(in_slot.endpoint_id, "ep-1");
+}
+
+fn create_key_pool_runtime(rotation: KeyRotation) -> LoadBalancerRuntime {
+    let pool = LoadBalancerEndpoint {
+        api_key: Some("sk-pool-key-0000000000".to_string()),
+        api_keys: vec![
+            "sk-pool-key-0000000000".to_string(),
+            "sk-pool-key-1111111111".to_string(),
+            "sk-pool-key-2222222222".to_string(),
+        ],
+        key_rotation: rotation,
+        ..endpoint("ep-pool", "codex")
+    };
+    let profile = LoadBalancerProfile {
+        id: "profile-1".to_string(),
+        model_mapping: SlotMapping {
+            sonnet: vec![slot_ref("ep-pool")],
+            ..SlotMapping::default()
+        },
+        ..LoadBalancerProfile::default()
+    };
+    build_runtime(profile, vec![pool], HashMap::new())
+}
+
+fn pick_key(runtime: &LoadBalancerRuntime) -> (ResolvedEndpoint, EndpointPermit) {
+    runtime
+        .resolve_and_acquire("claude-sonnet-4")
+        .expect("key pool endpoint should resolve")
+}
+
+#[test]
+fn test_key_pool_round_robin_rotates_keys() {
+    let runtime = create_key_pool_runtime(KeyRotation::RoundRobin);
+    assert_eq!(runtime.candidate_count_in_slot(ModelSlot::Sonnet), 3);
+
+    let picked: Vec<Option<usize>> = (0..4).map(|_| pick_key(&runtime).0.key_index).collect();
+    assert_eq!(picked, [Some(0), Some(1), Some(2), Some(0)]);
+
+    let (resolved, _permit) = pick_key(&runtime);
+    assert_eq!(resolved.key_index, Some(1));
+    assert_eq!(resolved.api_key.as_deref(), Some("sk-pool-key-1111111111"));
+}
+
+#[test]
+fn test_key_pool_least_used_prefers_idle_key() {
+    let runtime = create_key_pool_runtime(KeyRotation::LeastUsed);
+    let (first, first_permit) = pick_key(&runtime);
+    let (second, _second_permit) = pick_key(&runtime);
+    assert_eq!(first.key_index, Some(0));
+    assert_eq!(second.key_index, Some(1));
+
+    drop(first_permit);
+    // key 0 已空闲但用过一次，key 2 从未使用
+    assert_eq!(pick_key(&runtime).0.key_index, Some(2));
+    assert_eq!(pick_key(&runtime).0.key_index, Some(0));
+}
+
+#[test]
+fn test_key_cooldown_skips_key_without_touching_route_health() {
+    let runtime = create_key_pool_runtime(KeyRotation::RoundRobin);
+    let (first, _) = pick_key(&runtime);
+    assert!(runtime.cool_down_key(&first, 429, Some(120)));
+    let (second, _) = pick_key(&runtime);
+    assert!(runtime.cool_down_key(&second, 401, None));
+    assert!(!runtime.cool_down_key(&second, 500, None));
+
+    let status = &runtime.slot_status(ModelSlot::Sonnet)[0];
+    assert_eq!(status.health, EndpointHealth::Healthy);
+    assert_eq!(status.keys.len(), 3);
+    assert_eq!(status.keys[0].key, "sk-****0000");
+    assert!(status.keys[0].cooldown_remaining_secs.is_some());
+    assert!(status.keys[1].cooldown_remaining_secs.is_some());
+    assert!(status.keys[2].cooldown_remaining_secs.is_none());
+
+    // 只剩最后一个可用密钥时交回路由级处理
+    let (last, _) = pick_key(&runtime);
+    assert_eq!(last.key_index, Some(2));
+    assert!(!runtime.cool_down_key(&last, 429, Some(120)));
+    assert_eq!(pick_key(&runtime).0.key_index, Some(2));
+}
+
+#[test]
+fn test_clear_cooldowns_resets_key_cooldowns() {
+    let runtime = create_key_pool_runtime(KeyRotation::RoundRobin);
+    let (first, _) = pick_key(&runtime);
+    assert!(runtime.cool_down_key(&first, 429, Some(120)));
+    let (second, _) = pick_key(&runtime);
+    assert!(runtime.cool_down_key(&second, 401, None));
+
+    assert_eq!(runtime.clear_cooldowns(), 2);
+    let status = &runtime.slot_status(ModelSlot::Sonnet)[0];
+    assert!(status
+        .keys
+        .iter()
+        .all(|key| key.cooldown_remaining_secs.is_none()));
+    // 冷却撤销后重新参与轮换
+    let picked: Vec<Option<usize>> = (0..3).map(|_| pick_key(&runtime).0.key_index).collect();
+    assert!(picked.contains(&Some(0)) && picked.contains(&Some(1)));
+    assert_eq!(runtime.clear_cooldowns(), 0);
+}
+
+#[test]
+fn test_endpoint_without_key_pool_is_not_key_cooled() {
+    let runtime = create_test_runtime();
+    let resolved = resolve_opus_route(&runtime);
+    assert_eq!(resolved.key_index, None);
+    assert_eq!(resolved.api_key.as_deref(), Some("key1"));
+    assert!(!runtime.cool_down_key(&resolved, 429, Some(60)));
+    assert!(runtime.slot_status(ModelSlot::Opus)[0].keys.is_empty());
+}
+
+fn create_degradation_runtime(chain: Vec<DegradationStep>) -> LoadBalancerRuntime {
+    let profile = LoadBalancerProfile {
+        id: "profile-1".to_string(),
+        model_mapping: SlotMapping {
+            opus: vec![slot_ref("ep-opus")],
+            sonnet: vec![slot_ref("ep-sonnet")],
+            haiku: vec![],
+        },
+        session_affinity_ttl_seconds: 600,
+        degradation_chain: chain,
+        degradation_header: true,
+        ..LoadBalancerProfile::default()
+    };
+    build_runtime(
+        profile,
+        vec![endpoint("ep-opus", "codex"), endpoint("ep-sonnet", "codex")],
+        HashMap::new(),
+    )
+}
+
+fn degradation_step(slot: ModelSlot, reasoning_effort: Option<&str>) -> DegradationStep {
+    DegradationStep {
+        slot,
+        reasoning_effort: reasoning_effort.map(str::to_string),
+    }
+}
+
+#[test]
+fn test_exhausted_slot_degrades_along_chain() {
+    let runtime = create_degradation_runtime(vec![
+        degradation_step(ModelSlot::Opus, None),
+        degradation_step(ModelSlot::Haiku, Some("low")),
+        degradation_step(ModelSlot::Sonnet, Some("high")),
+    ]);
+    assert!(runtime.degradation_header_enabled());
+
+    let (primary, permit) = runtime.resolve_and_acquire("claude-opus-4-6").unwrap();
+    drop(permit);
+    assert_eq!(primary.degraded_from, None);
+
+    // 只在主 slot 没有可用候选时降级；haiku 没有候选，继续沿链找到 sonnet
+    runtime.mark_unavailable(&primary, "quota");
+    let (degraded, _permit) = runtime
+        .resolve_and_acquire_for_session("claude-opus-4-6", Some("session-1"))
+        .expect("should degrade to sonnet");
+    assert_eq!(degraded.endpoint_id, "ep-sonnet");
+    assert_eq!(degraded.slot, ModelSlot::Sonnet);
+    assert_eq!(degraded.degraded_from, Some(ModelSlot::Opus));
+    assert_eq!(degraded.reasoning_effort.as_deref(), Some("high"));
+
+    // 链只向后降级：sonnet 不可用时不会回到 opus
+    runtime.mark_unavailable(&degraded, "quota");
+    assert!(runtime.resolve_and_acquire("claude-sonnet-4-6").is_none());
+}
+
+#[test]
+fn test_no_degradation_without_chain() {
+    let runtime = create_degradation_runtime(Vec::new());
+    let (primary, permit) = runtime.resolve_and_acquire("claude-opus-4-6").unwrap();
+    drop(permit);
+    runtime.mark_unavailable(&primary, "quota");
+    assert!(runtime.resolve_and_acquire("claude-opus-4-6").is_none());
+}
+
+#[test]
+fn test_degradation_only_visits_allowed_slots() {
+    let runtime = create_degradation_runtime(vec![
+        degradation_step(ModelSlot::Opus, None),
+        degradation_step(ModelSlot::Sonnet, None),
+    ]);
+    let (primary, permit) = runtime.resolve_and_acquire("claude-opus-4-6").unwrap();
+    drop(permit);
+    runtime.mark_unavailable(&primary, "quota");
+
+    let opus_only = |slot: ModelSlot| slot == ModelSlot::Opus;
+    assert!(runtime
+        .resolve_and_acquire_in_slot_allowing(ModelSlot::Opus, "claude-opus-4-6", None, opus_only)
+        .is_none());
+    let (degraded, _permit) = runtime
+        .resolve_and_acquire_in_slot_allowing(ModelSlot::Opus, "claude-opus-4-6", None, |_| true)
+        .expect("should degrade to sonnet");
+    assert_eq!(degraded.slot, ModelSlot::Sonnet);
+}
+
+#[test]
+fn test_stream_failure_counts_against_route_and_alternate_excludes_it() {
+    let runtime = create_test_runtime();
+    let (stalled, permit) = runtime.resolve_and_acquire("claude-sonnet-4").unwrap();
+    drop(permit);
+    assert_eq!(stalled.endpoint_id, "ep-1");
+
+    for _ in 0..3 {
+        runtime.record_stream_failure(&stalled, "stream_idle_timeout");
+    }
+    assert_ne!(status_of(&runtime, "ep-1").health, EndpointHealth::Healthy);
+
+    // 另一个端点使用不同的 converter，由调用方按新 converter 重建请求
+    let (fallback, _permit) = runtime
+        .resolve_alternate_candidate(&stalled, None, "claude-sonnet-4")
+        .unwrap();
+    assert_eq!(fallback.endpoint_id, "ep-2");
+    assert_eq!(fallback.converter, "gemini");
+}